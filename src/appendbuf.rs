@@ -2,8 +2,10 @@ use std::fmt::{self, Debug, Formatter};
 use std::{mem, io};
 use std::sync::Arc;
 
+use error::{ErrorKind, IobufError};
 use raw::{Allocator, RawIobuf, write_failed};
 use impls::AROIobuf;
+use iobuf::Iobuf;
 use intlike::IntLike;
 
 /// Append-Only Input Buffer
@@ -19,7 +21,7 @@ use intlike::IntLike;
 ///
 /// Its primary interface is `fill`, which is the mechanism for appending data,
 /// and atomic_slice, which will take a position
-/// and a length and return a Result<AROIobuf, ()>
+/// and a length and return a Result<AROIobuf, IobufError>
 pub struct AppendBuf<'a> {
   raw: RawIobuf<'a>,
 }
@@ -61,16 +63,85 @@ impl<'a> AppendBuf<'a> {
     AppendBuf { raw: RawIobuf::new(len) }
   }
 
+  /// Constructs a new Iobuf with a buffer of `len` zeros, and the limits and
+  /// window set to the full size of the buffer.
+  #[inline(always)]
+  pub fn new_zeroed(len: usize) -> AppendBuf<'static> {
+    AppendBuf { raw: RawIobuf::new_zeroed(len) }
+  }
+
+  /// Constructs a new Iobuf with a buffer of size `len`, which tracks how far
+  /// it has been written, like `RWIobuf::new_uninit`. Slices covering bytes
+  /// that were never written are refused. `advance` counts as writing, since
+  /// it's how bytes written through `as_mut_window_slice` are committed.
+  ///
+  /// ```rust
+  /// use iobuf::{AppendBuf, ErrorKind, Iobuf};
+  ///
+  /// let mut buf = AppendBuf::new_uninit(8);
+  /// buf.fill(b"abc").unwrap();
+  /// assert!(buf.atomic_slice_to(-1).is_ok());
+  ///
+  /// buf.as_mut_window_slice()[..2].copy_from_slice(b"de");
+  /// buf.advance(2).unwrap();
+  /// let s = buf.atomic_slice_to(-1).unwrap();
+  /// unsafe { assert_eq!(s.as_window_slice(), b"abcde"); }
+  /// assert_eq!(s.peek_be::<u8>(4), Ok(b'e'));
+  /// ```
+  #[inline(always)]
+  pub fn new_uninit(len: usize) -> AppendBuf<'static> {
+    AppendBuf { raw: RawIobuf::new_uninit(len) }
+  }
+
   /// Constructs a new Iobuf with a buffer of size `len`, undefined contents,
   /// and the limits and window set to the full range of the buffer. The memory
   /// will be allocated out of the given allocator, instead of the global heap.
   ///
   /// The maximum length of an Iobuf is approximately 2 GB.
   #[inline(always)]
-  pub fn new_with_allocator(len: usize, allocator: Arc<Box<Allocator>>) -> AppendBuf<'static> {
+  pub fn new_with_allocator(len: usize, allocator: Arc<Box<dyn Allocator>>) -> AppendBuf<'static> {
     AppendBuf { raw: RawIobuf::new_with_allocator(len, allocator) }
   }
 
+  /// Constructs a new Iobuf with a buffer of size `len` and undefined contents,
+  /// like `new`, but with the start of the buffer aligned to `align` bytes.
+  /// Panics if `align` is not a power of two.
+  ///
+  /// ```rust
+  /// use iobuf::AppendBuf;
+  ///
+  /// let b = AppendBuf::new_aligned(100, 512);
+  ///
+  /// assert_eq!(b.as_window_slice().as_ptr() as usize % 512, 0);
+  /// assert_eq!(b.cap(), 100);
+  /// ```
+  #[inline(always)]
+  pub fn new_aligned(len: usize, align: usize) -> AppendBuf<'static> {
+    AppendBuf { raw: RawIobuf::new_aligned(len, align) }
+  }
+
+  /// The same as `new`, but returns an error instead of panicking if `len` is
+  /// too large or the memory can't be allocated.
+  ///
+  /// ```rust
+  /// use iobuf::{AppendBuf,Iobuf};
+  ///
+  /// let b = AppendBuf::try_new(10).unwrap();
+  /// assert_eq!(b.len(), 10);
+  /// assert!(AppendBuf::try_new(1 << 31).is_err());
+  /// ```
+  #[inline(always)]
+  pub fn try_new(len: usize) -> Result<AppendBuf<'static>, IobufError> {
+    RawIobuf::try_new(len).map(|raw| AppendBuf { raw: raw })
+  }
+
+  /// The same as `new_with_allocator`, but returns an error instead of
+  /// panicking if `len` is too large or the allocator fails.
+  #[inline(always)]
+  pub fn try_new_with_allocator(len: usize, allocator: Arc<Box<dyn Allocator>>) -> Result<AppendBuf<'static>, IobufError> {
+    RawIobuf::try_new_with_allocator(len, allocator).map(|raw| AppendBuf { raw: raw })
+  }
+
   /// Creates an AROIobuf as a slice of written buffer. This is space that preceeds
   /// the window in the buffer, or, more specifically, between the lo_min and lo offsets.
   /// This guarantees that the AROIobuf can be thought of as safely immutable while this
@@ -114,9 +185,8 @@ impl<'a> AppendBuf<'a> {
   ///   assert_eq!(z, b"EFGHIJKL");
   /// ```
   #[inline]
-  pub fn atomic_slice(&self, from: i32, to: i32) -> Result<AROIobuf, ()> {
+  pub fn atomic_slice(&self, from: i32, to: i32) -> Result<AROIobuf, IobufError> {
     unsafe {
-      let mut ret = self.raw.clone_atomic();
       let start = if from < 0 {
         self.raw.lo() - !from as u32
       } else {
@@ -128,8 +198,7 @@ impl<'a> AppendBuf<'a> {
         self.raw.lo_min() + to as u32
       };
       let lim = (start, end);
-      try!(ret.expand_limits_and_window(lim, lim));
-      Ok(mem::transmute(ret))
+      self.slice_limits(lim)
     }
   }
 
@@ -173,17 +242,15 @@ impl<'a> AppendBuf<'a> {
   /// ```
   ///
   #[inline]
-  pub fn atomic_slice_from(&self, pos: i32) -> Result<AROIobuf, ()> {
+  pub fn atomic_slice_from(&self, pos: i32) -> Result<AROIobuf, IobufError> {
     unsafe {
-      let mut ret = self.raw.clone_atomic();
       let lim = if pos < 0 {
         // overflow case should be handled by expand*
         (self.raw.lo() - !pos as u32, self.raw.lo())
       } else {
         (self.raw.lo_min() + pos as u32, self.raw.lo())
       };
-      try!(ret.expand_limits_and_window(lim, lim));
-      Ok(mem::transmute(ret))
+      self.slice_limits(lim)
     }
   }
 
@@ -226,17 +293,31 @@ impl<'a> AppendBuf<'a> {
   ///   assert_eq!(m, b"ABCDEFGHIJKLMNOP");
   /// ```
   #[inline]
-  pub fn atomic_slice_to(&self, pos: i32) -> Result<AROIobuf, ()> {
+  pub fn atomic_slice_to(&self, pos: i32) -> Result<AROIobuf, IobufError> {
     unsafe {
-      let mut ret = self.raw.clone_atomic();
       let lim = if pos < 0 {
         // overflow case should be handled by expand*
         (self.raw.lo_min(), self.raw.lo() - !pos as u32)
       } else {
         (self.raw.lo_min(), self.raw.lo_min() + pos as u32)
       };
-      try!(ret.expand_limits_and_window(lim, lim));
-      Ok(mem::transmute(ret))
+      self.slice_limits(lim)
+    }
+  }
+
+  /// An AROIobuf whose limits and window are `lim`, which must lie inside the
+  /// limits and have been written.
+  unsafe fn slice_limits(&self, lim: (u32, u32)) -> Result<AROIobuf, IobufError> {
+    let mut ret = self.raw.clone_atomic();
+    let checked =
+      ret.expand_limits_and_window(lim, lim)
+        .and_then(|()| ret.check_window_initialized());
+    match checked {
+      Ok(())  => Ok(mem::transmute(ret)),
+      Err(e) => {
+        ret.drop_atomic();
+        Err(e)
+      },
     }
   }
 
@@ -294,13 +375,13 @@ impl<'a> AppendBuf<'a> {
   /// assert_eq!(b.poke(0, data), Ok(()));
   /// assert_eq!(b.poke(3, data), Ok(()));
   /// assert_eq!(b.resize(7), Ok(()));
-  /// assert_eq!(b.poke(4, data), Err(())); // no partial write, just failure
+  /// assert!(b.poke(4, data).is_err()); // no partial write, just failure
   ///
   /// let expected = [ 1,2,3,1,2,3,4 ];
   /// unsafe { assert_eq!(b.as_window_slice(), expected); }
   /// ```
   #[inline(always)]
-  pub fn poke(&self, pos: u32, src: &[u8]) -> Result<(), ()> {
+  pub fn poke(&self, pos: u32, src: &[u8]) -> Result<(), IobufError> {
     self.raw.poke(pos, src)
   }
 
@@ -324,7 +405,7 @@ impl<'a> AppendBuf<'a> {
   /// unsafe { assert_eq!(b.as_window_slice(), expected); }
   /// ```
   #[inline(always)]
-  pub fn poke_be<T: IntLike>(&self, pos: u32, t: T) -> Result<(), ()> {
+  pub fn poke_be<T: IntLike>(&self, pos: u32, t: T) -> Result<(), IobufError> {
     self.raw.poke_be(pos, t)
   }
 
@@ -347,7 +428,7 @@ impl<'a> AppendBuf<'a> {
   /// unsafe { assert_eq!(b.as_window_slice(), [ 4, 5, 5, 9, 8, 7, 6 ]); }
   /// ```
   #[inline(always)]
-  pub fn poke_le<T: IntLike>(&self, pos: u32, t: T) -> Result<(), ()> {
+  pub fn poke_le<T: IntLike>(&self, pos: u32, t: T) -> Result<(), IobufError> {
     self.raw.poke_le(pos, t)
   }
 
@@ -367,14 +448,14 @@ impl<'a> AppendBuf<'a> {
   ///
   /// assert_eq!(b.fill(data), Ok(()));
   /// assert_eq!(b.fill(data), Ok(()));
-  /// assert_eq!(b.fill(data), Err(()));
+  /// assert!(b.fill(data).is_err());
   ///
   /// b.flip_lo();
   ///
   /// unsafe { assert_eq!(b.as_window_slice(), [ 1,2,3,4,1,2,3,4 ]); }
   /// ```
   #[inline(always)]
-  pub fn fill(&mut self, src: &[u8]) -> Result<(), ()> {
+  pub fn fill(&mut self, src: &[u8]) -> Result<(), IobufError> {
     self.raw.fill(src)
   }
 
@@ -392,7 +473,7 @@ impl<'a> AppendBuf<'a> {
   ///
   /// assert_eq!(b.fill_be(0x12345678u32), Ok(()));
   /// assert_eq!(b.fill_be(0x11223344u32), Ok(()));
-  /// assert_eq!(b.fill_be(0x54321123u32), Err(()));
+  /// assert!(b.fill_be(0x54321123u32).is_err());
   /// assert_eq!(b.fill_be(0x8877u16), Ok(()));
   ///
   /// b.flip_lo();
@@ -402,7 +483,7 @@ impl<'a> AppendBuf<'a> {
   ///                      , 0x88, 0x77 ]); }
   /// ```
   #[inline(always)]
-  pub fn fill_be<T: IntLike>(&mut self, t: T) -> Result<(), ()> {
+  pub fn fill_be<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> {
     self.raw.fill_be(t)
   }
 
@@ -420,7 +501,7 @@ impl<'a> AppendBuf<'a> {
   ///
   /// assert_eq!(b.fill_le(0x12345678u32), Ok(()));
   /// assert_eq!(b.fill_le(0x11223344u32), Ok(()));
-  /// assert_eq!(b.fill_le(0x54321123u32), Err(()));
+  /// assert!(b.fill_le(0x54321123u32).is_err());
   /// assert_eq!(b.fill_le(0x8877u16), Ok(()));
   ///
   /// b.flip_lo();
@@ -430,11 +511,11 @@ impl<'a> AppendBuf<'a> {
   ///                      , 0x77, 0x88 ]); }
   /// ```
   #[inline(always)]
-  pub fn fill_le<T: IntLike>(&mut self, t: T) -> Result<(), ()> {
+  pub fn fill_le<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> {
     self.raw.fill_le(t)
   }
 
-  /// Advances the lower bound of the window by `len`. `Err(())` will be
+  /// Advances the lower bound of the window by `len`. An error will be
   /// returned if you advance past the upper bound of the window.
   ///
   /// ```rust
@@ -442,11 +523,13 @@ impl<'a> AppendBuf<'a> {
   ///
   /// let mut b = ROIobuf::from_str("hello");
   /// assert_eq!(b.advance(3), Ok(()));
-  /// assert_eq!(b.advance(3), Err(()));
+  /// assert!(b.advance(3).is_err());
   /// unsafe { assert_eq!(b.as_window_slice(), b"lo"); }
   /// ```
   #[inline(always)]
-  pub fn advance(&mut self, len: u32) -> Result<(), ()> {
+  pub fn advance(&mut self, len: u32) -> Result<(), IobufError> {
+    self.raw.check_range_u32(0, len)?;
+    self.raw.mark_initialized(0, len as usize);
     self.raw.advance(len)
   }
 
@@ -468,16 +551,80 @@ impl<'a> AppendBuf<'a> {
   /// unsafe { assert_eq!(b.as_window_slice(), b"hello"); }
   /// ```
   #[inline]
-  pub fn reset(&mut self) -> Result<(), ()> {
+  pub fn reset(&mut self) -> Result<(), IobufError> {
     unsafe {
       if self.raw.is_unique_atomic() {
         Ok(self.raw.reset())
       } else {
-        Err(())
+        Err(self.raw.error(ErrorKind::NotUnique, 0, 0))
       }
     }
   }
 
+  /// Returns the number of references to the buffer, counting this one and
+  /// every slice taken from it.
+  ///
+  /// ```rust
+  /// use iobuf::AppendBuf;
+  ///
+  /// let mut buf = AppendBuf::new(8);
+  /// buf.fill(b"abcd").unwrap();
+  /// assert_eq!(buf.ref_count(), 1);
+  ///
+  /// let s = buf.atomic_slice_to(2).unwrap();
+  /// assert_eq!(buf.ref_count(), 2);
+  /// assert!(!buf.is_unique());
+  /// assert!(buf.shares_buffer_with(&s));
+  ///
+  /// // `reset` needs the slices to be gone.
+  /// assert!(buf.reset().is_err());
+  /// drop(s);
+  /// assert!(buf.reset().is_ok());
+  /// ```
+  #[inline(always)]
+  pub fn ref_count(&self) -> usize {
+    unsafe { self.raw.ref_count_atomic() }
+  }
+
+  /// Returns `true` if no slices of the buffer are alive, so `reset` will
+  /// succeed.
+  #[inline(always)]
+  pub fn is_unique(&self) -> bool {
+    unsafe { self.raw.is_unique_atomic() }
+  }
+
+  /// Returns `true` if `other` references this buffer, like a slice taken
+  /// from it.
+  #[inline(always)]
+  pub fn shares_buffer_with<Buf: Iobuf>(&self, other: &Buf) -> bool {
+    unsafe { self.raw.shares_buffer_with(other.as_raw()) }
+  }
+
+  /// Returns the tag of the buffer, which every slice taken from it shares.
+  /// See `Iobuf::tag`.
+  ///
+  /// ```rust
+  /// use iobuf::{AppendBuf, Iobuf};
+  ///
+  /// let mut buf = AppendBuf::new(8);
+  /// buf.set_tag(1234).unwrap();
+  /// buf.fill(b"abcd").unwrap();
+  ///
+  /// let s = buf.atomic_slice_to(2).unwrap();
+  /// assert_eq!(s.tag(), 1234);
+  /// ```
+  #[inline(always)]
+  pub fn tag(&self) -> u64 {
+    self.raw.tag()
+  }
+
+  /// Sets the tag of the buffer, as seen by every slice taken from it. See
+  /// `Iobuf::set_tag`.
+  #[inline(always)]
+  pub fn set_tag(&self, tag: u64) -> Result<(), IobufError> {
+    self.raw.set_tag(tag)
+  }
+
   /// Returns the capacity of the current writing window
   #[inline(always)]
   pub fn len(&self) -> u32 {
@@ -500,7 +647,7 @@ impl<'a> AppendBuf<'a> {
   /// are satisified. Returns `Err(msg)` if any invariant isn't satisfied.
   pub fn invariant(&self) -> Result<(), Box<String>> {
     // TODO: AppendBuf-specific invariants:
-    //   try!(self.raw.inv_check(x < y, "x must be less than y"));
+    //   self.raw.inv_check(x < y, "x must be less than y")?;
     self.raw.invariant()
   }
 
@@ -529,7 +676,7 @@ impl<'a> io::Write for AppendBuf<'a> {
   fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
     match self.fill(buf) {
       Ok(())  => Ok(buf.len()),
-      Err(()) => write_failed(),
+      Err(_)  => write_failed(),
     }
   }
 
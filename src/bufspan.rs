@@ -1,21 +1,41 @@
-use std::cmp::Ordering;
+use std::cmp::{self, Ordering};
 use std::fmt::{self, Formatter, Debug};
-use std::intrinsics::{assume, move_val_init};
+use std::io::{self, BufRead, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
 use std::iter::{self, IntoIterator, FromIterator};
 use std::mem;
+use std::ops::{Bound, RangeBounds};
 use std::option;
+use std::ptr;
 use std::slice;
 use std::vec;
 
+#[cfg(unix)]
+use std::os::unix::io::{AsFd, AsRawFd};
+
+#[cfg(unix)]
+use libc;
+
+use error::{ErrorKind, IobufError, iobuf_error};
+use intlike::{IntLike, from_be, from_le};
+use impls::{ROIobuf, RWIobuf};
 use iobuf::Iobuf;
 
 use BufSpan::{Empty, One, Many};
 use SpanIter::{Opt, Lot};
 use SpanMoveIter::{MoveOpt, MoveLot};
 
+/// The most `iovec`s a single `readv` or `writev` will take.
+#[cfg(unix)]
+fn max_iov() -> usize {
+  match unsafe { libc::sysconf(libc::_SC_IOV_MAX) } {
+    n if n > 0 => n as usize,
+    _          => 16,
+  }
+}
+
 #[cold]
 fn bytes_in_vbuf<Buf: Iobuf>(v: &[Buf]) -> usize {
-  v.into_iter().map(|b| b.len() as usize).sum()
+  v.iter().map(|b| b.len() as usize).sum()
 }
 
 #[test]
@@ -68,7 +88,7 @@ fn byte_equal_slice_vbuf<Buf: Iobuf>(v: &[Buf], mut other: &[u8]) -> bool {
   unsafe {
     for b in v {
       let b_as_slice = b.as_window_slice();
-      assume(other.len() >= b_as_slice.len());
+      debug_assert!(other.len() >= b_as_slice.len());
       let (start, new_other) = other.split_at(b_as_slice.len());
       if b_as_slice != start { return false; }
       other = new_other;
@@ -114,8 +134,8 @@ fn byte_equal_vbuf_vbuf<Buf1: Iobuf, Buf2: Iobuf>(x: &[Buf1], y: &[Buf2]) -> boo
   }
 
   unsafe {
-    x.into_iter().flat_map(|b| b.as_window_slice().into_iter())
-    .zip(y.iter().flat_map(|b| b.as_window_slice().into_iter()))
+    x.iter().flat_map(|b| b.as_window_slice().iter())
+    .zip(y.iter().flat_map(|b| b.as_window_slice().iter()))
     .all(|(x, y)| x == y)
   }
 }
@@ -213,7 +233,7 @@ fn test_starts_with_vbuf() {
 
 #[cold]
 fn ends_with_vbuf<Buf: Iobuf>(v: &[Buf], mut other: &[u8]) -> bool {
-  for b in v.into_iter().rev() {
+  for b in v.iter().rev() {
     let b = unsafe { b.as_window_slice() };
     match b.len().cmp(&other.len()) {
       Ordering::Greater => return b.ends_with(other),
@@ -257,11 +277,110 @@ fn test_ends_with_vbuf() {
   assert!(!ends_with_vbuf(&b0, b"987654321"));
 }
 
+/// Copies `dst.len()` bytes, starting `pos` bytes into the span, into `dst`,
+/// stitching them together across buffer boundaries. The range must already
+/// have been checked.
+fn peek_vbuf<Buf: Iobuf>(v: &[Buf], mut pos: usize, mut dst: &mut [u8]) {
+  for b in v {
+    if dst.is_empty() { return; }
+
+    let w = unsafe { b.as_window_slice() };
+    if pos >= w.len() {
+      pos -= w.len();
+      continue;
+    }
+
+    let n = cmp::min(w.len() - pos, dst.len());
+    let (head, tail) = mem::take(&mut dst).split_at_mut(n);
+    head.copy_from_slice(&w[pos..pos + n]);
+    dst = tail;
+    pos = 0;
+  }
+}
+
+#[test]
+fn test_peek_vbuf() {
+  use impls::ROIobuf;
+
+  let b0 = [
+    ROIobuf::from_str("1234"),
+    ROIobuf::from_str("5678"),
+    ROIobuf::from_str("9"),
+  ];
+
+  let mut dst = [0; 9];
+  peek_vbuf(&b0, 0, &mut dst);
+  assert_eq!(&dst, b"123456789");
+
+  let mut dst = [0; 4];
+  peek_vbuf(&b0, 3, &mut dst);
+  assert_eq!(&dst, b"4567");
+  peek_vbuf(&b0, 5, &mut dst);
+  assert_eq!(&dst, b"6789");
+  peek_vbuf(&b0, 4, &mut dst);
+  assert_eq!(&dst, b"5678");
+}
+
+/// Builds a span over `len` bytes, starting `pos` bytes into `v`, out of
+/// clones of the overlapping buffers. No bytes are copied. The range must
+/// already have been checked.
+fn slice_vbuf<Buf: Iobuf>(v: &[Buf], mut pos: usize, mut len: usize) -> BufSpan<Buf> {
+  let mut ret = BufSpan::new();
+
+  for b in v {
+    if len == 0 { break; }
+
+    let b_len = b.len() as usize;
+    if pos >= b_len {
+      pos -= b_len;
+      continue;
+    }
+
+    let n = cmp::min(b_len - pos, len);
+    let mut b = b.clone();
+    unsafe { b.unsafe_sub_window(pos as u32, n as u32) };
+    ret.push(b);
+
+    len -= n;
+    pos  = 0;
+  }
+
+  ret
+}
+
+#[test]
+fn test_slice_vbuf() {
+  use impls::ROIobuf;
+
+  let b0 = [
+    ROIobuf::from_str("1234"),
+    ROIobuf::from_str("5678"),
+    ROIobuf::from_str("9"),
+  ];
+
+  assert!(slice_vbuf(&b0, 0, 9).byte_equal_slice(b"123456789"));
+  assert!(slice_vbuf(&b0, 3, 2).byte_equal_slice(b"45"));
+  assert!(slice_vbuf(&b0, 4, 4).byte_equal_slice(b"5678"));
+  assert!(slice_vbuf(&b0, 5, 4).byte_equal_slice(b"6789"));
+  assert!(slice_vbuf(&b0, 9, 0).is_empty());
+  assert_eq!((&slice_vbuf(&b0, 2, 4)).into_iter().count(), 2);
+}
+
+/// Builds the error for a bad `(pos, len)` into a span of `total` bytes.
+#[cold]
+fn span_range_error(pos: usize, len: usize, total: usize) -> IobufError {
+  let kind =
+    if pos > total { ErrorKind::OutOfRange }
+    else           { ErrorKind::Incomplete };
+  let total = total as u64;
+  iobuf_error(kind, pos as u64, len as u64, (0, total), (0, total))
+}
+
 #[inline]
 fn cmp_buf_buf<Buf: Iobuf>(bx: &Buf, by: &Buf) -> Ordering {
   unsafe {
-    bx.as_window_slice().into_iter().cmp(
-    by.as_window_slice().into_iter())
+    bx.as_window_slice().iter().cmp(
+    by.as_window_slice().iter())
   }
 }
 
@@ -275,12 +394,12 @@ fn cmp_buf_vec<Buf: Iobuf>(b: &Buf, v: &[Buf]) -> Ordering {
     if b.len() >= x.len() {
       let (start, new_b) = b.split_at(x.len());
 
-      match start.into_iter().cmp(x.into_iter()) {
+      match start.iter().cmp(x.iter()) {
         Ordering::Equal => { b = new_b; }
         order => return order,
       }
     } else {
-      return (&b).into_iter().cmp(x.into_iter());
+      return b.iter().cmp(x.iter());
     }
   }
 
@@ -339,12 +458,12 @@ impl<Buf: Iobuf> Debug for BufSpan<Buf> {
 
     for b in self {
       if !first_time {
-        try!(write!(f, "\n"));
+        writeln!(f)?;
       } else {
         first_time = false;
       }
 
-      try!(b.fmt(f));
+      b.fmt(f)?;
     }
 
     Ok(())
@@ -393,7 +512,7 @@ impl<'a, Buf: Iobuf> IntoIterator for &'a BufSpan<Buf> {
     match *self {
       Empty       => Opt(None.into_iter()),
       One (ref b) => Opt(Some(b).into_iter()),
-      Many(ref v) => Lot(v.into_iter()),
+      Many(ref v) => Lot(v.iter()),
     }
   }
 }
@@ -444,10 +563,7 @@ impl<Buf: Iobuf> BufSpan<Buf> {
   /// ```
   #[inline]
   pub fn is_empty(&self) -> bool {
-    match *self {
-      Empty => true,
-      _     => false,
-    }
+    matches!(*self, Empty)
   }
 
   /// The fast path during pushing -- either fills in the first buffer, or
@@ -464,7 +580,7 @@ impl<Buf: Iobuf> BufSpan<Buf> {
       // stop all drop calls in this function, leaving any dropping that might
       // have to happen to `slow_push`.
       unsafe {
-        move_val_init(self, One(b));
+        ptr::write(self, One(b));
         return None;
       }
     }
@@ -473,11 +589,11 @@ impl<Buf: Iobuf> BufSpan<Buf> {
       Empty => unreachable!(),
       One(ref mut b0) => {
         match b0.extend_with(&b) {
-          Ok (()) => return None,
-          Err(()) => return Some(b),
+          Ok (()) => None,
+          Err(_)  => Some(b),
         }
       }
-      Many(_) => return Some(b),
+      Many(_) => Some(b),
     }
   }
 
@@ -524,7 +640,7 @@ impl<Buf: Iobuf> BufSpan<Buf> {
     if let Many(ref mut v) = *self {
       unsafe {
         let last_pos = v.len() - 1;
-        if let Err(()) = v.get_unchecked_mut(last_pos).extend_with(&b) {
+        if v.get_unchecked_mut(last_pos).extend_with(&b).is_err() {
           v.push(b);
         }
       }
@@ -533,12 +649,9 @@ impl<Buf: Iobuf> BufSpan<Buf> {
       let this = mem::replace(self, Empty);
       // We know that we're empty, therefore no drop glue needs to be run.
       unsafe {
-        move_val_init(self,
+        ptr::write(self,
           if let One(b0) = this {
-            let mut v = Vec::with_capacity(2);
-            v.push(b0);
-            v.push(b);
-            Many(v)
+            Many(vec![b0, b])
           } else {
             unreachable!()
           })
@@ -546,11 +659,401 @@ impl<Buf: Iobuf> BufSpan<Buf> {
     }
   }
 
+  /// The buffers in the span, as a slice.
+  #[inline]
+  fn bufs(&self) -> &[Buf] {
+    match *self {
+      Empty       => &[],
+      One (ref b) => slice::from_ref(b),
+      Many(ref v) => &v[..],
+    }
+  }
+
+  /// Returns `Ok` if `len` bytes starting `pos` bytes into the span are
+  /// readable.
+  #[inline]
+  fn check_range(&self, pos: usize, len: usize) -> Result<(), IobufError> {
+    match pos.checked_add(len) {
+      Some(end) if self.count_bytes_cmp(end) != Ordering::Less => Ok(()),
+      _ => Err(span_range_error(pos, len, self.count_bytes())),
+    }
+  }
+
+  /// Drops the first `len` bytes of the span, releasing any buffers that are
+  /// fully consumed. `len` must not be greater than `count_bytes()`.
+  fn advance_front(&mut self, mut len: usize) {
+    // Bytes left in a `One`, or buffers left in a `Many`. Either way, `0`
+    // means the span is now empty.
+    let left =
+      match *self {
+        Empty => return,
+        One(ref mut b) => {
+          unsafe { b.unsafe_advance(len as u32) };
+          b.len()
+        },
+        Many(ref mut v) => {
+          let mut consumed = 0;
+          while consumed < v.len() && len >= v[consumed].len() as usize {
+            len -= v[consumed].len() as usize;
+            consumed += 1;
+          }
+          if len > 0 {
+            unsafe { v[consumed].unsafe_advance(len as u32) };
+          }
+          v.drain(..consumed);
+          v.len() as u32
+        },
+      };
+
+    // Downgrade to the cheaper representations once buffers run out.
+    match (mem::replace(self, Empty), left) {
+      (_, 0) => {},
+      (Many(mut v), 1) => *self = One(v.pop().unwrap()),
+      (this, _) => *self = this,
+    }
+  }
+
+  /// Reads `dst.len()` bytes starting `pos` bytes into the span, even if they
+  /// straddle multiple buffers.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ErrorKind, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_str("hel"));
+  /// s.push(ROIobuf::from_str("lo"));
+  ///
+  /// let mut dst = [0; 3];
+  /// assert_eq!(s.peek(1, &mut dst), Ok(()));
+  /// assert_eq!(&dst, b"ell");
+  /// assert_eq!(s.peek(3, &mut dst).unwrap_err().kind(), ErrorKind::Incomplete);
+  /// ```
+  #[inline]
+  pub fn peek(&self, pos: usize, dst: &mut [u8]) -> Result<(), IobufError> {
+    self.check_range(pos, dst.len())?;
+    peek_vbuf(self.bufs(), pos, dst);
+    Ok(())
+  }
+
+  #[inline]
+  fn peek_raw<T: IntLike>(&self, pos: usize) -> Result<T, IobufError> {
+    unsafe {
+      let mut t: T = mem::zeroed();
+      self.peek(pos, slice::from_raw_parts_mut(&mut t as *mut T as *mut u8,
+                                               mem::size_of::<T>()))?;
+      Ok(t)
+    }
+  }
+
+  /// Reads a big-endian integer starting `pos` bytes into the span, even if
+  /// it straddles multiple buffers.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_slice(&[1, 2, 3]));
+  /// s.push(ROIobuf::from_slice(&[4, 5]));
+  ///
+  /// assert_eq!(s.peek_be(1), Ok(0x02030405u32));
+  /// assert!(s.peek_be::<u32>(2).is_err());
+  /// ```
+  #[inline]
+  pub fn peek_be<T: IntLike>(&self, pos: usize) -> Result<T, IobufError> {
+    self.peek_raw(pos).map(|t| unsafe { from_be(t) })
+  }
+
+  /// Reads a little-endian integer starting `pos` bytes into the span, even if
+  /// it straddles multiple buffers.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_slice(&[1, 2, 3]));
+  /// s.push(ROIobuf::from_slice(&[4, 5]));
+  ///
+  /// assert_eq!(s.peek_le(1), Ok(0x05040302u32));
+  /// ```
+  #[inline]
+  pub fn peek_le<T: IntLike>(&self, pos: usize) -> Result<T, IobufError> {
+    self.peek_raw(pos).map(|t| unsafe { from_le(t) })
+  }
+
+  /// Reads `dst.len()` bytes from the front of the span and advances past
+  /// them. Buffers that are fully consumed are dropped from the span. On
+  /// error, the span is left unchanged.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_str("hel"));
+  /// s.push(ROIobuf::from_str("lo"));
+  ///
+  /// let mut dst = [0; 4];
+  /// assert_eq!(s.consume(&mut dst), Ok(()));
+  /// assert_eq!(&dst, b"hell");
+  /// assert_eq!(s.count_bytes(), 1);
+  /// assert!(s.consume(&mut dst).is_err());
+  /// assert_eq!(s.count_bytes(), 1);
+  /// ```
+  #[inline]
+  pub fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError> {
+    self.peek(0, dst)?;
+    self.advance_front(dst.len());
+    Ok(())
+  }
+
+  /// Reads a big-endian integer from the front of the span and advances past
+  /// it. Buffers that are fully consumed are dropped from the span.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_slice(&[0, 0, 0]));
+  /// s.push(ROIobuf::from_slice(&[2, 0xAB, 0xCD]));
+  ///
+  /// let len: u32 = s.consume_be().unwrap();
+  /// assert_eq!(len, 2);
+  /// assert_eq!(s.consume_be::<u16>(), Ok(0xABCD));
+  /// assert!(s.is_empty());
+  /// ```
+  #[inline]
+  pub fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError> {
+    let t = self.peek_be(0)?;
+    self.advance_front(mem::size_of::<T>());
+    Ok(t)
+  }
+
+  /// Reads a little-endian integer from the front of the span and advances
+  /// past it. Buffers that are fully consumed are dropped from the span.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_slice(&[2, 0]));
+  /// s.push(ROIobuf::from_slice(&[0, 0]));
+  ///
+  /// assert_eq!(s.consume_le::<u32>(), Ok(2));
+  /// assert!(s.is_empty());
+  /// ```
+  #[inline]
+  pub fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError> {
+    let t = self.peek_le(0)?;
+    self.advance_front(mem::size_of::<T>());
+    Ok(t)
+  }
+
+  /// Returns a new span over the given byte range of this one. No bytes are
+  /// copied: the Iobufs overlapping the range are cloned, and the ones at the
+  /// edges are narrowed with `sub_window`.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ErrorKind, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_str("hello"));
+  /// s.push(ROIobuf::from_str(" world"));
+  ///
+  /// assert!(s.slice(3..8).unwrap().byte_equal_slice(b"lo wo"));
+  /// assert!(s.slice(6..).unwrap().byte_equal_slice(b"world"));
+  /// assert!(s.slice(..).unwrap().byte_equal_slice(b"hello world"));
+  /// assert_eq!(s.slice(8..12).unwrap_err().kind(), ErrorKind::Incomplete);
+  /// ```
+  pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Result<BufSpan<Buf>, IobufError> {
+    let start =
+      match range.start_bound() {
+        Bound::Included(&n) => n,
+        Bound::Excluded(&n) => n.saturating_add(1),
+        Bound::Unbounded    => 0,
+      };
+    let end =
+      match range.end_bound() {
+        Bound::Included(&n) => n.saturating_add(1),
+        Bound::Excluded(&n) => n,
+        Bound::Unbounded    => self.count_bytes(),
+      };
+
+    self.check_range(start, end.saturating_sub(start))?;
+
+    if end < start {
+      let total = self.count_bytes() as u64;
+      return Err(iobuf_error(ErrorKind::InvalidLimits, start as u64, 0,
+                             (0, total), (0, total)));
+    }
+
+    Ok(slice_vbuf(self.bufs(), start, end - start))
+  }
+
+  /// Splits the span in two at `pos`, returning spans over `[0, pos)` and
+  /// `[pos, count_bytes())`. Like `slice`, no bytes are copied.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_str("hello"));
+  /// s.push(ROIobuf::from_str(" world"));
+  ///
+  /// let (a, b) = s.split_at(7).unwrap();
+  /// assert!(a.byte_equal_slice(b"hello w"));
+  /// assert!(b.byte_equal_slice(b"orld"));
+  /// assert!(s.split_at(12).is_err());
+  /// ```
+  pub fn split_at(&self, pos: usize) -> Result<(BufSpan<Buf>, BufSpan<Buf>), IobufError> {
+    self.check_range(0, pos)?;
+    let total = self.count_bytes();
+    Ok((slice_vbuf(self.bufs(), 0, pos), slice_vbuf(self.bufs(), pos, total - pos)))
+  }
+
+  /// Removes the first `n` bytes from this span, returning them as a new span.
+  /// Buffers that are fully moved to the front half are dropped from this
+  /// span, and no bytes are copied. On error, the span is left unchanged.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_str("HDR:"));
+  /// s.push(ROIobuf::from_str("payload"));
+  ///
+  /// let hdr = s.split_off_front(4).unwrap();
+  /// assert!(hdr.byte_equal_slice(b"HDR:"));
+  /// assert!(s.byte_equal_slice(b"payload"));
+  /// assert!(s.split_off_front(8).is_err());
+  /// ```
+  pub fn split_off_front(&mut self, n: usize) -> Result<BufSpan<Buf>, IobufError> {
+    self.check_range(0, n)?;
+    let front = slice_vbuf(self.bufs(), 0, n);
+    self.advance_front(n);
+    Ok(front)
+  }
+
+  /// Writes as much of the span as `w` will take in a single `write_vectored`
+  /// call, with one `IoSlice` per buffer, then advances the span past the
+  /// bytes that were written. Returns the number of bytes written.
+  ///
+  /// Nothing is written if any window holds bytes of a `new_uninit` buffer
+  /// that were never written. That's an `InvalidInput` error instead.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_str("hello "));
+  /// s.push(ROIobuf::from_str("world"));
+  ///
+  /// let mut out = Vec::new();
+  /// assert_eq!(s.write_to(&mut out).unwrap(), 11);
+  /// assert_eq!(&out[..], b"hello world");
+  /// assert!(s.is_empty());
+  /// ```
+  pub fn write_to<W: Write>(&mut self, w: &mut W) -> io::Result<usize> {
+    let n = w.write_vectored(&self.window_slices()?)?;
+    self.advance_front(cmp::min(n, self.count_bytes()));
+    Ok(n)
+  }
+
+  /// One `IoSlice` per buffer, over its window. Fails if any window holds
+  /// never-written bytes of a `new_uninit` buffer, so they're never sent.
+  #[inline]
+  fn window_slices(&self) -> io::Result<Vec<IoSlice<'_>>> {
+    self.bufs().iter()
+      .map(|b| unsafe {
+        b.as_raw().check_window_initialized()?;
+        Ok(IoSlice::new(b.as_window_slice()))
+      })
+      .collect()
+  }
+
+  /// Writes the entire span to `w`, calling `write_to` until the span is
+  /// empty. Partial writes advance the span, so on error it holds exactly the
+  /// bytes that were not written.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_str("hello "));
+  /// s.push(ROIobuf::from_str("world"));
+  ///
+  /// let mut out = Vec::new();
+  /// s.write_all_to(&mut out).unwrap();
+  /// assert_eq!(&out[..], b"hello world");
+  /// ```
+  pub fn write_all_to<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+    while !self.is_empty() {
+      match self.write_to(w) {
+        Ok(0) =>
+          return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                    "failed to write the whole span")),
+        Ok(_) => {},
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+        Err(e) => return Err(e),
+      }
+    }
+    Ok(())
+  }
+
+  /// Writes as much of the span as possible to the file descriptor `fd` with
+  /// a single `writev`, advancing the span past the bytes that were written.
+  /// Returns the number of bytes written.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  /// use std::io::Read;
+  /// use std::os::unix::net::UnixStream;
+  ///
+  /// let (a, mut b) = UnixStream::pair().unwrap();
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_str("hello "));
+  /// s.push(ROIobuf::from_str("world"));
+  /// assert_eq!(s.write_to_fd(&a).unwrap(), 11);
+  /// drop(a);
+  ///
+  /// let mut out = String::new();
+  /// b.read_to_string(&mut out).unwrap();
+  /// assert_eq!(out, "hello world");
+  /// ```
+  #[cfg(unix)]
+  pub fn write_to_fd<F: AsFd>(&mut self, fd: F) -> io::Result<usize> {
+    let mut slices = self.window_slices()?;
+    slices.truncate(max_iov());
+
+    // `IoSlice` is guaranteed to be ABI compatible with `iovec` on Unix.
+    let n =
+      unsafe {
+        libc::writev(fd.as_fd().as_raw_fd(),
+                     slices.as_ptr() as *const libc::iovec,
+                     slices.len() as libc::c_int)
+      };
+    if n < 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    drop(slices);
+    self.advance_front(n as usize);
+    Ok(n as usize)
+  }
+
+  /// Returns a reader over the bytes in the `BufSpan`. The reader implements
+  /// `Read`, `BufRead` and `Seek`, and neither copies nor consumes the span.
+  /// Each call to `fill_buf` returns (the rest of) one underlying window.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, ROIobuf};
+  /// use std::io::Read;
+  ///
+  /// let mut s = BufSpan::from_buf(ROIobuf::from_str("hello "));
+  /// s.push(ROIobuf::from_str("world"));
+  ///
+  /// let mut out = String::new();
+  /// s.reader().read_to_string(&mut out).unwrap();
+  /// assert_eq!(out, "hello world");
+  /// assert_eq!(s.count_bytes(), 11);
+  /// ```
+  #[inline]
+  pub fn reader<'a>(&'a self) -> SpanReader<'a, Buf> {
+    SpanReader::new(self)
+  }
+
   /// Returns an iterator over the bytes in the `BufSpan`.
   #[inline]
   pub fn iter_bytes<'a>(&'a self) -> ByteIter<'a, Buf> {
     #[inline]
-    fn iter_buf_<B: Iobuf>(buf: &B) -> slice::Iter<u8> {
+    fn iter_buf_<B: Iobuf>(buf: &B) -> slice::Iter<'_, u8> {
       unsafe { buf.as_window_slice().iter() }
     }
 
@@ -681,9 +1184,9 @@ impl<Buf: Iobuf> BufSpan<Buf> {
   #[inline]
   pub fn append(&mut self, other: Self) {
     if self.is_empty() {
-      unsafe { move_val_init(self, other) }
+      unsafe { ptr::write(self, other) }
     } else {
-      self.extend(other.into_iter())
+      self.extend(other)
     }
   }
 
@@ -740,6 +1243,138 @@ impl<Buf: Iobuf> BufSpan<Buf> {
   }
 }
 
+/// One `IoSliceMut` per buffer, over its window.
+#[inline]
+fn window_slices_mut<'b>(bufs: &'b [RWIobuf]) -> Vec<IoSliceMut<'b>> {
+  bufs.iter()
+    .map(|b| IoSliceMut::new(unsafe { b.as_mut_window_slice() }))
+    .collect()
+}
+
+/// Reads into the windows of `bufs` with a single `read_vectored`, returning
+/// the number of bytes read. Nothing is advanced.
+fn read_vectored_vbuf<R: Read>(r: &mut R, bufs: &[RWIobuf]) -> io::Result<usize> {
+  r.read_vectored(&mut window_slices_mut(bufs))
+}
+
+/// Reads into the windows of `bufs` with a single `readv` on `fd`, returning
+/// the number of bytes read. Nothing is advanced.
+#[cfg(unix)]
+fn readv_vbuf<F: AsFd>(fd: F, bufs: &[RWIobuf]) -> io::Result<usize> {
+  let mut slices = window_slices_mut(bufs);
+  slices.truncate(max_iov());
+
+  // `IoSliceMut` is guaranteed to be ABI compatible with `iovec` on Unix.
+  let n =
+    unsafe {
+      libc::readv(fd.as_fd().as_raw_fd(),
+                  slices.as_mut_ptr() as *const libc::iovec,
+                  slices.len() as libc::c_int)
+    };
+  if n < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(n as usize)
+}
+
+/// Scatter-reads from `r` into the windows of `bufs` with a single
+/// `read_vectored` call. Each buffer is advanced past the bytes read into it,
+/// and the filled bytes are returned as a span. No bytes are copied.
+///
+/// ```rust
+/// use iobuf::{scatter_read, Iobuf, RWIobuf};
+///
+/// let mut bufs = vec![RWIobuf::new(4), RWIobuf::new(4)];
+/// let mut src: &[u8] = b"hello";
+///
+/// // `&[u8]` fills every slice it's given in one call.
+/// let got = scatter_read(&mut src, &mut bufs).unwrap();
+/// assert!(got.byte_equal_slice(b"hello"));
+/// assert_eq!(bufs[0].len(), 0);
+/// assert_eq!(bufs[1].len(), 3);
+/// ```
+pub fn scatter_read<'a, R: Read>(r: &mut R, bufs: &mut [RWIobuf<'a>])
+    -> io::Result<BufSpan<ROIobuf<'a>>> {
+  let mut n = read_vectored_vbuf(r, bufs)?;
+  let mut filled = BufSpan::new();
+
+  for b in bufs.iter_mut() {
+    if n == 0 { break; }
+    let k = cmp::min(n, b.len() as usize) as u32;
+    let mut rw = b.clone();
+    unsafe {
+      rw.unsafe_sub_window_to(k);
+      b.unsafe_advance(k);
+    }
+    rw.mark_written(k).unwrap();
+    filled.push(rw.read_only());
+    n -= k as usize;
+  }
+
+  Ok(filled)
+}
+
+impl<'a> BufSpan<RWIobuf<'a>> {
+  /// Scatter-reads from `r` into the windows of this span with a single
+  /// `read_vectored` call. The span is advanced past the filled bytes, which
+  /// are returned as a read-only span. No bytes are copied.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, RWIobuf};
+  ///
+  /// let mut free = BufSpan::from_buf(RWIobuf::new(4));
+  /// free.push(RWIobuf::new(4));
+  ///
+  /// let mut src: &[u8] = b"hello";
+  /// let got = free.read_from(&mut src).unwrap();
+  /// assert!(got.byte_equal_slice(b"hello"));
+  /// assert_eq!(free.count_bytes(), 3);
+  /// ```
+  pub fn read_from<R: Read>(&mut self, r: &mut R) -> io::Result<BufSpan<ROIobuf<'a>>> {
+    let n = read_vectored_vbuf(r, self.bufs())?;
+    Ok(self.take_filled(n))
+  }
+
+  /// Scatter-reads from the file descriptor `fd` into the windows of this
+  /// span with a single `readv`. Otherwise the same as `read_from`.
+  ///
+  /// ```rust
+  /// use iobuf::{BufSpan, RWIobuf};
+  /// use std::io::Write;
+  /// use std::os::unix::net::UnixStream;
+  ///
+  /// let (mut a, b) = UnixStream::pair().unwrap();
+  /// a.write_all(b"hello").unwrap();
+  ///
+  /// let mut free = BufSpan::from_buf(RWIobuf::new(4));
+  /// free.push(RWIobuf::new(4));
+  ///
+  /// let got = free.read_from_fd(&b).unwrap();
+  /// assert!(got.byte_equal_slice(b"hello"));
+  /// assert_eq!(free.count_bytes(), 3);
+  /// ```
+  #[cfg(unix)]
+  pub fn read_from_fd<F: AsFd>(&mut self, fd: F) -> io::Result<BufSpan<ROIobuf<'a>>> {
+    let n = readv_vbuf(fd, self.bufs())?;
+    Ok(self.take_filled(n))
+  }
+
+  /// Splits the first `n` bytes, which were just read into, off the front of
+  /// the span, as a read-only span.
+  fn take_filled(&mut self, n: usize) -> BufSpan<ROIobuf<'a>> {
+    let n = cmp::min(n, self.count_bytes());
+    let filled =
+      slice_vbuf(self.bufs(), 0, n).into_iter()
+        .map(|b| {
+          b.mark_written(b.len()).unwrap();
+          b.read_only()
+        })
+        .collect();
+    self.advance_front(n);
+    filled
+  }
+}
+
 impl<Buf: Iobuf> PartialEq for BufSpan<Buf> {
   #[inline]
   fn eq(&self, other: &Self) -> bool {
@@ -868,7 +1503,330 @@ impl<Buf: Iobuf> DoubleEndedIterator for SpanMoveIter<Buf> {
 
 impl<Buf: Iobuf> ExactSizeIterator for SpanMoveIter<Buf> {}
 
+/// A cursor over the bytes in a `BufSpan`, implementing `Read`, `BufRead` and
+/// `Seek`. The span is borrowed, not consumed, and no bytes are copied until
+/// they are read. Like an Iobuf's own `Read`, it stops at never-written bytes
+/// of a `new_uninit` buffer.
+pub struct SpanReader<'a, Buf: 'a> {
+  bufs: &'a [Buf],
+  // The buffer we're currently reading from. May be `bufs.len()` at the end.
+  idx:  usize,
+  // How far into `bufs[idx]`'s window we've read.
+  off:  usize,
+  // The absolute position, which may lie past the end after a `seek`.
+  pos:  u64,
+}
+
+impl<'a, Buf: Iobuf> SpanReader<'a, Buf> {
+  /// Creates a reader positioned at the start of `span`.
+  #[inline]
+  pub fn new(span: &'a BufSpan<Buf>) -> SpanReader<'a, Buf> {
+    SpanReader { bufs: span.bufs(), idx: 0, off: 0, pos: 0 }
+  }
+
+  /// The total number of bytes in the span.
+  #[inline]
+  fn total_len(&self) -> u64 {
+    self.bufs.iter().map(|b| b.len() as u64).sum()
+  }
+
+  /// Moves to the absolute position `pos`, which may be past the end.
+  fn seek_to(&mut self, pos: u64) {
+    let mut remaining = pos;
+    let mut idx = 0;
+
+    while idx < self.bufs.len() && remaining >= self.bufs[idx].len() as u64 {
+      remaining -= self.bufs[idx].len() as u64;
+      idx += 1;
+    }
+
+    self.idx = idx;
+    self.off = if idx < self.bufs.len() { remaining as usize } else { 0 };
+    self.pos = pos;
+  }
+}
+
+impl<'a, Buf: Iobuf> Read for SpanReader<'a, Buf> {
+  #[inline]
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = {
+      let src = self.fill_buf()?;
+      let n = cmp::min(src.len(), buf.len());
+      buf[..n].copy_from_slice(&src[..n]);
+      n
+    };
+    self.consume(n);
+    Ok(n)
+  }
+}
+
+impl<'a, Buf: Iobuf> BufRead for SpanReader<'a, Buf> {
+  #[inline]
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    while self.idx < self.bufs.len() {
+      let buf    = &self.bufs[self.idx];
+      let window = unsafe { buf.as_window_slice() };
+      if self.off < window.len() {
+        // Stops at never-written bytes, like an Iobuf's own `fill_buf`.
+        let written = unsafe { buf.as_raw().initialized_len() } as usize;
+        return Ok(&window[cmp::min(self.off, written)..written]);
+      }
+      self.idx += 1;
+      self.off  = 0;
+    }
+
+    Ok(&[])
+  }
+
+  #[inline]
+  fn consume(&mut self, amt: usize) {
+    if self.idx < self.bufs.len() {
+      let len = self.bufs[self.idx].len() as usize;
+      let amt = cmp::min(amt, len - self.off);
+      self.off += amt;
+      self.pos += amt as u64;
+    }
+  }
+}
+
+impl<'a, Buf: Iobuf> Seek for SpanReader<'a, Buf> {
+  fn seek(&mut self, style: SeekFrom) -> io::Result<u64> {
+    let (base, offset) =
+      match style {
+        SeekFrom::Start(n)   => { self.seek_to(n); return Ok(n); },
+        SeekFrom::End(n)     => (self.total_len(), n),
+        SeekFrom::Current(n) => (self.pos, n),
+      };
+
+    let new_pos =
+      if offset >= 0 { base.checked_add(offset as u64) }
+      else           { base.checked_sub(offset.unsigned_abs()) };
+
+    match new_pos {
+      Some(n) => { self.seek_to(n); Ok(n) },
+      None    => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                    "invalid seek to a negative or overflowing position")),
+    }
+  }
+}
+
+#[test]
+fn test_span_consume() {
+  use impls::ROIobuf;
+
+  let mut s = BufSpan::new();
+  s.push(ROIobuf::from_slice(&[0x00, 0x00]));
+  s.push(ROIobuf::from_slice(&[0x00, 0x05, 0xAA]));
+  s.push(ROIobuf::from_slice(&[0xBB, 0xCC, 0xDD]));
+
+  assert_eq!(s.peek_be::<u32>(0), Ok(5));
+  assert_eq!(s.peek_le::<u16>(3), Ok(0xAA05));
+  assert_eq!(s.peek_be::<u64>(1).unwrap_err().kind(), ErrorKind::Incomplete);
+  assert_eq!(s.peek_be::<u8>(9).unwrap_err().kind(), ErrorKind::OutOfRange);
+
+  assert_eq!(s.consume_be::<u32>(), Ok(5));
+  // The first buffer was fully consumed and dropped.
+  assert_eq!((&s).into_iter().count(), 2);
+  assert_eq!(s.consume_be::<u16>(), Ok(0xAABB));
+  assert!(matches!(s, One(_)));
+
+  let mut dst = [0; 3];
+  assert!(s.consume(&mut dst).is_err());
+  assert_eq!(s.count_bytes(), 2);
+  assert_eq!(s.consume_le::<u16>(), Ok(0xDDCC));
+  assert!(s.is_empty());
+  assert_eq!(s.consume(&mut []), Ok(()));
+}
+
+#[test]
+fn test_span_split() {
+  use impls::ROIobuf;
+
+  let mut s = BufSpan::new();
+  s.push(ROIobuf::from_str("1234"));
+  s.push(ROIobuf::from_str("5678"));
+  s.push(ROIobuf::from_str("9"));
+
+  assert!(s.slice(2..=5).unwrap().byte_equal_slice(b"3456"));
+  assert!(s.slice(9..).unwrap().is_empty());
+  let (lo, hi) = (5, 3);
+  assert_eq!(s.slice(lo..hi).unwrap_err().kind(), ErrorKind::InvalidLimits);
+  assert_eq!(s.slice(10..).unwrap_err().kind(), ErrorKind::OutOfRange);
+
+  let (a, b) = s.split_at(0).unwrap();
+  assert!(a.is_empty());
+  assert!(b == s);
+
+  let front = s.split_off_front(6).unwrap();
+  assert!(front.byte_equal_slice(b"123456"));
+  assert!(s.byte_equal_slice(b"789"));
+  assert_eq!((&s).into_iter().count(), 2);
+
+  let rest = s.split_off_front(3).unwrap();
+  assert!(rest.byte_equal_slice(b"789"));
+  assert!(s.is_empty());
+}
+
+#[cfg(test)]
+struct TrickleWriter(Vec<u8>);
+
 #[cfg(test)]
+impl Write for TrickleWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = cmp::min(buf.len(), 3);
+    self.0.extend_from_slice(&buf[..n]);
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+#[test]
+fn test_span_write_to() {
+  use impls::ROIobuf;
+
+  let mut s = BufSpan::new();
+  s.push(ROIobuf::from_str("1234"));
+  s.push(ROIobuf::from_str("5678"));
+  s.push(ROIobuf::from_str("9"));
+
+  // The default `write_vectored` only writes the first slice, and this writer
+  // only takes three bytes at a time.
+  let mut w = TrickleWriter(Vec::new());
+  assert_eq!(s.write_to(&mut w).unwrap(), 3);
+  assert!(s.byte_equal_slice(b"456789"));
+
+  s.write_all_to(&mut w).unwrap();
+  assert_eq!(&w.0[..], b"123456789");
+  assert!(s.is_empty());
+  assert_eq!(s.write_to(&mut w).unwrap(), 0);
+}
+
+#[test]
+fn test_span_never_sends_unwritten_bytes() {
+  let b = RWIobuf::new_uninit(8);
+  b.poke(0, b"abcd").unwrap();
+
+  let mut s = BufSpan::from_buf(b.clone());
+  let mut out = Vec::new();
+  assert_eq!(s.write_to(&mut out).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+  assert!(out.is_empty());
+  assert_eq!(s.count_bytes(), 8);
+
+  let mut r = s.reader();
+  assert_eq!(r.read(&mut [0; 8]).unwrap(), 4);
+  assert_eq!(r.read(&mut [0; 8]).unwrap(), 0);
+
+  b.poke(4, b"efgh").unwrap();
+  assert_eq!(s.write_to(&mut out).unwrap(), 8);
+  assert_eq!(&out[..], b"abcdefgh");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_span_write_to_fd() {
+  use impls::ROIobuf;
+  use std::fs::{self, OpenOptions};
+
+  let path = ::std::env::temp_dir().join(
+    format!("iobuf-write-to-fd-{}", ::std::process::id()));
+  let f = OpenOptions::new().read(true).write(true).create(true).truncate(true)
+            .open(&path).unwrap();
+
+  let mut s = BufSpan::new();
+  s.push(ROIobuf::from_str("hello "));
+  s.push(ROIobuf::from_str("world"));
+
+  assert_eq!(s.write_to_fd(&f).unwrap(), 11);
+  assert!(s.is_empty());
+  drop(f);
+
+  assert_eq!(&fs::read(&path).unwrap()[..], b"hello world");
+  fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_scatter_read() {
+  let mut bufs = vec![RWIobuf::new(3), RWIobuf::new(3), RWIobuf::new(3)];
+
+  let mut src: &[u8] = b"12345";
+  let got = scatter_read(&mut src, &mut bufs).unwrap();
+  assert!(got.byte_equal_slice(b"12345"));
+  assert_eq!((&got).into_iter().count(), 2);
+  assert_eq!(bufs.iter().map(|b| b.len()).collect::<Vec<_>>(), vec![0, 1, 3]);
+
+  let mut free: BufSpan<RWIobuf> = bufs.into_iter().collect();
+  let mut src: &[u8] = b"6789";
+  let got = free.read_from(&mut src).unwrap();
+  assert!(got.byte_equal_slice(b"6789"));
+  assert_eq!(free.count_bytes(), 0);
+  assert!(free.read_from(&mut src).unwrap().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_read_from_fd() {
+  use std::fs::{self, File};
+
+  let path = ::std::env::temp_dir().join(
+    format!("iobuf-read-from-fd-{}", ::std::process::id()));
+  fs::write(&path, b"hello world").unwrap();
+  let f = File::open(&path).unwrap();
+
+  let mut free = BufSpan::from_buf(RWIobuf::new(6));
+  free.push(RWIobuf::new(8));
+
+  let got = free.read_from_fd(&f).unwrap();
+  assert!(got.byte_equal_slice(b"hello world"));
+  assert_eq!(free.count_bytes(), 3);
+
+  drop(f);
+  fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_span_reader() {
+  use impls::ROIobuf;
+
+  let mut s = BufSpan::new();
+  s.push(ROIobuf::from_str("abc"));
+  s.push(ROIobuf::from_str(""));
+  s.push(ROIobuf::from_str("defg"));
+  s.push(ROIobuf::from_str("h"));
+
+  let mut r = s.reader();
+  assert_eq!(r.fill_buf().unwrap(), b"abc");
+  r.consume(2);
+  assert_eq!(r.fill_buf().unwrap(), b"c");
+
+  let mut dst = [0; 4];
+  assert_eq!(r.read(&mut dst).unwrap(), 1);
+  assert_eq!(r.read(&mut dst).unwrap(), 4);
+  assert_eq!(&dst, b"defg");
+  assert_eq!(r.stream_position().unwrap(), 7);
+
+  assert_eq!(r.seek(SeekFrom::End(-5)).unwrap(), 3);
+  assert_eq!(r.fill_buf().unwrap(), b"defg");
+  assert_eq!(r.seek(SeekFrom::Current(2)).unwrap(), 5);
+  assert_eq!(r.fill_buf().unwrap(), b"fg");
+  assert!(r.seek(SeekFrom::Current(-6)).is_err());
+
+  assert_eq!(r.seek(SeekFrom::Start(20)).unwrap(), 20);
+  assert_eq!(r.read(&mut dst).unwrap(), 0);
+
+  let mut all = Vec::new();
+  r.seek(SeekFrom::Start(0)).unwrap();
+  r.read_to_end(&mut all).unwrap();
+  assert_eq!(&all[..], b"abcdefgh");
+
+  // The span itself is untouched.
+  assert_eq!(s.count_bytes(), 8);
+
+  let e: BufSpan<ROIobuf> = BufSpan::new();
+  assert_eq!(e.reader().read(&mut dst).unwrap(), 0);
+}
+
+#[cfg(all(test, feature = "nightly"))]
 mod bench {
   use test::{black_box, Bencher};
   use super::super::iobuf::Iobuf;
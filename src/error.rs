@@ -0,0 +1,189 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+/// The reason an Iobuf operation failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+  /// The requested range starts inside the window, but runs past its end.
+  /// More data is needed before the operation can succeed. See
+  /// `IobufError::needed`.
+  Incomplete,
+  /// The requested position lies past the end of the window, or the operation
+  /// would move the window past the end of the limits.
+  OutOfRange,
+  /// The requested limits or window would widen the current ones, or are not
+  /// well-formed (the end comes before the start).
+  InvalidLimits,
+  /// The other Iobuf's window is not directly after this Iobuf's window.
+  NotAdjacent,
+  /// The range is too large to be represented by an ordinary Iobuf, which is
+  /// limited to approximately 2 GB.
+  TooLarge,
+  /// The operation requires the Iobuf to be the last reference to its buffer.
+  NotUnique,
+  /// The allocator could not provide memory for a new buffer.
+  AllocationFailed,
+  /// The requested range covers bytes of a `new_uninit` buffer that were
+  /// never written.
+  Uninitialized,
+  /// The operation needs the allocation header of an owned buffer, which
+  /// Iobufs over borrowed memory don't have.
+  Unowned,
+}
+
+/// An error from a bounds-checked Iobuf operation.
+///
+/// Besides the `kind` of failure, this records the range that was requested
+/// and a snapshot of the limits and window of the Iobuf at the time of the
+/// failure, so that callers can tell "need more bytes" apart from "asked for
+/// something nonsensical".
+///
+/// ```rust
+/// use iobuf::{ErrorKind, ROIobuf, Iobuf};
+///
+/// let mut b = ROIobuf::from_str("hello");
+/// assert_eq!(b.advance(2), Ok(()));
+///
+/// let err = b.peek_be::<u32>(1).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::Incomplete);
+/// assert_eq!(err.range(), (1, 4));
+/// assert_eq!(err.needed(), 2);
+/// assert_eq!(err.window(), (2, 5));
+/// assert_eq!(err.limits(), (0, 5));
+///
+/// let err = b.peek_be::<u8>(4).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::OutOfRange);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IobufError {
+  kind:   ErrorKind,
+  pos:    u64,
+  len:    u64,
+  lo_min: u64,
+  lo:     u64,
+  hi:     u64,
+  hi_max: u64,
+}
+
+/// Builds an `IobufError`. Both the limits and the window are [lo, hi).
+#[cold]
+pub fn iobuf_error(
+    kind:   ErrorKind,
+    pos:    u64,
+    len:    u64,
+    limits: (u64, u64),
+    window: (u64, u64)) -> IobufError {
+  IobufError {
+    kind:   kind,
+    pos:    pos,
+    len:    len,
+    lo_min: limits.0,
+    lo:     window.0,
+    hi:     window.1,
+    hi_max: limits.1,
+  }
+}
+
+impl IobufError {
+  /// Why the operation failed.
+  #[inline]
+  pub fn kind(&self) -> ErrorKind {
+    self.kind
+  }
+
+  /// The `(pos, len)` of the range the failed operation asked for. Positions
+  /// are relative to the start of the window, except for `InvalidLimits`, where
+  /// they are the requested limits as indexes into the buffer.
+  #[inline]
+  pub fn range(&self) -> (u64, u64) {
+    (self.pos, self.len)
+  }
+
+  /// The window, `[lo, hi)`, of the Iobuf when the operation failed.
+  #[inline]
+  pub fn window(&self) -> (u64, u64) {
+    (self.lo, self.hi)
+  }
+
+  /// The limits, `[lo_min, hi_max)`, of the Iobuf when the operation failed.
+  #[inline]
+  pub fn limits(&self) -> (u64, u64) {
+    (self.lo_min, self.hi_max)
+  }
+
+  /// The number of bytes missing from the end of the window for the requested
+  /// range to have fit. This is `0` unless the kind is `Incomplete`.
+  #[inline]
+  pub fn needed(&self) -> u64 {
+    match self.kind {
+      ErrorKind::Incomplete       =>
+        (self.pos + self.len).saturating_sub(self.hi - self.lo),
+      _ => 0,
+    }
+  }
+}
+
+impl Display for IobufError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self.kind {
+      ErrorKind::Incomplete       => write!(f, "need {} more bytes", self.needed())?,
+      ErrorKind::OutOfRange       => write!(f, "position out of range")?,
+      ErrorKind::InvalidLimits    => write!(f, "invalid limits or window")?,
+      ErrorKind::NotAdjacent      => write!(f, "buffers are not adjacent")?,
+      ErrorKind::TooLarge         => write!(f, "range too large for an Iobuf")?,
+      ErrorKind::NotUnique        => write!(f, "buffer is not unique")?,
+      ErrorKind::AllocationFailed => write!(f, "allocation failed")?,
+      ErrorKind::Uninitialized    => write!(f, "bytes were never written")?,
+      ErrorKind::Unowned          => write!(f, "buffer is borrowed")?,
+    }
+
+    write!(f, ": pos={}, len={}, limits=[{},{}), bounds=[{},{})",
+           self.pos, self.len, self.lo_min, self.hi_max, self.lo, self.hi)
+  }
+}
+
+impl Error for IobufError {
+  fn description(&self) -> &str {
+    match self.kind {
+      ErrorKind::Incomplete       => "Iobuf needs more bytes",
+      ErrorKind::OutOfRange       => "Iobuf position out of range",
+      ErrorKind::InvalidLimits    => "invalid Iobuf limits or window",
+      ErrorKind::NotAdjacent      => "Iobufs are not adjacent",
+      ErrorKind::TooLarge         => "range too large for an Iobuf",
+      ErrorKind::NotUnique        => "Iobuf is not unique",
+      ErrorKind::AllocationFailed => "Iobuf allocation failed",
+      ErrorKind::Uninitialized    => "Iobuf bytes were never written",
+      ErrorKind::Unowned          => "Iobuf buffer is borrowed",
+    }
+  }
+}
+
+impl From<IobufError> for io::Error {
+  fn from(e: IobufError) -> io::Error {
+    let kind =
+      match e.kind {
+        ErrorKind::Incomplete       => io::ErrorKind::UnexpectedEof,
+        ErrorKind::NotUnique        => io::ErrorKind::Other,
+        ErrorKind::AllocationFailed => io::ErrorKind::OutOfMemory,
+        _                           => io::ErrorKind::InvalidInput,
+      };
+    io::Error::new(kind, e)
+  }
+}
+
+#[test]
+fn incomplete_needed() {
+  let e = iobuf_error(ErrorKind::Incomplete, 2, 4, (0, 10), (3, 7));
+  assert_eq!(e.needed(), 2);
+
+  let e = iobuf_error(ErrorKind::OutOfRange, 5, 4, (0, 10), (3, 7));
+  assert_eq!(e.needed(), 0);
+}
+
+#[test]
+fn into_io_error() {
+  let e = iobuf_error(ErrorKind::Incomplete, 2, 4, (0, 10), (3, 7));
+  let e: io::Error = e.into();
+  assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,180 @@
+use std::mem::ManuallyDrop;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use raw::{Allocator, RawIobuf, ALLOCATION_HEADER_SIZE, DATA_ALIGNMENT, MAX_BUFFER_LEN};
+use raw::{buffer_too_big, memcpy, memmove};
+
+/// Calls a release hook instead of freeing memory. Never allocates.
+struct ReleaseAllocator {
+  release: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+}
+
+impl Allocator for ReleaseAllocator {
+  fn allocate(&self, _len: usize, _align: usize) -> Option<NonNull<u8>> {
+    None
+  }
+
+  fn deallocate(&self, _ptr: NonNull<u8>, _len: usize, _align: usize) {
+    let release = self.release.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(release) = release {
+      release()
+    }
+  }
+}
+
+/// Owns the allocation of an adopted `Vec<u8>`, until it's freed or handed
+/// back by `reclaim_vec`. Never allocates.
+struct VecAllocator {
+  /// Stored as an address to keep us `Send`.
+  base:      usize,
+  cap:       usize,
+  reclaimed: AtomicBool,
+}
+
+impl Allocator for VecAllocator {
+  fn allocate(&self, _len: usize, _align: usize) -> Option<NonNull<u8>> {
+    None
+  }
+
+  fn deallocate(&self, _ptr: NonNull<u8>, _len: usize, _align: usize) {
+    if !self.reclaimed.load(Ordering::Acquire) {
+      unsafe { drop(Vec::from_raw_parts(self.base as *mut u8, 0, self.cap)) }
+    }
+  }
+
+  fn reclaim_vec(&self) -> Option<(NonNull<u8>, usize)> {
+    self.reclaimed.store(true, Ordering::Release);
+    NonNull::new(self.base as *mut u8).map(|base| (base, self.cap))
+  }
+}
+
+/// Builds an Iobuf over `len` bytes of foreign memory at `ptr`, which calls
+/// `release` once the last reference is dropped. The caller must uphold the
+/// requirements of `RawIobuf::adopt`.
+pub unsafe fn from_raw_parts_with_release<F>(
+    ptr:     NonNull<u8>,
+    len:     usize,
+    release: F) -> RawIobuf<'static>
+    where F: FnOnce() + Send + 'static {
+  let allocator = ReleaseAllocator { release: Mutex::new(Some(Box::new(release))) };
+  RawIobuf::adopt(ptr, len, Arc::new(Box::new(allocator)))
+}
+
+/// Builds an Iobuf out of a `Vec`'s allocation. The bytes are shifted up to
+/// make room for the allocation header, which only reallocates if the `Vec`
+/// doesn't have enough spare capacity.
+pub fn from_vec(v: Vec<u8>) -> RawIobuf<'static> {
+  // Before anything's moved, so the `Vec` is freed as we unwind.
+  if v.len() > MAX_BUFFER_LEN {
+    buffer_too_big(v.len());
+  }
+
+  let mut v = ManuallyDrop::new(v);
+  v.reserve(ALLOCATION_HEADER_SIZE + DATA_ALIGNMENT - 1);
+
+  let len  = v.len();
+  let base = v.as_mut_ptr();
+  let data = (base as usize + ALLOCATION_HEADER_SIZE + DATA_ALIGNMENT - 1) & !(DATA_ALIGNMENT - 1);
+
+  unsafe {
+    memmove(data as *mut u8, base, len);
+
+    let allocator =
+      VecAllocator {
+        base:      base as usize,
+        cap:       v.capacity(),
+        reclaimed: AtomicBool::new(false),
+      };
+
+    RawIobuf::adopt(NonNull::new_unchecked(data as *mut u8), len, Arc::new(Box::new(allocator)))
+  }
+}
+
+/// Turns a unique Iobuf into a `Vec` of the bytes in its window. Buffers made
+/// by `from_vec` give their allocation back, everything else is copied.
+pub unsafe fn into_vec(mut raw: RawIobuf<'static>) -> Vec<u8> {
+  let (src, len) = {
+    let window = raw.as_window_slice();
+    (window.as_ptr(), window.len())
+  };
+
+  match raw.header().and_then(|h| h.allocator()).and_then(|a| a.reclaim_vec()) {
+    Some((base, cap)) => {
+      // The memory is ours now, so this only releases the allocator. It has to
+      // happen first, since moving the window may overwrite the header.
+      raw.drop_nonatomic();
+      memmove(base.as_ptr(), src, len);
+      Vec::from_raw_parts(base.as_ptr(), len, cap)
+    },
+    None => {
+      let mut v = Vec::with_capacity(len);
+      memcpy(v.as_mut_ptr(), src, len);
+      v.set_len(len);
+      raw.drop_nonatomic();
+      v
+    },
+  }
+}
+
+#[test]
+fn vec_round_trip() {
+  use impls::RWIobuf;
+  use iobuf::Iobuf;
+
+  let mut v = Vec::with_capacity(100);
+  v.extend_from_slice(b"hello, world");
+
+  let mut b = RWIobuf::from_vec(v);
+  assert_eq!(b.len(), 12);
+  assert_eq!(b.advance(7), Ok(()));
+
+  let c = b.clone();
+  let b = b.into_vec().unwrap_err();
+  drop(c);
+
+  let v = b.into_vec().unwrap();
+  assert_eq!(&v[..], b"world");
+  assert!(v.capacity() >= 100);
+}
+
+#[test]
+fn release_hook() {
+  use impls::RWIobuf;
+  use std::sync::atomic::AtomicUsize;
+
+  static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+  // Headroom for the header, then 16 bytes of data.
+  let mut storage = vec![0u128; 1 + ALLOCATION_HEADER_SIZE / 16];
+  let ptr = unsafe {
+    NonNull::new_unchecked((storage.as_mut_ptr() as *mut u8).add(ALLOCATION_HEADER_SIZE))
+  };
+
+  let b = unsafe {
+    RWIobuf::from_raw_parts_with_release(ptr, 16, || { RELEASED.fetch_add(1, Ordering::SeqCst); })
+  };
+  b.poke_be(0, 0xABu8).unwrap();
+
+  let c = b.clone();
+  drop(c);
+  assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+
+  // Copied, since it didn't come from a `Vec`.
+  let v = b.into_vec().unwrap();
+  assert_eq!(v.len(), 16);
+  assert_eq!(v[0], 0xAB);
+  assert_eq!(RELEASED.load(Ordering::SeqCst), 1);
+
+  drop(storage);
+}
+
+#[test]
+#[should_panic]
+#[cfg(target_pointer_width = "64")]
+fn oversized_vecs_are_refused() {
+  // Zeroed lazily, so this is cheap unless something touches it.
+  let v = vec![0u8; MAX_BUFFER_LEN + 1];
+  drop(::impls::RWIobuf::from_vec(v));
+}
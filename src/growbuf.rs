@@ -0,0 +1,187 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+use std::mem;
+use std::sync::Arc;
+
+use error::IobufError;
+use impls::RWIobuf;
+use intlike::IntLike;
+use iobuf::Iobuf;
+use raw::{Allocator, write_failed};
+
+/// A write-only buffer that grows as it's filled, instead of failing once it's
+/// full. It's meant for serializers that can't tell up front how much they'll
+/// write.
+///
+/// Bytes are appended with `fill`, `fill_be`, `fill_le` or `io::Write`. When
+/// the buffer runs out of room, it's reallocated out of the same allocator, at
+/// least doubling in size, up to the 2 GB limit of an Iobuf. `into_iobuf` then
+/// hands back everything written.
+///
+/// ```rust
+/// use std::io::Write;
+/// use iobuf::{GrowBuf, Iobuf};
+///
+/// let mut w = GrowBuf::with_capacity(2);
+/// w.fill_be(0xABCDu16).unwrap();
+/// w.fill_le(0x01020304u32).unwrap();
+/// write!(w, "{}", 42).unwrap();
+/// assert_eq!(w.as_slice(), [0xAB, 0xCD, 4, 3, 2, 1, b'4', b'2']);
+///
+/// let b = w.into_iobuf();
+/// assert_eq!(b.len(), 8);
+/// assert_eq!(b.peek_be::<u16>(0), Ok(0xABCD));
+/// ```
+pub struct GrowBuf {
+  /// The bytes written so far are `[lo_min, lo)`. The window is free space.
+  buf: RWIobuf<'static>,
+}
+
+impl GrowBuf {
+  /// Constructs an empty `GrowBuf`. This will not allocate until the first
+  /// write.
+  #[inline]
+  pub fn new() -> GrowBuf {
+    GrowBuf { buf: RWIobuf::empty() }
+  }
+
+  /// Constructs an empty `GrowBuf`, with room for `cap` bytes before it has to
+  /// grow.
+  #[inline]
+  pub fn with_capacity(cap: usize) -> GrowBuf {
+    GrowBuf { buf: RWIobuf::new(cap) }
+  }
+
+  /// Constructs an empty `GrowBuf`, with room for `cap` bytes. It, and every
+  /// buffer it grows into, is allocated out of `allocator`.
+  #[inline]
+  pub fn with_allocator(cap: usize, allocator: Arc<Box<dyn Allocator>>) -> GrowBuf {
+    GrowBuf { buf: RWIobuf::new_with_allocator(cap, allocator) }
+  }
+
+  /// Makes room for at least `additional` more bytes. Fails if that would be
+  /// more than an Iobuf can hold (`TooLarge`), or the allocator fails
+  /// (`AllocationFailed`).
+  #[inline]
+  pub fn reserve(&mut self, additional: usize) -> Result<(), IobufError> {
+    self.buf.reserve(additional)
+  }
+
+  /// Appends the bytes of `src`, growing if needed.
+  #[inline]
+  pub fn fill(&mut self, src: &[u8]) -> Result<(), IobufError> {
+    self.buf.reserve(src.len())?;
+    self.buf.fill(src)
+  }
+
+  /// Appends a big-endian primitive, growing if needed.
+  #[inline]
+  pub fn fill_be<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> {
+    self.buf.reserve(mem::size_of::<T>())?;
+    self.buf.fill_be(t)
+  }
+
+  /// Appends a little-endian primitive, growing if needed.
+  #[inline]
+  pub fn fill_le<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> {
+    self.buf.reserve(mem::size_of::<T>())?;
+    self.buf.fill_le(t)
+  }
+
+  /// The number of bytes written so far.
+  #[inline]
+  pub fn len(&self) -> u32 {
+    self.buf.lo() - self.buf.lo_min()
+  }
+
+  /// Returns whether or not `len() == 0`.
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// The number of bytes that fit before the buffer has to grow.
+  #[inline]
+  pub fn cap(&self) -> u32 {
+    self.buf.cap()
+  }
+
+  /// The bytes written so far.
+  #[inline]
+  pub fn as_slice(&self) -> &[u8] {
+    unsafe { &self.buf.as_limit_slice()[..self.len() as usize] }
+  }
+
+  /// Turns the `GrowBuf` into an Iobuf whose window is the bytes written.
+  #[inline]
+  pub fn into_iobuf(self) -> RWIobuf<'static> {
+    let mut buf = self.buf;
+    buf.flip_lo();
+    buf
+  }
+}
+
+impl Debug for GrowBuf {
+  #[inline]
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    Debug::fmt(&self.buf, f)
+  }
+}
+
+impl io::Write for GrowBuf {
+  #[inline]
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self.fill(buf) {
+      Ok(())  => Ok(buf.len()),
+      Err(_)  => write_failed(),
+    }
+  }
+
+  #[inline(always)]
+  fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+#[test]
+fn grows_through_allocator() {
+  use std::alloc::{self, Layout};
+  use std::ptr::NonNull;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+  struct Counting;
+
+  impl Allocator for Counting {
+    fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+      ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+      unsafe { NonNull::new(alloc::alloc(Layout::from_size_align(len, align).unwrap())) }
+    }
+
+    fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+      unsafe { alloc::dealloc(ptr.as_ptr(), Layout::from_size_align(len, align).unwrap()) }
+    }
+  }
+
+  let mut w = GrowBuf::with_allocator(1, Arc::new(Box::new(Counting)));
+  for i in 0..1000u32 {
+    w.fill_be(i).unwrap();
+  }
+  assert_eq!(w.len(), 4000);
+
+  // 1 byte, then 4, 8, ... 4096: doubling keeps the reallocations logarithmic.
+  assert_eq!(ALLOCATIONS.load(Ordering::SeqCst), 12);
+
+  let b = w.into_iobuf();
+  for i in 0..1000u32 {
+    assert_eq!(b.peek_be::<u32>(4 * i), Ok(i));
+  }
+}
+
+#[test]
+fn grow_from_empty() {
+  let mut w = GrowBuf::new();
+  assert_eq!(w.cap(), 0);
+  w.fill(b"hello").unwrap();
+  w.fill(b", world").unwrap();
+  assert_eq!(w.as_slice(), b"hello, world");
+}
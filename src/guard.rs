@@ -0,0 +1,151 @@
+use std::ptr::{self, NonNull};
+use std::sync::Arc;
+
+use libc;
+
+use mmap::page_size;
+use raw::Allocator;
+
+/// The byte `FreedMemory::Poison` fills freed buffers with.
+pub const POISON: u8 = 0xDE;
+
+/// What a `GuardPageAllocator` does with the memory of a freed buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FreedMemory {
+  /// Unmaps it, so its addresses may be handed out again.
+  Unmap,
+  /// Keeps it mapped, but inaccessible, so its addresses are never reused and
+  /// any access faults. The physical memory is still given back.
+  Protect,
+  /// Fills it with `POISON` and keeps it mapped read-only, so its addresses
+  /// are never reused, reads see an obvious pattern, and writes fault.
+  Poison,
+}
+
+/// A debugging `Allocator` that catches buffer overruns as they happen.
+///
+/// Every allocation gets its own mapping, placed so that the buffer ends right
+/// before a `PROT_NONE` guard page. Reading or writing past the end of the
+/// buffer, as the `unsafe_` methods and the mutable slices are free to do,
+/// then faults on the spot instead of silently touching other memory. What
+/// happens to freed buffers is up to `FreedMemory`, which can catch uses after
+/// free, too.
+///
+/// Buffers stay aligned to 16 bytes, so overruns of less than that past a
+/// buffer whose length isn't a multiple of 16 go unnoticed. Allocations
+/// aligned to more than a page always fail.
+///
+/// This takes at least two pages per buffer, and a system call for every
+/// allocation and deallocation, so it's only meant for test runs.
+///
+/// ```rust
+/// use iobuf::{FreedMemory, GuardPageAllocator, RWIobuf, Iobuf};
+///
+/// let guarded = GuardPageAllocator::new(FreedMemory::Protect);
+///
+/// let b = RWIobuf::new_with_allocator(32, guarded.allocator());
+/// assert_eq!(b.poke(28, b"abcd"), Ok(()));
+///
+/// // Don't do this. The next byte is on the guard page, so it would fault.
+/// // unsafe { b.unsafe_poke(32, b"!") }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct GuardPageAllocator {
+  freed:     FreedMemory,
+  page_size: usize,
+}
+
+/// Where an allocation's mapping starts, and how long it is, guard included.
+struct Mapping {
+  base: *mut u8,
+  len:  usize,
+}
+
+impl GuardPageAllocator {
+  /// Creates an allocator that treats freed buffers according to `freed`.
+  pub fn new(freed: FreedMemory) -> GuardPageAllocator {
+    GuardPageAllocator {
+      freed:     freed,
+      page_size: page_size(),
+    }
+  }
+
+  /// Returns this allocator in the form `new_with_allocator` and friends
+  /// expect.
+  #[inline]
+  pub fn allocator(&self) -> Arc<Box<dyn Allocator>> {
+    Arc::new(Box::new(*self))
+  }
+
+  /// The usable part of the mapping is `len`, rounded up to a whole number of
+  /// `align` and then of pages. The allocation sits at the end of it.
+  #[inline]
+  fn layout(&self, len: usize, align: usize) -> Option<(usize, usize)> {
+    let padded = len.checked_add(align - 1)? & !(align - 1);
+    let pages  = padded.checked_add(self.page_size - 1)? & !(self.page_size - 1);
+    Some((padded, pages))
+  }
+
+  /// The mapping of an allocation made by `allocate`.
+  #[inline]
+  unsafe fn mapping(&self, ptr: NonNull<u8>, len: usize, align: usize) -> Mapping {
+    let (padded, pages) = self.layout(len, align).unwrap();
+    Mapping {
+      base: ptr.as_ptr().add(padded).sub(pages),
+      len:  pages + self.page_size,
+    }
+  }
+}
+
+impl Allocator for GuardPageAllocator {
+  fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+    if align > self.page_size {
+      return None;
+    }
+    let (padded, pages) = self.layout(len, align)?;
+    let map_len = pages.checked_add(self.page_size)?;
+
+    unsafe {
+      let base =
+        libc::mmap(
+          ptr::null_mut(), map_len,
+          libc::PROT_READ | libc::PROT_WRITE,
+          libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+          -1, 0);
+      if base == libc::MAP_FAILED {
+        return None;
+      }
+
+      let base  = base as *mut u8;
+      let guard = base.add(pages);
+      if libc::mprotect(guard as *mut libc::c_void, self.page_size, libc::PROT_NONE) != 0 {
+        libc::munmap(base as *mut libc::c_void, map_len);
+        return None;
+      }
+
+      NonNull::new(guard.sub(padded))
+    }
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+    unsafe {
+      let m = self.mapping(ptr, len, align);
+      let data_len = m.len - self.page_size;
+      let base = m.base as *mut libc::c_void;
+
+      match self.freed {
+        FreedMemory::Unmap => {
+          libc::munmap(base, m.len);
+        },
+        FreedMemory::Protect => {
+          libc::madvise(base, data_len, libc::MADV_DONTNEED);
+          libc::mprotect(base, data_len, libc::PROT_NONE);
+        },
+        FreedMemory::Poison => {
+          ptr::write_bytes(m.base, POISON, data_len);
+          libc::mprotect(base, data_len, libc::PROT_READ);
+        },
+      }
+    }
+  }
+}
@@ -1,12 +1,19 @@
-use core::nonzero::NonZero;
+use std::ptr::NonNull;
 
 use std::fmt::{self, Debug, Formatter};
 use std::{io, mem};
+#[cfg(unix)]
+use std::path::Path;
 use std::sync::Arc;
 
-use raw::{Allocator, RawIobuf, write_failed};
+use error::IobufError;
+use foreign;
+use raw::{Allocator, RawIobuf, ALLOCATION_HEADER_SIZE, write_failed};
 use intlike::IntLike;
 use iobuf::Iobuf;
+#[cfg(unix)]
+use mmap;
+use weak::{AWeakIobuf, WeakIobuf};
 
 /// Read-Only Iobuf
 ///
@@ -55,7 +62,7 @@ impl<'a> Drop for ROIobuf<'a> {
 ///
 /// `poke` and `fill` write a value at a position relative to the start of
 /// the window. Only `fill` advances the window by the amount written.
-/// They are meant to be used with `try!`.
+/// They are meant to be used with `?`, and fail with an `IobufError`.
 ///
 /// A suffix `_be` means the data will be read big-endian. A suffix `_le` means
 /// the data will be read little-endian.
@@ -95,7 +102,7 @@ impl<'a> io::Write for RWIobuf<'a> {
   fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
     match self.fill(buf) {
       Ok(())  => Ok(buf.len()),
-      Err(()) => write_failed(),
+      Err(_)  => write_failed(),
     }
   }
 
@@ -103,6 +110,187 @@ impl<'a> io::Write for RWIobuf<'a> {
   fn flush(&mut self) -> io::Result<()> { Ok(()) }
 }
 
+// Reading consumes from the front of the window. `BufRead` hands out the
+// whole window at once, without copying. Both stop at never-written bytes.
+
+impl<'a> io::Read for ROIobuf<'a> {
+  #[inline]
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { Ok(self.raw.read_some(buf)) }
+}
+
+impl<'a> io::BufRead for ROIobuf<'a> {
+  #[inline]
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    let n = self.raw.initialized_len() as usize;
+    Ok(unsafe { &self.raw.as_window_slice()[..n] })
+  }
+
+  #[inline]
+  fn consume(&mut self, amt: usize) { self.raw.advance_saturating(amt) }
+}
+
+impl<'a> io::Read for RWIobuf<'a> {
+  #[inline]
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { Ok(self.raw.read_some(buf)) }
+}
+
+impl<'a> io::BufRead for RWIobuf<'a> {
+  #[inline]
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    let n = self.raw.initialized_len() as usize;
+    Ok(unsafe { &self.raw.as_window_slice()[..n] })
+  }
+
+  #[inline]
+  fn consume(&mut self, amt: usize) { self.raw.advance_saturating(amt) }
+}
+
+impl io::Read for AROIobuf {
+  #[inline]
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { Ok(self.raw.read_some(buf)) }
+}
+
+impl io::BufRead for AROIobuf {
+  #[inline]
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    let n = self.raw.initialized_len() as usize;
+    Ok(unsafe { &self.raw.as_window_slice()[..n] })
+  }
+
+  #[inline]
+  fn consume(&mut self, amt: usize) { self.raw.advance_saturating(amt) }
+}
+
+#[test]
+fn read_and_buf_read() {
+  use std::io::{BufRead, Read};
+
+  let mut b = ROIobuf::from_str("hello, world");
+  let mut dst = [0; 5];
+  assert_eq!(b.read(&mut dst).unwrap(), 5);
+  assert_eq!(&dst, b"hello");
+  assert_eq!(b.fill_buf().unwrap(), b", world");
+  BufRead::consume(&mut b, 2);
+  assert_eq!(b.len(), 5);
+
+  let mut rest = Vec::new();
+  assert_eq!(b.read_to_end(&mut rest).unwrap(), 5);
+  assert_eq!(&rest[..], b"world");
+  assert!(b.is_empty());
+  assert_eq!(b.read(&mut dst).unwrap(), 0);
+
+  let mut b = RWIobuf::from_str_copy("line one\nline two").atomic_read_only().unwrap();
+  let lines: Vec<String> = (&mut b).lines().map(|l| l.unwrap()).collect();
+  assert_eq!(lines, ["line one", "line two"]);
+  assert!(b.is_empty());
+}
+
+#[test]
+fn ref_counts_match_unique() {
+  let a = RWIobuf::from_str_copy("hello").atomic_read_only().unwrap();
+  assert_eq!((a.ref_count(), a.is_unique()), (1, true));
+
+  let b = a.clone();
+  assert_eq!((a.ref_count(), a.is_unique()), (2, false));
+  assert!(a.shares_buffer_with(&b));
+  let a = a.unique().unwrap_err();
+  drop(b);
+  assert!(a.unique().is_ok());
+
+  let s = [1, 2, 3];
+  let c = ROIobuf::from_slice(&s);
+  assert_eq!((c.ref_count(), c.is_unique()), (0, false));
+  assert!(!c.shares_buffer_with(&c.clone()));
+}
+
+#[test]
+fn make_mut_keeps_window() {
+  let mut a = RWIobuf::from_str_copy("hello, world").atomic_read_only().unwrap();
+  a.sub(2, 8).unwrap();
+  a.advance(3).unwrap();
+  let b = a.clone();
+
+  let c = a.make_mut();
+  assert!(!c.shares_buffer_with(&b));
+  assert_eq!((c.lo_min(), c.lo(), c.hi(), c.hi_max()), (0, 3, 8, 8));
+  unsafe {
+    assert_eq!(c.as_limit_slice(), b"llo, wor");
+    assert_eq!(c.as_window_slice(), b", wor");
+  }
+
+  let p = b.ptr();
+  assert_eq!(b.make_mut().ptr(), p);
+}
+
+#[test]
+fn unwritten_bytes_stay_writable_only() {
+  let b = RWIobuf::new_uninit(8);
+  let b = b.atomic_read_only().unwrap_err();
+  let b = b.unique().unwrap_err();
+
+  // Still writable, so it's the same buffer, still tracking what's written.
+  let p = b.ptr();
+  let b = b.make_mut();
+  assert_eq!(b.ptr(), p);
+  assert!(b.peek_be::<u8>(0).is_err());
+
+  b.poke(0, b"abcdefgh").unwrap();
+  assert!(b.atomic_read_only().is_ok());
+}
+
+#[test]
+fn read_only_views_stop_at_the_written_mark() {
+  use bufspan::BufSpan;
+  use error::ErrorKind;
+
+  let mut b = RWIobuf::new_uninit(16);
+  b.fill(b"abcd").unwrap();
+  b.flip_lo();
+  assert_eq!(b.extend(4), Ok(()));
+  let w = b.downgrade();
+
+  // Neither the window nor the limits can be moved past what's written.
+  assert_eq!(b.try_read_only().unwrap_err().kind(), ErrorKind::Uninitialized);
+  let mut r = b.read_only();
+  assert_eq!((r.len(), r.cap()), (4, 4));
+  r.reset();
+  assert_eq!(r.len(), 4);
+  assert!(r.extend(1).is_err());
+  assert_eq!(w.upgrade().unwrap().cap(), 4);
+
+  let mut span = BufSpan::from_buf(r);
+  let mut out = Vec::new();
+  span.write_all_to(&mut out).unwrap();
+  assert_eq!(&out[..], b"abcd");
+
+  // Later writes are seen by later views.
+  b.poke(4, b"ef").unwrap();
+  assert_eq!(b.read_only().cap(), 6);
+  drop(w);
+  assert_eq!(b.resize(6), Ok(()));
+  assert_eq!(b.unique().ok().unwrap().read_only().cap(), 6);
+}
+
+#[test]
+fn tags_follow_the_buffer() {
+  let mut a = RWIobuf::from_str_copy("hello, world");
+  a.set_tag(42).unwrap();
+
+  let b = a.clone().atomic_read_only().unwrap_err().read_only();
+  assert_eq!(b.tag(), 42);
+  b.set_tag(43).unwrap();
+  assert_eq!(a.tag(), 43);
+
+  // Moving to a bigger buffer is still the same Iobuf.
+  a.reserve(100).unwrap();
+  assert!(!a.shares_buffer_with(&b));
+  assert_eq!(a.tag(), 43);
+  assert_eq!(b.make_mut().tag(), 43);
+
+  assert_eq!(a.deep_clone_aligned(64).tag(), 0);
+  assert_eq!(a.try_deep_clone().unwrap().tag(), 0);
+}
+
 /// Atomic Read-Only Iobuf
 ///
 /// An `ROIobuf` which is safe to `Send` across tasks and `Share` with other tasks.
@@ -115,14 +303,13 @@ impl<'a> io::Write for RWIobuf<'a> {
 /// 0xFF, and consuming/validating these numbers in parallel in 4 other threads:
 ///
 /// ```rust
-/// #![feature(std_misc)]
-/// use iobuf::{RWIobuf, AROIobuf, Iobuf};
+/// use iobuf::{RWIobuf, AROIobuf, Iobuf, IobufError};
 /// use std::thread;
 ///
 /// // Write the bytes 0x00 - 0xFF into an Iobuf.
-/// fn fill(buf: &mut RWIobuf<'static>) -> Result<(), ()> {
+/// fn fill(buf: &mut RWIobuf<'static>) -> Result<(), IobufError> {
 ///   for i in 0x00u32 .. 0x100 {
-///     try!(buf.fill_be(i as u8));
+///     buf.fill_be(i as u8)?;
 ///   }
 ///
 ///   Ok(())
@@ -134,7 +321,7 @@ impl<'a> io::Write for RWIobuf<'a> {
 ///   loop {
 ///     let b: u8 =
 ///       match buf.consume_be::<u8>() {
-///         Err(()) => return Ok(()),
+///         Err(_) => return Ok(()),
 ///         Ok(b) => b
 ///       };
 ///
@@ -217,9 +404,11 @@ unsafe impl Send for UniqueIobuf {}
 unsafe impl Sync for UniqueIobuf {}
 
 impl UniqueIobuf {
-  /// Safely converts a `UniqueIobuf` into a `ROIobuf`.
+  /// Safely converts a `UniqueIobuf` into a `ROIobuf`. Its limits end at the
+  /// last written byte, as for `RWIobuf::read_only`.
   #[inline(always)]
-  pub fn read_only(self) -> ROIobuf<'static> {
+  pub fn read_only(mut self) -> ROIobuf<'static> {
+    self.raw.clamp_to_initialized();
     unsafe { mem::transmute(self) }
   }
 
@@ -229,9 +418,11 @@ impl UniqueIobuf {
     unsafe { mem::transmute(self) }
   }
 
-  /// Safely converts a `UniqueIobuf` into a `AROIobuf`.
+  /// Safely converts a `UniqueIobuf` into a `AROIobuf`. Its limits end at the
+  /// last written byte, as for `RWIobuf::read_only`.
   #[inline(always)]
-  pub fn atomic_read_only(self) -> AROIobuf {
+  pub fn atomic_read_only(mut self) -> AROIobuf {
+    self.raw.clamp_to_initialized();
     unsafe { mem::transmute(self) }
   }
 }
@@ -299,7 +490,7 @@ impl<'a> ROIobuf<'a> {
   /// Copies a `str` into a read-only Iobuf, whose memory comes from the given
   /// allocator.
   #[inline(always)]
-  pub fn from_str_copy_with_allocator(s: &str, allocator: Arc<Box<Allocator>>) -> ROIobuf<'static> {
+  pub fn from_str_copy_with_allocator(s: &str, allocator: Arc<Box<dyn Allocator>>) -> ROIobuf<'static> {
     ROIobuf { raw: RawIobuf::from_str_copy_with_allocator(s, allocator) }
   }
 
@@ -326,10 +517,51 @@ impl<'a> ROIobuf<'a> {
   /// Copies a byte vector into a new read-only Iobuf, whose memory comes from
   /// the given allocator.
   #[inline(always)]
-  pub fn from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<Allocator>>) -> ROIobuf<'static> {
+  pub fn from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<dyn Allocator>>) -> ROIobuf<'static> {
     ROIobuf { raw: RawIobuf::from_slice_copy_with_allocator(s, allocator) }
   }
 
+  /// The same as `from_slice_copy`, but returns an error instead of panicking
+  /// if the slice is too large or the memory can't be allocated.
+  ///
+  /// ```rust
+  /// use iobuf::{ROIobuf, Iobuf};
+  ///
+  /// let b = ROIobuf::try_from_slice_copy(b"hello").unwrap();
+  /// unsafe { assert_eq!(b.as_window_slice(), b"hello"); }
+  /// ```
+  #[inline(always)]
+  pub fn try_from_slice_copy(s: &[u8]) -> Result<ROIobuf<'static>, IobufError> {
+    RawIobuf::try_from_slice_copy(s).map(|raw| ROIobuf { raw: raw })
+  }
+
+  /// The same as `from_slice_copy_with_allocator`, but returns an error
+  /// instead of panicking if the slice is too large or the allocator fails.
+  #[inline(always)]
+  pub fn try_from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<dyn Allocator>>) -> Result<ROIobuf<'static>, IobufError> {
+    RawIobuf::try_from_slice_copy_with_allocator(s, allocator).map(|raw| ROIobuf { raw: raw })
+  }
+
+  /// Memory-maps the file at `path` read-only, as the buffer of a new Iobuf.
+  /// The limits and window cover the whole file, and nothing is copied. The
+  /// file is unmapped when the last Iobuf referencing it is dropped.
+  ///
+  /// The pages are mapped read-only, so the buffer is never unique: `unique`
+  /// and `atomic_read_only` refuse, and `make_mut` copies.
+  ///
+  /// # Safety
+  ///
+  /// The buffer is the file's pages, so it changes whenever the file does,
+  /// even under the slices this Iobuf hands out, and touching it after the
+  /// file is truncated kills the process with `SIGBUS`. The caller must make
+  /// sure that nothing, in this process or any other, truncates or writes to
+  /// the file until the last Iobuf referencing it is dropped.
+  #[cfg(unix)]
+  #[inline]
+  pub unsafe fn map_file<P: AsRef<Path>>(path: P) -> io::Result<ROIobuf<'static>> {
+    mmap::map_file(path.as_ref(), false).map(|raw| ROIobuf { raw: raw })
+  }
+
   /// Constructs an Iobuf from a slice. The Iobuf will not copy the slice
   /// contents, and therefore their lifetimes will be linked.
   ///
@@ -349,6 +581,13 @@ impl<'a> ROIobuf<'a> {
   pub fn from_slice(s: &'a [u8]) -> Self {
     ROIobuf { raw: RawIobuf::from_slice(s) }
   }
+
+  /// Makes a weak reference to the buffer, remembering the current limits and
+  /// window. It doesn't keep the buffer alive. See `WeakIobuf`.
+  #[inline]
+  pub fn downgrade(&self) -> WeakIobuf<'a> {
+    WeakIobuf::new(&self.raw)
+  }
 }
 
 impl<'a> RWIobuf<'a> {
@@ -371,6 +610,10 @@ impl<'a> RWIobuf<'a> {
   /// Constructs a new Iobuf with a buffer of size `len`, undefined contents,
   /// and the limits and window set to the full size of the buffer.
   ///
+  /// Reading the buffer before writing it will return whatever was left in
+  /// that memory. Prefer `new_zeroed`, or `new_uninit` if the zeroing is too
+  /// expensive.
+  ///
   /// The maximum length of an Iobuf is approximately 2 GB.
   ///
   /// ```rust
@@ -386,16 +629,97 @@ impl<'a> RWIobuf<'a> {
     RWIobuf { raw: RawIobuf::new(len) }
   }
 
+  /// Constructs a new Iobuf with a buffer of `len` zeros, and the limits and
+  /// window set to the full size of the buffer.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf,Iobuf};
+  ///
+  /// let b = RWIobuf::new_zeroed(10);
+  ///
+  /// assert_eq!(b.peek_be::<u64>(2), Ok(0));
+  /// ```
+  #[inline(always)]
+  pub fn new_zeroed(len: usize) -> RWIobuf<'static> {
+    RWIobuf { raw: RawIobuf::new_zeroed(len) }
+  }
+
+  /// Constructs a new Iobuf with a buffer of size `len` that is never read
+  /// before it's written. A high-water mark tracks how far the buffer has been
+  /// written from its start, and reads past it fail with `Uninitialized`, on
+  /// this Iobuf and on every Iobuf sharing its buffer.
+  ///
+  /// Writes that start past the mark don't move it, so fill the buffer from
+  /// the front. Use `mark_written` after writing through
+  /// `as_mut_window_slice`.
+  ///
+  /// ```rust
+  /// use iobuf::{ErrorKind,RWIobuf,Iobuf};
+  ///
+  /// let mut b = RWIobuf::new_uninit(10);
+  /// assert_eq!(b.peek_be::<u8>(0).unwrap_err().kind(), ErrorKind::Uninitialized);
+  ///
+  /// b.fill_be(0x1234u16).unwrap();
+  /// b.flip_lo();
+  /// assert_eq!(b.peek_be::<u16>(0), Ok(0x1234));
+  /// assert!(b.try_read_only().is_ok());
+  /// ```
+  #[inline(always)]
+  pub fn new_uninit(len: usize) -> RWIobuf<'static> {
+    RWIobuf { raw: RawIobuf::new_uninit(len) }
+  }
+
   /// Constructs a new Iobuf with a buffer of size `len`, undefined contents,
   /// and the limits and window set to the full range of the buffer. The memory
   /// will be allocated out of the given allocator, instead of the global heap.
   ///
   /// The maximum length of an Iobuf is approximately 2 GB.
   #[inline(always)]
-  pub fn new_with_allocator(len: usize, allocator: Arc<Box<Allocator>>) -> RWIobuf<'static> {
+  pub fn new_with_allocator(len: usize, allocator: Arc<Box<dyn Allocator>>) -> RWIobuf<'static> {
     RWIobuf { raw: RawIobuf::new_with_allocator(len, allocator) }
   }
 
+  /// Constructs a new Iobuf with a buffer of size `len` and undefined contents,
+  /// like `new`, but with the start of the buffer aligned to `align` bytes
+  /// instead of 16. This is useful for `O_DIRECT` I/O, or wide SIMD loads.
+  ///
+  /// Panics if `align` is not a power of two.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf,Iobuf};
+  ///
+  /// let b = RWIobuf::new_aligned(100, 4096);
+  ///
+  /// assert_eq!(b.ptr().as_ptr() as usize % 4096, 0);
+  /// assert_eq!(b.cap(), 100);
+  /// ```
+  #[inline(always)]
+  pub fn new_aligned(len: usize, align: usize) -> RWIobuf<'static> {
+    RWIobuf { raw: RawIobuf::new_aligned(len, align) }
+  }
+
+  /// The same as `new`, but returns an error instead of panicking if `len` is
+  /// too large (`TooLarge`) or the memory can't be allocated
+  /// (`AllocationFailed`).
+  ///
+  /// ```rust
+  /// use iobuf::{ErrorKind, RWIobuf, Iobuf};
+  ///
+  /// assert_eq!(RWIobuf::try_new(10).unwrap().len(), 10);
+  /// assert_eq!(RWIobuf::try_new(1 << 31).unwrap_err().kind(), ErrorKind::TooLarge);
+  /// ```
+  #[inline(always)]
+  pub fn try_new(len: usize) -> Result<RWIobuf<'static>, IobufError> {
+    RawIobuf::try_new(len).map(|raw| RWIobuf { raw: raw })
+  }
+
+  /// The same as `new_with_allocator`, but returns an error instead of
+  /// panicking if `len` is too large or the allocator fails.
+  #[inline(always)]
+  pub fn try_new_with_allocator(len: usize, allocator: Arc<Box<dyn Allocator>>) -> Result<RWIobuf<'static>, IobufError> {
+    RawIobuf::try_new_with_allocator(len, allocator).map(|raw| RWIobuf { raw: raw })
+  }
+
   /// Copies a `str` into a writeable Iobuf. The contents of the `str` will be
   /// copied, so prefer to use the non-copying constructors whenever possible.
   ///
@@ -419,7 +743,7 @@ impl<'a> RWIobuf<'a> {
   /// Copies a `str` into a writeable Iobuf, whose memory comes from the given
   /// allocator.
   #[inline(always)]
-  pub fn from_str_copy_with_allocator(s: &str, allocator: Arc<Box<Allocator>>) -> RWIobuf<'static> {
+  pub fn from_str_copy_with_allocator(s: &str, allocator: Arc<Box<dyn Allocator>>) -> RWIobuf<'static> {
     RWIobuf { raw: RawIobuf::from_str_copy_with_allocator(s, allocator) }
   }
 
@@ -470,10 +794,141 @@ impl<'a> RWIobuf<'a> {
   /// Copies a byte vector into a new writeable Iobuf, whose memory comes from
   /// the given allocator.
   #[inline(always)]
-  pub fn from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<Allocator>>) -> RWIobuf<'static> {
+  pub fn from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<dyn Allocator>>) -> RWIobuf<'static> {
     RWIobuf { raw: RawIobuf::from_slice_copy_with_allocator(s, allocator) }
   }
 
+  /// The same as `from_slice_copy`, but returns an error instead of panicking
+  /// if the slice is too large or the memory can't be allocated.
+  #[inline(always)]
+  pub fn try_from_slice_copy(s: &[u8]) -> Result<RWIobuf<'static>, IobufError> {
+    RawIobuf::try_from_slice_copy(s).map(|raw| RWIobuf { raw: raw })
+  }
+
+  /// The same as `from_slice_copy_with_allocator`, but returns an error
+  /// instead of panicking if the slice is too large or the allocator fails.
+  #[inline(always)]
+  pub fn try_from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<dyn Allocator>>) -> Result<RWIobuf<'static>, IobufError> {
+    RawIobuf::try_from_slice_copy_with_allocator(s, allocator).map(|raw| RWIobuf { raw: raw })
+  }
+
+  /// Takes ownership of a `Vec`'s allocation as the buffer of a new Iobuf,
+  /// whose limits and window cover the `Vec`'s contents.
+  ///
+  /// This is not zero-copy: the bytes are always moved up within the
+  /// allocation, to make room for the refcount in front of them. What it
+  /// saves is the allocation, and only if the `Vec` has at least
+  /// `RWIobuf::HEADROOM + 15` bytes of spare capacity. Otherwise it's grown
+  /// first, which copies it, as `Vec`s made by `to_vec` or `collect` usually
+  /// are. Its payoff is `into_vec`, which hands the allocation back.
+  ///
+  /// Panics if the `Vec` is longer than an Iobuf can be, before touching it.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf, Iobuf};
+  ///
+  /// let b = RWIobuf::from_vec(b"hello".to_vec());
+  /// unsafe { assert_eq!(b.as_window_slice(), b"hello"); }
+  /// assert_eq!(b.into_vec().unwrap(), b"hello".to_vec());
+  /// ```
+  #[inline]
+  pub fn from_vec(v: Vec<u8>) -> RWIobuf<'static> {
+    RWIobuf { raw: foreign::from_vec(v) }
+  }
+
+  /// The number of bytes which must precede memory passed to
+  /// `from_raw_parts_with_release`, for the Iobuf's refcount and bookkeeping.
+  /// Memory has to be allocated with this much room in front of it to be
+  /// adopted.
+  pub const HEADROOM: usize = ALLOCATION_HEADER_SIZE;
+
+  /// Builds an Iobuf over `len` bytes of foreign memory at `ptr`, without
+  /// copying. When the last Iobuf referencing it is dropped, `release` is
+  /// called, and may free the memory.
+  ///
+  /// This is unsafe because:
+  ///
+  ///   - `ptr` must be aligned to 16 bytes, and valid for reads and writes of
+  ///     `len` bytes until `release` is called.
+  ///   - The `RWIobuf::HEADROOM` bytes before `ptr` must also be writable, and
+  ///     must not be used by anything else until `release` is called.
+  ///
+  /// Buffers from elsewhere, like one `malloc`ed by a C library, rarely have
+  /// those bytes to spare. They can't be adopted, and have to be copied with
+  /// `from_slice_copy` instead.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf, Iobuf};
+  /// use std::ptr::NonNull;
+  ///
+  /// let mut storage = vec![0u128; 4];
+  /// let base = storage.as_mut_ptr() as *mut u8;
+  ///
+  /// let b = unsafe {
+  ///   let ptr = NonNull::new_unchecked(base.add(RWIobuf::HEADROOM));
+  ///   RWIobuf::from_raw_parts_with_release(ptr, 16, move || drop(storage))
+  /// };
+  /// assert_eq!(b.len(), 16);
+  /// ```
+  #[inline]
+  pub unsafe fn from_raw_parts_with_release<F>(ptr: NonNull<u8>, len: usize, release: F) -> RWIobuf<'static>
+      where F: FnOnce() + Send + 'static {
+    RWIobuf { raw: foreign::from_raw_parts_with_release(ptr, len, release) }
+  }
+
+  /// Memory-maps the file at `path` shared and writable, as the buffer of a
+  /// new Iobuf. The limits and window cover the whole file. Writes go to the
+  /// file, but may not reach the disk until `msync` is called or the file is
+  /// unmapped, which happens when the last Iobuf referencing it is dropped.
+  ///
+  /// # Safety
+  ///
+  /// The same as `ROIobuf::map_file`: nothing but this Iobuf and its clones
+  /// may truncate or write to the file until the last of them is dropped.
+  #[cfg(unix)]
+  #[inline]
+  pub unsafe fn map_file_mut<P: AsRef<Path>>(path: P) -> io::Result<RWIobuf<'static>> {
+    mmap::map_file(path.as_ref(), true).map(|raw| RWIobuf { raw: raw })
+  }
+
+  /// Returns the bytes in the window as a `Vec`, if this is the last Iobuf
+  /// referencing its buffer. Otherwise, returns `Err(self)`.
+  ///
+  /// Buffers made by `from_vec` hand their allocation back without allocating
+  /// (the window is shifted to the front of it). Other buffers are copied.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf, Iobuf};
+  ///
+  /// let mut b = RWIobuf::from_vec(b"hello, world".to_vec());
+  /// b.advance(7).unwrap();
+  ///
+  /// let c = b.clone();
+  /// let b = b.into_vec().unwrap_err();
+  /// drop(c);
+  ///
+  /// assert_eq!(b.into_vec().unwrap(), b"world".to_vec());
+  /// ```
+  #[inline]
+  pub fn into_vec(self) -> Result<Vec<u8>, RWIobuf<'a>> {
+    unsafe {
+      if !self.raw.is_unique_nonatomic() {
+        return Err(self);
+      }
+      // Unique buffers are always owned, and owned buffers are `'static`.
+      let raw: RawIobuf<'static> = mem::transmute(self);
+      Ok(foreign::into_vec(raw))
+    }
+  }
+
+  /// Synchronously writes changes within the limits back to the mapped file,
+  /// for Iobufs created by `map_file_mut`. For other Iobufs, this defers to
+  /// their `Allocator`'s `flush`, which usually does nothing.
+  #[inline]
+  pub fn msync(&self) -> io::Result<()> {
+    self.raw.flush_limits()
+  }
+
   /// Reads the data in the window as a mutable slice. Note that since `&mut`
   /// in rust really means `&unique`, this function lies. There can exist
   /// multiple slices of the same data. Therefore, this function is unsafe.
@@ -550,9 +1005,93 @@ impl<'a> RWIobuf<'a> {
   /// // now do read-only ops.
   /// assert_eq!(rb.len(), 4);
   /// ```
-  #[inline(always)]
+  ///
+  /// For a `new_uninit` buffer, the copy's limits end at the last written
+  /// byte, and so does its window if it went further, so it can never read
+  /// never-written bytes. See `try_read_only`.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf,Iobuf};
+  ///
+  /// let b = RWIobuf::new_uninit(8);
+  /// assert_eq!(b.poke(0, b"abc"), Ok(()));
+  ///
+  /// let mut r = b.read_only();
+  /// assert_eq!(r.len(), 3);
+  /// r.reset();
+  /// assert_eq!(r.cap(), 3);
+  /// ```
+  #[inline]
   pub fn read_only(&self) -> ROIobuf<'a> {
-    ROIobuf { raw: unsafe { self.raw.clone_nonatomic() } }
+    let mut raw = unsafe { self.raw.clone_nonatomic() };
+    raw.clamp_to_initialized();
+    ROIobuf { raw: raw }
+  }
+
+  /// The same as `read_only`, but returns an `Uninitialized` error instead of
+  /// shrinking the window if it holds never-written bytes.
+  #[inline]
+  pub fn try_read_only(&self) -> Result<ROIobuf<'a>, IobufError> {
+    self.raw.check_window_initialized()?;
+    Ok(self.read_only())
+  }
+
+  /// Makes a weak reference to the buffer, remembering the current limits and
+  /// window. It doesn't keep the buffer alive, and only upgrades to a
+  /// `ROIobuf`. See `WeakIobuf`.
+  #[inline]
+  pub fn downgrade(&self) -> WeakIobuf<'a> {
+    WeakIobuf::new(&self.raw)
+  }
+
+  /// Makes sure the window has room for `additional` bytes. If it's too
+  /// short, its end moves to the end of the limits. If that's still too
+  /// short, the limits are copied into a new, bigger buffer from the same
+  /// allocator, with the window in the same place. Growing by at least
+  /// doubling keeps repeated calls cheap. Borrowed, mapped and adopted memory,
+  /// or any buffer whose allocator fails, move to the global heap instead.
+  ///
+  /// Once moved, writes to this Iobuf are no longer seen by its clones, nor,
+  /// for a mapped file, by the file.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf,Iobuf};
+  ///
+  /// let mut b = RWIobuf::new(4);
+  /// assert_eq!(b.fill(b"abc"), Ok(()));
+  /// assert!(b.fill(b"defg").is_err());
+  ///
+  /// assert_eq!(b.reserve(4), Ok(()));
+  /// assert!(b.cap() >= 7);
+  /// assert_eq!(b.fill(b"defg"), Ok(()));
+  ///
+  /// b.flip_lo();
+  /// unsafe { assert_eq!(b.as_window_slice(), b"abcdefg"); }
+  /// ```
+  #[inline]
+  pub fn reserve(&mut self, additional: usize) -> Result<(), IobufError> {
+    self.raw.reserve_nonatomic(additional)
+  }
+
+  /// Records the first `len` bytes of the window as written, for buffers made
+  /// by `new_uninit`. This is needed after writing into the window through
+  /// `as_mut_window_slice`, since those writes can't be seen.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf,Iobuf};
+  ///
+  /// let b = RWIobuf::new_uninit(4);
+  /// unsafe { b.as_mut_window_slice()[..2].copy_from_slice(b"hi"); }
+  /// assert!(b.peek_be::<u8>(0).is_err());
+  ///
+  /// assert_eq!(b.mark_written(2), Ok(()));
+  /// assert_eq!(b.peek_be::<u8>(1), Ok(b'i'));
+  /// ```
+  #[inline]
+  pub fn mark_written(&self, len: u32) -> Result<(), IobufError> {
+    self.raw.check_range_u32(0, len)?;
+    self.raw.mark_initialized(0, len as usize);
+    Ok(())
   }
 
   /// Copies data from the window to the lower limit fo the iobuf and sets the
@@ -562,7 +1101,7 @@ impl<'a> RWIobuf<'a> {
   ///
   /// ```rust
   /// use std::result::Result::{self,Ok};
-  /// use iobuf::{RWIobuf,Iobuf};
+  /// use iobuf::{RWIobuf,Iobuf,IobufError};
   ///
   /// // A header, saying how many shorts will follow. Unfortunately, our buffer
   /// // isn't big enough for all the shorts! Assume the rest will be sent in a
@@ -578,8 +1117,8 @@ impl<'a> RWIobuf<'a> {
   ///
   /// // Returns a pair of the sum of shorts seen so far, and `true` if we're
   /// // finally done parsing. The sum will be partial if parsing is incomplete.
-  /// fn parse(b: &mut RWIobuf) -> Result<ParseState, ()> {
-  ///   let len: u8 = try!(b.consume_be());
+  /// fn parse(b: &mut RWIobuf) -> Result<ParseState, IobufError> {
+  ///   let len: u8 = b.consume_be()?;
   ///   let mut sum = 0u16;
   ///
   ///   for _ in (0u8 .. len) {
@@ -617,13 +1156,13 @@ impl<'a> RWIobuf<'a> {
   /// assert_eq!(b.poke(0, &data[..]), Ok(()));
   /// assert_eq!(b.poke(3, &data[..]), Ok(()));
   /// assert_eq!(b.resize(7), Ok(()));
-  /// assert_eq!(b.poke(4, &data[..]), Err(())); // no partial write, just failure
+  /// assert!(b.poke(4, &data[..]).is_err()); // no partial write, just failure
   ///
   /// let expected = [ 1,2,3,1,2,3,4 ];
   /// unsafe { assert_eq!(b.as_window_slice(), expected); }
   /// ```
   #[inline(always)]
-  pub fn poke(&self, pos: u32, src: &[u8]) -> Result<(), ()> { self.raw.poke(pos, src) }
+  pub fn poke(&self, pos: u32, src: &[u8]) -> Result<(), IobufError> { self.raw.poke(pos, src) }
 
   /// Writes a big-endian primitive at a given offset from the beginning of the
   /// window.
@@ -645,7 +1184,7 @@ impl<'a> RWIobuf<'a> {
   /// unsafe { assert_eq!(b.as_window_slice(), expected); }
   /// ```
   #[inline(always)]
-  pub fn poke_be<T: IntLike>(&self, pos: u32, t: T) -> Result<(), ()> { self.raw.poke_be(pos, t) }
+  pub fn poke_be<T: IntLike>(&self, pos: u32, t: T) -> Result<(), IobufError> { self.raw.poke_be(pos, t) }
 
   /// Writes a little-endian primitive at a given offset from the beginning of
   /// the window.
@@ -666,7 +1205,7 @@ impl<'a> RWIobuf<'a> {
   /// unsafe { assert_eq!(b.as_window_slice(), [ 4, 5, 5, 9, 8, 7, 6 ]); }
   /// ```
   #[inline(always)]
-  pub fn poke_le<T: IntLike>(&self, pos: u32, t: T) -> Result<(), ()> { self.raw.poke_le(pos, t) }
+  pub fn poke_le<T: IntLike>(&self, pos: u32, t: T) -> Result<(), IobufError> { self.raw.poke_le(pos, t) }
 
   /// Writes bytes from the supplied buffer, starting from the front of the
   /// window. Either the entire buffer is copied, or an error is returned
@@ -684,14 +1223,14 @@ impl<'a> RWIobuf<'a> {
   ///
   /// assert_eq!(b.fill(&data[..]), Ok(()));
   /// assert_eq!(b.fill(&data[..]), Ok(()));
-  /// assert_eq!(b.fill(&data[..]), Err(()));
+  /// assert!(b.fill(&data[..]).is_err());
   ///
   /// b.flip_lo();
   ///
   /// unsafe { assert_eq!(b.as_window_slice(), &[ 1,2,3,4,1,2,3,4 ][..]); }
   /// ```
   #[inline(always)]
-  pub fn fill(&mut self, src: &[u8]) -> Result<(), ()> { self.raw.fill(src) }
+  pub fn fill(&mut self, src: &[u8]) -> Result<(), IobufError> { self.raw.fill(src) }
 
   /// Writes a big-endian primitive into the beginning of the window.
   ///
@@ -707,7 +1246,7 @@ impl<'a> RWIobuf<'a> {
   ///
   /// assert_eq!(b.fill_be(0x12345678u32), Ok(()));
   /// assert_eq!(b.fill_be(0x11223344u32), Ok(()));
-  /// assert_eq!(b.fill_be(0x54321123u32), Err(()));
+  /// assert!(b.fill_be(0x54321123u32).is_err());
   /// assert_eq!(b.fill_be(0x8877u16), Ok(()));
   ///
   /// b.flip_lo();
@@ -717,7 +1256,7 @@ impl<'a> RWIobuf<'a> {
   ///                                          , 0x88, 0x77 ]); }
   /// ```
   #[inline(always)]
-  pub fn fill_be<T: IntLike>(&mut self, t: T) -> Result<(), ()> { self.raw.fill_be(t) }
+  pub fn fill_be<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> { self.raw.fill_be(t) }
 
   /// Writes a little-endian primitive into the beginning of the window.
   ///
@@ -733,7 +1272,7 @@ impl<'a> RWIobuf<'a> {
   ///
   /// assert_eq!(b.fill_le(0x12345678u32), Ok(()));
   /// assert_eq!(b.fill_le(0x11223344u32), Ok(()));
-  /// assert_eq!(b.fill_le(0x54321123u32), Err(()));
+  /// assert!(b.fill_le(0x54321123u32).is_err());
   /// assert_eq!(b.fill_le(0x8877u16), Ok(()));
   ///
   /// b.flip_lo();
@@ -743,7 +1282,7 @@ impl<'a> RWIobuf<'a> {
   ///                                          , 0x77, 0x88 ]); }
   /// ```
   #[inline(always)]
-  pub fn fill_le<T: IntLike>(&mut self, t: T) -> Result<(), ()> { self.raw.fill_le(t) }
+  pub fn fill_le<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> { self.raw.fill_le(t) }
 
   /// Writes the bytes at a given offset from the beginning of the window, into
   /// the supplied buffer. It is undefined behavior to write outside the iobuf
@@ -907,6 +1446,18 @@ impl<'a> RWIobuf<'a> {
 }
 
 impl AROIobuf {
+  /// Memory-maps the file at `path` read-only, as the buffer of a new,
+  /// atomically refcounted Iobuf. See `ROIobuf::map_file`.
+  ///
+  /// # Safety
+  ///
+  /// The same as `ROIobuf::map_file`.
+  #[cfg(unix)]
+  #[inline]
+  pub unsafe fn map_file<P: AsRef<Path>>(path: P) -> io::Result<AROIobuf> {
+    mmap::map_file(path.as_ref(), false).map(|raw| AROIobuf { raw: raw })
+  }
+
   /// Stops atomically reference counting a unique buffer. This method returns
   /// `Ok` if the `AROIobuf` is the last of its kind, and `Err` if it's not.
   ///
@@ -958,6 +1509,14 @@ impl AROIobuf {
       }
     }
   }
+
+  /// Makes a weak reference to the buffer, remembering the current limits and
+  /// window, which may be sent to other threads. It doesn't keep the buffer
+  /// alive. See `AWeakIobuf`.
+  #[inline]
+  pub fn downgrade(&self) -> AWeakIobuf {
+    AWeakIobuf::new(&self.raw)
+  }
 }
 
 impl<'a> Iobuf for ROIobuf<'a> {
@@ -965,10 +1524,30 @@ impl<'a> Iobuf for ROIobuf<'a> {
   fn deep_clone(&self) -> RWIobuf<'static> { RWIobuf { raw: self.raw.deep_clone() } }
 
   #[inline(always)]
-  fn deep_clone_with_allocator(&self, allocator: Arc<Box<Allocator>>) -> RWIobuf<'static> {
+  fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> RWIobuf<'static> {
     RWIobuf { raw: self.raw.deep_clone_with_allocator(allocator) }
   }
 
+  #[inline(always)]
+  fn deep_clone_aligned(&self, align: usize) -> RWIobuf<'static> {
+    RWIobuf { raw: self.raw.deep_clone_aligned(align) }
+  }
+
+  #[inline]
+  fn deep_clone_with_tag(&self) -> RWIobuf<'static> {
+    RWIobuf { raw: self.raw.deep_clone_with_tag() }
+  }
+
+  #[inline(always)]
+  fn try_deep_clone(&self) -> Result<RWIobuf<'static>, IobufError> {
+    self.raw.try_deep_clone().map(|raw| RWIobuf { raw: raw })
+  }
+
+  #[inline(always)]
+  fn try_deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> Result<RWIobuf<'static>, IobufError> {
+    self.raw.try_deep_clone_with_allocator(allocator).map(|raw| RWIobuf { raw: raw })
+  }
+
   #[inline(always)]
   fn unique(self) -> Result<UniqueIobuf, Self> {
     unsafe {
@@ -991,6 +1570,29 @@ impl<'a> Iobuf for ROIobuf<'a> {
     }
   }
 
+  #[inline]
+  fn make_mut(self) -> RWIobuf<'static> {
+    match self.unique() {
+      Ok(b)  => b.read_write(),
+      Err(b) => b.deep_clone_with_tag(),
+    }
+  }
+
+  #[inline(always)]
+  fn ref_count(&self) -> usize { unsafe { self.raw.ref_count_nonatomic() } }
+
+  #[inline(always)]
+  fn is_unique(&self) -> bool { unsafe { self.raw.is_unique_nonatomic() } }
+
+  #[inline(always)]
+  fn shares_buffer_with<Buf: Iobuf>(&self, other: &Buf) -> bool { unsafe { self.raw.shares_buffer_with(other.as_raw()) } }
+
+  #[inline(always)]
+  fn tag(&self) -> u64 { self.raw.tag() }
+
+  #[inline(always)]
+  fn set_tag(&self, tag: u64) -> Result<(), IobufError> { self.raw.set_tag(tag) }
+
   #[inline(always)]
   fn len(&self) -> u32 { self.raw.len() }
 
@@ -1007,13 +1609,13 @@ impl<'a> Iobuf for ROIobuf<'a> {
   unsafe fn as_limit_slice<'b>(&'b self) -> &'b [u8] { self.raw.as_limit_slice() }
 
   #[inline(always)]
-  fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), ()> { self.raw.sub_window(pos, len) }
+  fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), IobufError> { self.raw.sub_window(pos, len) }
 
   #[inline(always)]
-  fn sub_window_from(&mut self, pos: u32) -> Result<(), ()> { self.raw.sub_window_from(pos) }
+  fn sub_window_from(&mut self, pos: u32) -> Result<(), IobufError> { self.raw.sub_window_from(pos) }
 
   #[inline(always)]
-  fn sub_window_to(&mut self, len: u32) -> Result<(), ()> { self.raw.sub_window_to(len) }
+  fn sub_window_to(&mut self, len: u32) -> Result<(), IobufError> { self.raw.sub_window_to(len) }
 
   #[inline(always)]
   unsafe fn unsafe_sub_window(&mut self, pos: u32, len: u32) { self.raw.unsafe_sub_window(pos, len) }
@@ -1025,13 +1627,13 @@ impl<'a> Iobuf for ROIobuf<'a> {
   unsafe fn unsafe_sub_window_to(&mut self, len: u32) { self.raw.unsafe_sub_window_to(len) }
 
   #[inline(always)]
-  fn sub(&mut self, pos: u32, len: u32) -> Result<(), ()> { self.raw.sub(pos, len) }
+  fn sub(&mut self, pos: u32, len: u32) -> Result<(), IobufError> { self.raw.sub(pos, len) }
 
   #[inline(always)]
-  fn sub_from(&mut self, pos: u32) -> Result<(), ()> { self.raw.sub_from(pos) }
+  fn sub_from(&mut self, pos: u32) -> Result<(), IobufError> { self.raw.sub_from(pos) }
 
   #[inline(always)]
-  fn sub_to(&mut self, len: u32) -> Result<(), ()> { self.raw.sub_to(len) }
+  fn sub_to(&mut self, len: u32) -> Result<(), IobufError> { self.raw.sub_to(len) }
 
   #[inline(always)]
   unsafe fn unsafe_sub(&mut self, pos: u32, len: u32) { self.raw.unsafe_sub(pos, len) }
@@ -1043,19 +1645,19 @@ impl<'a> Iobuf for ROIobuf<'a> {
   unsafe fn unsafe_sub_to(&mut self, len: u32) { self.raw.unsafe_sub_to(len) }
 
   #[inline(always)]
-  fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), ()> { self.raw.set_limits_and_window(limits, window) }
+  fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), IobufError> { self.raw.set_limits_and_window(limits, window) }
 
   #[inline(always)]
   fn narrow(&mut self) { self.raw.narrow() }
 
   #[inline(always)]
-  fn advance(&mut self, len: u32) -> Result<(), ()> { self.raw.advance(len) }
+  fn advance(&mut self, len: u32) -> Result<(), IobufError> { self.raw.advance(len) }
 
   #[inline(always)]
   unsafe fn unsafe_advance(&mut self, len: u32) { self.raw.unsafe_advance(len) }
 
   #[inline(always)]
-  fn extend(&mut self, len: u32) -> Result<(), ()> { self.raw.extend(len) }
+  fn extend(&mut self, len: u32) -> Result<(), IobufError> { self.raw.extend(len) }
 
   #[inline(always)]
   unsafe fn unsafe_extend(&mut self, len: u32) { self.raw.unsafe_extend(len) }
@@ -1064,16 +1666,16 @@ impl<'a> Iobuf for ROIobuf<'a> {
   fn is_extended_by<Buf: Iobuf>(&self, other: &Buf) -> bool { unsafe { self.raw.is_extended_by(other.as_raw()) } }
 
   #[inline(always)]
-  fn extend_with<Buf: Iobuf>(&mut self, other: &Buf) -> Result<(), ()> { unsafe { self.raw.extend_with(other.as_raw()) } }
+  fn extend_with<Buf: Iobuf>(&mut self, other: &Buf) -> Result<(), IobufError> { unsafe { self.raw.extend_with(other.as_raw()) } }
 
   #[inline(always)]
-  fn resize(&mut self, len: u32) -> Result<(), ()> { self.raw.resize(len) }
+  fn resize(&mut self, len: u32) -> Result<(), IobufError> { self.raw.resize(len) }
 
   #[inline(always)]
   unsafe fn unsafe_resize(&mut self, len: u32) { self.raw.unsafe_resize(len) }
 
   #[inline(always)]
-  fn split_at(&self, pos: u32) -> Result<(Self, Self), ()> {
+  fn split_at(&self, pos: u32) -> Result<(Self, Self), IobufError> {
     self.raw.split_at_nonatomic(pos).map(|(a, b)| (ROIobuf { raw: a }, ROIobuf { raw: b }))
   }
 
@@ -1084,7 +1686,7 @@ impl<'a> Iobuf for ROIobuf<'a> {
   }
 
   #[inline(always)]
-  fn split_start_at(&mut self, pos: u32) -> Result<Self, ()> {
+  fn split_start_at(&mut self, pos: u32) -> Result<Self, IobufError> {
     self.raw.split_start_at_nonatomic(pos).map(|b| ROIobuf { raw: b })
   }
 
@@ -1112,24 +1714,24 @@ impl<'a> Iobuf for ROIobuf<'a> {
   fn hi_space(&self) -> u32 { self.raw.hi_space() }
 
   #[inline(always)]
-  fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), ()> { self.raw.peek(pos, dst) }
+  fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.peek(pos, dst) }
   #[inline(always)]
-  fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, ()> { self.raw.peek_be(pos) }
+  fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, IobufError> { self.raw.peek_be(pos) }
   #[inline(always)]
-  fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, ()> { self.raw.peek_le(pos) }
+  fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, IobufError> { self.raw.peek_le(pos) }
 
   #[inline(always)]
-  fn consume(&mut self, dst: &mut [u8]) -> Result<(), ()> { self.raw.consume(dst) }
+  fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.consume(dst) }
   #[inline(always)]
-  fn consume_be<T: IntLike>(&mut self) -> Result<T, ()> { self.raw.consume_be::<T>() }
+  fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_be::<T>() }
   #[inline(always)]
-  fn consume_le<T: IntLike>(&mut self) -> Result<T, ()> { self.raw.consume_le::<T>() }
+  fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_le::<T>() }
 
   #[inline(always)]
-  fn check_range(&self, pos: u32, len: u32) -> Result<(), ()> { self.raw.check_range_u32(pos, len) }
+  fn check_range(&self, pos: u32, len: u32) -> Result<(), IobufError> { self.raw.check_range_u32(pos, len) }
 
   #[inline(always)]
-  fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), ()> { self.raw.check_range_usize(pos, len) }
+  fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), IobufError> { self.raw.check_range_usize(pos, len) }
 
   #[inline(always)]
   fn check_range_fail(&self, pos: u32, len: u32) { self.raw.check_range_u32_fail(pos, len) }
@@ -1158,7 +1760,7 @@ impl<'a> Iobuf for ROIobuf<'a> {
   fn invariant(&self) -> Result<(), Box<String>> { self.raw.invariant() }
 
   #[inline(always)]
-  fn ptr(&self) -> NonZero<*mut u8> { self.raw.ptr() }
+  fn ptr(&self) -> NonNull<u8> { self.raw.ptr() }
   #[inline(always)]
   fn is_owned(&self) -> bool { self.raw.is_owned() }
   #[inline(always)]
@@ -1178,10 +1780,30 @@ impl Iobuf for AROIobuf {
   }
 
   #[inline(always)]
-  fn deep_clone_with_allocator(&self, allocator: Arc<Box<Allocator>>) -> RWIobuf<'static> {
+  fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> RWIobuf<'static> {
     RWIobuf { raw: self.raw.deep_clone_with_allocator(allocator) }
   }
 
+  #[inline(always)]
+  fn deep_clone_aligned(&self, align: usize) -> RWIobuf<'static> {
+    RWIobuf { raw: self.raw.deep_clone_aligned(align) }
+  }
+
+  #[inline]
+  fn deep_clone_with_tag(&self) -> RWIobuf<'static> {
+    RWIobuf { raw: self.raw.deep_clone_with_tag() }
+  }
+
+  #[inline(always)]
+  fn try_deep_clone(&self) -> Result<RWIobuf<'static>, IobufError> {
+    self.raw.try_deep_clone().map(|raw| RWIobuf { raw: raw })
+  }
+
+  #[inline(always)]
+  fn try_deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> Result<RWIobuf<'static>, IobufError> {
+    self.raw.try_deep_clone_with_allocator(allocator).map(|raw| RWIobuf { raw: raw })
+  }
+
   #[inline(always)]
   fn unique(self) -> Result<UniqueIobuf, AROIobuf> {
     unsafe {
@@ -1198,6 +1820,29 @@ impl Iobuf for AROIobuf {
     Ok(self)
   }
 
+  #[inline]
+  fn make_mut(self) -> RWIobuf<'static> {
+    match self.unique() {
+      Ok(b)  => b.read_write(),
+      Err(b) => b.deep_clone_with_tag(),
+    }
+  }
+
+  #[inline(always)]
+  fn ref_count(&self) -> usize { unsafe { self.raw.ref_count_atomic() } }
+
+  #[inline(always)]
+  fn is_unique(&self) -> bool { unsafe { self.raw.is_unique_atomic() } }
+
+  #[inline(always)]
+  fn shares_buffer_with<Buf: Iobuf>(&self, other: &Buf) -> bool { unsafe { self.raw.shares_buffer_with(other.as_raw()) } }
+
+  #[inline(always)]
+  fn tag(&self) -> u64 { self.raw.tag() }
+
+  #[inline(always)]
+  fn set_tag(&self, tag: u64) -> Result<(), IobufError> { self.raw.set_tag(tag) }
+
   #[inline(always)]
   fn len(&self) -> u32 { self.raw.len() }
 
@@ -1214,13 +1859,13 @@ impl Iobuf for AROIobuf {
   unsafe fn as_limit_slice<'b>(&'b self) -> &'b [u8] { self.raw.as_limit_slice() }
 
   #[inline(always)]
-  fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), ()> { self.raw.sub_window(pos, len) }
+  fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), IobufError> { self.raw.sub_window(pos, len) }
 
   #[inline(always)]
-  fn sub_window_from(&mut self, pos: u32) -> Result<(), ()> { self.raw.sub_window_from(pos) }
+  fn sub_window_from(&mut self, pos: u32) -> Result<(), IobufError> { self.raw.sub_window_from(pos) }
 
   #[inline(always)]
-  fn sub_window_to(&mut self, len: u32) -> Result<(), ()> { self.raw.sub_window_to(len) }
+  fn sub_window_to(&mut self, len: u32) -> Result<(), IobufError> { self.raw.sub_window_to(len) }
 
   #[inline(always)]
   unsafe fn unsafe_sub_window(&mut self, pos: u32, len: u32) { self.raw.unsafe_sub_window(pos, len) }
@@ -1232,13 +1877,13 @@ impl Iobuf for AROIobuf {
   unsafe fn unsafe_sub_window_to(&mut self, len: u32) { self.raw.unsafe_sub_window_to(len) }
 
   #[inline(always)]
-  fn sub(&mut self, pos: u32, len: u32) -> Result<(), ()> { self.raw.sub(pos, len) }
+  fn sub(&mut self, pos: u32, len: u32) -> Result<(), IobufError> { self.raw.sub(pos, len) }
 
   #[inline(always)]
-  fn sub_from(&mut self, pos: u32) -> Result<(), ()> { self.raw.sub_from(pos) }
+  fn sub_from(&mut self, pos: u32) -> Result<(), IobufError> { self.raw.sub_from(pos) }
 
   #[inline(always)]
-  fn sub_to(&mut self, len: u32) -> Result<(), ()> { self.raw.sub_to(len) }
+  fn sub_to(&mut self, len: u32) -> Result<(), IobufError> { self.raw.sub_to(len) }
 
   #[inline(always)]
   unsafe fn unsafe_sub(&mut self, pos: u32, len: u32) { self.raw.unsafe_sub(pos, len) }
@@ -1250,19 +1895,19 @@ impl Iobuf for AROIobuf {
   unsafe fn unsafe_sub_to(&mut self, len: u32) { self.raw.unsafe_sub_to(len) }
 
   #[inline(always)]
-  fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), ()> { self.raw.set_limits_and_window(limits, window) }
+  fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), IobufError> { self.raw.set_limits_and_window(limits, window) }
 
   #[inline(always)]
   fn narrow(&mut self) { self.raw.narrow() }
 
   #[inline(always)]
-  fn advance(&mut self, len: u32) -> Result<(), ()> { self.raw.advance(len) }
+  fn advance(&mut self, len: u32) -> Result<(), IobufError> { self.raw.advance(len) }
 
   #[inline(always)]
   unsafe fn unsafe_advance(&mut self, len: u32) { self.raw.unsafe_advance(len) }
 
   #[inline(always)]
-  fn extend(&mut self, len: u32) -> Result<(), ()> { self.raw.extend(len) }
+  fn extend(&mut self, len: u32) -> Result<(), IobufError> { self.raw.extend(len) }
 
   #[inline(always)]
   unsafe fn unsafe_extend(&mut self, len: u32) { self.raw.unsafe_extend(len) }
@@ -1271,16 +1916,16 @@ impl Iobuf for AROIobuf {
   fn is_extended_by<Buf: Iobuf>(&self, other: &Buf) -> bool { unsafe { self.raw.is_extended_by(other.as_raw()) } }
 
   #[inline(always)]
-  fn extend_with<Buf: Iobuf>(&mut self, other: &Buf) -> Result<(), ()> { unsafe { self.raw.extend_with(other.as_raw()) } }
+  fn extend_with<Buf: Iobuf>(&mut self, other: &Buf) -> Result<(), IobufError> { unsafe { self.raw.extend_with(other.as_raw()) } }
 
   #[inline(always)]
-  fn resize(&mut self, len: u32) -> Result<(), ()> { self.raw.resize(len) }
+  fn resize(&mut self, len: u32) -> Result<(), IobufError> { self.raw.resize(len) }
 
   #[inline(always)]
   unsafe fn unsafe_resize(&mut self, len: u32) { self.raw.unsafe_resize(len) }
 
   #[inline(always)]
-  fn split_at(&self, pos: u32) -> Result<(Self, Self), ()> {
+  fn split_at(&self, pos: u32) -> Result<(Self, Self), IobufError> {
     self.raw.split_at_atomic(pos).map(
       |(a, b)| (AROIobuf { raw: a },
                 AROIobuf { raw: b }))
@@ -1294,7 +1939,7 @@ impl Iobuf for AROIobuf {
   }
 
   #[inline(always)]
-  fn split_start_at(&mut self, pos: u32) -> Result<Self, ()> {
+  fn split_start_at(&mut self, pos: u32) -> Result<Self, IobufError> {
     self.raw.split_start_at_atomic(pos).map(
       |b| AROIobuf { raw: b })
   }
@@ -1323,24 +1968,24 @@ impl Iobuf for AROIobuf {
   fn hi_space(&self) -> u32 { self.raw.hi_space() }
 
   #[inline(always)]
-  fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), ()> { self.raw.peek(pos, dst) }
+  fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.peek(pos, dst) }
   #[inline(always)]
-  fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, ()> { self.raw.peek_be(pos) }
+  fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, IobufError> { self.raw.peek_be(pos) }
   #[inline(always)]
-  fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, ()> { self.raw.peek_le(pos) }
+  fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, IobufError> { self.raw.peek_le(pos) }
 
   #[inline(always)]
-  fn consume(&mut self, dst: &mut [u8]) -> Result<(), ()> { self.raw.consume(dst) }
+  fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.consume(dst) }
   #[inline(always)]
-  fn consume_be<T: IntLike>(&mut self) -> Result<T, ()> { self.raw.consume_be::<T>() }
+  fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_be::<T>() }
   #[inline(always)]
-  fn consume_le<T: IntLike>(&mut self) -> Result<T, ()> { self.raw.consume_le::<T>() }
+  fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_le::<T>() }
 
   #[inline(always)]
-  fn check_range(&self, pos: u32, len: u32) -> Result<(), ()> { self.raw.check_range_u32(pos, len) }
+  fn check_range(&self, pos: u32, len: u32) -> Result<(), IobufError> { self.raw.check_range_u32(pos, len) }
 
   #[inline(always)]
-  fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), ()> { self.raw.check_range_usize(pos, len) }
+  fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), IobufError> { self.raw.check_range_usize(pos, len) }
 
   #[inline(always)]
   fn check_range_fail(&self, pos: u32, len: u32) { self.raw.check_range_u32_fail(pos, len) }
@@ -1369,7 +2014,7 @@ impl Iobuf for AROIobuf {
   fn invariant(&self) -> Result<(), Box<String>> { self.raw.invariant() }
 
   #[inline(always)]
-  fn ptr(&self) -> NonZero<*mut u8> { self.raw.ptr() }
+  fn ptr(&self) -> NonNull<u8> { self.raw.ptr() }
   #[inline(always)]
   fn is_owned(&self) -> bool { self.raw.is_owned() }
   #[inline(always)]
@@ -1387,14 +2032,36 @@ impl<'a> Iobuf for RWIobuf<'a> {
   fn deep_clone(&self) -> RWIobuf<'static> { RWIobuf { raw: self.raw.deep_clone() } }
 
   #[inline(always)]
-  fn deep_clone_with_allocator(&self, allocator: Arc<Box<Allocator>>) -> RWIobuf<'static> {
+  fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> RWIobuf<'static> {
     RWIobuf { raw: self.raw.deep_clone_with_allocator(allocator) }
   }
 
+  #[inline(always)]
+  fn deep_clone_aligned(&self, align: usize) -> RWIobuf<'static> {
+    RWIobuf { raw: self.raw.deep_clone_aligned(align) }
+  }
+
+  #[inline]
+  fn deep_clone_with_tag(&self) -> RWIobuf<'static> {
+    RWIobuf { raw: self.raw.deep_clone_with_tag() }
+  }
+
+  #[inline(always)]
+  fn try_deep_clone(&self) -> Result<RWIobuf<'static>, IobufError> {
+    self.raw.try_deep_clone().map(|raw| RWIobuf { raw: raw })
+  }
+
+  #[inline(always)]
+  fn try_deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> Result<RWIobuf<'static>, IobufError> {
+    self.raw.try_deep_clone_with_allocator(allocator).map(|raw| RWIobuf { raw: raw })
+  }
+
+  // A `UniqueIobuf` may be turned read-only without further checks, so
+  // never-written bytes are refused here.
   #[inline(always)]
   fn unique(self) -> Result<UniqueIobuf, Self> {
     unsafe {
-      if self.raw.is_unique_atomic() {
+      if self.raw.is_unique_atomic() && self.raw.check_window_initialized().is_ok() {
         Ok(mem::transmute(self))
       } else {
         Err(self)
@@ -1403,9 +2070,10 @@ impl<'a> Iobuf for RWIobuf<'a> {
   }
 
   #[inline(always)]
-  fn atomic_read_only(self) -> Result<AROIobuf, Self> {
+  fn atomic_read_only(mut self) -> Result<AROIobuf, Self> {
     unsafe {
-      if self.raw.is_unique_nonatomic() {
+      if self.raw.is_unique_nonatomic() && self.raw.check_window_initialized().is_ok() {
+        self.raw.clamp_to_initialized();
         Ok(mem::transmute(self))
       } else {
         Err(self)
@@ -1413,6 +2081,34 @@ impl<'a> Iobuf for RWIobuf<'a> {
     }
   }
 
+  // Already writable, so it doesn't matter what was written.
+  #[inline]
+  fn make_mut(self) -> RWIobuf<'static> {
+    unsafe {
+      if self.raw.is_unique_nonatomic() {
+        // Unique buffers are always owned, and owned buffers are `'static`.
+        mem::transmute(self)
+      } else {
+        self.deep_clone_with_tag()
+      }
+    }
+  }
+
+  #[inline(always)]
+  fn ref_count(&self) -> usize { unsafe { self.raw.ref_count_nonatomic() } }
+
+  #[inline(always)]
+  fn is_unique(&self) -> bool { unsafe { self.raw.is_unique_nonatomic() } }
+
+  #[inline(always)]
+  fn shares_buffer_with<Buf: Iobuf>(&self, other: &Buf) -> bool { unsafe { self.raw.shares_buffer_with(other.as_raw()) } }
+
+  #[inline(always)]
+  fn tag(&self) -> u64 { self.raw.tag() }
+
+  #[inline(always)]
+  fn set_tag(&self, tag: u64) -> Result<(), IobufError> { self.raw.set_tag(tag) }
+
   #[inline(always)]
   fn len(&self) -> u32 { self.raw.len() }
 
@@ -1429,13 +2125,13 @@ impl<'a> Iobuf for RWIobuf<'a> {
   unsafe fn as_limit_slice<'b>(&'b self) -> &'b [u8] { self.raw.as_limit_slice() }
 
   #[inline(always)]
-  fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), ()> { self.raw.sub_window(pos, len) }
+  fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), IobufError> { self.raw.sub_window(pos, len) }
 
   #[inline(always)]
-  fn sub_window_from(&mut self, pos: u32) -> Result<(), ()> { self.raw.sub_window_from(pos) }
+  fn sub_window_from(&mut self, pos: u32) -> Result<(), IobufError> { self.raw.sub_window_from(pos) }
 
   #[inline(always)]
-  fn sub_window_to(&mut self, len: u32) -> Result<(), ()> { self.raw.sub_window_to(len) }
+  fn sub_window_to(&mut self, len: u32) -> Result<(), IobufError> { self.raw.sub_window_to(len) }
 
   #[inline(always)]
   unsafe fn unsafe_sub_window(&mut self, pos: u32, len: u32) { self.raw.unsafe_sub_window(pos, len) }
@@ -1447,13 +2143,13 @@ impl<'a> Iobuf for RWIobuf<'a> {
   unsafe fn unsafe_sub_window_to(&mut self, len: u32) { self.raw.unsafe_sub_window_to(len) }
 
   #[inline(always)]
-  fn sub(&mut self, pos: u32, len: u32) -> Result<(), ()> { self.raw.sub(pos, len) }
+  fn sub(&mut self, pos: u32, len: u32) -> Result<(), IobufError> { self.raw.sub(pos, len) }
 
   #[inline(always)]
-  fn sub_from(&mut self, pos: u32) -> Result<(), ()> { self.raw.sub_from(pos) }
+  fn sub_from(&mut self, pos: u32) -> Result<(), IobufError> { self.raw.sub_from(pos) }
 
   #[inline(always)]
-  fn sub_to(&mut self, len: u32) -> Result<(), ()> { self.raw.sub_to(len) }
+  fn sub_to(&mut self, len: u32) -> Result<(), IobufError> { self.raw.sub_to(len) }
 
   #[inline(always)]
   unsafe fn unsafe_sub(&mut self, pos: u32, len: u32) { self.raw.unsafe_sub(pos, len) }
@@ -1465,19 +2161,19 @@ impl<'a> Iobuf for RWIobuf<'a> {
   unsafe fn unsafe_sub_to(&mut self, len: u32) { self.raw.unsafe_sub_to(len) }
 
   #[inline(always)]
-  fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), ()> { self.raw.set_limits_and_window(limits, window) }
+  fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), IobufError> { self.raw.set_limits_and_window(limits, window) }
 
   #[inline(always)]
   fn narrow(&mut self) { self.raw.narrow() }
 
   #[inline(always)]
-  fn advance(&mut self, len: u32) -> Result<(), ()> { self.raw.advance(len) }
+  fn advance(&mut self, len: u32) -> Result<(), IobufError> { self.raw.advance(len) }
 
   #[inline(always)]
   unsafe fn unsafe_advance(&mut self, len: u32) { self.raw.unsafe_advance(len) }
 
   #[inline(always)]
-  fn extend(&mut self, len: u32) -> Result<(), ()> { self.raw.extend(len) }
+  fn extend(&mut self, len: u32) -> Result<(), IobufError> { self.raw.extend(len) }
 
   #[inline(always)]
   unsafe fn unsafe_extend(&mut self, len: u32) { self.raw.unsafe_extend(len) }
@@ -1486,16 +2182,16 @@ impl<'a> Iobuf for RWIobuf<'a> {
   fn is_extended_by<Buf: Iobuf>(&self, other: &Buf) -> bool { unsafe { self.raw.is_extended_by(other.as_raw()) } }
 
   #[inline(always)]
-  fn extend_with<Buf: Iobuf>(&mut self, other: &Buf) -> Result<(), ()> { unsafe { self.raw.extend_with(other.as_raw()) } }
+  fn extend_with<Buf: Iobuf>(&mut self, other: &Buf) -> Result<(), IobufError> { unsafe { self.raw.extend_with(other.as_raw()) } }
 
   #[inline(always)]
-  fn resize(&mut self, len: u32) -> Result<(), ()> { self.raw.resize(len) }
+  fn resize(&mut self, len: u32) -> Result<(), IobufError> { self.raw.resize(len) }
 
   #[inline(always)]
   unsafe fn unsafe_resize(&mut self, len: u32) { self.raw.unsafe_resize(len) }
 
   #[inline(always)]
-  fn split_at(&self, pos: u32) -> Result<(Self, Self), ()> {
+  fn split_at(&self, pos: u32) -> Result<(Self, Self), IobufError> {
     self.raw.split_at_nonatomic(pos).map(|(a, b)| (RWIobuf { raw: a }, RWIobuf { raw: b }))
   }
 
@@ -1506,7 +2202,7 @@ impl<'a> Iobuf for RWIobuf<'a> {
   }
 
   #[inline(always)]
-  fn split_start_at(&mut self, pos: u32) -> Result<Self, ()> {
+  fn split_start_at(&mut self, pos: u32) -> Result<Self, IobufError> {
     self.raw.split_start_at_nonatomic(pos).map(|b| RWIobuf { raw: b })
   }
 
@@ -1534,24 +2230,24 @@ impl<'a> Iobuf for RWIobuf<'a> {
   fn hi_space(&self) -> u32 { self.raw.hi_space() }
 
   #[inline(always)]
-  fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), ()> { self.raw.peek(pos, dst) }
+  fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.peek(pos, dst) }
   #[inline(always)]
-  fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, ()> { self.raw.peek_be(pos) }
+  fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, IobufError> { self.raw.peek_be(pos) }
   #[inline(always)]
-  fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, ()> { self.raw.peek_le(pos) }
+  fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, IobufError> { self.raw.peek_le(pos) }
 
   #[inline(always)]
-  fn consume(&mut self, dst: &mut [u8]) -> Result<(), ()> { self.raw.consume(dst) }
+  fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.consume(dst) }
   #[inline(always)]
-  fn consume_be<T: IntLike>(&mut self) -> Result<T, ()> { self.raw.consume_be::<T>() }
+  fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_be::<T>() }
   #[inline(always)]
-  fn consume_le<T: IntLike>(&mut self) -> Result<T, ()> { self.raw.consume_le::<T>() }
+  fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_le::<T>() }
 
   #[inline(always)]
-  fn check_range(&self, pos: u32, len: u32) -> Result<(), ()> { self.raw.check_range_u32(pos, len) }
+  fn check_range(&self, pos: u32, len: u32) -> Result<(), IobufError> { self.raw.check_range_u32(pos, len) }
 
   #[inline(always)]
-  fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), ()> { self.raw.check_range_usize(pos, len) }
+  fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), IobufError> { self.raw.check_range_usize(pos, len) }
 
   #[inline(always)]
   fn check_range_fail(&self, pos: u32, len: u32) { self.raw.check_range_u32_fail(pos, len) }
@@ -1580,7 +2276,7 @@ impl<'a> Iobuf for RWIobuf<'a> {
   fn invariant(&self) -> Result<(), Box<String>> { self.raw.invariant() }
 
   #[inline(always)]
-  fn ptr(&self) -> NonZero<*mut u8> { self.raw.ptr() }
+  fn ptr(&self) -> NonNull<u8> { self.raw.ptr() }
   #[inline(always)]
   fn is_owned(&self) -> bool { self.raw.is_owned() }
   #[inline(always)]
@@ -1663,7 +2359,7 @@ mod test {
           }
         }
 
-        box v.into_iter()
+        Box::new(v.into_iter())
       }
     }
 
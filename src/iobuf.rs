@@ -1,8 +1,9 @@
-use core::nonzero::NonZero;
+use std::ptr::NonNull;
 
 use std::fmt::Debug;
 use std::sync::Arc;
 
+use error::IobufError;
 use raw::{Allocator, RawIobuf};
 use impls::{AROIobuf, RWIobuf, UniqueIobuf};
 use intlike::IntLike;
@@ -13,7 +14,8 @@ use intlike::IntLike;
 /// contents. This allows them to be used with both `ROIobuf`s and `RWIobuf`s.
 ///
 /// `peek` accesses a value at a position relative to the start of the
-/// window without advancing, and is meant to be used with `try!`. Its dual,
+/// window without advancing, and is meant to be used with `?`. Failures
+/// are reported as an `IobufError`, which says why and where. Its dual,
 /// `poke`, is only implemented for `RWIobuf`, since it needs to write into the
 /// buffer.
 ///
@@ -47,7 +49,52 @@ pub trait Iobuf: Clone + Debug {
   /// Copies the data byte-by-byte in the Iobuf into a new, writable Iobuf.
   /// The new Iobuf will have storage allocated out of `allocator`, and will not
   /// share the buffer with the original Iobuf.
-  fn deep_clone_with_allocator(&self, allocator: Arc<Box<Allocator>>) -> RWIobuf<'static>;
+  fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> RWIobuf<'static>;
+
+  /// Copies the data byte-by-byte in the Iobuf into a new, writable Iobuf,
+  /// whose buffer is aligned to `align` bytes. Panics if `align` is not a
+  /// power of two.
+  ///
+  /// ```rust
+  /// use iobuf::{ROIobuf,Iobuf};
+  ///
+  /// let b = ROIobuf::from_str("hello");
+  /// let c = b.deep_clone_aligned(64);
+  ///
+  /// assert_eq!(c.ptr().as_ptr() as usize % 64, 0);
+  /// unsafe { assert_eq!(c.as_window_slice(), b"hello"); }
+  /// ```
+  fn deep_clone_aligned(&self, align: usize) -> RWIobuf<'static>;
+
+  /// The same as `deep_clone`, but the copy keeps this buffer's `tag`, which
+  /// every other deep clone starts over at `0`.
+  ///
+  /// ```rust
+  /// use iobuf::{ROIobuf,Iobuf};
+  ///
+  /// let b = ROIobuf::from_str_copy("hello");
+  /// b.set_tag(7).unwrap();
+  ///
+  /// assert_eq!(b.deep_clone().tag(), 0);
+  /// assert_eq!(b.deep_clone_with_tag().tag(), 7);
+  /// ```
+  fn deep_clone_with_tag(&self) -> RWIobuf<'static>;
+
+  /// The same as `deep_clone`, but returns an error instead of panicking if
+  /// the memory for the copy can't be allocated.
+  ///
+  /// ```rust
+  /// use iobuf::{ROIobuf,Iobuf};
+  ///
+  /// let b = ROIobuf::from_str("hello");
+  /// let c = b.try_deep_clone().unwrap();
+  /// unsafe { assert_eq!(c.as_window_slice(), b"hello"); }
+  /// ```
+  fn try_deep_clone(&self) -> Result<RWIobuf<'static>, IobufError>;
+
+  /// The same as `deep_clone_with_allocator`, but returns an error instead of
+  /// panicking if `allocator` fails.
+  fn try_deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> Result<RWIobuf<'static>, IobufError>;
 
   /// Returns `Ok` if the Iobuf is the last to reference the underlying data,
   /// and converts it to a `UniqueIobuf` for sending to another task. This can
@@ -59,7 +106,9 @@ pub trait Iobuf: Clone + Debug {
   /// `UniqueIobuf`.
   ///
   /// Returns `Err` if the buffer is not the last to reference the underlying
-  /// data. If this case is hit, the buffer passed by value is returned by value.
+  /// data, or if it's an `RWIobuf` whose window holds never-written bytes of a
+  /// `new_uninit` buffer. If this case is hit, the buffer passed by value is
+  /// returned by value.
   ///
   /// ```rust
   /// use iobuf::{RWIobuf,Iobuf};
@@ -82,7 +131,9 @@ pub trait Iobuf: Clone + Debug {
   /// are created and written in one thread, and consumed in another.
   ///
   /// Returns `Err` if the buffer is not the last to reference the underlying
-  /// data. If this case is hit, the buffer passed by value is returned by value.
+  /// data, or if it's an `RWIobuf` whose window holds never-written bytes of a
+  /// `new_uninit` buffer. If this case is hit, the buffer passed by value is
+  /// returned by value.
   ///
   /// ```rust
   /// use iobuf::{RWIobuf,Iobuf};
@@ -99,6 +150,115 @@ pub trait Iobuf: Clone + Debug {
   /// ```
   fn atomic_read_only(self) -> Result<AROIobuf, Self>;
 
+  /// Turns the Iobuf into a writable one, without copying if it's the last
+  /// reference to its buffer. Otherwise, the limits are copied into a new
+  /// buffer, and the window keeps its place inside them. Either way, the
+  /// buffer's `tag` is kept.
+  ///
+  /// ```rust
+  /// use iobuf::{ROIobuf,RWIobuf,Iobuf};
+  ///
+  /// let a = RWIobuf::from_str_copy("hello, world");
+  /// let mut b = a.read_only();
+  /// assert_eq!(b.advance(7), Ok(()));
+  ///
+  /// // Shared, so `b` gets its own copy.
+  /// let c = b.make_mut();
+  /// assert!(!c.shares_buffer_with(&a));
+  /// assert_eq!((c.lo(), c.hi()), (7, 12));
+  /// assert_eq!(c.poke(0, b"W"), Ok(()));
+  /// unsafe { assert_eq!(a.as_window_slice(), b"hello, world"); }
+  ///
+  /// // Unique, so `a`'s buffer is reused.
+  /// let p = a.ptr();
+  /// let d: ROIobuf = a.read_only();
+  /// drop(a);
+  /// assert_eq!(d.make_mut().ptr(), p);
+  /// ```
+  fn make_mut(self) -> RWIobuf<'static>;
+
+  /// Returns the number of Iobufs sharing this one's buffer, including this
+  /// one. Iobufs over borrowed memory aren't refcounted, and return `0`.
+  ///
+  /// ```rust
+  /// use iobuf::{ROIobuf,Iobuf};
+  ///
+  /// let a = ROIobuf::from_str_copy("hello");
+  /// let b = a.clone();
+  /// assert_eq!(a.ref_count(), 2);
+  /// drop(b);
+  /// assert_eq!(a.ref_count(), 1);
+  ///
+  /// assert_eq!(ROIobuf::from_str("hello").ref_count(), 0);
+  /// ```
+  fn ref_count(&self) -> usize;
+
+  /// Returns `true` if this is the only Iobuf referencing its buffer, and it
+  /// has no `WeakIobuf`s either. This is exactly when `unique` succeeds, and
+  /// writing to the buffer can't be seen through any other Iobuf. Buffers
+  /// over read-only memory, like `map_file`'s, are never unique.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf,Iobuf};
+  ///
+  /// let a = RWIobuf::new(10);
+  /// assert!(a.is_unique());
+  ///
+  /// let b = a.clone();
+  /// assert!(!a.is_unique());
+  /// drop(b);
+  /// assert!(a.is_unique());
+  /// ```
+  fn is_unique(&self) -> bool;
+
+  /// Returns `true` if both Iobufs reference the same buffer, whatever their
+  /// windows and limits. Borrowed memory isn't refcounted, so it's never
+  /// considered shared.
+  ///
+  /// ```rust
+  /// use iobuf::{ROIobuf,RWIobuf,Iobuf};
+  ///
+  /// let a = RWIobuf::from_str_copy("hello, world");
+  /// let (b, c) = a.split_at(5).unwrap();
+  /// assert!(b.shares_buffer_with(&c));
+  /// assert!(a.read_only().shares_buffer_with(&b));
+  ///
+  /// assert!(!a.shares_buffer_with(&a.deep_clone()));
+  /// assert!(!ROIobuf::from_str("hi").shares_buffer_with(&ROIobuf::from_str("hi")));
+  /// ```
+  fn shares_buffer_with<Buf: Iobuf>(&self, other: &Buf) -> bool;
+
+  /// Returns the tag of the buffer: a word of user metadata, like a receive
+  /// timestamp or a trace id, that's kept in the allocation header. It's
+  /// shared by every Iobuf over the buffer, whatever their windows and limits,
+  /// so it follows clones, `split_at` and friends. Buffers start out with a
+  /// tag of `0`, and borrowed memory always has a tag of `0`.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf,Iobuf};
+  ///
+  /// let a = RWIobuf::from_str_copy("hello, world");
+  /// a.set_tag(0xC0FFEE).unwrap();
+  ///
+  /// let (b, c) = a.split_at(5).unwrap();
+  /// assert_eq!(b.tag(), 0xC0FFEE);
+  /// assert_eq!(c.read_only().tag(), 0xC0FFEE);
+  /// ```
+  fn tag(&self) -> u64;
+
+  /// Sets the tag of the buffer, as seen by every Iobuf sharing it. See
+  /// `tag`. Fails with `Unowned` for Iobufs over borrowed memory, which have
+  /// nowhere to keep it.
+  ///
+  /// ```rust
+  /// use iobuf::{ErrorKind,ROIobuf,Iobuf};
+  ///
+  /// let b = ROIobuf::from_str("hello");
+  /// assert_eq!(b.set_tag(1).unwrap_err().kind(), ErrorKind::Unowned);
+  /// assert_eq!(b.tag(), 0);
+  /// ```
+  fn set_tag(&self, tag: u64) -> Result<(), IobufError>;
+
   /// Returns the size of the window.
   ///
   /// ```rust
@@ -208,7 +368,7 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// let mut b = ROIobuf::from_str("hello");
   /// assert_eq!(b.advance(2), Ok(()));
-  /// assert_eq!(b.sub_window(0, 5), Err(())); // boom
+  /// assert!(b.sub_window(0, 5).is_err()); // boom
   /// ```
   ///
   /// If you want to slice from the start, use `sub_to`:
@@ -230,17 +390,17 @@ pub trait Iobuf: Clone + Debug {
   /// assert_eq!(b.sub_window_from(2), Ok(()));
   /// unsafe { assert_eq!(b.as_window_slice(), b"llo") };
   /// ```
-  fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), ()>;
+  fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), IobufError>;
 
   /// Changes the Iobuf's bounds to start at `pos`, and go to the end of the
   /// current window.
-  fn sub_window_from(&mut self, pos: u32) -> Result<(), ()>;
+  fn sub_window_from(&mut self, pos: u32) -> Result<(), IobufError>;
 
   /// Changes the Iobuf's bounds to extend for only `len` bytes.
   ///
   /// This is the same as `resize`, but might make more semantic sense at the
   /// call site depending on context.
-  fn sub_window_to(&mut self, len: u32) -> Result<(), ()>;
+  fn sub_window_to(&mut self, len: u32) -> Result<(), IobufError>;
 
   /// The same as `sub_window`, but no bounds checks are performed. You should
   /// probably just use `sub_window`.
@@ -278,7 +438,7 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// let mut b = ROIobuf::from_str("hello");
   /// assert_eq!(b.advance(2), Ok(()));
-  /// assert_eq!(b.sub(0, 5), Err(())); // boom
+  /// assert!(b.sub(0, 5).is_err()); // boom
   /// ```
   ///
   /// If you want to slice from the start, use `sub_to`:
@@ -300,15 +460,15 @@ pub trait Iobuf: Clone + Debug {
   /// assert_eq!(b.sub_from(2), Ok(()));
   /// unsafe { assert_eq!(b.as_window_slice(), b"llo") };
   /// ```
-  fn sub(&mut self, pos: u32, len: u32) -> Result<(), ()>;
+  fn sub(&mut self, pos: u32, len: u32) -> Result<(), IobufError>;
 
   /// Changes the Iobuf's limits and bounds to start from `pos` and extend to
   /// the end of the current window.
-  fn sub_from(&mut self, pos: u32) -> Result<(), ()>;
+  fn sub_from(&mut self, pos: u32) -> Result<(), IobufError>;
 
   /// Changes the Iobuf's limits and bounds to start at the beginning of the
   /// current window, and extend for `len` bytes.
-  fn sub_to(&mut self, len: u32) -> Result<(), ()>;
+  fn sub_to(&mut self, len: u32) -> Result<(), IobufError>;
 
   /// The same as `sub`, but no bounds checks are performed. You should probably
   /// just use `sub`.
@@ -322,8 +482,8 @@ pub trait Iobuf: Clone + Debug {
   /// probably just use `sub_to`.
   unsafe fn unsafe_sub_to(&mut self, len: u32);
 
-  /// Overrides the existing limits and window of the Iobuf, returning `Err(())`
-  /// if attempting to widen either of them.
+  /// Overrides the existing limits and window of the Iobuf, returning an
+  /// `InvalidLimits` error if attempting to widen either of them.
   ///
   /// ```rust
   /// use iobuf::{ROIobuf,Iobuf};
@@ -333,11 +493,11 @@ pub trait Iobuf: Clone + Debug {
   /// assert_eq!(b.cap(), 2);
   /// assert_eq!(b.len(), 1);
   /// // trying to shrink the limits...
-  /// assert_eq!(b.set_limits_and_window((1, 4), (2, 2)), Err(()));
+  /// assert!(b.set_limits_and_window((1, 4), (2, 2)).is_err());
   /// // trying to shrink the window...
-  /// assert_eq!(b.set_limits_and_window((1, 3), (2, 4)), Err(()));
+  /// assert!(b.set_limits_and_window((1, 3), (2, 4)).is_err());
   /// ```
-  fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), ()>;
+  fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), IobufError>;
 
   /// Sets the limits to the current window.
   ///
@@ -351,7 +511,7 @@ pub trait Iobuf: Clone + Debug {
   /// ```
   fn narrow(&mut self);
 
-  /// Advances the lower bound of the window by `len`. `Err(())` will be
+  /// Advances the lower bound of the window by `len`. An error will be
   /// returned if you advance past the upper bound of the window.
   ///
   /// ```rust
@@ -359,10 +519,10 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// let mut b = ROIobuf::from_str("hello");
   /// assert_eq!(b.advance(3), Ok(()));
-  /// assert_eq!(b.advance(3), Err(()));
+  /// assert!(b.advance(3).is_err());
   /// unsafe { assert_eq!(b.as_window_slice(), b"lo"); }
   /// ```
-  fn advance(&mut self, len: u32) -> Result<(), ()>;
+  fn advance(&mut self, len: u32) -> Result<(), IobufError>;
 
   /// Advances the lower bound of the window by `len`. No bounds checking will
   /// be performed.
@@ -374,18 +534,18 @@ pub trait Iobuf: Clone + Debug {
   /// ```rust
   /// use std::mem;
   /// use std::result::Result::{self,Ok};
-  /// use iobuf::{ROIobuf,Iobuf};
+  /// use iobuf::{ROIobuf,Iobuf,IobufError};
   ///
   /// let data = [2, 0x12, 0x34, 0x56, 0x78];
   /// let mut b = ROIobuf::from_slice(&data);
   ///
-  /// fn parse<B: Iobuf>(b: &mut B) -> Result<u16, ()> {
-  ///   let num_shorts: u8 = try!(b.consume_be());
+  /// fn parse<B: Iobuf>(b: &mut B) -> Result<u16, IobufError> {
+  ///   let num_shorts: u8 = b.consume_be()?;
   ///   let short_size = mem::size_of::<u16>() as u32;
   ///   let num_bytes = num_shorts as u32 * short_size;
   ///
   ///   unsafe {
-  ///     try!(b.check_range(0, num_bytes));
+  ///     b.check_range(0, num_bytes)?;
   ///
   ///     let mut sum = 0u16;
   ///
@@ -409,17 +569,17 @@ pub trait Iobuf: Clone + Debug {
   /// ```rust
   /// use std::mem;
   /// use std::result::Result::{self,Ok};
-  /// use iobuf::{ROIobuf,Iobuf};
+  /// use iobuf::{ROIobuf,Iobuf,IobufError};
   /// let data = [2, 0x12, 0x34, 0x56, 0x78];
   /// let mut b = ROIobuf::from_slice(&data);
   ///
-  /// fn parse<B: Iobuf>(b: &mut B) -> Result<u16, ()> {
-  ///   let num_shorts: u8 = try!(b.consume_be());
+  /// fn parse<B: Iobuf>(b: &mut B) -> Result<u16, IobufError> {
+  ///   let num_shorts: u8 = b.consume_be()?;
   ///   let short_size = mem::size_of::<u16>() as u32;
   ///   let num_bytes = num_shorts as u32 * short_size;
   ///
   ///   unsafe {
-  ///     try!(b.check_range(0, num_bytes));
+  ///     b.check_range(0, num_bytes)?;
   ///
   ///     let mut sum = 0u16;
   ///
@@ -436,8 +596,8 @@ pub trait Iobuf: Clone + Debug {
   /// ```
   unsafe fn unsafe_advance(&mut self, len: u32);
 
-  /// Advances the upper bound of the window by `len`. `Err(())` will be
-  /// returned if you advance past the upper limit.
+  /// Advances the upper bound of the window by `len`. An `OutOfRange` error
+  /// will be returned if you advance past the upper limit.
   ///
   /// ```rust
   /// use iobuf::{ROIobuf,Iobuf};
@@ -446,10 +606,10 @@ pub trait Iobuf: Clone + Debug {
   /// assert_eq!(b.resize(2), Ok(()));
   /// assert_eq!(b.extend(1), Ok(()));
   /// unsafe { assert_eq!(b.as_window_slice(), b"hel"); }
-  /// assert_eq!(b.extend(3), Err(()));
+  /// assert!(b.extend(3).is_err());
   /// unsafe { assert_eq!(b.as_window_slice(), b"hel"); }
   /// ```
-  fn extend(&mut self, len: u32) -> Result<(), ()>;
+  fn extend(&mut self, len: u32) -> Result<(), IobufError>;
 
   /// Advances the upper bound of the window by `len`. No bounds checking will
   /// be performed.
@@ -485,7 +645,8 @@ pub trait Iobuf: Clone + Debug {
 
   /// Attempts to extend an Iobuf with the contents of another Iobuf. If this
   /// Iobuf's window is not the region directly before the other Iobuf's window,
-  /// no extension will be performed and `Err(())` will be returned. If the
+  /// no extension will be performed and a `NotAdjacent` error will be
+  /// returned. If the
   /// operation was successful, `Ok(())` will be returned.
   ///
   /// ```rust
@@ -500,7 +661,7 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// // Different allocations => not an extension.
   /// assert_eq!(d.sub_window_from(2), Ok(()));
-  /// assert_eq!(a.extend_with(&d), Err(()));
+  /// assert!(a.extend_with(&d).is_err());
   ///
   /// // b actually IS an extension of a.
   /// assert_eq!(b.sub_window_from(2), Ok(()));
@@ -513,9 +674,9 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// // a == "he", b == "lo", it's missing the "l", therefore not an extension.
   /// assert_eq!(c.sub_window_from(3), Ok(()));
-  /// assert_eq!(b.extend_with(&a), Err(()));
+  /// assert!(b.extend_with(&a).is_err());
   /// ```
-  fn extend_with<Buf: Iobuf>(&mut self, other: &Buf) -> Result<(), ()>;
+  fn extend_with<Buf: Iobuf>(&mut self, other: &Buf) -> Result<(), IobufError>;
 
   /// Sets the length of the window, provided it does not exceed the limits.
   ///
@@ -526,11 +687,11 @@ pub trait Iobuf: Clone + Debug {
   /// assert_eq!(b.resize(3), Ok(()));
   /// assert_eq!(b.peek_be(2), Ok(b'l'));
   /// assert_eq!(unsafe { b.as_window_slice() }, b"hel");
-  /// assert_eq!(b.peek_be::<u8>(3), Err(()));
+  /// assert!(b.peek_be::<u8>(3).is_err());
   /// assert_eq!(b.advance(1), Ok(()));
-  /// assert_eq!(b.resize(5), Err(()));
+  /// assert!(b.resize(5).is_err());
   /// ```
-  fn resize(&mut self, len: u32) -> Result<(), ()>;
+  fn resize(&mut self, len: u32) -> Result<(), IobufError>;
 
   /// Sets the length of the window. No bounds checking will be performed.
   ///
@@ -557,7 +718,7 @@ pub trait Iobuf: Clone + Debug {
   /// let mut b = ROIobuf::from_str("helloworld");
   ///
   /// match b.split_at(5) {
-  ///   Err(())    => panic!("This won't happen."),
+  ///   Err(_)     => panic!("This won't happen."),
   ///   Ok((c, d)) => unsafe {
   ///     assert_eq!(c.as_window_slice(), b"hello");
   ///     assert_eq!(d.as_window_slice(), b"world");
@@ -565,7 +726,7 @@ pub trait Iobuf: Clone + Debug {
   /// }
   ///
   /// match b.split_at(0) {
-  ///   Err(())    => panic!("This won't happen, either."),
+  ///   Err(_)     => panic!("This won't happen, either."),
   ///   Ok((c, d)) => unsafe {
   ///     assert_eq!(c.as_window_slice(), b"");
   ///     assert_eq!(d.as_window_slice(), b"helloworld");
@@ -574,10 +735,10 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// match b.split_at(10000) {
   ///   Ok(_)   => panic!("This won't happen!"),
-  ///   Err(()) => unsafe { assert_eq!(b.as_window_slice(), b"helloworld"); },
+  ///   Err(_)  => unsafe { assert_eq!(b.as_window_slice(), b"helloworld"); },
   /// }
   /// ```
-  fn split_at(&self, pos: u32) -> Result<(Self, Self), ()>;
+  fn split_at(&self, pos: u32) -> Result<(Self, Self), IobufError>;
 
   /// Like `split_at`, but does not perform bounds checking.
   unsafe fn unsafe_split_at(&self, pos: u32) -> (Self, Self);
@@ -590,7 +751,7 @@ pub trait Iobuf: Clone + Debug {
   /// let mut b = ROIobuf::from_str("helloworld");
   ///
   /// match b.split_start_at(5) {
-  ///   Err(()) => panic!("This won't happen."),
+  ///   Err(_)  => panic!("This won't happen."),
   ///   Ok(c)   => unsafe {
   ///     assert_eq!(b.as_window_slice(), b"world");
   ///     assert_eq!(c.as_window_slice(), b"hello");
@@ -598,7 +759,7 @@ pub trait Iobuf: Clone + Debug {
   /// }
   ///
   /// match b.split_start_at(0) {
-  ///   Err(()) => panic!("This won't happen, either."),
+  ///   Err(_)  => panic!("This won't happen, either."),
   ///   Ok(c)   => unsafe {
   ///     assert_eq!(b.as_window_slice(), b"world");
   ///     assert_eq!(c.as_window_slice(), b"");
@@ -607,10 +768,10 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// match b.split_start_at(10000) {
   ///   Ok(_)   => panic!("This won't happen!"),
-  ///   Err(()) => unsafe { assert_eq!(b.as_window_slice(), b"world"); },
+  ///   Err(_)  => unsafe { assert_eq!(b.as_window_slice(), b"world"); },
   /// }
   /// ```
-  fn split_start_at(&mut self, pos: u32) -> Result<Self, ()>;
+  fn split_start_at(&mut self, pos: u32) -> Result<Self, IobufError>;
 
   /// Like `split_start_at`, but does not perform bounds checking.
   unsafe fn unsafe_split_start_at(&mut self, pos: u32) -> Self;
@@ -757,9 +918,9 @@ pub trait Iobuf: Clone + Debug {
   /// assert_eq!(tgt4.iter().map(|&x| x).fold(0,|a, b| a + b), 10);
   /// assert_eq!(b.peek(1, &mut tgt3), Ok(()));
   /// assert_eq!(tgt3.iter().map(|&x| x).fold(0, |a, b| a + b), 9);
-  /// assert_eq!(b.peek(1, &mut tgt4), Err(()));
+  /// assert!(b.peek(1, &mut tgt4).is_err());
   /// ```
-  fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), ()>;
+  fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), IobufError>;
 
   /// Reads a big-endian primitive at a given offset from the beginning of the
   /// window.
@@ -776,9 +937,9 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// assert_eq!(b.peek_be(0), Ok(0x0203u16));
   /// assert_eq!(b.peek_be(1), Ok(0x0304u16));
-  /// assert_eq!(b.peek_be::<u16>(2), Err(()));
+  /// assert!(b.peek_be::<u16>(2).is_err());
   /// ```
-  fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, ()>;
+  fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, IobufError>;
 
   /// Reads a little-endian primitive at a given offset from the beginning of
   /// the window.
@@ -795,9 +956,9 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// assert_eq!(b.peek_le(0), Ok(0x0302u16));
   /// assert_eq!(b.peek_le(1), Ok(0x0403u16));
-  /// assert_eq!(b.peek_le::<u16>(2), Err(()));
+  /// assert!(b.peek_le::<u16>(2).is_err());
   /// ```
-  fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, ()>;
+  fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, IobufError>;
 
   /// Reads bytes, starting from the front of the window, into the supplied
   /// buffer. Either the entire buffer is filled, or an error is returned
@@ -817,11 +978,11 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// assert_eq!(b.consume(&mut tgt3), Ok(()));
   /// assert_eq!(tgt3.iter().map(|&x| x).fold(0, |a, b| a + b), 6);
-  /// assert_eq!(b.consume(&mut tgt3), Err(()));
+  /// assert!(b.consume(&mut tgt3).is_err());
   /// assert_eq!(b.consume(&mut tgt1), Ok(()));
   /// assert_eq!(tgt1[0], 4);
   /// ```
-  fn consume(&mut self, dst: &mut [u8]) -> Result<(), ()>;
+  fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError>;
 
   /// Reads a big-endian primitive from the beginning of the window.
   ///
@@ -839,10 +1000,10 @@ pub trait Iobuf: Clone + Debug {
   /// assert_eq!(b.advance(1), Ok(()));
   ///
   /// assert_eq!(b.consume_be(), Ok(0x0203u16));
-  /// assert_eq!(b.consume_be::<u16>(), Err(()));
+  /// assert!(b.consume_be::<u16>().is_err());
   /// assert_eq!(b.consume_be(), Ok(0x04u8));
   /// ```
-  fn consume_be<T: IntLike>(&mut self) -> Result<T, ()>;
+  fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError>;
 
   /// Reads a little-endian primitive from the beginning of the window.
   ///
@@ -860,13 +1021,16 @@ pub trait Iobuf: Clone + Debug {
   /// assert_eq!(b.advance(1), Ok(()));
   ///
   /// assert_eq!(b.consume_le(), Ok(0x0302u16));
-  /// assert_eq!(b.consume_le::<u16>(), Err(()));
+  /// assert!(b.consume_le::<u16>().is_err());
   /// assert_eq!(b.consume_le(), Ok(0x04u8));
   /// ```
-  fn consume_le<T: IntLike>(&mut self) -> Result<T, ()>;
+  fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError>;
 
-  /// Returns an `Err(())` if the `len` bytes, starting at `pos`, are not all
-  /// in the window. To be used with the `try!` macro.
+  /// Returns an error if the `len` bytes, starting at `pos`, are not all in the
+  /// window. To be used with the `?` operator.
+  ///
+  /// The error is `Incomplete` if `pos` is inside the window but the range runs
+  /// past its end, and `OutOfRange` if `pos` itself is past the end.
   ///
   /// Make sure you use this in conjunction with the `unsafe` combinators. It
   /// is recommended you minimize your bounds checks by doing it once with
@@ -878,22 +1042,22 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// ```rust
   /// use std::result::Result::{self,Ok};
-  /// use iobuf::{ROIobuf,Iobuf};
+  /// use iobuf::{ROIobuf,Iobuf,IobufError};
   ///
   /// // [ number of byte buffers, size of first byte buffer, ...bytes, etc. ]
   /// let data = [ 0x02, 0x02, 0x55, 0x66, 0x03, 0x11, 0x22, 0x33 ];
   /// let mut b = ROIobuf::from_slice(&data);
   ///
-  /// fn parse<B: Iobuf>(b: &mut B) -> Result<usize, ()> {
+  /// fn parse<B: Iobuf>(b: &mut B) -> Result<usize, IobufError> {
   ///   let mut sum = 0;
   ///
-  ///   let num_buffers: u8 = try!(b.consume_be());
+  ///   let num_buffers: u8 = b.consume_be()?;
   ///
   ///   for _ in 0..num_buffers {
-  ///     let len: u8 = try!(b.consume_be());
+  ///     let len: u8 = b.consume_be()?;
   ///
   ///     unsafe {
-  ///       try!(b.check_range(0, len as u32));
+  ///       b.check_range(0, len as u32)?;
   ///
   ///       for _ in 0..len {
   ///         sum += b.unsafe_consume_be::<u8>() as usize;
@@ -906,7 +1070,7 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// assert_eq!(parse(&mut b), Ok(0x55 + 0x66 + 0x11 + 0x22 + 0x33));
   /// ```
-  fn check_range(&self, pos: u32, len: u32) -> Result<(), ()>;
+  fn check_range(&self, pos: u32, len: u32) -> Result<(), IobufError>;
 
   /// The same as `check_range`, but with a `usize` length. If you're checking
   /// the range of something which might overflow an `i32`, use this version
@@ -917,11 +1081,11 @@ pub trait Iobuf: Clone + Debug {
   ///
   /// let mut b = ROIobuf::from_str("hello");
   ///
-  /// assert_eq!(b.check_range_usize(1u32, 5usize), Err(()));
+  /// assert!(b.check_range_usize(1u32, 5usize).is_err());
   /// ```
-  fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), ()>;
+  fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), IobufError>;
 
-  /// The same as `check_range`, but fails if the bounds check returns `Err(())`.
+  /// The same as `check_range`, but fails if the bounds check returns an error.
   ///
   /// ```should_fail
   /// use iobuf::{ROIobuf,Iobuf};
@@ -932,8 +1096,8 @@ pub trait Iobuf: Clone + Debug {
   /// ```
   fn check_range_fail(&self, pos: u32, len: u32);
 
-  /// The same as `check_range_usize`, but fails if the bounds check returns
-  /// `Err(())`.
+  /// The same as `check_range_usize`, but fails if the bounds check returns an
+  /// error.
   ///
   /// ```should_fail
   /// use iobuf::{ROIobuf,Iobuf};
@@ -1073,7 +1237,7 @@ pub trait Iobuf: Clone + Debug {
 
   /// Gets a pointer to the start of the internal backing buffer. This is
   /// extremely low level, and it is not recommended you use this interface.
-  fn ptr(&self) -> NonZero<*mut u8>;
+  fn ptr(&self) -> NonNull<u8>;
 
   /// Returns `true` if the Iobuf points to owned memory (i.e. has to do a
   /// refcount modification on `clone` or `drop`) or borrowed memory.
@@ -0,0 +1,2194 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+#[cfg(unix)]
+use std::path::Path;
+use std::ptr::{self, NonNull};
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use bufspan::BufSpan;
+use error::{ErrorKind, IobufError, iobuf_error};
+use impls::{ROIobuf, RWIobuf};
+use intlike::{IntLike, from_be, from_le, to_be, to_le};
+#[cfg(unix)]
+use mmap;
+use raw::{Allocator, AllocationHeader, ALLOCATION_HEADER_SIZE, MAX_BUFFER_LEN};
+use raw::{allocate_buffer, allocator_into_raw, header_of, memcpy, show_bytes, write_failed};
+
+/// The largest buffer a `LargeIobuf` can hold. We're bound by the largest
+/// allocation (and pointer offset) the platform supports, not by the width of
+/// the window fields.
+pub const MAX_LARGE_BUFFER_LEN: u64 = isize::MAX as u64 - ALLOCATION_HEADER_SIZE as u64;
+
+/// The bitmask to get the "is the buffer owned" bit.
+const LARGE_OWNED_MASK: u64 = 1 << 63;
+
+#[cold]
+fn bad_large_range(pos: u64, len: u64) -> ! {
+  panic!("LargeIobuf got invalid range: pos={}, len={}", pos, len)
+}
+
+#[cold]
+fn large_buffer_too_big(actual_size: u64) -> ! {
+  panic!("Tried to create a LargeIobuf that's too big: {} bytes. Max size = {}",
+         actual_size, MAX_LARGE_BUFFER_LEN)
+}
+
+/// The representation of all the `LargeIobuf`s. This is a `RawIobuf` with
+/// 64-bit offsets, and shares its allocation header and refcounting scheme.
+pub struct RawLargeIobuf<'a> {
+  buf:    NonNull<u8>,
+  // Same as `RawIobuf`: the highest bit says whether `buf` is preceded by an
+  // `AllocationHeader`.
+  lo_min_and_owned_bit: u64,
+  lo:     u64,
+  hi:     u64,
+  hi_max: u64,
+  lifetm: PhantomData<&'a ()>,
+}
+
+#[test]
+fn check_sane_raw_large_size() {
+  assert_eq!(mem::size_of::<RawLargeIobuf>(), mem::size_of::<*mut u8>() + 32);
+}
+
+impl<'a> RawLargeIobuf<'a> {
+  fn new_impl(
+      len:       u64,
+      allocator: Option<NonNull<()>>) -> RawLargeIobuf<'static> {
+    unsafe {
+      if len > MAX_LARGE_BUFFER_LEN {
+        large_buffer_too_big(len);
+      }
+
+      RawLargeIobuf {
+        buf:    allocate_buffer(len as usize, allocator),
+        lo_min_and_owned_bit: LARGE_OWNED_MASK,
+        lo:     0,
+        hi:     len,
+        hi_max: len,
+        lifetm: PhantomData,
+      }
+    }
+  }
+
+  #[inline]
+  pub fn new(len: u64) -> RawLargeIobuf<'static> {
+    RawLargeIobuf::new_impl(len, None)
+  }
+
+  #[inline]
+  pub fn new_with_allocator(len: u64, allocator: Arc<Box<dyn Allocator>>) -> RawLargeIobuf<'static> {
+    RawLargeIobuf::new_impl(len, Some(allocator_into_raw(allocator)))
+  }
+
+  #[inline]
+  pub fn empty() -> RawLargeIobuf<'static> {
+    RawLargeIobuf {
+      buf:    NonNull::dangling(),
+      lo_min_and_owned_bit: 0,
+      lo:     0,
+      hi:     0,
+      hi_max: 0,
+      lifetm: PhantomData,
+    }
+  }
+
+  #[inline]
+  pub fn from_slice(s: &'a [u8]) -> Self {
+    unsafe {
+      let len = s.len() as u64;
+
+      RawLargeIobuf {
+        buf:    NonNull::new_unchecked(s.as_ptr() as *mut u8),
+        lo_min_and_owned_bit: 0,
+        lo:     0,
+        hi:     len,
+        hi_max: len,
+        lifetm: PhantomData,
+      }
+    }
+  }
+
+  #[inline]
+  pub fn from_slice_copy(s: &[u8]) -> RawLargeIobuf<'static> {
+    unsafe {
+      let b = RawLargeIobuf::new(s.len() as u64);
+      memcpy(b.buf.as_ptr(), s.as_ptr(), s.len());
+      b
+    }
+  }
+
+  #[inline]
+  pub fn from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<dyn Allocator>>) -> RawLargeIobuf<'static> {
+    unsafe {
+      let b = RawLargeIobuf::new_with_allocator(s.len() as u64, allocator);
+      memcpy(b.buf.as_ptr(), s.as_ptr(), s.len());
+      b
+    }
+  }
+
+  #[inline]
+  pub fn deep_clone(&self) -> RawLargeIobuf<'static> {
+    unsafe {
+      let mut b = RawLargeIobuf::from_slice_copy(self.as_limit_slice());
+      let lo_min = self.lo_min();
+      b.lo = self.lo - lo_min;
+      b.hi = self.hi - lo_min;
+      b
+    }
+  }
+
+  #[inline]
+  pub fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> RawLargeIobuf<'static> {
+    unsafe {
+      let mut b = RawLargeIobuf::from_slice_copy_with_allocator(self.as_limit_slice(), allocator);
+      let lo_min = self.lo_min();
+      b.lo = self.lo - lo_min;
+      b.hi = self.hi - lo_min;
+      b
+    }
+  }
+
+  /// Builds an error of the given kind, recording the current limits and
+  /// window alongside the requested range.
+  #[cold]
+  pub fn error(&self, kind: ErrorKind, pos: u64, len: u64) -> IobufError {
+    iobuf_error(kind, pos, len, (self.lo_min(), self.hi_max), (self.lo, self.hi))
+  }
+
+  #[cold]
+  fn range_error(&self, pos: u64, len: u64) -> IobufError {
+    let kind =
+      if pos > self.len() { ErrorKind::OutOfRange }
+      else                { ErrorKind::Incomplete };
+    self.error(kind, pos, len)
+  }
+
+  #[cold]
+  fn inv_fail(&self, msg: &str) -> Box<String> {
+    Box::new(format!("{}: LargeIobuf {{ buf: {:?}, owned: {}, lo_min: {}, lo: {}, hi: {}, hi_max: {} }}",
+      msg,
+      self.buf.as_ptr(), self.is_owned(), self.lo_min(), self.lo, self.hi, self.hi_max))
+  }
+
+  #[inline]
+  fn inv_check(&self, is_valid: bool, msg: &str) -> Result<(), Box<String>> {
+    if is_valid { Ok(()) } else { Err(self.inv_fail(msg)) }
+  }
+
+  pub fn invariant(&self) -> Result<(), Box<String>> {
+    self.inv_check(self.lo_min() <= self.hi_max,
+      "The end of the limits must be after the start of the limits")?;
+
+    self.inv_check(self.lo <= self.hi,
+      "The end of the window must be after the start of the window")?;
+
+    self.inv_check(self.lo_min() <= self.lo,
+      "The start of the window must be after the start of the limits")?;
+
+    self.inv_check(self.hi <= self.hi_max,
+      "The end of the limits must be after the end of the window")?;
+
+    Ok(())
+  }
+
+  #[cfg(not(debug))]
+  #[inline(always)]
+  fn debug_check_invariants<T>(&self, t: T) -> T {
+    t
+  }
+
+  #[cfg(debug)]
+  fn debug_check_invariants<T>(&self, t: T) -> T {
+    match self.invariant() {
+      Ok(())   => t,
+      Err(msg) => panic!("{}", msg)
+    }
+  }
+
+  #[inline]
+  pub fn header(&self) -> Option<&mut AllocationHeader> {
+    unsafe {
+      if self.is_owned() { Some(header_of(self.buf)) } else { None }
+    }
+  }
+
+  #[inline]
+  pub unsafe fn clone_nonatomic(&self) -> Self {
+    if let Some(h) = self.header() { h.inc_ref_count_nonatomic() }
+    RawLargeIobuf { lifetm: PhantomData, ..*self }
+  }
+
+  #[inline]
+  pub unsafe fn clone_atomic(&self) -> Self {
+    if let Some(h) = self.header() { h.inc_ref_count_atomic() }
+    RawLargeIobuf { lifetm: PhantomData, ..*self }
+  }
+
+  #[inline]
+  pub unsafe fn drop_nonatomic(&mut self) {
+    let buf = self.buf;
+    if let Some(h) = self.header() {
+      h.drop_strong_nonatomic(buf);
+    }
+
+    // Reset the owned bit, to prevent double-frees when drop reform lands.
+    self.lo_min_and_owned_bit = 0;
+  }
+
+  #[inline]
+  pub unsafe fn drop_atomic(&mut self) {
+    let buf = self.buf;
+    if let Some(h) = self.header() {
+      h.drop_strong_atomic(buf);
+    }
+
+    // Reset the owned bit, to prevent double-frees when drop reform lands.
+    self.lo_min_and_owned_bit = 0;
+  }
+
+  #[inline]
+  pub unsafe fn ref_count_nonatomic(&self) -> usize {
+    self.header().map_or(0, |h| h.nonatomic_refcount())
+  }
+
+  #[inline]
+  pub unsafe fn ref_count_atomic(&self) -> usize {
+    self.header().map_or(0, |h| h.atomic_refcount().load(Ordering::SeqCst))
+  }
+
+  /// `true` if `other` references the same owned buffer.
+  #[inline]
+  pub fn shares_buffer_with<Buf: LargeIobuf>(&self, other: &Buf) -> bool {
+    self.is_owned() && other.is_owned() && self.buf == other.ptr()
+  }
+
+  #[inline]
+  pub unsafe fn is_unique_nonatomic(&self) -> bool {
+    self.header().is_some_and(|h| h.is_unique_nonatomic())
+  }
+
+  #[inline]
+  pub unsafe fn is_unique_atomic(&self) -> bool {
+    self.header().is_some_and(|h| h.is_unique_atomic())
+  }
+
+  #[inline(always)]
+  pub fn ptr(&self) -> NonNull<u8> { self.buf }
+
+  #[inline(always)]
+  pub fn is_owned(&self) -> bool { self.lo_min_and_owned_bit & LARGE_OWNED_MASK != 0 }
+
+  #[inline(always)]
+  pub fn lo_min(&self) -> u64 { self.lo_min_and_owned_bit & !LARGE_OWNED_MASK }
+
+  #[inline]
+  fn set_lo_min(&mut self, new_value: u64) {
+    self.lo_min_and_owned_bit &= LARGE_OWNED_MASK;
+    self.lo_min_and_owned_bit |= new_value;
+  }
+
+  #[inline(always)]
+  pub fn lo(&self) -> u64 { self.lo }
+
+  #[inline(always)]
+  pub fn hi(&self) -> u64 { self.hi }
+
+  #[inline(always)]
+  pub fn hi_max(&self) -> u64 { self.hi_max }
+
+  #[inline(always)]
+  pub fn len(&self) -> u64 { self.hi - self.lo }
+
+  #[inline(always)]
+  pub fn cap(&self) -> u64 { self.hi_max - self.lo_min() }
+
+  #[inline(always)]
+  pub fn is_empty(&self) -> bool { self.hi == self.lo }
+
+  #[inline]
+  pub unsafe fn as_limit_slice<'b>(&'b self) -> &'b [u8] {
+    slice::from_raw_parts(self.buf.as_ptr().offset(self.lo_min() as isize), self.cap() as usize)
+  }
+
+  #[inline]
+  pub unsafe fn as_window_slice<'b>(&'b self) -> &'b [u8] {
+    slice::from_raw_parts(self.buf.as_ptr().offset(self.lo as isize), self.len() as usize)
+  }
+
+  #[inline]
+  pub unsafe fn as_mut_limit_slice<'b>(&'b self) -> &'b mut [u8] {
+    slice::from_raw_parts_mut(self.buf.as_ptr().offset(self.lo_min() as isize), self.cap() as usize)
+  }
+
+  #[inline]
+  pub unsafe fn as_mut_window_slice<'b>(&'b self) -> &'b mut [u8] {
+    slice::from_raw_parts_mut(self.buf.as_ptr().offset(self.lo as isize), self.len() as usize)
+  }
+
+  #[inline]
+  pub fn check_range(&self, pos: u64, len: u64) -> Result<(), IobufError> {
+    match pos.checked_add(len) {
+      Some(end) if end <= self.len() => Ok(()),
+      _ => Err(self.range_error(pos, len)),
+    }
+  }
+
+  #[inline]
+  pub fn check_range_fail(&self, pos: u64, len: u64) {
+    if self.check_range(pos, len).is_err() {
+      bad_large_range(pos, len);
+    }
+  }
+
+  #[inline]
+  fn debug_check_range(&self, pos: u64, len: u64) {
+    if cfg!(debug) {
+      self.check_range_fail(pos, len);
+    }
+  }
+
+  #[inline]
+  pub fn sub_window(&mut self, pos: u64, len: u64) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(pos, len)?;
+      Ok(self.unsafe_sub_window(pos, len))
+    }
+  }
+
+  #[inline]
+  pub fn sub_window_from(&mut self, pos: u64) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(pos, 0)?;
+      Ok(self.unsafe_sub_window_from(pos))
+    }
+  }
+
+  #[inline]
+  pub fn sub_window_to(&mut self, len: u64) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(0, len)?;
+      Ok(self.unsafe_sub_window_to(len))
+    }
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_sub_window(&mut self, pos: u64, len: u64) {
+    self.debug_check_range(pos, len);
+    self.unsafe_sub_window_from(pos);
+    self.unsafe_sub_window_to(len);
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_sub_window_from(&mut self, pos: u64) {
+    self.debug_check_range(pos, 0);
+    self.lo += pos;
+    self.debug_check_invariants(())
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_sub_window_to(&mut self, len: u64) {
+    self.debug_check_range(0, len);
+    self.hi = self.lo + len;
+    self.debug_check_invariants(())
+  }
+
+  #[inline]
+  pub fn sub(&mut self, pos: u64, len: u64) -> Result<(), IobufError> {
+    self.sub_window(pos, len)?;
+    Ok(self.narrow())
+  }
+
+  #[inline]
+  pub fn sub_from(&mut self, pos: u64) -> Result<(), IobufError> {
+    self.sub_window_from(pos)?;
+    Ok(self.narrow())
+  }
+
+  #[inline]
+  pub fn sub_to(&mut self, len: u64) -> Result<(), IobufError> {
+    self.sub_window_to(len)?;
+    Ok(self.narrow())
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_sub(&mut self, pos: u64, len: u64) {
+    self.unsafe_sub_window(pos, len);
+    self.narrow();
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_sub_from(&mut self, pos: u64) {
+    self.unsafe_sub_window_from(pos);
+    self.narrow();
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_sub_to(&mut self, len: u64) {
+    self.unsafe_sub_window_to(len);
+    self.narrow();
+  }
+
+  /// Both the limits and the window are [lo, hi).
+  #[inline]
+  pub fn set_limits_and_window(&mut self, limits: (u64, u64), window: (u64, u64)) -> Result<(), IobufError> {
+    let (new_lo_min, new_hi_max) = limits;
+    let (new_lo, new_hi) = window;
+
+    if new_hi_max < new_lo_min
+    || new_hi     < new_lo
+    || new_lo_min < self.lo_min()
+    || new_hi_max > self.hi_max
+    || new_lo     < self.lo
+    || new_hi     > self.hi {
+      return Err(self.error(ErrorKind::InvalidLimits,
+                            new_lo_min, new_hi_max.saturating_sub(new_lo_min)));
+    }
+
+    self.set_lo_min(new_lo_min);
+    self.lo     = new_lo;
+    self.hi     = new_hi;
+    self.hi_max = new_hi_max;
+
+    Ok(self.debug_check_invariants(()))
+  }
+
+  #[inline]
+  pub fn narrow(&mut self) {
+    let lo = self.lo;
+    self.set_lo_min(lo);
+    self.hi_max = self.hi;
+  }
+
+  #[inline]
+  pub fn advance(&mut self, len: u64) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(0, len)?;
+      Ok(self.unsafe_advance(len))
+    }
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_advance(&mut self, len: u64) {
+    self.debug_check_range(0, len);
+    self.lo += len;
+    self.debug_check_invariants(())
+  }
+
+  #[inline]
+  pub fn extend(&mut self, len: u64) -> Result<(), IobufError> {
+    unsafe {
+      if len > self.hi_space() {
+        return Err(self.error(ErrorKind::OutOfRange, self.len(), len));
+      }
+
+      Ok(self.unsafe_extend(len))
+    }
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_extend(&mut self, len: u64) {
+    if cfg!(debug) && len > self.hi_space() {
+      bad_large_range(self.hi, len);
+    }
+    self.hi += len;
+    self.debug_check_invariants(())
+  }
+
+  #[inline]
+  pub fn resize(&mut self, len: u64) -> Result<(), IobufError> {
+    self.sub_window_to(len)
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_resize(&mut self, len: u64) {
+    self.unsafe_sub_window_to(len)
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_split_at_nonatomic(&self, pos: u64) -> (Self, Self) {
+    let mut ret = (self.clone_nonatomic(), self.clone_nonatomic());
+    ret.0.unsafe_resize(pos);
+    ret.1.unsafe_advance(pos);
+    ret
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_split_at_atomic(&self, pos: u64) -> (Self, Self) {
+    let mut ret = (self.clone_atomic(), self.clone_atomic());
+    ret.0.unsafe_resize(pos);
+    ret.1.unsafe_advance(pos);
+    ret
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_split_start_at_nonatomic(&mut self, pos: u64) -> Self {
+    let mut ret = self.clone_nonatomic();
+    ret.unsafe_resize(pos);
+    self.unsafe_advance(pos);
+    ret
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_split_start_at_atomic(&mut self, pos: u64) -> Self {
+    let mut ret = self.clone_atomic();
+    ret.unsafe_resize(pos);
+    self.unsafe_advance(pos);
+    ret
+  }
+
+  #[inline]
+  pub fn rewind(&mut self) {
+    self.lo = self.lo_min();
+  }
+
+  #[inline]
+  pub fn reset(&mut self) {
+    self.lo = self.lo_min();
+    self.hi = self.hi_max;
+  }
+
+  #[inline]
+  pub fn flip_lo(&mut self) {
+    self.hi = self.lo;
+    self.lo = self.lo_min();
+  }
+
+  #[inline]
+  pub fn flip_hi(&mut self) {
+    self.lo = self.hi;
+    self.hi = self.hi_max;
+  }
+
+  #[inline(always)]
+  pub fn lo_space(&self) -> u64 { self.lo - self.lo_min() }
+
+  #[inline(always)]
+  pub fn hi_space(&self) -> u64 { self.hi_max - self.hi }
+
+  #[inline]
+  pub fn peek(&self, pos: u64, dst: &mut [u8]) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(pos, dst.len() as u64)?;
+      Ok(self.unsafe_peek(pos, dst))
+    }
+  }
+
+  #[inline]
+  pub fn peek_be<T: IntLike>(&self, pos: u64) -> Result<T, IobufError> {
+    unsafe {
+      self.check_range(pos, mem::size_of::<T>() as u64)?;
+      Ok(self.unsafe_peek_be(pos))
+    }
+  }
+
+  #[inline]
+  pub fn peek_le<T: IntLike>(&self, pos: u64) -> Result<T, IobufError> {
+    unsafe {
+      self.check_range(pos, mem::size_of::<T>() as u64)?;
+      Ok(self.unsafe_peek_le(pos))
+    }
+  }
+
+  #[inline]
+  pub fn poke(&self, pos: u64, src: &[u8]) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(pos, src.len() as u64)?;
+      Ok(self.unsafe_poke(pos, src))
+    }
+  }
+
+  #[inline]
+  pub fn poke_be<T: IntLike>(&self, pos: u64, t: T) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(pos, mem::size_of::<T>() as u64)?;
+      Ok(self.unsafe_poke_be(pos, t))
+    }
+  }
+
+  #[inline]
+  pub fn poke_le<T: IntLike>(&self, pos: u64, t: T) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(pos, mem::size_of::<T>() as u64)?;
+      Ok(self.unsafe_poke_le(pos, t))
+    }
+  }
+
+  #[inline]
+  pub fn fill(&mut self, src: &[u8]) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(0, src.len() as u64)?;
+      Ok(self.unsafe_fill(src))
+    }
+  }
+
+  #[inline]
+  pub fn fill_be<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(0, mem::size_of::<T>() as u64)?;
+      Ok(self.unsafe_fill_be(t))
+    }
+  }
+
+  #[inline]
+  pub fn fill_le<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(0, mem::size_of::<T>() as u64)?;
+      Ok(self.unsafe_fill_le(t))
+    }
+  }
+
+  #[inline]
+  pub fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError> {
+    unsafe {
+      self.check_range(0, dst.len() as u64)?;
+      Ok(self.unsafe_consume(dst))
+    }
+  }
+
+  #[inline]
+  pub fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError> {
+    unsafe {
+      self.check_range(0, mem::size_of::<T>() as u64)?;
+      Ok(self.unsafe_consume_be())
+    }
+  }
+
+  #[inline]
+  pub fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError> {
+    unsafe {
+      self.check_range(0, mem::size_of::<T>() as u64)?;
+      Ok(self.unsafe_consume_le())
+    }
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_peek(&self, pos: u64, dst: &mut [u8]) {
+    let len = dst.len();
+    self.debug_check_range(pos, len as u64);
+    memcpy(dst.as_mut_ptr(), self.buf.as_ptr().offset((self.lo + pos) as isize), len);
+  }
+
+  #[inline]
+  unsafe fn unsafe_peek_raw<T: IntLike>(&self, pos: u64) -> T {
+    let len = mem::size_of::<T>();
+    self.debug_check_range(pos, len as u64);
+    ptr::read_unaligned(self.buf.as_ptr().offset((self.lo + pos) as isize) as *const T)
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_peek_be<T: IntLike>(&self, pos: u64) -> T {
+    from_be(self.unsafe_peek_raw(pos))
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_peek_le<T: IntLike>(&self, pos: u64) -> T {
+    from_le(self.unsafe_peek_raw(pos))
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_poke(&self, pos: u64, src: &[u8]) {
+    let len = src.len();
+    self.debug_check_range(pos, len as u64);
+    memcpy(self.buf.as_ptr().offset((self.lo + pos) as isize), src.as_ptr(), len);
+  }
+
+  #[inline]
+  unsafe fn unsafe_poke_raw<T: IntLike>(&self, pos: u64, t: T) {
+    let len = mem::size_of::<T>();
+    self.debug_check_range(pos, len as u64);
+    memcpy(self.buf.as_ptr().offset((self.lo + pos) as isize), &t as *const T as *const u8, len);
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_poke_be<T: IntLike>(&self, pos: u64, t: T) {
+    self.unsafe_poke_raw(pos, to_be(t))
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_poke_le<T: IntLike>(&self, pos: u64, t: T) {
+    self.unsafe_poke_raw(pos, to_le(t))
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_fill(&mut self, src: &[u8]) {
+    self.unsafe_poke(0, src);
+    self.unsafe_advance(src.len() as u64);
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_fill_be<T: IntLike>(&mut self, t: T) {
+    self.unsafe_poke_be(0, t);
+    self.unsafe_advance(mem::size_of::<T>() as u64);
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_fill_le<T: IntLike>(&mut self, t: T) {
+    self.unsafe_poke_le(0, t);
+    self.unsafe_advance(mem::size_of::<T>() as u64);
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_consume(&mut self, dst: &mut [u8]) {
+    self.unsafe_peek(0, dst);
+    self.unsafe_advance(dst.len() as u64);
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_consume_be<T: IntLike>(&mut self) -> T {
+    let ret = self.unsafe_peek_be(0);
+    self.unsafe_advance(mem::size_of::<T>() as u64);
+    ret
+  }
+
+  #[inline]
+  pub unsafe fn unsafe_consume_le<T: IntLike>(&mut self) -> T {
+    let ret = self.unsafe_peek_le(0);
+    self.unsafe_advance(mem::size_of::<T>() as u64);
+    ret
+  }
+
+  /// Checks that the window is small enough to be viewed through an ordinary
+  /// Iobuf.
+  #[inline]
+  pub fn check_fits_iobuf(&self) -> Result<(), IobufError> {
+    if self.len() > MAX_BUFFER_LEN as u64 {
+      Err(self.error(ErrorKind::TooLarge, 0, self.len()))
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Views the window as a `BufSpan` of ordinary Iobufs, each as large as
+  /// possible.
+  pub fn to_span<'b>(&'b self) -> BufSpan<ROIobuf<'b>> {
+    unsafe {
+      self.as_window_slice()
+          .chunks(MAX_BUFFER_LEN)
+          .map(ROIobuf::from_slice)
+          .collect()
+    }
+  }
+
+  #[cold]
+  pub fn show(&self, f: &mut Formatter, ty: &str) -> fmt::Result {
+    writeln!(f, "{} LargeIobuf, limits=[{},{}), bounds=[{},{})",
+                ty, self.lo_min(), self.hi_max, self.lo, self.hi)?;
+
+    if self.lo == self.hi { return write!(f, "<empty buffer>"); }
+
+    show_bytes(f, unsafe { self.as_window_slice() })
+  }
+}
+
+/// A `LargeIobuf` is an `Iobuf` with 64-bit offsets, for buffers that don't fit
+/// in the 2 GB an ordinary Iobuf can address.
+///
+/// The API mirrors `Iobuf`, with `u64` positions and lengths. Prefer ordinary
+/// Iobufs where possible: a `LargeIobuf` is 40 bytes instead of 24. To hand a
+/// piece of a large buffer to code expecting an `Iobuf`, narrow the window and
+/// use `as_iobuf`, or take the whole window with `to_span`.
+pub trait LargeIobuf: Clone + Debug {
+  /// Copies the data byte-by-byte in the LargeIobuf into a new, writeable
+  /// LargeIobuf. The new LargeIobuf and the old one will not share storage.
+  fn deep_clone(&self) -> LargeRWIobuf<'static>;
+
+  /// Copies the data byte-by-byte in the LargeIobuf into a new, writeable
+  /// LargeIobuf, whose storage is allocated out of `allocator`.
+  fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> LargeRWIobuf<'static>;
+
+  /// Returns `Ok` if the LargeIobuf is the last to reference the underlying
+  /// data, and converts it to a `LargeAROIobuf`, which can be shared across
+  /// threads. Otherwise, the LargeIobuf is handed back in `Err`.
+  fn atomic_read_only(self) -> Result<LargeAROIobuf, Self>;
+
+  /// Returns the size of the window.
+  fn len(&self) -> u64;
+
+  /// Returns the size of the limits.
+  fn cap(&self) -> u64;
+
+  /// `true` if `len() == 0`.
+  fn is_empty(&self) -> bool;
+
+  /// Reads the data in the window as a slice.
+  unsafe fn as_window_slice<'b>(&'b self) -> &'b [u8];
+
+  /// Reads the data in the limits as a slice.
+  unsafe fn as_limit_slice<'b>(&'b self) -> &'b [u8];
+
+  /// Changes the window to `[pos, pos + len)` relative to the current window.
+  fn sub_window(&mut self, pos: u64, len: u64) -> Result<(), IobufError>;
+
+  /// Changes the window to start `pos` bytes into the current window.
+  fn sub_window_from(&mut self, pos: u64) -> Result<(), IobufError>;
+
+  /// Changes the window to the first `len` bytes of the current window.
+  fn sub_window_to(&mut self, len: u64) -> Result<(), IobufError>;
+
+  /// `sub_window`, without bounds checks.
+  unsafe fn unsafe_sub_window(&mut self, pos: u64, len: u64);
+
+  /// `sub_window_from`, without bounds checks.
+  unsafe fn unsafe_sub_window_from(&mut self, pos: u64);
+
+  /// `sub_window_to`, without bounds checks.
+  unsafe fn unsafe_sub_window_to(&mut self, len: u64);
+
+  /// `sub_window`, then narrows the limits to the new window.
+  fn sub(&mut self, pos: u64, len: u64) -> Result<(), IobufError>;
+
+  /// `sub_window_from`, then narrows the limits to the new window.
+  fn sub_from(&mut self, pos: u64) -> Result<(), IobufError>;
+
+  /// `sub_window_to`, then narrows the limits to the new window.
+  fn sub_to(&mut self, len: u64) -> Result<(), IobufError>;
+
+  /// `sub`, without bounds checks.
+  unsafe fn unsafe_sub(&mut self, pos: u64, len: u64);
+
+  /// `sub_from`, without bounds checks.
+  unsafe fn unsafe_sub_from(&mut self, pos: u64);
+
+  /// `sub_to`, without bounds checks.
+  unsafe fn unsafe_sub_to(&mut self, len: u64);
+
+  /// Overrides the existing limits and window. Both are `[lo, hi)`, indexed
+  /// from the start of the buffer, and may only shrink.
+  fn set_limits_and_window(&mut self, limits: (u64, u64), window: (u64, u64)) -> Result<(), IobufError>;
+
+  /// Sets the limits to the current window.
+  fn narrow(&mut self);
+
+  /// Advances the lower bound of the window by `len`.
+  fn advance(&mut self, len: u64) -> Result<(), IobufError>;
+
+  /// `advance`, without bounds checks.
+  unsafe fn unsafe_advance(&mut self, len: u64);
+
+  /// Advances the upper bound of the window by `len`, up to the limits.
+  fn extend(&mut self, len: u64) -> Result<(), IobufError>;
+
+  /// `extend`, without bounds checks.
+  unsafe fn unsafe_extend(&mut self, len: u64);
+
+  /// Sets the length of the window, keeping its start.
+  fn resize(&mut self, len: u64) -> Result<(), IobufError>;
+
+  /// `resize`, without bounds checks.
+  unsafe fn unsafe_resize(&mut self, len: u64);
+
+  /// Splits the LargeIobuf into two at `pos`, relative to the window. The
+  /// buffer is shared, not copied.
+  fn split_at(&self, pos: u64) -> Result<(Self, Self), IobufError>;
+
+  /// `split_at`, without bounds checks.
+  unsafe fn unsafe_split_at(&self, pos: u64) -> (Self, Self);
+
+  /// Returns the first `pos` bytes of the window as a new LargeIobuf, and
+  /// advances this one past them.
+  fn split_start_at(&mut self, pos: u64) -> Result<Self, IobufError>;
+
+  /// `split_start_at`, without bounds checks.
+  unsafe fn unsafe_split_start_at(&mut self, pos: u64) -> Self;
+
+  /// Sets the lower bound of the window to the lower limit.
+  fn rewind(&mut self);
+
+  /// Sets the window to the limits.
+  fn reset(&mut self);
+
+  /// Sets the window to `[lo_min, lo)`.
+  fn flip_lo(&mut self);
+
+  /// Sets the window to `[hi, hi_max)`.
+  fn flip_hi(&mut self);
+
+  /// The number of bytes between the lower limit and the start of the window.
+  fn lo_space(&self) -> u64;
+
+  /// The number of bytes between the end of the window and the upper limit.
+  fn hi_space(&self) -> u64;
+
+  /// Reads `dst.len()` bytes, starting at `pos` in the window, into `dst`.
+  fn peek(&self, pos: u64, dst: &mut [u8]) -> Result<(), IobufError>;
+
+  /// Reads a big-endian integer at `pos` in the window.
+  fn peek_be<T: IntLike>(&self, pos: u64) -> Result<T, IobufError>;
+
+  /// Reads a little-endian integer at `pos` in the window.
+  fn peek_le<T: IntLike>(&self, pos: u64) -> Result<T, IobufError>;
+
+  /// Reads `dst.len()` bytes from the start of the window into `dst`, and
+  /// advances past them.
+  fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError>;
+
+  /// Reads a big-endian integer from the start of the window, and advances
+  /// past it.
+  fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError>;
+
+  /// Reads a little-endian integer from the start of the window, and advances
+  /// past it.
+  fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError>;
+
+  /// Returns `Ok` if `[pos, pos + len)` lies within the window.
+  fn check_range(&self, pos: u64, len: u64) -> Result<(), IobufError>;
+
+  /// `check_range`, but panics on failure.
+  fn check_range_fail(&self, pos: u64, len: u64);
+
+  /// `peek`, without bounds checks.
+  unsafe fn unsafe_peek(&self, pos: u64, dst: &mut [u8]);
+
+  /// `peek_be`, without bounds checks.
+  unsafe fn unsafe_peek_be<T: IntLike>(&self, pos: u64) -> T;
+
+  /// `peek_le`, without bounds checks.
+  unsafe fn unsafe_peek_le<T: IntLike>(&self, pos: u64) -> T;
+
+  /// `consume`, without bounds checks.
+  unsafe fn unsafe_consume(&mut self, dst: &mut [u8]);
+
+  /// `consume_be`, without bounds checks.
+  unsafe fn unsafe_consume_be<T: IntLike>(&mut self) -> T;
+
+  /// `consume_le`, without bounds checks.
+  unsafe fn unsafe_consume_le<T: IntLike>(&mut self) -> T;
+
+  /// Borrows the window as an ordinary Iobuf, whose limits are the window.
+  /// Fails with `ErrorKind::TooLarge` if the window is more than 2 GB, in which
+  /// case `sub_window` down to a smaller range first, or use `to_span`.
+  ///
+  /// ```rust
+  /// use iobuf::{Iobuf, LargeIobuf, LargeROIobuf};
+  ///
+  /// let mut b = LargeROIobuf::from_str_copy("hello, world");
+  /// assert_eq!(b.sub_window(7, 5), Ok(()));
+  ///
+  /// let small = b.as_iobuf().unwrap();
+  /// assert_eq!(small.len(), 5);
+  /// unsafe { assert_eq!(small.as_window_slice(), b"world"); }
+  /// ```
+  fn as_iobuf<'b>(&'b self) -> Result<ROIobuf<'b>, IobufError>;
+
+  /// Borrows the window as a span of ordinary Iobufs. Every Iobuf but the last
+  /// one is as large as an Iobuf can be.
+  ///
+  /// ```rust
+  /// use iobuf::{LargeIobuf, LargeROIobuf};
+  ///
+  /// let b = LargeROIobuf::from_str_copy("hello");
+  /// assert!(b.to_span().byte_equal_slice(b"hello"));
+  /// ```
+  fn to_span<'b>(&'b self) -> BufSpan<ROIobuf<'b>>;
+
+  /// Returns the number of LargeIobufs sharing this one's buffer, including
+  /// this one. LargeIobufs over borrowed memory aren't refcounted, and return
+  /// `0`.
+  ///
+  /// ```rust
+  /// use iobuf::{LargeIobuf, LargeROIobuf};
+  ///
+  /// let a = LargeROIobuf::from_str_copy("hello");
+  /// let b = a.clone();
+  /// assert_eq!(a.ref_count(), 2);
+  /// drop(b);
+  /// assert_eq!(a.ref_count(), 1);
+  ///
+  /// assert_eq!(LargeROIobuf::from_str("hello").ref_count(), 0);
+  /// ```
+  fn ref_count(&self) -> usize;
+
+  /// Returns `true` if this is the only LargeIobuf referencing its buffer.
+  /// This is exactly when `atomic_read_only` succeeds, or `read_write` for a
+  /// `LargeAROIobuf`.
+  ///
+  /// ```rust
+  /// use iobuf::{LargeIobuf, LargeRWIobuf};
+  ///
+  /// let a = LargeRWIobuf::new(10);
+  /// assert!(a.is_unique());
+  ///
+  /// let b = a.clone();
+  /// assert!(!a.is_unique());
+  /// drop(b);
+  /// assert!(a.is_unique());
+  /// ```
+  fn is_unique(&self) -> bool;
+
+  /// Returns `true` if both LargeIobufs reference the same buffer, whatever
+  /// their windows and limits. Borrowed memory isn't refcounted, so it's
+  /// never considered shared.
+  ///
+  /// ```rust
+  /// use iobuf::{LargeIobuf, LargeROIobuf};
+  ///
+  /// let a = LargeROIobuf::from_str_copy("hello, world");
+  /// let (b, c) = a.split_at(5).unwrap();
+  /// assert!(b.shares_buffer_with(&c));
+  ///
+  /// assert!(!a.shares_buffer_with(&a.deep_clone()));
+  /// assert!(!LargeROIobuf::from_str("hi").shares_buffer_with(&LargeROIobuf::from_str("hi")));
+  /// ```
+  fn shares_buffer_with<Buf: LargeIobuf>(&self, other: &Buf) -> bool;
+
+  /// Checks internal state of the LargeIobuf, to ensure that internal
+  /// invariants are satisified. Returns `Err(msg)` if any invariant isn't
+  /// satisfied.
+  fn invariant(&self) -> Result<(), Box<String>>;
+
+  /// Gets a pointer to the start of the internal backing buffer.
+  fn ptr(&self) -> NonNull<u8>;
+
+  /// Returns `true` if the LargeIobuf points to owned memory.
+  fn is_owned(&self) -> bool;
+
+  /// Returns an index into the buffer returned by `ptr` that represents the
+  /// inclusive lower bound of the limits.
+  fn lo_min(&self) -> u64;
+
+  /// Returns an index into the buffer returned by `ptr` that represents the
+  /// inclusive lower bound of the window.
+  fn lo(&self) -> u64;
+
+  /// Returns an index into the buffer returned by `ptr` that represents the
+  /// exclusive upper bound of the window.
+  fn hi(&self) -> u64;
+
+  /// Returns an index into the buffer returned by `ptr` that represents the
+  /// exclusive upper bound of the limits.
+  fn hi_max(&self) -> u64;
+}
+
+/// Read-Only LargeIobuf
+///
+/// The 64-bit counterpart of `ROIobuf`.
+pub struct LargeROIobuf<'a> {
+  raw: RawLargeIobuf<'a>,
+}
+
+/// Read-Write LargeIobuf
+///
+/// The 64-bit counterpart of `RWIobuf`.
+///
+/// ```rust
+/// use iobuf::{LargeIobuf, LargeRWIobuf};
+///
+/// let mut b = LargeRWIobuf::new(16);
+/// assert_eq!(b.fill_be(0xDEADBEEFu32), Ok(()));
+/// assert_eq!(b.fill_le(1u64), Ok(()));
+/// b.flip_lo();
+///
+/// assert_eq!(b.len(), 12);
+/// assert_eq!(b.consume_be::<u32>(), Ok(0xDEADBEEF));
+/// assert_eq!(b.consume_le::<u64>(), Ok(1));
+/// assert!(b.is_empty());
+/// ```
+pub struct LargeRWIobuf<'a> {
+  raw: RawLargeIobuf<'a>,
+}
+
+/// Atomic Read-Only LargeIobuf
+///
+/// The 64-bit counterpart of `AROIobuf`, which may be sent and shared across
+/// threads. Create one with `.atomic_read_only()`.
+pub struct LargeAROIobuf {
+  raw: RawLargeIobuf<'static>,
+}
+
+unsafe impl Send for LargeAROIobuf {}
+unsafe impl Sync for LargeAROIobuf {}
+
+#[test]
+fn check_sane_large_iobuf_size() {
+  assert_eq!(mem::size_of::<LargeROIobuf>(),  mem::size_of::<*mut u8>() + 32);
+  assert_eq!(mem::size_of::<LargeRWIobuf>(),  mem::size_of::<*mut u8>() + 32);
+  assert_eq!(mem::size_of::<LargeAROIobuf>(), mem::size_of::<*mut u8>() + 32);
+}
+
+impl<'a> Clone for LargeROIobuf<'a> {
+  #[inline(always)]
+  fn clone(&self) -> Self { LargeROIobuf { raw: unsafe { self.raw.clone_nonatomic() } } }
+}
+
+impl<'a> Clone for LargeRWIobuf<'a> {
+  #[inline(always)]
+  fn clone(&self) -> Self { LargeRWIobuf { raw: unsafe { self.raw.clone_nonatomic() } } }
+}
+
+impl Clone for LargeAROIobuf {
+  #[inline(always)]
+  fn clone(&self) -> Self { LargeAROIobuf { raw: unsafe { self.raw.clone_atomic() } } }
+}
+
+impl<'a> Drop for LargeROIobuf<'a> {
+  #[inline(always)]
+  fn drop(&mut self) { unsafe { self.raw.drop_nonatomic() } }
+}
+
+impl<'a> Drop for LargeRWIobuf<'a> {
+  #[inline(always)]
+  fn drop(&mut self) { unsafe { self.raw.drop_nonatomic() } }
+}
+
+impl Drop for LargeAROIobuf {
+  #[inline(always)]
+  fn drop(&mut self) { unsafe { self.raw.drop_atomic() } }
+}
+
+impl<'a> Debug for LargeROIobuf<'a> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result { self.raw.show(f, "read-only") }
+}
+
+impl<'a> Debug for LargeRWIobuf<'a> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result { self.raw.show(f, "read-write") }
+}
+
+impl Debug for LargeAROIobuf {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result { self.raw.show(f, "atomic read-only") }
+}
+
+impl<'a> io::Write for LargeRWIobuf<'a> {
+  #[inline(always)]
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self.fill(buf) {
+      Ok(())  => Ok(buf.len()),
+      Err(_)  => write_failed(),
+    }
+  }
+
+  #[inline(always)]
+  fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl<'a> LargeROIobuf<'a> {
+  /// Constructs a trivially empty LargeIobuf. This will not allocate.
+  #[inline(always)]
+  pub fn empty() -> LargeROIobuf<'static> {
+    LargeROIobuf { raw: RawLargeIobuf::empty() }
+  }
+
+  /// Constructs a LargeIobuf from a slice, without copying. The LargeIobuf
+  /// will not outlive the slice.
+  #[inline(always)]
+  pub fn from_slice(s: &'a [u8]) -> Self {
+    LargeROIobuf { raw: RawLargeIobuf::from_slice(s) }
+  }
+
+  /// Constructs a LargeIobuf from a `str`, without copying.
+  #[inline(always)]
+  pub fn from_str(s: &'a str) -> Self {
+    LargeROIobuf::from_slice(s.as_bytes())
+  }
+
+  /// Copies a slice into a new LargeIobuf.
+  #[inline(always)]
+  pub fn from_slice_copy(s: &[u8]) -> LargeROIobuf<'static> {
+    LargeROIobuf { raw: RawLargeIobuf::from_slice_copy(s) }
+  }
+
+  /// Copies a `str` into a new LargeIobuf.
+  #[inline(always)]
+  pub fn from_str_copy(s: &str) -> LargeROIobuf<'static> {
+    LargeROIobuf::from_slice_copy(s.as_bytes())
+  }
+
+  /// Copies a slice into a new LargeIobuf, whose memory comes from the given
+  /// allocator.
+  #[inline(always)]
+  pub fn from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<dyn Allocator>>) -> LargeROIobuf<'static> {
+    LargeROIobuf { raw: RawLargeIobuf::from_slice_copy_with_allocator(s, allocator) }
+  }
+
+  /// Memory-maps the file at `path` read-only, as the buffer of a new
+  /// LargeIobuf. Unlike `ROIobuf::map_file`, the file may be larger than
+  /// 2 GB. The limits and window cover the whole file, and nothing is copied.
+  ///
+  /// # Safety
+  ///
+  /// The same as `ROIobuf::map_file`: nothing may truncate or write to the
+  /// file until the last LargeIobuf referencing it is dropped.
+  #[cfg(unix)]
+  #[inline]
+  pub unsafe fn map_file<P: AsRef<Path>>(path: P) -> io::Result<LargeROIobuf<'static>> {
+    mmap::map_large_file(path.as_ref()).map(|raw| LargeROIobuf { raw: raw })
+  }
+}
+
+impl<'a> LargeRWIobuf<'a> {
+  /// Constructs a trivially empty LargeIobuf. This will not allocate.
+  #[inline(always)]
+  pub fn empty() -> LargeRWIobuf<'static> {
+    LargeRWIobuf { raw: RawLargeIobuf::empty() }
+  }
+
+  /// Constructs a new LargeIobuf with a buffer of size `len`, undefined
+  /// contents, and the limits and window set to the full size of the buffer.
+  ///
+  /// Unlike `RWIobuf::new`, `len` may exceed 2 GB.
+  #[inline(always)]
+  pub fn new(len: u64) -> LargeRWIobuf<'static> {
+    LargeRWIobuf { raw: RawLargeIobuf::new(len) }
+  }
+
+  /// Constructs a new LargeIobuf with a buffer of size `len`, allocated out of
+  /// the given allocator.
+  #[inline(always)]
+  pub fn new_with_allocator(len: u64, allocator: Arc<Box<dyn Allocator>>) -> LargeRWIobuf<'static> {
+    LargeRWIobuf { raw: RawLargeIobuf::new_with_allocator(len, allocator) }
+  }
+
+  /// Constructs a LargeIobuf from a mutable slice, without copying. The
+  /// LargeIobuf will not outlive the slice.
+  #[inline(always)]
+  pub fn from_slice(s: &'a mut [u8]) -> Self {
+    LargeRWIobuf { raw: RawLargeIobuf::from_slice(s) }
+  }
+
+  /// Copies a slice into a new, writeable LargeIobuf.
+  #[inline(always)]
+  pub fn from_slice_copy(s: &[u8]) -> LargeRWIobuf<'static> {
+    LargeRWIobuf { raw: RawLargeIobuf::from_slice_copy(s) }
+  }
+
+  /// Copies a `str` into a new, writeable LargeIobuf.
+  #[inline(always)]
+  pub fn from_str_copy(s: &str) -> LargeRWIobuf<'static> {
+    LargeRWIobuf::from_slice_copy(s.as_bytes())
+  }
+
+  /// Copies a slice into a new, writeable LargeIobuf, whose memory comes from
+  /// the given allocator.
+  #[inline(always)]
+  pub fn from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<dyn Allocator>>) -> LargeRWIobuf<'static> {
+    LargeRWIobuf { raw: RawLargeIobuf::from_slice_copy_with_allocator(s, allocator) }
+  }
+
+  /// Gets a read-only copy of this LargeIobuf. The buffer is shared.
+  #[inline(always)]
+  pub fn read_only(&self) -> LargeROIobuf<'a> {
+    LargeROIobuf { raw: unsafe { self.raw.clone_nonatomic() } }
+  }
+
+  /// Reads the data in the window as a mutable slice. See
+  /// `RWIobuf::as_mut_window_slice` for why this is unsafe.
+  #[inline(always)]
+  pub unsafe fn as_mut_window_slice<'b>(&'b self) -> &'b mut [u8] {
+    self.raw.as_mut_window_slice()
+  }
+
+  /// Reads the data in the limits as a mutable slice. See
+  /// `RWIobuf::as_mut_limit_slice` for why this is unsafe.
+  #[inline(always)]
+  pub unsafe fn as_mut_limit_slice<'b>(&'b self) -> &'b mut [u8] {
+    self.raw.as_mut_limit_slice()
+  }
+
+  /// Borrows the window as an ordinary, writeable Iobuf. Fails with
+  /// `ErrorKind::TooLarge` if the window is more than 2 GB.
+  ///
+  /// ```rust
+  /// use iobuf::{Iobuf, LargeIobuf, LargeRWIobuf};
+  ///
+  /// let b = LargeRWIobuf::from_str_copy("hello");
+  /// {
+  ///   let small = b.as_rw_iobuf().unwrap();
+  ///   assert_eq!(small.poke_be(0, b'j'), Ok(()));
+  /// }
+  /// unsafe { assert_eq!(b.as_window_slice(), b"jello"); }
+  /// ```
+  #[inline]
+  pub fn as_rw_iobuf<'b>(&'b self) -> Result<RWIobuf<'b>, IobufError> {
+    self.raw.check_fits_iobuf()?;
+    Ok(RWIobuf::from_slice(unsafe { self.raw.as_mut_window_slice() }))
+  }
+
+  /// Writes `src` at `pos` in the window.
+  #[inline(always)]
+  pub fn poke(&self, pos: u64, src: &[u8]) -> Result<(), IobufError> { self.raw.poke(pos, src) }
+
+  /// Writes a big-endian integer at `pos` in the window.
+  #[inline(always)]
+  pub fn poke_be<T: IntLike>(&self, pos: u64, t: T) -> Result<(), IobufError> { self.raw.poke_be(pos, t) }
+
+  /// Writes a little-endian integer at `pos` in the window.
+  #[inline(always)]
+  pub fn poke_le<T: IntLike>(&self, pos: u64, t: T) -> Result<(), IobufError> { self.raw.poke_le(pos, t) }
+
+  /// Writes `src` at the start of the window, and advances past it.
+  #[inline(always)]
+  pub fn fill(&mut self, src: &[u8]) -> Result<(), IobufError> { self.raw.fill(src) }
+
+  /// Writes a big-endian integer at the start of the window, and advances past
+  /// it.
+  #[inline(always)]
+  pub fn fill_be<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> { self.raw.fill_be(t) }
+
+  /// Writes a little-endian integer at the start of the window, and advances
+  /// past it.
+  #[inline(always)]
+  pub fn fill_le<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> { self.raw.fill_le(t) }
+
+  /// `poke`, without bounds checks.
+  #[inline(always)]
+  pub unsafe fn unsafe_poke(&self, pos: u64, src: &[u8]) { self.raw.unsafe_poke(pos, src) }
+
+  /// `poke_be`, without bounds checks.
+  #[inline(always)]
+  pub unsafe fn unsafe_poke_be<T: IntLike>(&self, pos: u64, t: T) { self.raw.unsafe_poke_be(pos, t) }
+
+  /// `poke_le`, without bounds checks.
+  #[inline(always)]
+  pub unsafe fn unsafe_poke_le<T: IntLike>(&self, pos: u64, t: T) { self.raw.unsafe_poke_le(pos, t) }
+
+  /// `fill`, without bounds checks.
+  #[inline(always)]
+  pub unsafe fn unsafe_fill(&mut self, src: &[u8]) { self.raw.unsafe_fill(src) }
+
+  /// `fill_be`, without bounds checks.
+  #[inline(always)]
+  pub unsafe fn unsafe_fill_be<T: IntLike>(&mut self, t: T) { self.raw.unsafe_fill_be(t) }
+
+  /// `fill_le`, without bounds checks.
+  #[inline(always)]
+  pub unsafe fn unsafe_fill_le<T: IntLike>(&mut self, t: T) { self.raw.unsafe_fill_le(t) }
+}
+
+impl LargeAROIobuf {
+  /// Stops atomically reference counting a unique buffer. This method returns
+  /// `Ok` if the `LargeAROIobuf` is the last of its kind, and `Err` if it's
+  /// not.
+  #[inline(always)]
+  pub fn read_only(self) -> Result<LargeROIobuf<'static>, LargeAROIobuf> {
+    unsafe {
+      if self.raw.is_unique_atomic() { Ok(mem::transmute(self)) } else { Err(self) }
+    }
+  }
+
+  /// Stops atomically reference counting a unique buffer. This method returns
+  /// `Ok` if the `LargeAROIobuf` is the last of its kind, and `Err` if it's
+  /// not.
+  #[inline(always)]
+  pub fn read_write(self) -> Result<LargeRWIobuf<'static>, LargeAROIobuf> {
+    unsafe {
+      if self.raw.is_unique_atomic() { Ok(mem::transmute(self)) } else { Err(self) }
+    }
+  }
+}
+
+impl<'a> LargeIobuf for LargeROIobuf<'a> {
+  #[inline(always)]
+  fn deep_clone(&self) -> LargeRWIobuf<'static> { LargeRWIobuf { raw: self.raw.deep_clone() } }
+
+  #[inline(always)]
+  fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> LargeRWIobuf<'static> {
+    LargeRWIobuf { raw: self.raw.deep_clone_with_allocator(allocator) }
+  }
+
+  #[inline(always)]
+  fn atomic_read_only(self) -> Result<LargeAROIobuf, Self> {
+    unsafe {
+      if self.raw.is_unique_nonatomic() { Ok(mem::transmute(self)) } else { Err(self) }
+    }
+  }
+
+  #[inline(always)]
+  fn len(&self) -> u64 { self.raw.len() }
+
+  #[inline(always)]
+  fn cap(&self) -> u64 { self.raw.cap() }
+
+  #[inline(always)]
+  fn is_empty(&self) -> bool { self.raw.is_empty() }
+
+  #[inline(always)]
+  unsafe fn as_window_slice<'b>(&'b self) -> &'b [u8] { self.raw.as_window_slice() }
+
+  #[inline(always)]
+  unsafe fn as_limit_slice<'b>(&'b self) -> &'b [u8] { self.raw.as_limit_slice() }
+
+  #[inline(always)]
+  fn sub_window(&mut self, pos: u64, len: u64) -> Result<(), IobufError> { self.raw.sub_window(pos, len) }
+
+  #[inline(always)]
+  fn sub_window_from(&mut self, pos: u64) -> Result<(), IobufError> { self.raw.sub_window_from(pos) }
+
+  #[inline(always)]
+  fn sub_window_to(&mut self, len: u64) -> Result<(), IobufError> { self.raw.sub_window_to(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_window(&mut self, pos: u64, len: u64) { self.raw.unsafe_sub_window(pos, len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_window_from(&mut self, pos: u64) { self.raw.unsafe_sub_window_from(pos) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_window_to(&mut self, len: u64) { self.raw.unsafe_sub_window_to(len) }
+
+  #[inline(always)]
+  fn sub(&mut self, pos: u64, len: u64) -> Result<(), IobufError> { self.raw.sub(pos, len) }
+
+  #[inline(always)]
+  fn sub_from(&mut self, pos: u64) -> Result<(), IobufError> { self.raw.sub_from(pos) }
+
+  #[inline(always)]
+  fn sub_to(&mut self, len: u64) -> Result<(), IobufError> { self.raw.sub_to(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub(&mut self, pos: u64, len: u64) { self.raw.unsafe_sub(pos, len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_from(&mut self, pos: u64) { self.raw.unsafe_sub_from(pos) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_to(&mut self, len: u64) { self.raw.unsafe_sub_to(len) }
+
+  #[inline(always)]
+  fn set_limits_and_window(&mut self, limits: (u64, u64), window: (u64, u64)) -> Result<(), IobufError> { self.raw.set_limits_and_window(limits, window) }
+
+  #[inline(always)]
+  fn narrow(&mut self) { self.raw.narrow() }
+
+  #[inline(always)]
+  fn advance(&mut self, len: u64) -> Result<(), IobufError> { self.raw.advance(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_advance(&mut self, len: u64) { self.raw.unsafe_advance(len) }
+
+  #[inline(always)]
+  fn extend(&mut self, len: u64) -> Result<(), IobufError> { self.raw.extend(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_extend(&mut self, len: u64) { self.raw.unsafe_extend(len) }
+
+  #[inline(always)]
+  fn resize(&mut self, len: u64) -> Result<(), IobufError> { self.raw.resize(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_resize(&mut self, len: u64) { self.raw.unsafe_resize(len) }
+
+  #[inline(always)]
+  fn split_at(&self, pos: u64) -> Result<(Self, Self), IobufError> {
+    unsafe {
+      self.raw.check_range(pos, 0)?;
+      Ok(self.unsafe_split_at(pos))
+    }
+  }
+
+  #[inline(always)]
+  unsafe fn unsafe_split_at(&self, pos: u64) -> (Self, Self) {
+    let (a, b) = self.raw.unsafe_split_at_nonatomic(pos);
+    (LargeROIobuf { raw: a }, LargeROIobuf { raw: b })
+  }
+
+  #[inline(always)]
+  fn split_start_at(&mut self, pos: u64) -> Result<Self, IobufError> {
+    unsafe {
+      self.raw.check_range(pos, 0)?;
+      Ok(self.unsafe_split_start_at(pos))
+    }
+  }
+
+  #[inline(always)]
+  unsafe fn unsafe_split_start_at(&mut self, pos: u64) -> Self {
+    LargeROIobuf { raw: self.raw.unsafe_split_start_at_nonatomic(pos) }
+  }
+
+  #[inline(always)]
+  fn rewind(&mut self) { self.raw.rewind() }
+
+  #[inline(always)]
+  fn reset(&mut self) { self.raw.reset() }
+
+  #[inline(always)]
+  fn flip_lo(&mut self) { self.raw.flip_lo() }
+
+  #[inline(always)]
+  fn flip_hi(&mut self) { self.raw.flip_hi() }
+
+  #[inline(always)]
+  fn lo_space(&self) -> u64 { self.raw.lo_space() }
+
+  #[inline(always)]
+  fn hi_space(&self) -> u64 { self.raw.hi_space() }
+
+  #[inline(always)]
+  fn peek(&self, pos: u64, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.peek(pos, dst) }
+  #[inline(always)]
+  fn peek_be<T: IntLike>(&self, pos: u64) -> Result<T, IobufError> { self.raw.peek_be(pos) }
+  #[inline(always)]
+  fn peek_le<T: IntLike>(&self, pos: u64) -> Result<T, IobufError> { self.raw.peek_le(pos) }
+
+  #[inline(always)]
+  fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.consume(dst) }
+  #[inline(always)]
+  fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_be::<T>() }
+  #[inline(always)]
+  fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_le::<T>() }
+
+  #[inline(always)]
+  fn check_range(&self, pos: u64, len: u64) -> Result<(), IobufError> { self.raw.check_range(pos, len) }
+
+  #[inline(always)]
+  fn check_range_fail(&self, pos: u64, len: u64) { self.raw.check_range_fail(pos, len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_peek(&self, pos: u64, dst: &mut [u8]) { self.raw.unsafe_peek(pos, dst) }
+  #[inline(always)]
+  unsafe fn unsafe_peek_be<T: IntLike>(&self, pos: u64) -> T { self.raw.unsafe_peek_be(pos) }
+  #[inline(always)]
+  unsafe fn unsafe_peek_le<T: IntLike>(&self, pos: u64) -> T { self.raw.unsafe_peek_le(pos) }
+
+  #[inline(always)]
+  unsafe fn unsafe_consume(&mut self, dst: &mut [u8]) { self.raw.unsafe_consume(dst) }
+  #[inline(always)]
+  unsafe fn unsafe_consume_be<T: IntLike>(&mut self) -> T { self.raw.unsafe_consume_be::<T>() }
+  #[inline(always)]
+  unsafe fn unsafe_consume_le<T: IntLike>(&mut self) -> T { self.raw.unsafe_consume_le::<T>() }
+
+  #[inline]
+  fn as_iobuf<'b>(&'b self) -> Result<ROIobuf<'b>, IobufError> {
+    self.raw.check_fits_iobuf()?;
+    Ok(ROIobuf::from_slice(unsafe { self.raw.as_window_slice() }))
+  }
+
+  #[inline(always)]
+  fn to_span<'b>(&'b self) -> BufSpan<ROIobuf<'b>> { self.raw.to_span() }
+
+  #[inline(always)]
+  fn ref_count(&self) -> usize { unsafe { self.raw.ref_count_nonatomic() } }
+
+  #[inline(always)]
+  fn is_unique(&self) -> bool { unsafe { self.raw.is_unique_nonatomic() } }
+
+  #[inline(always)]
+  fn shares_buffer_with<Buf: LargeIobuf>(&self, other: &Buf) -> bool { self.raw.shares_buffer_with(other) }
+
+  #[inline(always)]
+  fn invariant(&self) -> Result<(), Box<String>> { self.raw.invariant() }
+
+  #[inline(always)]
+  fn ptr(&self) -> NonNull<u8> { self.raw.ptr() }
+  #[inline(always)]
+  fn is_owned(&self) -> bool { self.raw.is_owned() }
+  #[inline(always)]
+  fn lo_min(&self) -> u64 { self.raw.lo_min() }
+  #[inline(always)]
+  fn lo(&self) -> u64 { self.raw.lo() }
+  #[inline(always)]
+  fn hi(&self) -> u64 { self.raw.hi() }
+  #[inline(always)]
+  fn hi_max(&self) -> u64 { self.raw.hi_max() }
+}
+
+impl<'a> LargeIobuf for LargeRWIobuf<'a> {
+  #[inline(always)]
+  fn deep_clone(&self) -> LargeRWIobuf<'static> { LargeRWIobuf { raw: self.raw.deep_clone() } }
+
+  #[inline(always)]
+  fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> LargeRWIobuf<'static> {
+    LargeRWIobuf { raw: self.raw.deep_clone_with_allocator(allocator) }
+  }
+
+  #[inline(always)]
+  fn atomic_read_only(self) -> Result<LargeAROIobuf, Self> {
+    unsafe {
+      if self.raw.is_unique_nonatomic() { Ok(mem::transmute(self)) } else { Err(self) }
+    }
+  }
+
+  #[inline(always)]
+  fn len(&self) -> u64 { self.raw.len() }
+
+  #[inline(always)]
+  fn cap(&self) -> u64 { self.raw.cap() }
+
+  #[inline(always)]
+  fn is_empty(&self) -> bool { self.raw.is_empty() }
+
+  #[inline(always)]
+  unsafe fn as_window_slice<'b>(&'b self) -> &'b [u8] { self.raw.as_window_slice() }
+
+  #[inline(always)]
+  unsafe fn as_limit_slice<'b>(&'b self) -> &'b [u8] { self.raw.as_limit_slice() }
+
+  #[inline(always)]
+  fn sub_window(&mut self, pos: u64, len: u64) -> Result<(), IobufError> { self.raw.sub_window(pos, len) }
+
+  #[inline(always)]
+  fn sub_window_from(&mut self, pos: u64) -> Result<(), IobufError> { self.raw.sub_window_from(pos) }
+
+  #[inline(always)]
+  fn sub_window_to(&mut self, len: u64) -> Result<(), IobufError> { self.raw.sub_window_to(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_window(&mut self, pos: u64, len: u64) { self.raw.unsafe_sub_window(pos, len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_window_from(&mut self, pos: u64) { self.raw.unsafe_sub_window_from(pos) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_window_to(&mut self, len: u64) { self.raw.unsafe_sub_window_to(len) }
+
+  #[inline(always)]
+  fn sub(&mut self, pos: u64, len: u64) -> Result<(), IobufError> { self.raw.sub(pos, len) }
+
+  #[inline(always)]
+  fn sub_from(&mut self, pos: u64) -> Result<(), IobufError> { self.raw.sub_from(pos) }
+
+  #[inline(always)]
+  fn sub_to(&mut self, len: u64) -> Result<(), IobufError> { self.raw.sub_to(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub(&mut self, pos: u64, len: u64) { self.raw.unsafe_sub(pos, len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_from(&mut self, pos: u64) { self.raw.unsafe_sub_from(pos) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_to(&mut self, len: u64) { self.raw.unsafe_sub_to(len) }
+
+  #[inline(always)]
+  fn set_limits_and_window(&mut self, limits: (u64, u64), window: (u64, u64)) -> Result<(), IobufError> { self.raw.set_limits_and_window(limits, window) }
+
+  #[inline(always)]
+  fn narrow(&mut self) { self.raw.narrow() }
+
+  #[inline(always)]
+  fn advance(&mut self, len: u64) -> Result<(), IobufError> { self.raw.advance(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_advance(&mut self, len: u64) { self.raw.unsafe_advance(len) }
+
+  #[inline(always)]
+  fn extend(&mut self, len: u64) -> Result<(), IobufError> { self.raw.extend(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_extend(&mut self, len: u64) { self.raw.unsafe_extend(len) }
+
+  #[inline(always)]
+  fn resize(&mut self, len: u64) -> Result<(), IobufError> { self.raw.resize(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_resize(&mut self, len: u64) { self.raw.unsafe_resize(len) }
+
+  #[inline(always)]
+  fn split_at(&self, pos: u64) -> Result<(Self, Self), IobufError> {
+    unsafe {
+      self.raw.check_range(pos, 0)?;
+      Ok(self.unsafe_split_at(pos))
+    }
+  }
+
+  #[inline(always)]
+  unsafe fn unsafe_split_at(&self, pos: u64) -> (Self, Self) {
+    let (a, b) = self.raw.unsafe_split_at_nonatomic(pos);
+    (LargeRWIobuf { raw: a }, LargeRWIobuf { raw: b })
+  }
+
+  #[inline(always)]
+  fn split_start_at(&mut self, pos: u64) -> Result<Self, IobufError> {
+    unsafe {
+      self.raw.check_range(pos, 0)?;
+      Ok(self.unsafe_split_start_at(pos))
+    }
+  }
+
+  #[inline(always)]
+  unsafe fn unsafe_split_start_at(&mut self, pos: u64) -> Self {
+    LargeRWIobuf { raw: self.raw.unsafe_split_start_at_nonatomic(pos) }
+  }
+
+  #[inline(always)]
+  fn rewind(&mut self) { self.raw.rewind() }
+
+  #[inline(always)]
+  fn reset(&mut self) { self.raw.reset() }
+
+  #[inline(always)]
+  fn flip_lo(&mut self) { self.raw.flip_lo() }
+
+  #[inline(always)]
+  fn flip_hi(&mut self) { self.raw.flip_hi() }
+
+  #[inline(always)]
+  fn lo_space(&self) -> u64 { self.raw.lo_space() }
+
+  #[inline(always)]
+  fn hi_space(&self) -> u64 { self.raw.hi_space() }
+
+  #[inline(always)]
+  fn peek(&self, pos: u64, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.peek(pos, dst) }
+  #[inline(always)]
+  fn peek_be<T: IntLike>(&self, pos: u64) -> Result<T, IobufError> { self.raw.peek_be(pos) }
+  #[inline(always)]
+  fn peek_le<T: IntLike>(&self, pos: u64) -> Result<T, IobufError> { self.raw.peek_le(pos) }
+
+  #[inline(always)]
+  fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.consume(dst) }
+  #[inline(always)]
+  fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_be::<T>() }
+  #[inline(always)]
+  fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_le::<T>() }
+
+  #[inline(always)]
+  fn check_range(&self, pos: u64, len: u64) -> Result<(), IobufError> { self.raw.check_range(pos, len) }
+
+  #[inline(always)]
+  fn check_range_fail(&self, pos: u64, len: u64) { self.raw.check_range_fail(pos, len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_peek(&self, pos: u64, dst: &mut [u8]) { self.raw.unsafe_peek(pos, dst) }
+  #[inline(always)]
+  unsafe fn unsafe_peek_be<T: IntLike>(&self, pos: u64) -> T { self.raw.unsafe_peek_be(pos) }
+  #[inline(always)]
+  unsafe fn unsafe_peek_le<T: IntLike>(&self, pos: u64) -> T { self.raw.unsafe_peek_le(pos) }
+
+  #[inline(always)]
+  unsafe fn unsafe_consume(&mut self, dst: &mut [u8]) { self.raw.unsafe_consume(dst) }
+  #[inline(always)]
+  unsafe fn unsafe_consume_be<T: IntLike>(&mut self) -> T { self.raw.unsafe_consume_be::<T>() }
+  #[inline(always)]
+  unsafe fn unsafe_consume_le<T: IntLike>(&mut self) -> T { self.raw.unsafe_consume_le::<T>() }
+
+  #[inline]
+  fn as_iobuf<'b>(&'b self) -> Result<ROIobuf<'b>, IobufError> {
+    self.raw.check_fits_iobuf()?;
+    Ok(ROIobuf::from_slice(unsafe { self.raw.as_window_slice() }))
+  }
+
+  #[inline(always)]
+  fn to_span<'b>(&'b self) -> BufSpan<ROIobuf<'b>> { self.raw.to_span() }
+
+  #[inline(always)]
+  fn ref_count(&self) -> usize { unsafe { self.raw.ref_count_nonatomic() } }
+
+  #[inline(always)]
+  fn is_unique(&self) -> bool { unsafe { self.raw.is_unique_nonatomic() } }
+
+  #[inline(always)]
+  fn shares_buffer_with<Buf: LargeIobuf>(&self, other: &Buf) -> bool { self.raw.shares_buffer_with(other) }
+
+  #[inline(always)]
+  fn invariant(&self) -> Result<(), Box<String>> { self.raw.invariant() }
+
+  #[inline(always)]
+  fn ptr(&self) -> NonNull<u8> { self.raw.ptr() }
+  #[inline(always)]
+  fn is_owned(&self) -> bool { self.raw.is_owned() }
+  #[inline(always)]
+  fn lo_min(&self) -> u64 { self.raw.lo_min() }
+  #[inline(always)]
+  fn lo(&self) -> u64 { self.raw.lo() }
+  #[inline(always)]
+  fn hi(&self) -> u64 { self.raw.hi() }
+  #[inline(always)]
+  fn hi_max(&self) -> u64 { self.raw.hi_max() }
+}
+
+impl LargeIobuf for LargeAROIobuf {
+  #[inline(always)]
+  fn deep_clone(&self) -> LargeRWIobuf<'static> { LargeRWIobuf { raw: self.raw.deep_clone() } }
+
+  #[inline(always)]
+  fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> LargeRWIobuf<'static> {
+    LargeRWIobuf { raw: self.raw.deep_clone_with_allocator(allocator) }
+  }
+
+  #[inline(always)]
+  fn atomic_read_only(self) -> Result<LargeAROIobuf, Self> { Ok(self) }
+
+  #[inline(always)]
+  fn len(&self) -> u64 { self.raw.len() }
+
+  #[inline(always)]
+  fn cap(&self) -> u64 { self.raw.cap() }
+
+  #[inline(always)]
+  fn is_empty(&self) -> bool { self.raw.is_empty() }
+
+  #[inline(always)]
+  unsafe fn as_window_slice<'b>(&'b self) -> &'b [u8] { self.raw.as_window_slice() }
+
+  #[inline(always)]
+  unsafe fn as_limit_slice<'b>(&'b self) -> &'b [u8] { self.raw.as_limit_slice() }
+
+  #[inline(always)]
+  fn sub_window(&mut self, pos: u64, len: u64) -> Result<(), IobufError> { self.raw.sub_window(pos, len) }
+
+  #[inline(always)]
+  fn sub_window_from(&mut self, pos: u64) -> Result<(), IobufError> { self.raw.sub_window_from(pos) }
+
+  #[inline(always)]
+  fn sub_window_to(&mut self, len: u64) -> Result<(), IobufError> { self.raw.sub_window_to(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_window(&mut self, pos: u64, len: u64) { self.raw.unsafe_sub_window(pos, len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_window_from(&mut self, pos: u64) { self.raw.unsafe_sub_window_from(pos) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_window_to(&mut self, len: u64) { self.raw.unsafe_sub_window_to(len) }
+
+  #[inline(always)]
+  fn sub(&mut self, pos: u64, len: u64) -> Result<(), IobufError> { self.raw.sub(pos, len) }
+
+  #[inline(always)]
+  fn sub_from(&mut self, pos: u64) -> Result<(), IobufError> { self.raw.sub_from(pos) }
+
+  #[inline(always)]
+  fn sub_to(&mut self, len: u64) -> Result<(), IobufError> { self.raw.sub_to(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub(&mut self, pos: u64, len: u64) { self.raw.unsafe_sub(pos, len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_from(&mut self, pos: u64) { self.raw.unsafe_sub_from(pos) }
+
+  #[inline(always)]
+  unsafe fn unsafe_sub_to(&mut self, len: u64) { self.raw.unsafe_sub_to(len) }
+
+  #[inline(always)]
+  fn set_limits_and_window(&mut self, limits: (u64, u64), window: (u64, u64)) -> Result<(), IobufError> { self.raw.set_limits_and_window(limits, window) }
+
+  #[inline(always)]
+  fn narrow(&mut self) { self.raw.narrow() }
+
+  #[inline(always)]
+  fn advance(&mut self, len: u64) -> Result<(), IobufError> { self.raw.advance(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_advance(&mut self, len: u64) { self.raw.unsafe_advance(len) }
+
+  #[inline(always)]
+  fn extend(&mut self, len: u64) -> Result<(), IobufError> { self.raw.extend(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_extend(&mut self, len: u64) { self.raw.unsafe_extend(len) }
+
+  #[inline(always)]
+  fn resize(&mut self, len: u64) -> Result<(), IobufError> { self.raw.resize(len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_resize(&mut self, len: u64) { self.raw.unsafe_resize(len) }
+
+  #[inline(always)]
+  fn split_at(&self, pos: u64) -> Result<(Self, Self), IobufError> {
+    unsafe {
+      self.raw.check_range(pos, 0)?;
+      Ok(self.unsafe_split_at(pos))
+    }
+  }
+
+  #[inline(always)]
+  unsafe fn unsafe_split_at(&self, pos: u64) -> (Self, Self) {
+    let (a, b) = self.raw.unsafe_split_at_atomic(pos);
+    (LargeAROIobuf { raw: a }, LargeAROIobuf { raw: b })
+  }
+
+  #[inline(always)]
+  fn split_start_at(&mut self, pos: u64) -> Result<Self, IobufError> {
+    unsafe {
+      self.raw.check_range(pos, 0)?;
+      Ok(self.unsafe_split_start_at(pos))
+    }
+  }
+
+  #[inline(always)]
+  unsafe fn unsafe_split_start_at(&mut self, pos: u64) -> Self {
+    LargeAROIobuf { raw: self.raw.unsafe_split_start_at_atomic(pos) }
+  }
+
+  #[inline(always)]
+  fn rewind(&mut self) { self.raw.rewind() }
+
+  #[inline(always)]
+  fn reset(&mut self) { self.raw.reset() }
+
+  #[inline(always)]
+  fn flip_lo(&mut self) { self.raw.flip_lo() }
+
+  #[inline(always)]
+  fn flip_hi(&mut self) { self.raw.flip_hi() }
+
+  #[inline(always)]
+  fn lo_space(&self) -> u64 { self.raw.lo_space() }
+
+  #[inline(always)]
+  fn hi_space(&self) -> u64 { self.raw.hi_space() }
+
+  #[inline(always)]
+  fn peek(&self, pos: u64, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.peek(pos, dst) }
+  #[inline(always)]
+  fn peek_be<T: IntLike>(&self, pos: u64) -> Result<T, IobufError> { self.raw.peek_be(pos) }
+  #[inline(always)]
+  fn peek_le<T: IntLike>(&self, pos: u64) -> Result<T, IobufError> { self.raw.peek_le(pos) }
+
+  #[inline(always)]
+  fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError> { self.raw.consume(dst) }
+  #[inline(always)]
+  fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_be::<T>() }
+  #[inline(always)]
+  fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError> { self.raw.consume_le::<T>() }
+
+  #[inline(always)]
+  fn check_range(&self, pos: u64, len: u64) -> Result<(), IobufError> { self.raw.check_range(pos, len) }
+
+  #[inline(always)]
+  fn check_range_fail(&self, pos: u64, len: u64) { self.raw.check_range_fail(pos, len) }
+
+  #[inline(always)]
+  unsafe fn unsafe_peek(&self, pos: u64, dst: &mut [u8]) { self.raw.unsafe_peek(pos, dst) }
+  #[inline(always)]
+  unsafe fn unsafe_peek_be<T: IntLike>(&self, pos: u64) -> T { self.raw.unsafe_peek_be(pos) }
+  #[inline(always)]
+  unsafe fn unsafe_peek_le<T: IntLike>(&self, pos: u64) -> T { self.raw.unsafe_peek_le(pos) }
+
+  #[inline(always)]
+  unsafe fn unsafe_consume(&mut self, dst: &mut [u8]) { self.raw.unsafe_consume(dst) }
+  #[inline(always)]
+  unsafe fn unsafe_consume_be<T: IntLike>(&mut self) -> T { self.raw.unsafe_consume_be::<T>() }
+  #[inline(always)]
+  unsafe fn unsafe_consume_le<T: IntLike>(&mut self) -> T { self.raw.unsafe_consume_le::<T>() }
+
+  #[inline]
+  fn as_iobuf<'b>(&'b self) -> Result<ROIobuf<'b>, IobufError> {
+    self.raw.check_fits_iobuf()?;
+    Ok(ROIobuf::from_slice(unsafe { self.raw.as_window_slice() }))
+  }
+
+  #[inline(always)]
+  fn to_span<'b>(&'b self) -> BufSpan<ROIobuf<'b>> { self.raw.to_span() }
+
+  #[inline(always)]
+  fn ref_count(&self) -> usize { unsafe { self.raw.ref_count_atomic() } }
+
+  #[inline(always)]
+  fn is_unique(&self) -> bool { unsafe { self.raw.is_unique_atomic() } }
+
+  #[inline(always)]
+  fn shares_buffer_with<Buf: LargeIobuf>(&self, other: &Buf) -> bool { self.raw.shares_buffer_with(other) }
+
+  #[inline(always)]
+  fn invariant(&self) -> Result<(), Box<String>> { self.raw.invariant() }
+
+  #[inline(always)]
+  fn ptr(&self) -> NonNull<u8> { self.raw.ptr() }
+  #[inline(always)]
+  fn is_owned(&self) -> bool { self.raw.is_owned() }
+  #[inline(always)]
+  fn lo_min(&self) -> u64 { self.raw.lo_min() }
+  #[inline(always)]
+  fn lo(&self) -> u64 { self.raw.lo() }
+  #[inline(always)]
+  fn hi(&self) -> u64 { self.raw.hi() }
+  #[inline(always)]
+  fn hi_max(&self) -> u64 { self.raw.hi_max() }
+}
+
+#[test]
+fn large_peek_and_consume() {
+  let mut b = LargeRWIobuf::new(24);
+  assert_eq!(b.fill_be(0x0102030405060708u64), Ok(()));
+  assert_eq!(b.fill_le(0x0102030405060708u64), Ok(()));
+  assert_eq!(b.fill(b"abcdefgh"), Ok(()));
+  assert!(b.fill_be(1u8).is_err());
+  b.flip_lo();
+
+  assert_eq!(b.peek_be::<u8>(0), Ok(1));
+  assert_eq!(b.peek_be::<u8>(8), Ok(8));
+  assert_eq!(b.consume_be::<u64>(), Ok(0x0102030405060708));
+  assert_eq!(b.consume_le::<u64>(), Ok(0x0102030405060708));
+
+  let (front, back) = b.split_at(3).unwrap();
+  unsafe {
+    assert_eq!(front.as_window_slice(), b"abc");
+    assert_eq!(back.as_window_slice(), b"defgh");
+  }
+
+  let err = back.peek_be::<u64>(1).unwrap_err();
+  assert_eq!(err.kind(), ErrorKind::Incomplete);
+  assert_eq!(err.window(), (19, 24));
+}
+
+#[test]
+fn large_as_iobuf() {
+  let mut b = LargeROIobuf::from_str_copy("hello, world");
+  assert_eq!(b.sub(7, 5), Ok(()));
+  assert_eq!(b.lo_min(), 7);
+
+  {
+    use iobuf::Iobuf;
+    let small = b.as_iobuf().unwrap();
+    assert_eq!(small.len(), 5);
+  }
+
+  // Pretend the window is over 2 GB, without allocating that much. The error
+  // is raised before anything is read.
+  b.raw.hi = b.raw.lo + MAX_BUFFER_LEN as u64 + 1;
+  assert_eq!(b.as_iobuf().unwrap_err().kind(), ErrorKind::TooLarge);
+  b.raw.hi = b.raw.lo + 5;
+}
+
+#[test]
+fn large_ref_counts() {
+  let a = LargeRWIobuf::from_str_copy("hello");
+  let b = a.clone();
+  assert_eq!(a.ref_count(), 2);
+  assert!(!a.is_unique());
+  assert!(a.shares_buffer_with(&b));
+  drop(b);
+
+  let a = a.atomic_read_only().unwrap();
+  assert_eq!(a.ref_count(), 1);
+  assert!(a.is_unique());
+  let c = a.clone();
+  assert!(!c.is_unique());
+  assert!(c.shares_buffer_with(&a));
+  assert!(!c.shares_buffer_with(&c.deep_clone()));
+}
+
+#[test]
+fn large_atomic_round_trip() {
+  let b = LargeROIobuf::from_str_copy("hello");
+  let c = b.clone();
+  let b = b.atomic_read_only().unwrap_err();
+  drop(c);
+
+  let a = b.atomic_read_only().unwrap();
+  let a2 = a.clone();
+  let a = a.read_write().unwrap_err();
+  drop(a2);
+  let rw = a.read_write().unwrap();
+  assert!(rw.to_span().byte_equal_slice(b"hello"));
+}
+
+/// Runs `f` over `len` bytes of zeroed memory that's only backed by real pages
+/// once it's written to, so tests can cross the 2 GB mark cheaply.
+#[cfg(all(test, unix, target_pointer_width = "64"))]
+fn with_sparse<F: FnOnce(&mut [u8])>(len: usize, f: F) {
+  use libc;
+
+  unsafe {
+    let p =
+      libc::mmap(
+        ptr::null_mut(), len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+        -1, 0);
+    assert!(p != libc::MAP_FAILED);
+    f(slice::from_raw_parts_mut(p as *mut u8, len));
+    libc::munmap(p, len);
+  }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+#[test]
+fn large_offsets_past_2gb() {
+  let two_gb = 1u64 << 31;
+
+  with_sparse((1 << 32) + 4096, |s| {
+    let len = s.len() as u64;
+    let mut b = LargeRWIobuf::from_slice(s);
+    assert_eq!(b.len(), len);
+
+    assert_eq!(b.poke_be(two_gb - 4, 0x0102030405060708u64), Ok(()));
+    assert_eq!(b.poke_le(two_gb + 8, 0xdeadbeefu32), Ok(()));
+    assert_eq!(b.poke_be(len - 8, 0x1122334455667788u64), Ok(()));
+    assert!(b.poke_be(len - 7, 0u64).is_err());
+
+    assert_eq!(b.peek_be::<u64>(two_gb - 4), Ok(0x0102030405060708));
+    assert_eq!(b.peek_be::<u32>(two_gb), Ok(0x05060708));
+    assert_eq!(b.peek_le::<u32>(two_gb + 8), Ok(0xdeadbeef));
+    assert_eq!(b.peek_be::<u64>(len - 8), Ok(0x1122334455667788));
+
+    let err = b.peek_be::<u64>(len - 4).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Incomplete);
+    assert_eq!(err.window(), (0, len));
+
+    assert_eq!(b.advance(two_gb + 8), Ok(()));
+    assert_eq!(b.lo(), two_gb + 8);
+    assert_eq!(b.consume_le::<u32>(), Ok(0xdeadbeef));
+    assert_eq!(b.lo(), two_gb + 12);
+  });
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+#[test]
+fn large_as_iobuf_past_2gb() {
+  use iobuf::Iobuf;
+
+  let two_gb = 1u64 << 31;
+
+  with_sparse((1 << 32) + 4096, |s| {
+    let b = LargeRWIobuf::from_slice(s);
+    assert_eq!(b.poke(two_gb - 2, b"abcd"), Ok(()));
+    assert_eq!(b.poke(two_gb + MAX_BUFFER_LEN as u64 - 1, b"z"), Ok(()));
+
+    // A small window straddling the 2 GB mark.
+    let mut small = b.clone();
+    assert_eq!(small.sub_window(two_gb - 2, 4), Ok(()));
+    {
+      let i = small.as_iobuf().unwrap();
+      assert_eq!(i.len(), 4);
+      unsafe { assert_eq!(i.as_window_slice(), b"abcd"); }
+    }
+
+    // The largest window an Iobuf can hold, starting past 2 GB.
+    let mut biggest = b.clone();
+    assert_eq!(biggest.sub_window(two_gb, MAX_BUFFER_LEN as u64), Ok(()));
+    {
+      let i = biggest.as_rw_iobuf().unwrap();
+      assert_eq!(i.len(), MAX_BUFFER_LEN as u32);
+      assert_eq!(i.peek_be::<u16>(0), Ok(0x6364));
+      assert_eq!(i.peek_be::<u8>(MAX_BUFFER_LEN as u32 - 1), Ok(b'z'));
+    }
+
+    // One byte more, whether it starts before or after the 2 GB mark.
+    let mut too_big = b.clone();
+    assert_eq!(too_big.sub_window(two_gb, MAX_BUFFER_LEN as u64 + 1), Ok(()));
+    assert_eq!(too_big.as_iobuf().unwrap_err().kind(), ErrorKind::TooLarge);
+
+    let mut too_big = b.clone();
+    assert_eq!(too_big.sub_window(1, two_gb), Ok(()));
+    assert_eq!(too_big.as_rw_iobuf().unwrap_err().kind(), ErrorKind::TooLarge);
+  });
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+#[test]
+fn large_to_span_chunks_past_2gb() {
+  use iobuf::Iobuf;
+
+  let max = MAX_BUFFER_LEN as u64;
+
+  with_sparse((1 << 32) + 4096, |s| {
+    let len = s.len() as u64;
+    let mut b = LargeRWIobuf::from_slice(s);
+    assert_eq!(b.poke_be(max - 1, 0x0102u16), Ok(()));
+    assert_eq!(b.poke_be(2 * max - 1, 0x0304u16), Ok(()));
+    assert_eq!(b.poke_be(len - 1, 0x05u8), Ok(()));
+
+    {
+      let span = b.to_span();
+      let lens: Vec<u64> = (&span).into_iter().map(|i| i.len() as u64).collect();
+      assert_eq!(lens, vec![max, max, len - 2 * max]);
+      assert_eq!(span.count_bytes() as u64, len);
+    }
+
+    // Each chunk starts right where the last one ended.
+    assert_eq!(b.sub_window_from(max - 1), Ok(()));
+    let span = b.to_span();
+    let firsts: Vec<u8> = (&span).into_iter().map(|i| i.peek_be(0).unwrap()).collect();
+    assert_eq!(firsts, vec![0x01, 0x03]);
+    let lasts: Vec<u8> = (&span).into_iter().map(|i| i.peek_be(i.len() - 1).unwrap()).collect();
+    assert_eq!(lasts, vec![0x00, 0x05]);
+    assert_eq!(span.peek_be::<u16>(0), Ok(0x0102));
+    assert_eq!(span.peek_be::<u16>(max as usize), Ok(0x0304));
+  });
+}
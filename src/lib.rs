@@ -19,11 +19,11 @@
 //! construct multiple views into the same data.
 //!
 //! To keep the struct small (24 bytes!), the maximum size of an Iobuf is 2 GB.
-//! Please let me know if you need more than this. I have never before seen a
-//! use case for Iobufs larger than 2 GB, and it gives us a 40% smaller struct
-//! compared to supporting INT64_MAX bytes. The improved size allows much better
-//! register/cache usage and faster moves, both of which are critical for
-//! performance.
+//! This gives us a 40% smaller struct compared to supporting INT64_MAX bytes.
+//! The improved size allows much better register/cache usage and faster moves,
+//! both of which are critical for performance. If you really need more, the
+//! `LargeIobuf` family (`LargeROIobuf`, `LargeRWIobuf`, `LargeAROIobuf`) uses
+//! 64-bit offsets, and can lend out ordinary Iobufs for sub-ranges under 2 GB.
 //!
 //! Although this library is designed for efficiency, and hence gives you lots
 //! of ways to omit bounds checks, that does not mean it's recommended you do.
@@ -39,34 +39,65 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
-#![feature(plugin)]
-#![feature(core_intrinsics)]
-#![feature(nonzero)]
-#![feature(allocator_api)]
-#![feature(alloc)]
-#![feature(unique)]
-#![feature(fn_must_use)]
+// The `unsafe_` functions share the safety contract spelled out on `Iobuf`,
+// `Ok(self.unsafe_foo())` is the house style for bounds-checked wrappers, and
+// the mutable slice accessors deliberately hand out `&mut` from `&self`.
+#![allow(clippy::missing_safety_doc)]
+#![allow(clippy::unit_arg)]
+#![allow(clippy::mut_from_ref)]
+#![allow(clippy::needless_lifetimes)]
+#![allow(clippy::missing_transmute_annotations)]
+// `invariant` boxes its message to keep `Result<(), _>` one word wide.
+#![allow(clippy::box_collection)]
+#![allow(clippy::should_implement_trait)]
+#![allow(clippy::new_without_default)]
+#![allow(clippy::manual_is_multiple_of)]
+#![allow(clippy::redundant_field_names)]
+#![allow(clippy::needless_borrowed_reference)]
 
-#![cfg_attr(test, feature(test))]
+#![cfg_attr(all(test, feature = "nightly"), feature(test))]
 
-extern crate alloc;
-extern crate core;
-
-#[cfg(test)] extern crate test;
+#[cfg(all(test, feature = "nightly"))] extern crate test;
+#[cfg(unix)] extern crate libc;
 //#[cfg(test)] extern crate quickcheck;
 
+pub use error::{ErrorKind, IobufError};
 pub use intlike::IntLike;
 pub use raw::Allocator;
 pub use iobuf::Iobuf;
 pub use impls::{RWIobuf, ROIobuf, AROIobuf, UniqueIobuf};
 pub use ringbuf::IORingbuf;
-pub use bufspan::{BufSpan, ByteIter, SpanIter, SpanMoveIter};
+pub use bufspan::{BufSpan, ByteIter, SpanIter, SpanMoveIter, SpanReader, scatter_read};
 pub use appendbuf::AppendBuf;
+pub use growbuf::GrowBuf;
+pub use large::{LargeIobuf, LargeROIobuf, LargeRWIobuf, LargeAROIobuf};
+pub use pool::{PoolAllocator, PoolStats};
+pub use tracking::{TrackingAllocator, TrackingStats, LiveBuffers};
+pub use quota::QuotaAllocator;
+#[cfg(unix)]
+pub use guard::{FreedMemory, GuardPageAllocator, POISON};
+#[cfg(unix)]
+pub use shm::{ShmAllocator, SharedIobuf};
+pub use weak::{WeakIobuf, AWeakIobuf};
 
 mod raw;
+mod error;
 mod intlike;
 mod iobuf;
 mod impls;
 mod ringbuf;
 mod bufspan;
 mod appendbuf;
+mod growbuf;
+mod large;
+mod pool;
+mod tracking;
+mod quota;
+mod weak;
+mod foreign;
+#[cfg(unix)]
+mod mmap;
+#[cfg(unix)]
+mod guard;
+#[cfg(unix)]
+mod shm;
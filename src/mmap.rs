@@ -0,0 +1,311 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr::{self, NonNull};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc;
+
+use error::{ErrorKind, iobuf_error};
+use large::{RawLargeIobuf, MAX_LARGE_BUFFER_LEN};
+use raw::{Allocator, RawIobuf, ALLOCATION_HEADER_SIZE, MAX_BUFFER_LEN};
+
+#[inline]
+pub fn page_size() -> usize {
+  unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Hands out a single, already mapped region to `allocate_buffer`, once, and
+/// unmaps it when the last Iobuf referencing it is dropped. Made by
+/// `map_behind_header`.
+///
+/// The region is one anonymous page, whose last bytes hold the allocation
+/// header, followed directly by the mapping of the file. That way the file's
+/// data sits exactly where Iobufs expect their buffer to be.
+pub struct MmapAllocator {
+  /// Where the allocation header goes, `ALLOCATION_HEADER_SIZE` bytes before
+  /// the end of the first page, or `0` once it's been handed out.
+  header:    AtomicUsize,
+  page_size: usize,
+  /// Whether the file was mapped writable. If not, its pages are read-only.
+  writable:  bool,
+}
+
+impl MmapAllocator {
+  /// The start of the whole mapping, given the header's address.
+  #[inline]
+  fn base(&self, header: NonNull<u8>) -> *mut libc::c_void {
+    (header.as_ptr() as usize + ALLOCATION_HEADER_SIZE - self.page_size) as *mut libc::c_void
+  }
+}
+
+impl Allocator for MmapAllocator {
+  fn allocate(&self, _len: usize, _align: usize) -> Option<NonNull<u8>> {
+    NonNull::new(self.header.swap(0, Ordering::Relaxed) as *mut u8)
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, _align: usize) {
+    // `len` is the header plus the file, or just the header once the file's
+    // been unmapped by `release_data`.
+    let map_len = self.page_size - ALLOCATION_HEADER_SIZE + len;
+    unsafe { libc::munmap(self.base(ptr), map_len); }
+  }
+
+  fn release_data(&self, ptr: NonNull<u8>, len: usize) -> bool {
+    // The file starts on the page after the header's.
+    unsafe { libc::munmap(ptr.as_ptr() as *mut libc::c_void, len) == 0 }
+  }
+
+  fn flush(&self, ptr: NonNull<u8>, len: usize) -> io::Result<()> {
+    // `msync` needs a page-aligned address.
+    let addr  = ptr.as_ptr() as usize;
+    let start = addr & !(self.page_size - 1);
+    let ret = unsafe {
+      libc::msync(start as *mut libc::c_void, len + (addr - start), libc::MS_SYNC)
+    };
+    if ret == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+  }
+
+  fn read_only(&self) -> bool {
+    !self.writable
+  }
+}
+
+/// Maps `len` bytes of `fd`, from `off` on, shared and with the protection
+/// `prot`, directly after an anonymous page whose last bytes are left for
+/// the allocation header. Returns the allocator that hands the header out,
+/// to build the Iobuf with.
+///
+/// `off` must be a multiple of the page size.
+pub unsafe fn map_behind_header(
+    fd:   RawFd,
+    off:  u64,
+    len:  usize,
+    prot: libc::c_int) -> io::Result<MmapAllocator> {
+  let page_size = page_size();
+  let map_len =
+    match page_size.checked_add(len) {
+      Some(map_len) => map_len,
+      None => return Err(io::Error::from_raw_os_error(libc::ENOMEM)),
+    };
+
+  let base =
+    libc::mmap(
+      ptr::null_mut(), map_len,
+      libc::PROT_READ | libc::PROT_WRITE,
+      libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+      -1, 0);
+  if base == libc::MAP_FAILED {
+    return Err(io::Error::last_os_error());
+  }
+
+  if len > 0 {
+    let data =
+      libc::mmap(
+        (base as *mut u8).add(page_size) as *mut libc::c_void, len,
+        prot, libc::MAP_SHARED | libc::MAP_FIXED,
+        fd, off as libc::off_t);
+    if data == libc::MAP_FAILED {
+      let err = io::Error::last_os_error();
+      libc::munmap(base, map_len);
+      return Err(err);
+    }
+  }
+
+  Ok(MmapAllocator {
+    header:    AtomicUsize::new(base as usize + page_size - ALLOCATION_HEADER_SIZE),
+    page_size: page_size,
+    writable:  prot & libc::PROT_WRITE != 0,
+  })
+}
+
+/// Opens the file at `path`, and maps all of it behind a header.
+unsafe fn map_whole_file(path: &Path, writable: bool, max_len: u64) -> io::Result<(usize, MmapAllocator)> {
+  let file = OpenOptions::new().read(true).write(writable).open(path)?;
+
+  let len = file.metadata()?.len();
+  if len > max_len {
+    return Err(iobuf_error(ErrorKind::TooLarge, 0, len, (0, 0), (0, 0)).into());
+  }
+
+  // Read-only mappings really are, so they cost no swap or commit charge.
+  // `read_only` keeps their buffers from ever being unique, so nothing hands
+  // out a writable Iobuf over them.
+  let prot = if writable { libc::PROT_READ | libc::PROT_WRITE } else { libc::PROT_READ };
+  let allocator = map_behind_header(file.as_raw_fd(), 0, len as usize, prot)?;
+  Ok((len as usize, allocator))
+}
+
+/// Maps the file at `path` into memory, read-only or shared read-write, as
+/// the buffer of a new Iobuf. The limits and window cover the whole file.
+///
+/// The caller upholds the `# Safety` contract of `ROIobuf::map_file`.
+pub unsafe fn map_file(path: &Path, writable: bool) -> io::Result<RawIobuf<'static>> {
+  let (len, allocator) = map_whole_file(path, writable, MAX_BUFFER_LEN as u64)?;
+  // The size was checked, and the allocator can't fail.
+  Ok(RawIobuf::new_with_allocator(len, Arc::new(Box::new(allocator))))
+}
+
+/// The same as `map_file`, read-only, for files of any size.
+pub unsafe fn map_large_file(path: &Path) -> io::Result<RawLargeIobuf<'static>> {
+  let (len, allocator) = map_whole_file(path, false, MAX_LARGE_BUFFER_LEN)?;
+  Ok(RawLargeIobuf::new_with_allocator(len as u64, Arc::new(Box::new(allocator))))
+}
+
+#[cfg(test)]
+fn temp_file(name: &str, contents: &[u8]) -> ::std::path::PathBuf {
+  let path = ::std::env::temp_dir().join(
+    format!("iobuf-{}-{}", name, ::std::process::id()));
+  ::std::fs::write(&path, contents).unwrap();
+  path
+}
+
+#[test]
+fn map_and_split() {
+  use impls::ROIobuf;
+  use iobuf::Iobuf;
+
+  let path = temp_file("map-and-split", b"hello, world");
+  let b = unsafe { ROIobuf::map_file(&path).unwrap() };
+  assert_eq!(b.len(), 12);
+
+  let (l, r) = b.split_at(5).unwrap();
+  drop(b);
+  unsafe {
+    assert_eq!(l.as_window_slice(), b"hello");
+    assert_eq!(r.as_window_slice(), b", world");
+  }
+  drop(l);
+  drop(r);
+
+  ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn read_only_maps_are_copied_on_write() {
+  use impls::{AROIobuf, ROIobuf};
+  use iobuf::Iobuf;
+
+  let path = temp_file("map-cow", b"hello");
+  let b = unsafe { ROIobuf::map_file(&path).unwrap() };
+  assert_eq!(b.ref_count(), 1);
+  assert!(!b.is_unique());
+
+  let c = b.clone().make_mut();
+  assert!(!c.shares_buffer_with(&b));
+  c.poke(0, b"J").unwrap();
+  unsafe { assert_eq!(b.as_window_slice(), b"hello"); }
+  assert!(b.unique().is_err());
+
+  let a = unsafe { AROIobuf::map_file(&path).unwrap() };
+  let a = a.read_write().unwrap_err();
+  assert!(!a.clone().make_mut().shares_buffer_with(&a));
+
+  assert_eq!(&::std::fs::read(&path).unwrap()[..], b"hello");
+  ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn map_mut_and_flush() {
+  use impls::RWIobuf;
+
+  let path = temp_file("map-mut", b"abcdef");
+  {
+    let b = unsafe { RWIobuf::map_file_mut(&path).unwrap() };
+    b.poke(2, b"XY").unwrap();
+    b.msync().unwrap();
+  }
+  assert_eq!(&::std::fs::read(&path).unwrap()[..], b"abXYef");
+
+  ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn mapped_buffers_move_to_the_heap() {
+  use impls::RWIobuf;
+  use iobuf::Iobuf;
+
+  let path = temp_file("map-reserve", b"abc");
+  let mut b = unsafe { RWIobuf::map_file_mut(&path).unwrap() };
+  let mapped = b.clone();
+  b.advance(3).unwrap();
+  assert_eq!(b.reserve(10), Ok(()));
+  assert!(!b.shares_buffer_with(&mapped));
+  assert!(b.fill(b"defg").is_ok());
+  b.flip_lo();
+  unsafe { assert_eq!(b.as_window_slice(), b"abcdefg"); }
+
+  // The file never sees writes after the move.
+  drop(mapped);
+  drop(b);
+  assert_eq!(&::std::fs::read(&path).unwrap()[..], b"abc");
+
+  ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn dead_mappings_are_unmapped() {
+  use impls::ROIobuf;
+  use iobuf::Iobuf;
+
+  let path = temp_file("map-weak", &[7; 10000]);
+  let b = unsafe { ROIobuf::map_file(&path).unwrap() };
+  let w = b.downgrade();
+  let (p, len) = (b.ptr().as_ptr(), b.len() as usize);
+  drop(b);
+
+  // Only the header's page is still mapped.
+  let ret = unsafe { libc::msync(p as *mut libc::c_void, len, libc::MS_ASYNC) };
+  assert_eq!(ret, -1);
+  assert!(w.upgrade().is_none());
+  drop(w);
+
+  ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+fn map_files_over_2gb() {
+  use large::{LargeIobuf, LargeROIobuf};
+  use std::io::{Seek, SeekFrom, Write};
+
+  // Sparse, so it costs nothing.
+  let path = temp_file("map-large", b"head");
+  let len = 3 << 30;
+  {
+    let mut f = OpenOptions::new().write(true).open(&path).unwrap();
+    f.seek(SeekFrom::Start(len - 4)).unwrap();
+    f.write_all(b"tail").unwrap();
+  }
+
+  let b = unsafe { LargeROIobuf::map_file(&path).unwrap() };
+  assert_eq!(b.len(), len);
+  assert!(!b.is_unique());
+
+  let mut tail = [0; 4];
+  b.peek(len - 4, &mut tail).unwrap();
+  assert_eq!(&tail, b"tail");
+  b.peek(0, &mut tail).unwrap();
+  assert_eq!(&tail, b"head");
+
+  // Too large for a plain Iobuf.
+  assert!(unsafe { ::impls::ROIobuf::map_file(&path) }.is_err());
+
+  drop(b);
+  ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn map_empty_file() {
+  use impls::AROIobuf;
+  use iobuf::Iobuf;
+
+  let path = temp_file("map-empty", b"");
+  let b = unsafe { AROIobuf::map_file(&path).unwrap() };
+  assert!(b.is_empty());
+  drop(b);
+
+  ::std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,404 @@
+use std::alloc::{self, Layout};
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use raw::{Allocator, ALLOCATION_HEADER_SIZE, MAX_BUFFER_LEN};
+
+/// Pooled blocks are aligned to a cache line. Requests for a stricter alignment
+/// bypass the pool.
+const BLOCK_ALIGNMENT: usize = 64;
+
+/// The smallest size class holds 64 usable bytes.
+const MIN_CLASS_SHIFT: u32 = 6;
+
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The ids of the pools that haven't been dropped yet.
+static LIVE_POOLS: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+
+/// Bumped every time a pool is dropped, so that each thread knows to throw
+/// away its free lists for dead pools.
+static POOL_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Cached blocks of a single size class, all allocated from the global heap
+/// with `BLOCK_ALIGNMENT`.
+struct FreeList {
+  block_len: usize,
+  blocks:    Vec<NonNull<u8>>,
+}
+
+impl Drop for FreeList {
+  fn drop(&mut self) {
+    for &b in &self.blocks {
+      unsafe { heap_deallocate(b, self.block_len, BLOCK_ALIGNMENT) }
+    }
+  }
+}
+
+thread_local! {
+  /// Each thread's free lists, indexed by pool id, then size class.
+  static FREE_LISTS: RefCell<HashMap<usize, Vec<FreeList>>> = RefCell::new(HashMap::new());
+  /// The `POOL_GENERATION` this thread's free lists were last purged at.
+  static SEEN_GENERATION: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Frees this thread's free lists for pools that have since been dropped, if
+/// any have been.
+#[inline]
+fn purge_dead_pools(lists: &mut HashMap<usize, Vec<FreeList>>) {
+  let generation = POOL_GENERATION.load(Ordering::Acquire);
+  if SEEN_GENERATION.with(|seen| seen.replace(generation)) != generation {
+    let live = LIVE_POOLS.lock().unwrap_or_else(|e| e.into_inner());
+    lists.retain(|id, _| live.contains(id));
+  }
+}
+
+#[inline]
+fn heap_allocate(len: usize, align: usize) -> Option<NonNull<u8>> {
+  unsafe { NonNull::new(alloc::alloc(Layout::from_size_align(len, align).unwrap())) }
+}
+
+#[inline]
+unsafe fn heap_deallocate(ptr: NonNull<u8>, len: usize, align: usize) {
+  alloc::dealloc(ptr.as_ptr(), Layout::from_size_align(len, align).unwrap())
+}
+
+/// Counters describing how well a `PoolAllocator` is doing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PoolStats {
+  /// Allocations served from a free list.
+  pub hits:      u64,
+  /// Allocations that fit a size class, but had to go to the heap because the
+  /// free list was empty.
+  pub misses:    u64,
+  /// Allocations too big (or too strictly aligned) for any size class, which
+  /// always go to the heap.
+  pub oversized: u64,
+}
+
+struct PoolInner {
+  id:         usize,
+  max_shift:  u32,
+  max_cached: usize,
+  hits:       AtomicU64,
+  misses:     AtomicU64,
+  oversized:  AtomicU64,
+}
+
+impl PoolInner {
+  #[inline]
+  fn num_classes(&self) -> usize {
+    (self.max_shift - MIN_CLASS_SHIFT + 1) as usize
+  }
+
+  /// The length of the blocks in size class `class`, including room for the
+  /// Iobuf allocation header.
+  #[inline]
+  fn block_len(class: usize) -> usize {
+    (1 << (class as u32 + MIN_CLASS_SHIFT)) + ALLOCATION_HEADER_SIZE
+  }
+
+  /// The size class an allocation of `len` bytes belongs in, if any.
+  #[inline]
+  fn class_of(&self, len: usize, align: usize) -> Option<usize> {
+    let data_len = len.saturating_sub(ALLOCATION_HEADER_SIZE);
+    if align > BLOCK_ALIGNMENT || data_len > 1 << self.max_shift {
+      return None;
+    }
+    let shift = data_len.next_power_of_two().trailing_zeros();
+    Some(shift.saturating_sub(MIN_CLASS_SHIFT) as usize)
+  }
+
+  /// Runs `f` on this thread's free lists for this pool. Returns `None` if
+  /// they're unavailable, because the thread is shutting down.
+  #[inline]
+  fn with_free_lists<T, F: FnOnce(&mut Vec<FreeList>) -> T>(&self, f: F) -> Option<T> {
+    FREE_LISTS.try_with(|lists| {
+      let mut lists = lists.try_borrow_mut().ok()?;
+      purge_dead_pools(&mut lists);
+      let lists =
+        lists.entry(self.id).or_insert_with(|| {
+          (0..self.num_classes())
+            .map(|c| FreeList { block_len: PoolInner::block_len(c), blocks: Vec::new() })
+            .collect()
+        });
+      Some(f(lists))
+    }).ok().and_then(|x| x)
+  }
+
+  /// Caches `ptr` on this thread's free list, if there's room. Returns `false`
+  /// if the caller must free it instead.
+  #[inline]
+  fn retain(&self, class: usize, ptr: NonNull<u8>) -> bool {
+    let max_cached = self.max_cached;
+    self.with_free_lists(|lists| {
+      let blocks = &mut lists[class].blocks;
+      if blocks.len() < max_cached {
+        blocks.push(ptr);
+        true
+      } else {
+        false
+      }
+    }).unwrap_or(false)
+  }
+}
+
+impl Drop for PoolInner {
+  fn drop(&mut self) {
+    // Other threads free their lists for this pool the next time they use any
+    // pool, or when they exit.
+    let id = self.id;
+    LIVE_POOLS.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    POOL_GENERATION.fetch_add(1, Ordering::Release);
+
+    let _ = FREE_LISTS.try_with(|lists| {
+      let removed = lists.try_borrow_mut().ok().and_then(|mut l| l.remove(&id));
+      drop(removed);
+    });
+  }
+}
+
+/// An `Allocator` which recycles buffers through per-thread free lists, instead
+/// of going to the heap every time.
+///
+/// Allocations are rounded up to size classes of power-of-two usable bytes,
+/// from 64 bytes up to a maximum chosen at construction. Larger allocations
+/// are passed straight through to the heap. When the last reference to a
+/// pooled buffer is dropped, its memory goes onto the dropping thread's free
+/// list for that size class, unless that list already holds `max_cached`
+/// blocks. Once the pool itself is dropped, every thread frees the blocks it
+/// cached for it the next time it touches any pool, or when it exits.
+///
+/// `PoolAllocator` is a cheap handle: clones share the same pool and
+/// statistics.
+///
+/// ```rust
+/// use iobuf::{PoolAllocator, RWIobuf};
+///
+/// let pool = PoolAllocator::new(64 * 1024, 128);
+///
+/// for _ in 0..10 {
+///   let b = RWIobuf::new_with_allocator(4096, pool.allocator());
+///   drop(b);
+/// }
+///
+/// let stats = pool.stats();
+/// assert_eq!(stats.misses, 1);
+/// assert_eq!(stats.hits, 9);
+/// ```
+#[derive(Clone)]
+pub struct PoolAllocator {
+  inner: Arc<PoolInner>,
+}
+
+impl PoolAllocator {
+  /// Creates a new pool, which recycles buffers of up to `max_len` bytes
+  /// (rounded up to a power of two), and keeps up to `max_cached` free blocks
+  /// per size class, per thread.
+  pub fn new(max_len: usize, max_cached: usize) -> PoolAllocator {
+    let max_shift = cmp::min(max_len, MAX_BUFFER_LEN).next_power_of_two().trailing_zeros();
+    let id = NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed);
+    LIVE_POOLS.lock().unwrap_or_else(|e| e.into_inner()).insert(id);
+    PoolAllocator {
+      inner: Arc::new(PoolInner {
+        id:         id,
+        max_shift:  if max_shift < MIN_CLASS_SHIFT { MIN_CLASS_SHIFT } else { max_shift },
+        max_cached: max_cached,
+        hits:       AtomicU64::new(0),
+        misses:     AtomicU64::new(0),
+        oversized:  AtomicU64::new(0),
+      }),
+    }
+  }
+
+  /// Returns this pool in the form `new_with_allocator` and friends expect.
+  #[inline]
+  pub fn allocator(&self) -> Arc<Box<dyn Allocator>> {
+    Arc::new(Box::new(self.clone()))
+  }
+
+  /// Fills the calling thread's free list for buffers of `len` bytes with up
+  /// to `count` blocks (but never more than `max_cached`), so that the first
+  /// allocations don't have to go to the heap. Does nothing if `len` is too
+  /// large to be pooled.
+  ///
+  /// ```rust
+  /// use iobuf::{PoolAllocator, RWIobuf};
+  ///
+  /// let pool = PoolAllocator::new(4096, 16);
+  /// pool.prewarm(4096, 4);
+  ///
+  /// let _b = RWIobuf::new_with_allocator(4096, pool.allocator());
+  /// assert_eq!(pool.stats().hits, 1);
+  /// assert_eq!(pool.stats().misses, 0);
+  /// ```
+  pub fn prewarm(&self, len: usize, count: usize) {
+    let inner = &*self.inner;
+    let class =
+      match inner.class_of(len + ALLOCATION_HEADER_SIZE, BLOCK_ALIGNMENT) {
+        Some(class) => class,
+        None        => return,
+      };
+    let block_len = PoolInner::block_len(class);
+
+    inner.with_free_lists(|lists| {
+      let blocks = &mut lists[class].blocks;
+      let target = cmp::min(blocks.len() + count, inner.max_cached);
+      while blocks.len() < target {
+        match heap_allocate(block_len, BLOCK_ALIGNMENT) {
+          Some(b) => blocks.push(b),
+          None    => break,
+        }
+      }
+    });
+  }
+
+  /// A snapshot of the pool's hit and miss counters, across all threads.
+  #[inline]
+  pub fn stats(&self) -> PoolStats {
+    PoolStats {
+      hits:      self.inner.hits.load(Ordering::Relaxed),
+      misses:    self.inner.misses.load(Ordering::Relaxed),
+      oversized: self.inner.oversized.load(Ordering::Relaxed),
+    }
+  }
+}
+
+impl Allocator for PoolAllocator {
+  fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+    let inner = &*self.inner;
+    match inner.class_of(len, align) {
+      None => {
+        inner.oversized.fetch_add(1, Ordering::Relaxed);
+        heap_allocate(len, align)
+      },
+      Some(class) => {
+        match inner.with_free_lists(|lists| lists[class].blocks.pop()).and_then(|b| b) {
+          Some(b) => {
+            inner.hits.fetch_add(1, Ordering::Relaxed);
+            Some(b)
+          },
+          None => {
+            inner.misses.fetch_add(1, Ordering::Relaxed);
+            heap_allocate(PoolInner::block_len(class), BLOCK_ALIGNMENT)
+          },
+        }
+      },
+    }
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+    let inner = &*self.inner;
+    unsafe {
+      match inner.class_of(len, align) {
+        None => heap_deallocate(ptr, len, align),
+        Some(class) =>
+          if !inner.retain(class, ptr) {
+            heap_deallocate(ptr, PoolInner::block_len(class), BLOCK_ALIGNMENT)
+          },
+      }
+    }
+  }
+}
+
+#[test]
+fn size_classes() {
+  let pool = PoolAllocator::new(4096, 1);
+  let inner = &*pool.inner;
+  let h = ALLOCATION_HEADER_SIZE;
+
+  assert_eq!(inner.class_of(h, 16), Some(0));
+  assert_eq!(inner.class_of(h + 64, 16), Some(0));
+  assert_eq!(inner.class_of(h + 65, 16), Some(1));
+  assert_eq!(inner.class_of(h + 4096, 16), Some(6));
+  assert_eq!(inner.class_of(h + 4097, 16), None);
+  assert_eq!(inner.class_of(h + 64, 128), None);
+  assert_eq!(PoolInner::block_len(6), 4096 + h);
+}
+
+#[test]
+fn bounded_retention() {
+  use impls::RWIobuf;
+
+  let pool = PoolAllocator::new(1024, 2);
+  let bufs: Vec<RWIobuf> =
+    (0..4).map(|_| RWIobuf::new_with_allocator(1000, pool.allocator())).collect();
+  assert_eq!(pool.stats().misses, 4);
+  drop(bufs);
+
+  // Only two were kept.
+  let bufs: Vec<RWIobuf> =
+    (0..4).map(|_| RWIobuf::new_with_allocator(1000, pool.allocator())).collect();
+  assert_eq!(pool.stats(), PoolStats { hits: 2, misses: 6, oversized: 0 });
+  drop(bufs);
+
+  let _big = RWIobuf::new_with_allocator(1025, pool.allocator());
+  assert_eq!(pool.stats().oversized, 1);
+}
+
+#[test]
+fn pooled_data_is_usable() {
+  use impls::RWIobuf;
+  use iobuf::Iobuf;
+
+  let pool = PoolAllocator::new(4096, 8);
+  for i in 0..16u8 {
+    let mut b = RWIobuf::new_with_allocator(100, pool.allocator());
+    assert_eq!(b.cap(), 100);
+    b.fill(&[i; 100]).unwrap();
+    b.flip_lo();
+    unsafe { assert_eq!(b.as_window_slice(), &[i; 100][..]); }
+  }
+}
+
+#[test]
+fn cross_thread_release() {
+  use impls::RWIobuf;
+  use iobuf::Iobuf;
+  use std::thread;
+
+  let pool = PoolAllocator::new(4096, 8);
+  let b = RWIobuf::new_with_allocator(4096, pool.allocator()).atomic_read_only().unwrap();
+
+  // Freed on another thread, so it lands on that thread's free list, which is
+  // released when the thread exits.
+  thread::spawn(move || drop(b)).join().unwrap();
+
+  let _b = RWIobuf::new_with_allocator(4096, pool.allocator());
+  assert_eq!(pool.stats().misses, 2);
+}
+
+#[test]
+fn dead_pools_are_purged_from_other_threads() {
+  use impls::RWIobuf;
+  use std::sync::mpsc;
+  use std::thread;
+
+  let (to_worker, work) = mpsc::channel::<Option<PoolAllocator>>();
+  let (to_main, done) = mpsc::channel::<usize>();
+
+  // A long-lived worker, caching blocks for pools that come and go.
+  let worker = thread::spawn(move || {
+    while let Some(pool) = work.recv().unwrap() {
+      drop(RWIobuf::new_with_allocator(1000, pool.allocator()));
+      drop(pool);
+      to_main.send(FREE_LISTS.with(|l| l.borrow().len())).unwrap();
+    }
+  });
+
+  for _ in 0..8 {
+    let pool = PoolAllocator::new(1024, 4);
+    to_worker.send(Some(pool.clone())).unwrap();
+    // The worker's list for this pool is still cached, but any it had for
+    // the pools dropped before this one are gone.
+    assert_eq!(done.recv().unwrap(), 1);
+    drop(pool);
+  }
+
+  to_worker.send(None).unwrap();
+  worker.join().unwrap();
+}
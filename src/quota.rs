@@ -0,0 +1,241 @@
+use std::io;
+use std::ptr::NonNull;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use raw::{Allocator, HeapAllocator, ALLOCATION_HEADER_SIZE};
+
+struct QuotaInner {
+  allocator: Arc<Box<dyn Allocator>>,
+  limit:     usize,
+  /// Bytes charged against the budget.
+  used:      Mutex<usize>,
+  /// Signalled whenever bytes are given back.
+  freed:     Condvar,
+}
+
+impl QuotaInner {
+  #[inline]
+  fn lock(&self) -> MutexGuard<'_, usize> {
+    self.used.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Waits until `len` more bytes fit in the budget, or `timeout` runs out.
+  /// Returns the lock if they do, with the bytes still uncharged.
+  fn wait_for(&self, len: usize, timeout: Option<Duration>) -> Option<MutexGuard<'_, usize>> {
+    if len > self.limit {
+      return None;
+    }
+
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut used = self.lock();
+
+    while *used + len > self.limit {
+      let left =
+        match deadline {
+          None           => return None,
+          Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+        };
+      if left == Duration::from_secs(0) {
+        return None;
+      }
+      used = self.freed.wait_timeout(used, left).unwrap_or_else(|e| e.into_inner()).0;
+    }
+
+    Some(used)
+  }
+
+  fn release(&self, len: usize) {
+    *self.lock() -= len;
+    self.freed.notify_all();
+  }
+}
+
+/// An `Allocator` which wraps another one, and caps the bytes that may be
+/// allocated out of it at once.
+///
+/// Every buffer is charged for its length plus its allocation header, until
+/// it's freed. An allocation that would go over the budget fails, which makes
+/// the `try_` constructors return an `AllocationFailed` error (and the others
+/// panic). Handles made with `blocking` instead wait for other buffers to be
+/// freed, up to a timeout, before giving up.
+///
+/// Producers, like network readers, can also call `wait_for_room` to hold off
+/// pulling in more data until there's memory for it.
+///
+/// `QuotaAllocator` is a cheap handle: clones share the same budget.
+///
+/// ```rust
+/// use iobuf::{ErrorKind, QuotaAllocator, RWIobuf};
+///
+/// let quota = QuotaAllocator::heap(1024);
+///
+/// let a = RWIobuf::try_new_with_allocator(512, quota.allocator()).unwrap();
+/// let err = RWIobuf::try_new_with_allocator(512, quota.allocator()).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::AllocationFailed);
+///
+/// drop(a);
+/// assert!(RWIobuf::try_new_with_allocator(512, quota.allocator()).is_ok());
+/// ```
+#[derive(Clone)]
+pub struct QuotaAllocator {
+  inner:   Arc<QuotaInner>,
+  /// How long allocations wait for room. `None` fails them right away.
+  timeout: Option<Duration>,
+}
+
+impl QuotaAllocator {
+  /// Allows up to `limit` bytes at a time to be allocated out of `allocator`.
+  pub fn new(allocator: Arc<Box<dyn Allocator>>, limit: usize) -> QuotaAllocator {
+    QuotaAllocator {
+      inner: Arc::new(QuotaInner {
+        allocator: allocator,
+        limit:     limit,
+        used:      Mutex::new(0),
+        freed:     Condvar::new(),
+      }),
+      timeout: None,
+    }
+  }
+
+  /// Allows up to `limit` bytes at a time to be allocated out of the heap.
+  pub fn heap(limit: usize) -> QuotaAllocator {
+    QuotaAllocator::new(Arc::new(Box::new(HeapAllocator)), limit)
+  }
+
+  /// Returns a handle to the same budget, whose allocations wait up to
+  /// `timeout` for room before failing. Allocations bigger than the whole
+  /// budget still fail right away.
+  ///
+  /// ```rust
+  /// use std::thread;
+  /// use std::time::Duration;
+  /// use iobuf::{QuotaAllocator, RWIobuf};
+  ///
+  /// let quota = QuotaAllocator::heap(1024);
+  /// let a = RWIobuf::new_with_allocator(512, quota.allocator());
+  ///
+  /// let blocking = quota.blocking(Duration::from_secs(10));
+  /// let t = thread::spawn(move || {
+  ///   RWIobuf::try_new_with_allocator(512, blocking.allocator()).is_ok()
+  /// });
+  ///
+  /// thread::sleep(Duration::from_millis(10));
+  /// drop(a);
+  /// assert!(t.join().unwrap());
+  /// ```
+  pub fn blocking(&self, timeout: Duration) -> QuotaAllocator {
+    QuotaAllocator {
+      inner:   self.inner.clone(),
+      timeout: Some(timeout),
+    }
+  }
+
+  /// Returns this allocator in the form `new_with_allocator` and friends
+  /// expect.
+  #[inline]
+  pub fn allocator(&self) -> Arc<Box<dyn Allocator>> {
+    Arc::new(Box::new(self.clone()))
+  }
+
+  /// The most bytes that may be allocated at once.
+  #[inline]
+  pub fn limit(&self) -> usize {
+    self.inner.limit
+  }
+
+  /// The bytes currently allocated, headers included.
+  #[inline]
+  pub fn used(&self) -> usize {
+    *self.inner.lock()
+  }
+
+  /// Waits until a buffer of `len` bytes would fit in the budget, or until
+  /// `timeout` runs out. Returns whether it fits.
+  ///
+  /// Nothing is set aside, so another thread may take the room first.
+  pub fn wait_for_room(&self, len: usize, timeout: Duration) -> bool {
+    match len.checked_add(ALLOCATION_HEADER_SIZE) {
+      Some(len) => self.inner.wait_for(len, Some(timeout)).is_some(),
+      None      => false,
+    }
+  }
+}
+
+impl Allocator for QuotaAllocator {
+  fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+    *self.inner.wait_for(len, self.timeout)? += len;
+
+    match self.inner.allocator.allocate(len, align) {
+      Some(ptr) => Some(ptr),
+      None      => { self.inner.release(len); None },
+    }
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+    self.inner.allocator.deallocate(ptr, len, align);
+    self.inner.release(len);
+  }
+
+  fn flush(&self, ptr: NonNull<u8>, len: usize) -> io::Result<()> {
+    self.inner.allocator.flush(ptr, len)
+  }
+
+  fn read_only(&self) -> bool {
+    self.inner.allocator.read_only()
+  }
+
+  fn release_data(&self, ptr: NonNull<u8>, len: usize) -> bool {
+    let released = self.inner.allocator.release_data(ptr, len);
+    if released {
+      self.inner.release(len);
+    }
+    released
+  }
+
+  fn reclaim_vec(&self) -> Option<(NonNull<u8>, usize)> {
+    self.inner.allocator.reclaim_vec()
+  }
+}
+
+#[test]
+fn never_over_budget() {
+  use impls::RWIobuf;
+
+  let h = ALLOCATION_HEADER_SIZE;
+  let quota = QuotaAllocator::heap(3 * (h + 64));
+
+  let bufs: Vec<_> =
+    (0..3).map(|_| RWIobuf::new_with_allocator(64, quota.allocator())).collect();
+  assert_eq!(quota.used(), quota.limit());
+  assert!(RWIobuf::try_new_with_allocator(1, quota.allocator()).is_err());
+
+  // Too big to ever fit, so it doesn't wait.
+  let blocking = quota.blocking(Duration::from_secs(60));
+  assert!(RWIobuf::try_new_with_allocator(quota.limit(), blocking.allocator()).is_err());
+
+  // Nothing's freed, so it waits out the timeout.
+  let start = Instant::now();
+  assert!(!quota.wait_for_room(64, Duration::from_millis(20)));
+  assert!(start.elapsed() >= Duration::from_millis(20));
+
+  drop(bufs);
+  assert_eq!(quota.used(), 0);
+  assert!(quota.wait_for_room(64, Duration::from_millis(0)));
+}
+
+#[test]
+fn failed_allocations_are_refunded() {
+  use impls::RWIobuf;
+
+  struct Exhausted;
+
+  impl Allocator for Exhausted {
+    fn allocate(&self, _len: usize, _align: usize) -> Option<NonNull<u8>> { None }
+    fn deallocate(&self, _ptr: NonNull<u8>, _len: usize, _align: usize) {}
+  }
+
+  let quota = QuotaAllocator::new(Arc::new(Box::new(Exhausted)), 1024);
+  assert!(RWIobuf::try_new_with_allocator(16, quota.allocator()).is_err());
+  assert_eq!(quota.used(), 0);
+}
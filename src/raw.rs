@@ -1,30 +1,23 @@
-use alloc::heap::{Heap, Alloc, Layout};
-use core::ptr::Unique;
-
-use core::nonzero::NonZero;
-
+use std::alloc::{self, Layout};
+use std::cmp;
 use std::fmt::{self, Formatter};
 use std::io;
 use std::marker::PhantomData;
 use std::mem;
-use std::ptr;
-use std::i32;
-use std::u32;
+use std::ptr::{self, NonNull};
 use std::sync::Arc;
-use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::atomic::{self, AtomicU64, AtomicUsize, Ordering};
 use std::slice;
 
+use error::{ErrorKind, IobufError, iobuf_error};
 use intlike::{IntLike, from_be, from_le, to_be, to_le};
-
-#[cfg(target_pointer_width = "64")]
-const TARGET_WORD_SIZE: usize = 64;
-
-#[cfg(target_pointer_width = "32")]
-const TARGET_WORD_SIZE: usize = 32;
+#[cfg(unix)]
+use shm;
 
 /// All Iobuf data is aligned to 16 bytes. This allows people to write more
-/// efficient SIMD code on Iobufs.
-const DATA_ALIGNMENT: usize = 16;
+/// efficient SIMD code on Iobufs. Buffers made by the `new_aligned`
+/// constructors can be aligned further.
+pub const DATA_ALIGNMENT: usize = 16;
 
 const U32_BITS: usize = 32;
 
@@ -32,37 +25,96 @@ const U32_BITS: usize = 32;
 /// By limiting the buffer sizes, we can bring the struct down from 40 bytes to
 /// 24 bytes -- a 40% reduction. This frees up precious cache and registers for
 /// the actual processing.
-const MAX_BUFFER_LEN: usize = i32::MAX as usize - ALLOCATION_HEADER_SIZE;
+pub const MAX_BUFFER_LEN: usize = i32::MAX as usize - ALLOCATION_HEADER_SIZE;
 
 /// The bitmask to get the "is the buffer owned" bit.
 const OWNED_MASK: u32 = 1 << (U32_BITS - 1);
 
+/// The bitmask to get the "does the header track written bytes" bit.
+const UNINIT_MASK: u32 = 1 << (U32_BITS - 1);
+
 /// Used to provide custom memory to Iobufs, instead of just using the heap.
 pub trait Allocator: Sync + Send {
-  /// Allocates `len` bytes of memory, with an alignment of `align`.
-  fn allocate(&self, len: usize, align: usize) -> *mut u8;
+  /// Allocates `len` bytes of memory, with an alignment of `align`. Returns
+  /// `None` if the memory could not be allocated.
+  fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>>;
 
   /// Deallocates memory allocated by `allocate`.
-  fn deallocate(&self, ptr: NonZero<*mut u8>, len: usize, align: usize);
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize);
+
+  /// Writes any changes to the `len` bytes at `ptr`, which lie inside memory
+  /// returned by `allocate`, back to wherever the memory came from. Most
+  /// allocators have nowhere to write to, so by default this does nothing.
+  fn flush(&self, _ptr: NonNull<u8>, _len: usize) -> io::Result<()> {
+    Ok(())
+  }
+
+  /// Whether the memory handed out by `allocate` may not be written to, like
+  /// a read-only file mapping. Buffers from such an allocator are never
+  /// unique, so `unique`, `make_mut` and friends copy them instead of handing
+  /// out an `RWIobuf`. By default, memory is writable.
+  fn read_only(&self) -> bool {
+    false
+  }
+
+  /// Called when the last strong reference to a buffer is dropped while weak
+  /// ones are left, with its data: the `len` bytes at `ptr`, right after the
+  /// allocation header. The header has to stay until the last weak reference
+  /// goes, but the data may be freed now. Returns whether it was. If so,
+  /// `deallocate` is later called with the header's length alone.
+  ///
+  /// By default, and for the global heap, the data shares the header's
+  /// allocation, so nothing is freed until `deallocate`.
+  fn release_data(&self, _ptr: NonNull<u8>, _len: usize) -> bool {
+    false
+  }
+
+  /// Used by `RWIobuf::into_vec` to take back the allocation of a buffer made
+  /// by `RWIobuf::from_vec`, as `(pointer, capacity)`. Once this returns
+  /// `Some`, `deallocate` must not free the memory.
+  #[doc(hidden)]
+  fn reclaim_vec(&self) -> Option<(NonNull<u8>, usize)> {
+    None
+  }
 }
 
+// Aligned so that its size is a multiple of `DATA_ALIGNMENT`, which keeps the
+// data after it aligned.
+#[repr(align(16))]
 pub struct AllocationHeader {
-  allocator: Option<NonZero<*mut ()>>,
+  allocator: Option<NonNull<()>>,
   allocation_length: usize,
   refcount: usize,
-  _pad: usize,
+  // The number of `WeakIobuf`s, plus one shared by all the strong references.
+  // The allocation lives until this reaches zero.
+  weak: usize,
+  // The high-water mark of written bytes, as an index into the buffer. Reads
+  // past it are refused. `UNTRACKED` unless made by `new_uninit`.
+  initialized: usize,
+  // User metadata, shared by every Iobuf over the buffer. See `Iobuf::tag`.
+  // Atomic in its own right, since a plain `u64` may only be 4-byte aligned.
+  tag: AtomicU64,
 }
 
-// Needed because size_of isn't compile-time.
-const ALLOCATION_HEADER_SIZE: usize = 4*TARGET_WORD_SIZE/8;
+pub const ALLOCATION_HEADER_SIZE: usize = mem::size_of::<AllocationHeader>();
+
+/// The `initialized` mark of buffers whose every byte may be read.
+const UNTRACKED: usize = usize::MAX;
 
 #[test]
 fn correct_header_size() {
   assert_eq!(ALLOCATION_HEADER_SIZE, mem::size_of::<AllocationHeader>());
-  assert!(ALLOCATION_HEADER_SIZE % DATA_ALIGNMENT == 0);
+  assert_eq!(ALLOCATION_HEADER_SIZE % DATA_ALIGNMENT, 0);
   assert!(mem::align_of::<AllocationHeader>() <= DATA_ALIGNMENT);
 }
 
+#[test]
+fn heap_allocator_refuses_empty_allocations() {
+  assert!(HeapAllocator.allocate(0, 1).is_none());
+  let p = HeapAllocator.allocate(1, 1).unwrap();
+  HeapAllocator.deallocate(p, 1, 1);
+}
+
 #[test]
 fn result_of_boxed_string() {
   assert_eq!(mem::size_of::<Result<(), Box<String>>>(),
@@ -72,52 +124,100 @@ fn result_of_boxed_string() {
 /// Rust has the wrong parameter order.
 /// > http://internals.rust-lang.org/t/memcpy-is-backwards/1797
 #[inline(always)]
-unsafe fn memcpy<T>(dst: *mut T, src: *const T, n: usize) {
+pub unsafe fn memcpy<T>(dst: *mut T, src: *const T, n: usize) {
   ptr::copy_nonoverlapping(src, dst, n)
 }
 
 /// Rust has the wrong parameter order.
 /// > http://internals.rust-lang.org/t/memcpy-is-backwards/1797
 #[inline(always)]
-unsafe fn memmove<T>(dst: *mut T, src: *const T, n: usize) {
+pub unsafe fn memmove<T>(dst: *mut T, src: *const T, n: usize) {
   ptr::copy(src, dst, n)
 }
 
 impl AllocationHeader {
   #[inline]
-  fn allocate(&self, len: usize) -> *mut u8 {
+  fn allocate(&self, len: usize) -> Option<NonNull<u8>> {
     unsafe {
       match self.allocator {
         None => {
-          Heap.alloc(Layout::from_size_align(len, DATA_ALIGNMENT).unwrap())
-              .expect("could not allocate memory")
+          NonNull::new(alloc::alloc(Layout::from_size_align(len, DATA_ALIGNMENT).unwrap()))
         },
         Some(allocator) => {
-          let raw_allocator = allocator.get();
-          let allocator: &Arc<Box<Allocator>> = mem::transmute(&raw_allocator);
+          let allocator = &*(allocator.as_ptr() as *const Box<dyn Allocator>);
           allocator.allocate(len, DATA_ALIGNMENT)
         }
       }
     }
   }
 
+  /// The bytes allocated for the buffer, header included.
+  #[inline(always)]
+  pub fn allocation_length(&self) -> usize {
+    self.allocation_length
+  }
+
+  /// The reference to the allocator the buffer holds, as made by
+  /// `allocator_into_raw`, or `None` for the global heap.
   #[inline(always)]
-  unsafe fn nonatomic_refcount(&self) -> usize {
+  pub fn raw_allocator(&self) -> Option<NonNull<()>> {
+    self.allocator
+  }
+
+  #[inline(always)]
+  pub unsafe fn nonatomic_refcount(&self) -> usize {
     self.refcount
   }
 
   #[inline(always)]
-  unsafe fn atomic_refcount<'a>(&'a self) -> &'a AtomicUsize {
-    mem::transmute(&self.refcount)
+  pub unsafe fn atomic_refcount<'a>(&'a self) -> &'a AtomicUsize {
+    &*(&self.refcount as *const usize as *const AtomicUsize)
+  }
+
+  #[inline(always)]
+  pub unsafe fn nonatomic_weak_count(&self) -> usize {
+    self.weak
+  }
+
+  #[inline(always)]
+  pub unsafe fn atomic_weak_count<'a>(&'a self) -> &'a AtomicUsize {
+    &*(&self.weak as *const usize as *const AtomicUsize)
   }
 
   #[inline(always)]
-  unsafe fn inc_ref_count_atomic(&mut self) {
+  pub unsafe fn initialized<'a>(&'a self) -> &'a AtomicUsize {
+    &*(&self.initialized as *const usize as *const AtomicUsize)
+  }
+
+  #[inline(always)]
+  pub fn tag(&self) -> &AtomicU64 {
+    &self.tag
+  }
+
+  // A buffer with weak references isn't unique, since they could be upgraded
+  // at any time. Only a strong reference can make a weak one, so neither count
+  // can change under us once both are 1. Nor is read-only memory, since a
+  // unique buffer may be written to.
+
+  #[inline]
+  pub unsafe fn is_unique_nonatomic(&self) -> bool {
+    self.refcount == 1 && self.weak == 1 && !self.read_only()
+  }
+
+  #[inline]
+  pub unsafe fn is_unique_atomic(&self) -> bool {
+    self.atomic_refcount().load(Ordering::SeqCst) == 1
+      && self.atomic_weak_count().load(Ordering::SeqCst) == 1
+      && !self.read_only()
+  }
+
+  #[inline(always)]
+  pub unsafe fn inc_ref_count_atomic(&mut self) {
     self.atomic_refcount().fetch_add(1, Ordering::Relaxed);
   }
 
   #[inline(always)]
-  unsafe fn inc_ref_count_nonatomic(&mut self) {
+  pub unsafe fn inc_ref_count_nonatomic(&mut self) {
     self.refcount += 1;
   }
 
@@ -126,26 +226,100 @@ impl AllocationHeader {
     unsafe {
       Deallocator {
         allocation_length: self.allocation_length,
-        allocator:
-          match self.allocator {
-            None => None,
-            Some(allocator) => mem::transmute(allocator.get()),
-          },
+        allocator:         self.allocator.map(|allocator| allocator_from_raw(allocator)),
+      }
+    }
+  }
+
+  #[inline(always)]
+  pub unsafe fn inc_weak_count_atomic(&mut self) {
+    self.atomic_weak_count().fetch_add(1, Ordering::Relaxed);
+  }
+
+  #[inline(always)]
+  pub unsafe fn inc_weak_count_nonatomic(&mut self) {
+    self.weak += 1;
+  }
+
+  /// Takes a new strong reference, unless the last one is already gone.
+  #[inline]
+  pub unsafe fn upgrade_nonatomic(&mut self) -> Result<(), ()> {
+    if self.refcount == 0 {
+      return Err(());
+    }
+    self.refcount += 1;
+    Ok(())
+  }
+
+  #[inline]
+  pub unsafe fn upgrade_atomic(&mut self) -> Result<(), ()> {
+    let refcount = self.atomic_refcount();
+    let mut n = refcount.load(Ordering::Relaxed);
+    loop {
+      if n == 0 {
+        return Err(());
+      }
+      match refcount.compare_exchange_weak(n, n + 1, Ordering::Acquire, Ordering::Relaxed) {
+        Ok(_)  => return Ok(()),
+        Err(m) => n = m,
       }
     }
   }
 
+  /// Drops a strong reference to the buffer at `buf`, and frees it if that
+  /// was the last reference of any kind. If only weak ones are left, the data
+  /// is handed back early, through `Allocator::release_data`.
   #[inline]
-  #[must_use]
-  unsafe fn dec_ref_count_nonatomic(&mut self) -> Result<(), ()> {
+  pub unsafe fn drop_strong_nonatomic(&mut self, buf: NonNull<u8>) {
     debug_assert!(self.refcount != 0);
     self.refcount -= 1;
-    err_if(self.refcount == 0)
+    if self.refcount != 0 {
+      return;
+    }
+    if self.weak > 1 {
+      self.release_data(buf);
+    }
+    // Release the weak reference held by the strong ones.
+    if self.dec_weak_count_nonatomic().is_err() {
+      self.deallocate(buf);
+    }
+  }
+
+  #[inline]
+  pub unsafe fn drop_strong_atomic(&mut self, buf: NonNull<u8>) {
+    if self.dec_ref_count_atomic_only().is_ok() {
+      return;
+    }
+    // Only strong references make weak ones, so if there are none left now,
+    // there never will be.
+    if self.atomic_weak_count().load(Ordering::Acquire) > 1 {
+      self.release_data(buf);
+    }
+    if self.dec_weak_count_atomic().is_err() {
+      self.deallocate(buf);
+    }
+  }
+
+  #[cold]
+  unsafe fn release_data(&mut self, buf: NonNull<u8>) {
+    #[cfg(unix)]
+    {
+      if let Some(released) = shm::release_exported_data(buf) {
+        if released {
+          self.allocation_length = ALLOCATION_HEADER_SIZE;
+        }
+        return;
+      }
+    }
+    let len = self.allocation_length - ALLOCATION_HEADER_SIZE;
+    if len > 0 && self.allocator().is_some_and(|a| a.release_data(buf, len)) {
+      self.allocation_length = ALLOCATION_HEADER_SIZE;
+    }
   }
 
+  /// Drops a strong reference. Returns `Err` if it was the last one.
   #[inline]
-  #[must_use]
-  unsafe fn dec_ref_count_atomic(&mut self) -> Result<(), ()> {
+  unsafe fn dec_ref_count_atomic_only(&mut self) -> Result<(), ()> {
     if self.atomic_refcount().fetch_sub(1, Ordering::Release) == 1 {
       atomic::fence(Ordering::Acquire);
       Err(())
@@ -154,9 +328,84 @@ impl AllocationHeader {
     }
   }
 
+  /// Returns `Err` if the allocation has to be freed.
+  #[inline]
+  pub unsafe fn dec_ref_count_atomic(&mut self) -> Result<(), ()> {
+    if self.dec_ref_count_atomic_only().is_err() {
+      // Release the weak reference held by the strong ones.
+      self.dec_weak_count_atomic()
+    } else {
+      Ok(())
+    }
+  }
+
+  #[inline]
+  pub unsafe fn dec_weak_count_nonatomic(&mut self) -> Result<(), ()> {
+    debug_assert!(self.weak != 0);
+    self.weak -= 1;
+    err_if(self.weak == 0)
+  }
+
+  #[inline]
+  pub unsafe fn dec_weak_count_atomic(&mut self) -> Result<(), ()> {
+    if self.atomic_weak_count().fetch_sub(1, Ordering::Release) == 1 {
+      atomic::fence(Ordering::Acquire);
+      Err(())
+    } else {
+      Ok(())
+    }
+  }
+
+  /// The allocator the buffer came from, or `None` for the global heap.
+  #[inline]
+  pub unsafe fn allocator<'a>(&'a self) -> Option<&'a dyn Allocator> {
+    self.allocator.map(|allocator| &**(allocator.as_ptr() as *const Box<dyn Allocator>))
+  }
+
+  /// Another reference to the allocator the buffer came from, or `None` for
+  /// the global heap.
+  #[inline]
+  pub unsafe fn allocator_arc(&self) -> Option<Arc<Box<dyn Allocator>>> {
+    self.allocator.map(|allocator| {
+      let allocator = allocator.as_ptr() as *const Box<dyn Allocator>;
+      Arc::increment_strong_count(allocator);
+      Arc::from_raw(allocator)
+    })
+  }
+
+  /// Whether the buffer's memory may not be written to.
+  #[inline]
+  pub unsafe fn read_only(&self) -> bool {
+    self.allocator().is_some_and(|a| a.read_only())
+  }
+
+  /// Flushes `len` bytes at `ptr` through the buffer's allocator, if any.
+  pub unsafe fn flush(&self, ptr: NonNull<u8>, len: usize) -> io::Result<()> {
+    match self.allocator {
+      None            => Ok(()),
+      Some(allocator) => (*(allocator.as_ptr() as *const Box<dyn Allocator>)).flush(ptr, len),
+    }
+  }
+
+  /// Overwrites what's used to free the buffer, like another process sharing
+  /// the memory could.
+  #[cfg(test)]
+  pub fn scribble(&mut self, allocator: Option<NonNull<()>>, allocation_length: usize) {
+    self.allocator = allocator;
+    self.allocation_length = allocation_length;
+  }
+
   // Keep this out of line to allow inlining of the drop glue.
   #[cold]
-  unsafe fn deallocate(&self, buf: NonZero<*mut u8>) {
+  pub unsafe fn deallocate(&self, buf: NonNull<u8>) {
+    // Another process could have written this header.
+    #[cfg(unix)]
+    {
+      if let Some((len, allocator)) = shm::take_exported(buf) {
+        let deallocator = Deallocator { allocation_length: len, allocator: Some(allocator_from_raw(allocator)) };
+        return deallocator.deallocate(buf);
+      }
+    }
     self.deallocator().deallocate(buf)
   }
 }
@@ -165,24 +414,97 @@ impl AllocationHeader {
 /// header.
 struct Deallocator {
   allocation_length: usize,
-  allocator: Option<Arc<Box<Allocator>>>,
+  allocator: Option<Arc<Box<dyn Allocator>>>,
 }
 
 impl Deallocator {
-  fn deallocate(self, ptr: NonZero<*mut u8>) {
+  fn deallocate(self, ptr: NonNull<u8>) {
     unsafe {
-      let ptr: *mut u8 = ptr.get().offset(-(ALLOCATION_HEADER_SIZE as isize));
+      let ptr: *mut u8 = ptr.as_ptr().offset(-(ALLOCATION_HEADER_SIZE as isize));
       match self.allocator {
         None => {
-          Heap.dealloc(ptr, Layout::from_size_align(self.allocation_length, DATA_ALIGNMENT).unwrap())
+          alloc::dealloc(ptr, Layout::from_size_align(self.allocation_length, DATA_ALIGNMENT).unwrap())
         },
         Some(alloc) =>
-          alloc.deallocate(NonZero::new_unchecked(ptr), self.allocation_length, DATA_ALIGNMENT),
+          alloc.deallocate(NonNull::new_unchecked(ptr), self.allocation_length, DATA_ALIGNMENT),
       }
     }
   }
 }
 
+/// The global heap, as an `Allocator`, for allocators that wrap another one.
+pub struct HeapAllocator;
+
+impl Allocator for HeapAllocator {
+  fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+    // The global allocator doesn't take zero-sized layouts.
+    if len == 0 {
+      return None;
+    }
+    unsafe { NonNull::new(alloc::alloc(Layout::from_size_align(len, align).ok()?)) }
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+    unsafe { alloc::dealloc(ptr.as_ptr(), Layout::from_size_align_unchecked(len, align)) }
+  }
+}
+
+/// Allocates buffers whose data is aligned to more than `DATA_ALIGNMENT`.
+///
+/// Every allocation is padded at the front, so that the header ends, and the
+/// data starts, on an `align` boundary. The padding comes out of the global
+/// heap, or `inner` if there is one.
+struct AlignedAllocator {
+  align: usize,
+  inner: Option<Arc<Box<dyn Allocator>>>,
+}
+
+impl AlignedAllocator {
+  /// The number of bytes in front of the header.
+  #[inline]
+  fn padding(&self) -> usize {
+    let header_end = (ALLOCATION_HEADER_SIZE + self.align - 1) & !(self.align - 1);
+    header_end - ALLOCATION_HEADER_SIZE
+  }
+}
+
+impl Allocator for AlignedAllocator {
+  fn allocate(&self, len: usize, _align: usize) -> Option<NonNull<u8>> {
+    let padding = self.padding();
+    let total   = len.checked_add(padding)?;
+    let base =
+      match self.inner {
+        None            => HeapAllocator.allocate(total, self.align)?,
+        Some(ref inner) => inner.allocate(total, self.align)?,
+      };
+    unsafe { Some(NonNull::new_unchecked(base.as_ptr().add(padding))) }
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, _align: usize) {
+    let padding = self.padding();
+    let base    = unsafe { NonNull::new_unchecked(ptr.as_ptr().sub(padding)) };
+    match self.inner {
+      None            => HeapAllocator.deallocate(base, len + padding, self.align),
+      Some(ref inner) => inner.deallocate(base, len + padding, self.align),
+    }
+  }
+
+  fn flush(&self, ptr: NonNull<u8>, len: usize) -> io::Result<()> {
+    match self.inner {
+      None            => Ok(()),
+      Some(ref inner) => inner.flush(ptr, len),
+    }
+  }
+
+  fn read_only(&self) -> bool {
+    self.inner.as_ref().is_some_and(|inner| inner.read_only())
+  }
+
+  fn release_data(&self, ptr: NonNull<u8>, len: usize) -> bool {
+    self.inner.as_ref().is_some_and(|inner| inner.release_data(ptr, len))
+  }
+}
+
 // By factoring out the calls to `panic!`, we prevent rustc from emitting a ton
 // of formatting code in our tight, little functions, and also help guide
 // inlining.
@@ -193,14 +515,34 @@ fn bad_range(pos: u64, len: u64) -> ! {
 }
 
 #[cold]
-fn buffer_too_big(actual_size: usize) -> ! {
+pub fn buffer_too_big(actual_size: usize) -> ! {
   panic!("Tried to create an Iobuf that's too big: {} bytes. Max size = {}",
          actual_size, MAX_BUFFER_LEN)
 }
 
 #[cold]
 fn allocator_returned_null() -> ! {
-  panic!("Iobuf's allocator returned NULL. Out of memory?");
+  panic!("Iobuf's allocator failed. Out of memory?");
+}
+
+/// Panics with the message matching an error from a `try_` constructor.
+#[cold]
+fn allocation_failed(e: IobufError) -> ! {
+  match e.kind() {
+    ErrorKind::TooLarge => buffer_too_big(e.range().1 as usize),
+    _                   => allocator_returned_null(),
+  }
+}
+
+/// Builds the error for a failure to allocate a buffer of `len` bytes.
+#[cold]
+pub fn allocation_error(kind: ErrorKind, len: usize) -> IobufError {
+  iobuf_error(kind, 0, len as u64, (0, 0), (0, 0))
+}
+
+#[cold]
+fn bad_alignment(align: usize) -> ! {
+  panic!("Iobuf alignment must be a power of two, got {}", align);
 }
 
 #[cold]
@@ -210,7 +552,7 @@ fn improperly_aligned_data(ptr: *mut u8) -> ! {
 
 #[cold]
 pub fn write_failed() -> io::Result<usize> {
-  Err(io::Error::new(io::ErrorKind::Other, "No space left in iobuf."))
+  Err(io::Error::other("No space left in iobuf."))
 }
 
 #[inline(always)]
@@ -218,6 +560,82 @@ fn err_if(cond: bool) -> Result<(), ()> {
   if cond { Err(()) } else { Ok(()) }
 }
 
+/// Leaks a reference to `allocator`, for storing in an `AllocationHeader`. It's
+/// reclaimed when the buffer is deallocated.
+#[inline]
+pub fn allocator_into_raw(allocator: Arc<Box<dyn Allocator>>) -> NonNull<()> {
+  unsafe { NonNull::new_unchecked(Arc::into_raw(allocator) as *mut ()) }
+}
+
+/// Reclaims a reference leaked by `allocator_into_raw`.
+#[inline]
+pub unsafe fn allocator_from_raw(allocator: NonNull<()>) -> Arc<Box<dyn Allocator>> {
+  Arc::from_raw(allocator.as_ptr() as *const Box<dyn Allocator>)
+}
+
+/// Allocates an `AllocationHeader` followed by `len` bytes of data, and returns
+/// a pointer to the data. The header starts out with a refcount of 1.
+///
+/// The caller is responsible for checking `len` against its own size limits.
+pub unsafe fn allocate_buffer(
+    len:       usize,
+    allocator: Option<NonNull<()>>) -> NonNull<u8> {
+  match try_allocate_buffer(len, allocator) {
+    Some(buf) => buf,
+    None      => allocator_returned_null(),
+  }
+}
+
+/// The same as `allocate_buffer`, but returns `None` if the allocator fails.
+/// The reference to the allocator is released in that case.
+pub unsafe fn try_allocate_buffer(
+    len:       usize,
+    allocator: Option<NonNull<()>>) -> Option<NonNull<u8>> {
+  let data_len = ALLOCATION_HEADER_SIZE + len;
+
+  let allocation_header =
+    AllocationHeader {
+      allocator:         allocator,
+      allocation_length: data_len,
+      refcount:          1,
+      weak:              1,
+      initialized:       UNTRACKED,
+      tag:               AtomicU64::new(0),
+    };
+
+  let buf =
+    match allocation_header.allocate(data_len) {
+      Some(buf) => buf.as_ptr(),
+      None => {
+        drop(allocation_header.deallocator());
+        return None;
+      },
+    };
+
+  ptr::write(buf as *mut AllocationHeader, allocation_header);
+
+  let buf: *mut u8 = buf.add(ALLOCATION_HEADER_SIZE);
+
+  if buf as usize % DATA_ALIGNMENT != 0 {
+    improperly_aligned_data(buf);
+  }
+
+  Some(NonNull::new_unchecked(buf))
+}
+
+/// Gets the header of a buffer returned by `allocate_buffer`.
+#[inline]
+pub unsafe fn header_of<'a>(buf: NonNull<u8>) -> &'a mut AllocationHeader {
+  &mut *(buf.as_ptr().offset(-(ALLOCATION_HEADER_SIZE as isize)) as *mut AllocationHeader)
+}
+
+/// How many of the first `cap` bytes of the buffer at `buf` may be read. That's
+/// all of them, unless it was made by `new_uninit`.
+#[inline]
+pub unsafe fn written_len(buf: NonNull<u8>, cap: usize) -> usize {
+  cmp::min(header_of(buf).initialized().load(Ordering::Relaxed), cap)
+}
+
 /// A `RawIobuf` is the representation of both a `RWIobuf` and a `ROIobuf`.
 /// It is very cheap to clone, as the backing buffer is shared and refcounted.
 pub struct RawIobuf<'a> {
@@ -225,7 +643,7 @@ pub struct RawIobuf<'a> {
   // If the buf was allocated by us (i.e. the owned bit is set), the bytes
   // immediately preceding represent the allocation header. See the `header`
   // function.
-  buf:    NonZero<*mut u8>,
+  buf:    NonNull<u8>,
   // If the highest bit of this is set, `buf` is owned and the data before the
   // pointeer is valid. If it is not set, then the buffer wasn't allocated by us:
   // it's owned by someone else. Therefore, there's no header, and no need to
@@ -233,7 +651,11 @@ pub struct RawIobuf<'a> {
   lo_min_and_owned_bit: u32,
   lo:     u32,
   hi:     u32,
-  hi_max: u32,
+  // If the highest bit of this is set, the buffer was made by `new_uninit` (or
+  // copied from one), and the header's `initialized` mark has to be checked
+  // before reading. If not, every byte may be read, without touching the
+  // header.
+  hi_max_and_uninit_bit: u32,
   lifetm: PhantomData<&'a ()>,
 }
 
@@ -244,67 +666,123 @@ fn check_sane_raw_size() {
 }
 
 impl<'a> RawIobuf<'a> {
-  pub fn new_impl(
+  /// Allocates a buffer of `len` bytes whose data is aligned to `align`, which
+  /// must be a power of two. Anything above `DATA_ALIGNMENT` moves the header
+  /// up into a padded allocation, so it still sits right before the data.
+  pub fn try_new_impl(
       len:       usize,
-      allocator: Option<NonZero<*mut ()>>) -> RawIobuf<'static> {
+      allocator: Option<Arc<Box<dyn Allocator>>>,
+      align:     usize) -> Result<RawIobuf<'static>, IobufError> {
     unsafe {
-      if len > MAX_BUFFER_LEN {
-        buffer_too_big(len);
+      if !align.is_power_of_two() {
+        bad_alignment(align);
       }
 
-      let data_len = ALLOCATION_HEADER_SIZE + len;
+      if len > MAX_BUFFER_LEN {
+        return Err(allocation_error(ErrorKind::TooLarge, len));
+      }
 
-      let allocation_header =
-        AllocationHeader {
-          allocator:         allocator,
-          allocation_length: data_len,
-          refcount:          1,
-          _pad:              0,
+      let allocator =
+        if align > DATA_ALIGNMENT {
+          let aligned: Box<dyn Allocator> =
+            Box::new(AlignedAllocator { align: align, inner: allocator });
+          Some(Arc::new(aligned))
+        } else {
+          allocator
         };
 
-      let buf = allocation_header.allocate(data_len);
-
-      if buf.is_null() {
-        allocator_returned_null();
+      match try_allocate_buffer(len, allocator.map(allocator_into_raw)) {
+        Some(buf) =>
+          Ok(RawIobuf {
+            buf:    buf,
+            lo_min_and_owned_bit: OWNED_MASK,
+            lo:     0,
+            hi:     len as u32,
+            hi_max_and_uninit_bit: len as u32,
+            lifetm: PhantomData,
+          }),
+        None => Err(allocation_error(ErrorKind::AllocationFailed, len)),
       }
+    }
+  }
 
-      ptr::write(buf as *mut AllocationHeader, allocation_header);
+  #[inline]
+  pub fn new(len: usize) -> RawIobuf<'static> {
+    RawIobuf::try_new(len).unwrap_or_else(|e| allocation_failed(e))
+  }
 
-      let buf: *mut u8 = buf.offset(ALLOCATION_HEADER_SIZE as isize);
+  #[inline]
+  pub fn new_with_allocator(len: usize, allocator: Arc<Box<dyn Allocator>>) -> RawIobuf<'static> {
+    RawIobuf::try_new_with_allocator(len, allocator).unwrap_or_else(|e| allocation_failed(e))
+  }
 
-      if buf as usize % DATA_ALIGNMENT != 0 {
-        improperly_aligned_data(buf);
-      }
+  #[inline]
+  pub fn new_aligned(len: usize, align: usize) -> RawIobuf<'static> {
+    RawIobuf::try_new_aligned(len, align).unwrap_or_else(|e| allocation_failed(e))
+  }
 
-      RawIobuf {
-        buf:    NonZero::new_unchecked(buf),
-        lo_min_and_owned_bit: OWNED_MASK,
-        lo:     0,
-        hi:     len as u32,
-        hi_max: len as u32,
-        lifetm: PhantomData,
-      }
+  #[inline]
+  pub fn new_zeroed(len: usize) -> RawIobuf<'static> {
+    let b = RawIobuf::new(len);
+    unsafe { ptr::write_bytes(b.buf.as_ptr(), 0, len) }
+    b
+  }
+
+  #[inline]
+  pub fn new_uninit(len: usize) -> RawIobuf<'static> {
+    let mut b = RawIobuf::new(len);
+    if let Some(h) = b.header() {
+      h.initialized = 0;
+      b.hi_max_and_uninit_bit |= UNINIT_MASK;
     }
+    b
   }
 
   #[inline]
-  pub fn new(len: usize) -> RawIobuf<'static> {
-    RawIobuf::new_impl(len, None)
+  pub fn try_new(len: usize) -> Result<RawIobuf<'static>, IobufError> {
+    RawIobuf::try_new_impl(len, None, DATA_ALIGNMENT)
   }
 
   #[inline]
-  pub fn new_with_allocator(len: usize, allocator: Arc<Box<Allocator>>) -> RawIobuf<'static> {
-    unsafe {
-      let allocator: *mut () = mem::transmute(allocator);
-      RawIobuf::new_impl(len, Some(NonZero::new_unchecked(allocator)))
-    }
+  pub fn try_new_with_allocator(len: usize, allocator: Arc<Box<dyn Allocator>>) -> Result<RawIobuf<'static>, IobufError> {
+    RawIobuf::try_new_impl(len, Some(allocator), DATA_ALIGNMENT)
+  }
+
+  #[inline]
+  pub fn try_new_aligned(len: usize, align: usize) -> Result<RawIobuf<'static>, IobufError> {
+    RawIobuf::try_new_impl(len, None, align)
   }
 
   #[cold]
   fn inv_fail(&self, msg: &str) -> Box<String> {
     Box::new(format!("{}: Iobuf {{ buf: {:?}, owned: {}, lo_min: {}, lo: {}, hi: {}, hi_max: {} }}",
       msg,
-      self.buf.get(), self.is_owned(), self.lo_min(), self.lo(), self.hi(), self.hi_max()))
+      self.buf.as_ptr(), self.is_owned(), self.lo_min(), self.lo(), self.hi(), self.hi_max()))
+  }
+
+  /// Builds an error of the given kind, recording the current limits and
+  /// window alongside the requested range.
+  #[cold]
+  pub fn error(&self, kind: ErrorKind, pos: u64, len: u64) -> IobufError {
+    iobuf_error(kind, pos, len,
+                (self.lo_min() as u64, self.hi_max() as u64),
+                (self.lo       as u64, self.hi     as u64))
+  }
+
+  /// The error for a failed `check_range`. A range starting inside the window
+  /// just needs more bytes. One starting past it is out of range.
+  #[cold]
+  fn range_error(&self, pos: u64, len: u64) -> IobufError {
+    let kind =
+      if pos > self.len() as u64 { ErrorKind::OutOfRange }
+      else                       { ErrorKind::Incomplete };
+    self.error(kind, pos, len)
+  }
+
+  #[cold]
+  fn limits_error(&self, limits: (u32, u32)) -> IobufError {
+    let (lo_min, hi_max) = limits;
+    self.error(ErrorKind::InvalidLimits, lo_min as u64, hi_max.saturating_sub(lo_min) as u64)
   }
 
   /// Checks a single invariant condition, returning a boxed (for size savings)
@@ -319,17 +797,17 @@ impl<'a> RawIobuf<'a> {
   }
 
   pub fn invariant(&self) -> Result<(), Box<String>> {
-    try!(self.inv_check(self.lo_min() <= self.hi_max(),
-      "The end of the limits must be after the start of the limits"));
+    self.inv_check(self.lo_min() <= self.hi_max(),
+      "The end of the limits must be after the start of the limits")?;
 
-    try!(self.inv_check(self.lo() <= self.hi(),
-      "The end of the window must be after the start of the window"));
+    self.inv_check(self.lo() <= self.hi(),
+      "The end of the window must be after the start of the window")?;
 
-    try!(self.inv_check(self.lo_min() <= self.lo(),
-      "The start of the window must be after the start of the limits"));
+    self.inv_check(self.lo_min() <= self.lo(),
+      "The start of the window must be after the start of the limits")?;
 
-    try!(self.inv_check(self.hi() <= self.hi_max(),
-      "The end of the limits must be after the end of the window"));
+    self.inv_check(self.hi() <= self.hi_max(),
+      "The end of the limits must be after the end of the window")?;
 
     Ok(())
   }
@@ -344,18 +822,18 @@ impl<'a> RawIobuf<'a> {
   fn debug_check_invariants<T>(&self, t: T) -> T {
     match self.invariant() {
       Ok(())   => t,
-      Err(msg) => panic!(&*msg)
+      Err(msg) => panic!("{}", msg)
     }
   }
 
   #[inline]
   pub fn empty() -> RawIobuf<'static> {
     RawIobuf {
-      buf:    unsafe { NonZero::new_unchecked(Unique::empty().as_ptr()) },
+      buf:    NonNull::dangling(),
       lo_min_and_owned_bit: 0,
       lo:     0,
       hi:     0,
-      hi_max: 0,
+      hi_max_and_uninit_bit: 0,
       lifetm: PhantomData,
     }
   }
@@ -380,13 +858,8 @@ impl<'a> RawIobuf<'a> {
   unsafe fn nonatomic_dec_ref_count(&self) {
     let buf = self.buf;
 
-    match self.header() {
-      None => {},
-      Some(h) => {
-        if h.dec_ref_count_nonatomic().is_err() {
-          h.deallocate(buf);
-        }
-      }
+    if let Some(h) = self.header() {
+      h.drop_strong_nonatomic(buf);
     }
   }
 
@@ -394,13 +867,8 @@ impl<'a> RawIobuf<'a> {
   unsafe fn atomic_dec_ref_count(&self) {
     let buf = self.buf;
 
-    match self.header() {
-      None => {},
-      Some(h) => {
-        if h.dec_ref_count_atomic().is_err() {
-          h.deallocate(buf);
-        }
-      }
+    if let Some(h) = self.header() {
+      h.drop_strong_atomic(buf);
     }
   }
 
@@ -413,7 +881,7 @@ impl<'a> RawIobuf<'a> {
       lo_min_and_owned_bit: self.lo_min_and_owned_bit,
       lo:     self.lo,
       hi:     self.hi,
-      hi_max: self.hi_max,
+      hi_max_and_uninit_bit: self.hi_max_and_uninit_bit,
       lifetm: PhantomData,
     }
   }
@@ -441,7 +909,7 @@ impl<'a> RawIobuf<'a> {
     self.lo_min_and_owned_bit = source.lo_min_and_owned_bit;
     self.lo     = source.lo;
     self.hi     = source.hi;
-    self.hi_max = source.hi_max;
+    self.hi_max_and_uninit_bit = source.hi_max_and_uninit_bit;
 
     self.debug_check_invariants(())
   }
@@ -455,7 +923,7 @@ impl<'a> RawIobuf<'a> {
       lo_min_and_owned_bit: self.lo_min_and_owned_bit,
       lo:     self.lo,
       hi:     self.hi,
-      hi_max: self.hi_max,
+      hi_max_and_uninit_bit: self.hi_max_and_uninit_bit,
       lifetm: PhantomData,
     }
   }
@@ -471,7 +939,7 @@ impl<'a> RawIobuf<'a> {
     self.lo_min_and_owned_bit = source.lo_min_and_owned_bit;
     self.lo     = source.lo;
     self.hi     = source.hi;
-    self.hi_max = source.hi_max;
+    self.hi_max_and_uninit_bit = source.hi_max_and_uninit_bit;
 
     self.debug_check_invariants(())
   }
@@ -492,6 +960,81 @@ impl<'a> RawIobuf<'a> {
     self.lo_min_and_owned_bit = 0;
   }
 
+  /// A copy of the limits and window, without touching any refcount.
+  #[inline]
+  unsafe fn alias(&self) -> RawIobuf<'a> {
+    RawIobuf {
+      buf:    self.buf,
+      lo_min_and_owned_bit: self.lo_min_and_owned_bit,
+      lo:     self.lo,
+      hi:     self.hi,
+      hi_max_and_uninit_bit: self.hi_max_and_uninit_bit,
+      lifetm: PhantomData,
+    }
+  }
+
+  /// A weak reference to the buffer, with the same limits and window. Borrowed
+  /// buffers aren't refcounted, so their weak references always upgrade.
+  #[inline]
+  pub unsafe fn downgrade_nonatomic(&self) -> RawIobuf<'a> {
+    if let Some(h) = self.header() {
+      h.inc_weak_count_nonatomic();
+    }
+    self.alias()
+  }
+
+  #[inline]
+  pub unsafe fn downgrade_atomic(&self) -> RawIobuf<'a> {
+    if let Some(h) = self.header() {
+      h.inc_weak_count_atomic();
+    }
+    self.alias()
+  }
+
+  /// A strong reference from a weak one, if the buffer is still alive.
+  #[inline]
+  pub unsafe fn upgrade_nonatomic(&self) -> Option<RawIobuf<'a>> {
+    if let Some(h) = self.header() {
+      if h.upgrade_nonatomic().is_err() {
+        return None;
+      }
+    }
+    Some(self.alias())
+  }
+
+  #[inline]
+  pub unsafe fn upgrade_atomic(&self) -> Option<RawIobuf<'a>> {
+    if let Some(h) = self.header() {
+      if h.upgrade_atomic().is_err() {
+        return None;
+      }
+    }
+    Some(self.alias())
+  }
+
+  /// Drops a weak reference made by `downgrade_nonatomic`.
+  #[inline]
+  pub unsafe fn drop_weak_nonatomic(&mut self) {
+    let buf = self.buf;
+    if let Some(h) = self.header() {
+      if h.dec_weak_count_nonatomic().is_err() {
+        h.deallocate(buf);
+      }
+    }
+    self.lo_min_and_owned_bit = 0;
+  }
+
+  #[inline]
+  pub unsafe fn drop_weak_atomic(&mut self) {
+    let buf = self.buf;
+    if let Some(h) = self.header() {
+      if h.dec_weak_count_atomic().is_err() {
+        h.deallocate(buf);
+      }
+    }
+    self.lo_min_and_owned_bit = 0;
+  }
+
   #[inline]
   pub fn lo_min(&self) -> u32 {
     self.lo_min_and_owned_bit & !OWNED_MASK
@@ -499,10 +1042,8 @@ impl<'a> RawIobuf<'a> {
 
   #[inline]
   pub fn set_lo_min(&mut self, new_value: u32) {
-    if cfg!(debug) {
-      if new_value > MAX_BUFFER_LEN as u32 {
-        panic!("new lo_min out of range (max = {:X}): {:X}", MAX_BUFFER_LEN, new_value);
-      }
+    if cfg!(debug) && new_value > MAX_BUFFER_LEN as u32 {
+      panic!("new lo_min out of range (max = {:X}): {:X}", MAX_BUFFER_LEN, new_value);
     }
     self.lo_min_and_owned_bit &= OWNED_MASK;
     self.lo_min_and_owned_bit |= new_value;
@@ -519,14 +1060,65 @@ impl<'a> RawIobuf<'a> {
   pub fn header(&self) -> Option<&mut AllocationHeader> {
     unsafe {
       if self.is_owned() {
-        Some(mem::transmute(
-          self.buf.get().offset(-(ALLOCATION_HEADER_SIZE as isize))))
+        Some(header_of(self.buf))
       } else {
         None
       }
     }
   }
 
+  /// Takes ownership of `len` bytes at `buf`, writing an allocation header into
+  /// the `ALLOCATION_HEADER_SIZE` bytes before it. When the last reference is
+  /// dropped, the header's memory and the buffer are handed to `allocator`'s
+  /// `deallocate` as a single allocation.
+  ///
+  /// `buf` must be aligned to `DATA_ALIGNMENT`, and the header's bytes must be
+  /// writable and unused by anyone else.
+  pub unsafe fn adopt(
+      buf:       NonNull<u8>,
+      len:       usize,
+      allocator: Arc<Box<dyn Allocator>>) -> RawIobuf<'static> {
+    if len > MAX_BUFFER_LEN {
+      buffer_too_big(len);
+    }
+
+    if buf.as_ptr() as usize % DATA_ALIGNMENT != 0 {
+      improperly_aligned_data(buf.as_ptr());
+    }
+
+    ptr::write(
+      buf.as_ptr().offset(-(ALLOCATION_HEADER_SIZE as isize)) as *mut AllocationHeader,
+      AllocationHeader {
+        allocator:         Some(allocator_into_raw(allocator)),
+        allocation_length: ALLOCATION_HEADER_SIZE + len,
+        refcount:          1,
+        weak:              1,
+        initialized:       UNTRACKED,
+        tag:               AtomicU64::new(0),
+      });
+
+    RawIobuf {
+      buf:    buf,
+      lo_min_and_owned_bit: OWNED_MASK,
+      lo:     0,
+      hi:     len as u32,
+      hi_max_and_uninit_bit: len as u32,
+      lifetm: PhantomData,
+    }
+  }
+
+  /// Flushes the limits through the buffer's allocator. See `Allocator::flush`.
+  #[inline]
+  pub fn flush_limits(&self) -> io::Result<()> {
+    match self.header() {
+      None    => Ok(()),
+      Some(h) => unsafe {
+        let limits = self.as_raw_limit_slice();
+        h.flush(NonNull::new_unchecked(limits.as_ptr() as *mut u8), limits.len())
+      },
+    }
+  }
+
   #[inline]
   pub fn from_str(s: &'a str) -> Self {
     RawIobuf::from_slice(s.as_bytes())
@@ -538,7 +1130,7 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline]
-  pub fn from_str_copy_with_allocator(s: &str, allocator: Arc<Box<Allocator>>) -> RawIobuf<'static> {
+  pub fn from_str_copy_with_allocator(s: &str, allocator: Arc<Box<dyn Allocator>>) -> RawIobuf<'static> {
     RawIobuf::from_slice_copy_with_allocator(s.as_bytes(), allocator)
   }
 
@@ -552,11 +1144,11 @@ impl<'a> RawIobuf<'a> {
       }
 
       RawIobuf {
-        buf:    NonZero::new_unchecked(s.as_ptr() as *mut u8),
+        buf:    NonNull::new_unchecked(s.as_ptr() as *mut u8),
         lo_min_and_owned_bit: 0,
         lo:     0,
         hi:     len as u32,
-        hi_max: len as u32,
+        hi_max_and_uninit_bit: len as u32,
         lifetm: PhantomData,
       }
     }
@@ -564,69 +1156,190 @@ impl<'a> RawIobuf<'a> {
 
   #[inline]
   pub fn from_slice_copy(s: &[u8]) -> RawIobuf<'static> {
+    RawIobuf::try_from_slice_copy(s).unwrap_or_else(|e| allocation_failed(e))
+  }
+
+  #[inline]
+  pub fn from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<dyn Allocator>>) -> RawIobuf<'static> {
+    RawIobuf::try_from_slice_copy_with_allocator(s, allocator).unwrap_or_else(|e| allocation_failed(e))
+  }
+
+  #[inline]
+  pub fn try_from_slice_copy(s: &[u8]) -> Result<RawIobuf<'static>, IobufError> {
     unsafe {
-      let b = RawIobuf::new(s.len());
-      memcpy(b.buf.get(), s.as_ptr(), s.len());
-      b
+      let b = RawIobuf::try_new(s.len())?;
+      memcpy(b.buf.as_ptr(), s.as_ptr(), s.len());
+      Ok(b)
     }
   }
 
   #[inline]
-  pub fn from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<Allocator>>) -> RawIobuf<'static> {
+  pub fn try_from_slice_copy_with_allocator(s: &[u8], allocator: Arc<Box<dyn Allocator>>) -> Result<RawIobuf<'static>, IobufError> {
     unsafe {
-      let b = RawIobuf::new_with_allocator(s.len(), allocator);
-      memcpy(b.buf.get(), s.as_ptr(), s.len());
-      b
+      let b = RawIobuf::try_new_with_allocator(s.len(), allocator)?;
+      memcpy(b.buf.as_ptr(), s.as_ptr(), s.len());
+      Ok(b)
     }
   }
 
   #[inline]
   pub fn deep_clone(&self) -> RawIobuf<'static> {
-    unsafe {
-      let mut b = RawIobuf::from_slice_copy(self.as_limit_slice());
+    self.try_deep_clone().unwrap_or_else(|e| allocation_failed(e))
+  }
 
-      let lo_min = self.lo_min();
+  #[inline]
+  pub fn deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> RawIobuf<'static> {
+    self.try_deep_clone_with_allocator(allocator).unwrap_or_else(|e| allocation_failed(e))
+  }
 
-      b.lo = self.lo - lo_min;
-      b.hi = self.hi - lo_min;
+  #[inline]
+  pub fn deep_clone_aligned(&self, align: usize) -> RawIobuf<'static> {
+    self.try_deep_clone_impl(None, align).unwrap_or_else(|e| allocation_failed(e))
+  }
 
-      b
-    }
+  #[inline]
+  pub fn try_deep_clone(&self) -> Result<RawIobuf<'static>, IobufError> {
+    self.try_deep_clone_impl(None, DATA_ALIGNMENT)
   }
 
   #[inline]
-  pub fn deep_clone_with_allocator(&self, allocator: Arc<Box<Allocator>>) -> RawIobuf<'static> {
-    unsafe {
-      let mut b = RawIobuf::from_slice_copy_with_allocator(self.as_limit_slice(), allocator);
+  pub fn deep_clone_with_tag(&self) -> RawIobuf<'static> {
+    let b = self.deep_clone();
+    self.copy_tag_to(&b);
+    b
+  }
+
+  #[inline]
+  pub fn try_deep_clone_with_allocator(&self, allocator: Arc<Box<dyn Allocator>>) -> Result<RawIobuf<'static>, IobufError> {
+    self.try_deep_clone_impl(Some(allocator), DATA_ALIGNMENT)
+  }
 
+  fn try_deep_clone_impl(
+      &self,
+      allocator: Option<Arc<Box<dyn Allocator>>>,
+      align:     usize) -> Result<RawIobuf<'static>, IobufError> {
+    unsafe {
       let lo_min = self.lo_min();
+      let mut b = RawIobuf::try_new_impl(self.cap() as usize, allocator, align)?;
+      self.copy_limits_to(&mut b);
 
       b.lo = self.lo - lo_min;
       b.hi = self.hi - lo_min;
 
-      b
+      Ok(b)
+    }
+  }
+
+  /// Makes the window at least `additional` bytes long. If it's shorter, its
+  /// end moves to the end of the limits. If that's not enough either, the
+  /// limits are first copied into a new buffer, at least twice as big, from
+  /// the same allocator, or the global heap if it can't provide one.
+  pub fn reserve_nonatomic(&mut self, additional: usize) -> Result<(), IobufError> {
+    if additional <= self.len() as usize {
+      return Ok(());
+    }
+
+    let lo_min = self.lo_min() as usize;
+    let needed = (self.lo as usize).saturating_add(additional);
+
+    if needed > self.hi_max() as usize {
+      if needed - lo_min > MAX_BUFFER_LEN {
+        return Err(self.error(ErrorKind::TooLarge, 0, additional as u64));
+      }
+
+      let cap = cmp::min(cmp::max(needed - lo_min, 2 * self.cap() as usize), MAX_BUFFER_LEN);
+
+      unsafe {
+        // Mapped and adopted memory come from allocators that never allocate.
+        let allocator = self.header().and_then(|h| h.allocator_arc());
+        let mut b =
+          match allocator {
+            Some(allocator) =>
+              RawIobuf::try_new_impl(cap, Some(allocator), DATA_ALIGNMENT)
+                .or_else(|_| RawIobuf::try_new_impl(cap, None, DATA_ALIGNMENT)),
+            None =>
+              RawIobuf::try_new_impl(cap, None, DATA_ALIGNMENT),
+          }.map_err(|e| self.error(e.kind(), 0, additional as u64))?;
+        self.copy_limits_to(&mut b);
+        self.copy_tag_to(&b);
+        b.lo = self.lo - lo_min as u32;
+
+        self.drop_nonatomic();
+        *self = b;
+      }
+    }
+
+    self.hi = self.hi_max();
+    self.debug_check_invariants(Ok(()))
+  }
+
+  /// Copies the limits to the start of `dst`'s buffer, which must be big
+  /// enough. Never-written bytes aren't copied, and stay unreadable in `dst`.
+  unsafe fn copy_limits_to(&self, dst: &mut RawIobuf) {
+    let lo_min  = self.lo_min();
+    let written = self.initialized_hi().saturating_sub(lo_min) as usize;
+    memcpy(dst.buf.as_ptr(), self.buf.as_ptr().offset(lo_min as isize), written);
+
+    if self.tracks_initialized() {
+      if let Some(h) = dst.header() {
+        h.initialized = written;
+        dst.hi_max_and_uninit_bit |= UNINIT_MASK;
+      }
     }
   }
 
   #[inline]
-  pub unsafe fn is_unique_nonatomic(&self) -> bool {
-    match self.header() {
-      Some(ref header) if header.nonatomic_refcount() == 1 => true,
-      _ => false,
+  fn copy_tag_to(&self, dst: &RawIobuf) {
+    if let Some(h) = dst.header() {
+      h.tag().store(self.tag(), Ordering::Relaxed)
     }
   }
 
+  /// The tag shared by every Iobuf over the buffer, or `0` if it's borrowed.
   #[inline]
-  pub unsafe fn is_unique_atomic(&self) -> bool {
+  pub fn tag(&self) -> u64 {
+    self.header().map_or(0, |h| h.tag().load(Ordering::Relaxed))
+  }
+
+  /// Borrowed buffers have no header to keep a tag in.
+  #[inline]
+  pub fn set_tag(&self, tag: u64) -> Result<(), IobufError> {
     match self.header() {
-      Some(ref header) if header.atomic_refcount().load(Ordering::SeqCst) == 1 => true,
-      _ => false,
+      Some(h) => Ok(h.tag().store(tag, Ordering::Relaxed)),
+      None    => Err(self.error(ErrorKind::Unowned, 0, 0)),
     }
   }
 
+  /// The refcount of the buffer, or `0` if it's borrowed.
+  #[inline]
+  pub unsafe fn ref_count_nonatomic(&self) -> usize {
+    self.header().map_or(0, |h| h.nonatomic_refcount())
+  }
+
+  #[inline]
+  pub unsafe fn ref_count_atomic(&self) -> usize {
+    self.header().map_or(0, |h| h.atomic_refcount().load(Ordering::SeqCst))
+  }
+
+  /// `true` if both reference the same owned buffer.
+  #[inline]
+  pub fn shares_buffer_with<'b>(&self, other: &RawIobuf<'b>) -> bool {
+    self.is_owned() && other.is_owned() && self.buf == other.buf
+  }
+
+  #[inline]
+  pub unsafe fn is_unique_nonatomic(&self) -> bool {
+    self.header().is_some_and(|h| h.is_unique_nonatomic())
+  }
+
+  #[inline]
+  pub unsafe fn is_unique_atomic(&self) -> bool {
+    self.header().is_some_and(|h| h.is_unique_atomic())
+  }
+
   #[inline]
   pub unsafe fn as_raw_limit_slice<'b>(&'b self) -> &'b [u8] {
-      return slice::from_raw_parts(self.buf.get().offset(self.lo_min() as isize), self.cap() as usize);
+    slice::from_raw_parts(self.buf.as_ptr().offset(self.lo_min() as isize), self.cap() as usize)
   }
 
   #[inline]
@@ -642,7 +1355,7 @@ impl<'a> RawIobuf<'a> {
 
   #[inline]
   pub unsafe fn as_raw_window_slice<'b>(&'b self) -> &'b [u8] {
-      return slice::from_raw_parts(self.buf.get().offset(self.lo as isize), self.len() as usize);
+    slice::from_raw_parts(self.buf.as_ptr().offset(self.lo as isize), self.len() as usize)
   }
 
   #[inline]
@@ -657,17 +1370,21 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline]
-  pub fn check_range(&self, pos: u64, len: u64) -> Result<(), ()> {
-    err_if(pos + len > self.len() as u64)
+  pub fn check_range(&self, pos: u64, len: u64) -> Result<(), IobufError> {
+    if pos + len > self.len() as u64 {
+      Err(self.range_error(pos, len))
+    } else {
+      Ok(())
+    }
   }
 
   #[inline]
-  pub fn check_range_u32(&self, pos: u32, len: u32) -> Result<(), ()> {
+  pub fn check_range_u32(&self, pos: u32, len: u32) -> Result<(), IobufError> {
     self.check_range(pos as u64, len as u64)
   }
 
   #[inline]
-  pub fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), ()> {
+  pub fn check_range_usize(&self, pos: u32, len: usize) -> Result<(), IobufError> {
     self.check_range(pos as u64, len as u64)
   }
 
@@ -700,25 +1417,25 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline]
-  pub fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), ()> {
+  pub fn sub_window(&mut self, pos: u32, len: u32) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, len));
+      self.check_range_u32(pos, len)?;
       Ok(self.unsafe_sub_window(pos, len))
     }
   }
 
   #[inline]
-  pub fn sub_window_from(&mut self, pos: u32) -> Result<(), ()> {
+  pub fn sub_window_from(&mut self, pos: u32) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, 0));
+      self.check_range_u32(pos, 0)?;
       Ok(self.unsafe_sub_window_from(pos))
     }
   }
 
   #[inline]
-  pub fn sub_window_to(&mut self, len: u32) -> Result<(), ()> {
+  pub fn sub_window_to(&mut self, len: u32) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(0, len));
+      self.check_range_u32(0, len)?;
       Ok(self.unsafe_sub_window_to(len))
     }
   }
@@ -744,25 +1461,25 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline]
-  pub fn sub(&mut self, pos: u32, len: u32) -> Result<(), ()> {
+  pub fn sub(&mut self, pos: u32, len: u32) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, len));
+      self.check_range_u32(pos, len)?;
       Ok(self.unsafe_sub(pos, len))
     }
   }
 
   #[inline]
-  pub fn sub_from(&mut self, pos: u32) -> Result<(), ()> {
+  pub fn sub_from(&mut self, pos: u32) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, 0));
+      self.check_range_u32(pos, 0)?;
       Ok(self.unsafe_sub_from(pos))
     }
   }
 
   #[inline]
-  pub fn sub_to(&mut self, len: u32) -> Result<(), ()> {
+  pub fn sub_to(&mut self, len: u32) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(0, len));
+      self.check_range_u32(0, len)?;
       Ok(self.unsafe_sub_to(len))
     }
   }
@@ -790,42 +1507,46 @@ impl<'a> RawIobuf<'a> {
 
   /// Both the limits and the window are [lo, hi).
   #[inline]
-  pub fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), ()> {
+  pub fn set_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), IobufError> {
     let (new_lo_min, new_hi_max) = limits;
     let (new_lo, new_hi) = window;
     let lo_min = self.lo_min();
 
-    try!(err_if(new_hi_max < new_lo_min));
-    try!(err_if(new_hi     < new_lo));
-    try!(err_if(new_lo_min < lo_min));
-    try!(err_if(new_hi_max > self.hi_max));
-    try!(err_if(new_lo     < self.lo));
-    try!(err_if(new_hi     > self.hi));
+    if new_hi_max < new_lo_min
+    || new_hi     < new_lo
+    || new_lo_min < lo_min
+    || new_hi_max > self.hi_max()
+    || new_lo     < self.lo
+    || new_hi     > self.hi {
+      return Err(self.limits_error(limits));
+    }
 
     self.set_lo_min(new_lo_min);
     self.lo     = new_lo;
     self.hi     = new_hi;
-    self.hi_max = new_hi_max;
+    self.set_hi_max(new_hi_max);
 
     Ok(self.debug_check_invariants(()))
   }
 
   /// Both the limits and the window are [lo, hi).
   #[inline]
-  pub fn expand_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), ()> {
+  pub fn expand_limits_and_window(&mut self, limits: (u32, u32), window: (u32, u32)) -> Result<(), IobufError> {
     let (new_lo_min, new_hi_max) = limits;
     let (new_lo, new_hi) = window;
     let lo_min = self.lo_min();
 
-    try!(err_if(new_hi_max < new_lo_min));
-    try!(err_if(new_hi     < new_lo));
-    try!(err_if(new_lo_min < lo_min));
-    try!(err_if(new_hi_max > self.hi_max));
+    if new_hi_max < new_lo_min
+    || new_hi     < new_lo
+    || new_lo_min < lo_min
+    || new_hi_max > self.hi_max() {
+      return Err(self.limits_error(limits));
+    }
 
     self.set_lo_min(new_lo_min);
     self.lo     = new_lo;
     self.hi     = new_hi;
-    self.hi_max = new_hi_max;
+    self.set_hi_max(new_hi_max);
 
     Ok(self.debug_check_invariants(()))
   }
@@ -837,7 +1558,7 @@ impl<'a> RawIobuf<'a> {
 
   #[inline]
   pub fn cap(&self) -> u32 {
-    self.hi_max - self.lo_min()
+    self.hi_max() - self.lo_min()
   }
 
   #[inline]
@@ -849,14 +1570,15 @@ impl<'a> RawIobuf<'a> {
   pub fn narrow(&mut self) {
     let lo = self.lo;
     self.set_lo_min(lo);
-    self.hi_max = self.hi;
+    let hi = self.hi;
+    self.set_hi_max(hi);
     self.debug_check_invariants(())
   }
 
   #[inline]
-  pub fn advance(&mut self, len: u32) -> Result<(), ()> {
+  pub fn advance(&mut self, len: u32) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(0, len));
+      self.check_range_u32(0, len)?;
       self.unsafe_advance(len);
       Ok(())
     }
@@ -870,13 +1592,15 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline]
-  pub fn extend(&mut self, len: u32) -> Result<(), ()> {
+  pub fn extend(&mut self, len: u32) -> Result<(), IobufError> {
     unsafe {
       let hi     = self.hi     as u64;
-      let hi_max = self.hi_max as u64;
+      let hi_max = self.hi_max() as u64;
       let new_hi = hi + len    as u64;
 
-      try!(err_if(new_hi > hi_max));
+      if new_hi > hi_max {
+        return Err(self.error(ErrorKind::OutOfRange, self.len() as u64, len as u64));
+      }
 
       Ok(self.unsafe_extend(len))
     }
@@ -886,7 +1610,7 @@ impl<'a> RawIobuf<'a> {
   pub unsafe fn unsafe_extend(&mut self, len: u32) {
     if cfg!(debug) {
       let hi     = self.hi     as u64;
-      let hi_max = self.hi_max as u64;
+      let hi_max = self.hi_max() as u64;
       let new_hi = hi + len    as u64;
 
       if new_hi > hi_max {
@@ -901,24 +1625,26 @@ impl<'a> RawIobuf<'a> {
   #[inline]
   pub fn is_extended_by<'b>(&self, other: &RawIobuf<'b>) -> bool {
     unsafe {
-      self.buf.get().offset(self.hi as isize) == other.buf.get().offset(other.lo as isize)
+      self.buf.as_ptr().offset(self.hi as isize) == other.buf.as_ptr().offset(other.lo as isize)
          // check_range, but with `cap()` instead of `len()`.
-      && self.hi as u64 + other.len() as u64 <= self.hi_max as u64
+      && self.hi as u64 + other.len() as u64 <= self.hi_max() as u64
     }
   }
 
   #[inline]
-  pub fn extend_with<'b>(&mut self, other: &RawIobuf<'b>) -> Result<(), ()> {
+  pub fn extend_with<'b>(&mut self, other: &RawIobuf<'b>) -> Result<(), IobufError> {
     unsafe {
-      try!(err_if(!self.is_extended_by(other)));
+      if !self.is_extended_by(other) {
+        return Err(self.error(ErrorKind::NotAdjacent, self.len() as u64, other.len() as u64));
+      }
       Ok(self.unsafe_extend(other.len()))
     }
   }
 
   #[inline]
-  pub fn resize(&mut self, len: u32) -> Result<(), ()> {
+  pub fn resize(&mut self, len: u32) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(0, len));
+      self.check_range_u32(0, len)?;
       Ok(self.unsafe_resize(len))
     }
   }
@@ -931,9 +1657,9 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline]
-  pub fn split_at_nonatomic(&self, pos: u32) -> Result<(Self, Self), ()> {
+  pub fn split_at_nonatomic(&self, pos: u32) -> Result<(Self, Self), IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, 0));
+      self.check_range_u32(pos, 0)?;
       Ok(self.unsafe_split_at_nonatomic(pos))
     }
   }
@@ -948,9 +1674,9 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline]
-  pub fn split_start_at_nonatomic(&mut self, pos: u32) -> Result<Self, ()> {
+  pub fn split_start_at_nonatomic(&mut self, pos: u32) -> Result<Self, IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, 0));
+      self.check_range_u32(pos, 0)?;
       Ok(self.unsafe_split_start_at_nonatomic(pos))
     }
   }
@@ -965,9 +1691,9 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline]
-  pub fn split_at_atomic(&self, pos: u32) -> Result<(Self, Self), ()> {
+  pub fn split_at_atomic(&self, pos: u32) -> Result<(Self, Self), IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, 0));
+      self.check_range_u32(pos, 0)?;
       Ok(self.unsafe_split_at_atomic(pos))
     }
   }
@@ -982,9 +1708,9 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline]
-  pub fn split_start_at_atomic(&mut self, pos: u32) -> Result<Self, ()> {
+  pub fn split_start_at_atomic(&mut self, pos: u32) -> Result<Self, IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, 0));
+      self.check_range_u32(pos, 0)?;
       Ok(self.unsafe_split_start_at_atomic(pos))
     }
   }
@@ -1007,7 +1733,7 @@ impl<'a> RawIobuf<'a> {
   #[inline]
   pub fn reset(&mut self) {
     self.lo = self.lo_min();
-    self.hi = self.hi_max;
+    self.hi = self.hi_max();
     self.debug_check_invariants(())
   }
 
@@ -1021,7 +1747,7 @@ impl<'a> RawIobuf<'a> {
   #[inline]
   pub fn flip_hi(&mut self) {
     self.lo = self.hi;
-    self.hi = self.hi_max;
+    self.hi = self.hi_max();
     self.debug_check_invariants(())
   }
 
@@ -1032,7 +1758,23 @@ impl<'a> RawIobuf<'a> {
 
   #[inline]
   pub fn hi_space(&self) -> u32 {
-    self.hi_max - self.hi
+    self.hi_max() - self.hi
+  }
+
+  /// Copies as much of the window as fits into `dst`, and advances past it.
+  /// Stops short of never-written bytes. Returns the number of bytes copied.
+  #[inline]
+  pub fn read_some(&mut self, dst: &mut [u8]) -> usize {
+    let n = cmp::min(self.initialized_len() as usize, dst.len());
+    unsafe { self.unsafe_consume(&mut dst[..n]) }
+    n
+  }
+
+  /// Advances the window by `len`, or to its end if it's shorter than that.
+  #[inline]
+  pub fn advance_saturating(&mut self, len: usize) {
+    let n = cmp::min(self.len() as usize, len);
+    unsafe { self.unsafe_advance(n as u32) }
   }
 
   #[inline]
@@ -1042,109 +1784,190 @@ impl<'a> RawIobuf<'a> {
       let lo_min = self.lo_min();
 
       memmove(
-        self.buf.get().offset(lo_min as isize),
-        self.buf.get().offset(self.lo as isize),
+        self.buf.as_ptr().offset(lo_min as isize),
+        self.buf.as_ptr().offset(self.lo as isize),
         len as usize);
 
       self.lo = lo_min + len;
-      self.hi = self.hi_max;
+      self.hi = self.hi_max();
 
       self.debug_check_invariants(())
     }
   }
 
+  /// The index into the buffer below which every byte has been written. Only
+  /// buffers made by `new_uninit` start out short of `hi_max`.
+  #[inline]
+  pub fn initialized_hi(&self) -> u32 {
+    match self.header() {
+      Some(h) if self.tracks_initialized() => unsafe {
+        cmp::min(h.initialized().load(Ordering::Relaxed), self.hi_max() as usize) as u32
+      },
+      _ => self.hi_max(),
+    }
+  }
+
+  /// The number of bytes at the start of the window that have been written.
+  #[inline]
+  pub fn initialized_len(&self) -> u32 {
+    cmp::min(self.initialized_hi().saturating_sub(self.lo), self.len())
+  }
+
+  /// Checks that the `len` bytes at `pos` in the window have been written.
+  /// The range itself must already have been checked.
+  #[inline]
+  pub fn check_initialized(&self, pos: u32, len: usize) -> Result<(), IobufError> {
+    if !self.tracks_initialized() {
+      return Ok(());
+    }
+    if let Some(h) = self.header() {
+      let end = self.lo as usize + pos as usize + len;
+      if end > unsafe { h.initialized().load(Ordering::Relaxed) } {
+        return Err(self.error(ErrorKind::Uninitialized, pos as u64, len as u64));
+      }
+    }
+    Ok(())
+  }
+
+  /// Checks that every byte in the window has been written.
+  #[inline]
+  pub fn check_window_initialized(&self) -> Result<(), IobufError> {
+    self.check_initialized(0, self.len() as usize)
+  }
+
+  /// Pulls the upper limit in to the written mark, and the window with it if
+  /// need be, so that a read-only view can never reach never-written bytes,
+  /// even by moving its window.
+  #[inline]
+  pub fn clamp_to_initialized(&mut self) {
+    if !self.tracks_initialized() {
+      return;
+    }
+    let hwm = cmp::max(self.initialized_hi(), self.lo_min());
+    if hwm < self.hi_max() {
+      self.lo = cmp::min(self.lo, hwm);
+      self.hi = cmp::min(self.hi, hwm);
+      self.set_hi_max(hwm);
+    }
+    self.debug_check_invariants(())
+  }
+
+  /// Records that the `len` bytes at `pos` in the window were written. The
+  /// mark only moves for writes starting at or below it, so that every byte
+  /// under it really has been written.
+  #[inline]
+  pub fn mark_initialized(&self, pos: u32, len: usize) {
+    if !self.tracks_initialized() {
+      return;
+    }
+    if let Some(h) = self.header() {
+      let start = self.lo as usize + pos as usize;
+      let mark  = unsafe { h.initialized() };
+      let hwm   = mark.load(Ordering::Relaxed);
+      if start <= hwm && start + len > hwm {
+        mark.store(start + len, Ordering::Relaxed);
+      }
+    }
+  }
+
   #[inline]
-  pub fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), ()> {
+  pub fn peek(&self, pos: u32, dst: &mut [u8]) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_usize(pos, dst.len()));
+      self.check_range_usize(pos, dst.len())?;
+      self.check_initialized(pos, dst.len())?;
       Ok(self.unsafe_peek(pos, dst))
     }
   }
 
   #[inline]
-  pub fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, ()> {
+  pub fn peek_be<T: IntLike>(&self, pos: u32) -> Result<T, IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, mem::size_of::<T>() as u32));
+      self.check_range_u32(pos, mem::size_of::<T>() as u32)?;
+      self.check_initialized(pos, mem::size_of::<T>())?;
       Ok(self.unsafe_peek_be::<T>(pos))
     }
   }
 
   #[inline]
-  pub fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, ()> {
+  pub fn peek_le<T: IntLike>(&self, pos: u32) -> Result<T, IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, mem::size_of::<T>() as u32));
+      self.check_range_u32(pos, mem::size_of::<T>() as u32)?;
+      self.check_initialized(pos, mem::size_of::<T>())?;
       Ok(self.unsafe_peek_le::<T>(pos))
     }
   }
 
   #[inline]
-  pub fn poke(&self, pos: u32, src: &[u8]) -> Result<(), ()> {
+  pub fn poke(&self, pos: u32, src: &[u8]) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_usize(pos, src.len()));
+      self.check_range_usize(pos, src.len())?;
       Ok(self.unsafe_poke(pos, src))
     }
   }
 
   #[inline]
-  pub fn poke_be<T: IntLike>(&self, pos: u32, t: T) -> Result<(), ()> {
+  pub fn poke_be<T: IntLike>(&self, pos: u32, t: T) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, mem::size_of::<T>() as u32));
+      self.check_range_u32(pos, mem::size_of::<T>() as u32)?;
       Ok(self.unsafe_poke_be(pos, t))
     }
   }
 
   #[inline]
-  pub fn poke_le<T: IntLike>(&self, pos: u32, t: T) -> Result<(), ()> {
+  pub fn poke_le<T: IntLike>(&self, pos: u32, t: T) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(pos, mem::size_of::<T>() as u32));
+      self.check_range_u32(pos, mem::size_of::<T>() as u32)?;
       Ok(self.unsafe_poke_le(pos, t))
     }
   }
 
   #[inline]
-  pub fn fill(&mut self, src: &[u8]) -> Result<(), ()> {
+  pub fn fill(&mut self, src: &[u8]) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_usize(0, src.len()));
+      self.check_range_usize(0, src.len())?;
       Ok(self.unsafe_fill(src))
     }
   }
 
   #[inline]
-  pub fn fill_be<T: IntLike>(&mut self, t: T) -> Result<(), ()> {
+  pub fn fill_be<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(0, mem::size_of::<T>() as u32));
+      self.check_range_u32(0, mem::size_of::<T>() as u32)?;
       Ok(self.unsafe_fill_be(t))
     }
   }
 
   #[inline]
-  pub fn fill_le<T: IntLike>(&mut self, t: T) -> Result<(), ()> {
+  pub fn fill_le<T: IntLike>(&mut self, t: T) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_u32(0, mem::size_of::<T>() as u32));
+      self.check_range_u32(0, mem::size_of::<T>() as u32)?;
       Ok(self.unsafe_fill_le(t)) // Ok, unsafe fillet? om nom.
     }
   }
 
   #[inline]
-  pub fn consume(&mut self, dst: &mut [u8]) -> Result<(), ()> {
+  pub fn consume(&mut self, dst: &mut [u8]) -> Result<(), IobufError> {
     unsafe {
-      try!(self.check_range_usize(0, dst.len()));
+      self.check_range_usize(0, dst.len())?;
+      self.check_initialized(0, dst.len())?;
       Ok(self.unsafe_consume(dst))
     }
   }
 
   #[inline]
-  pub fn consume_le<T: IntLike>(&mut self) -> Result<T, ()> {
+  pub fn consume_le<T: IntLike>(&mut self) -> Result<T, IobufError> {
     unsafe {
-      try!(self.check_range_u32(0, mem::size_of::<T>() as u32));
+      self.check_range_u32(0, mem::size_of::<T>() as u32)?;
+      self.check_initialized(0, mem::size_of::<T>())?;
       Ok(self.unsafe_consume_le())
     }
   }
 
   #[inline]
-  pub fn consume_be<T: IntLike>(&mut self) -> Result<T, ()> {
+  pub fn consume_be<T: IntLike>(&mut self) -> Result<T, IobufError> {
     unsafe {
-      try!(self.check_range_u32(0, mem::size_of::<T>() as u32));
+      self.check_range_u32(0, mem::size_of::<T>() as u32)?;
+      self.check_initialized(0, mem::size_of::<T>())?;
       Ok(self.unsafe_consume_be())
     }
   }
@@ -1155,8 +1978,8 @@ impl<'a> RawIobuf<'a> {
     self.debug_check_range_usize(pos, len);
 
     memcpy(
-      dst.as_mut_ptr() as *mut u8,
-      self.buf.get().offset((self.lo + pos) as isize),
+      dst.as_mut_ptr(),
+      self.buf.as_ptr().offset((self.lo + pos) as isize),
       len);
   }
 
@@ -1165,14 +1988,7 @@ impl<'a> RawIobuf<'a> {
     let len = mem::size_of::<T>();
     self.debug_check_range_usize(pos, len);
 
-    let mut dst: T = mem::uninitialized();
-
-    memcpy(
-      &mut dst as *mut T as *mut u8,
-      self.buf.get().offset((self.lo + pos) as isize),
-      len);
-
-    dst
+    ptr::read_unaligned(self.buf.as_ptr().offset((self.lo + pos) as isize) as *const T)
   }
 
   #[inline]
@@ -1189,9 +2005,10 @@ impl<'a> RawIobuf<'a> {
   pub unsafe fn unsafe_poke(&self, pos: u32, src: &[u8]) {
     let len = src.len();
     self.debug_check_range_usize(pos, len);
+    self.mark_initialized(pos, len);
 
     memcpy(
-      self.buf.get().offset((self.lo + pos) as isize),
+      self.buf.as_ptr().offset((self.lo + pos) as isize),
       src.as_ptr(),
       len);
   }
@@ -1200,9 +2017,10 @@ impl<'a> RawIobuf<'a> {
   unsafe fn unsafe_poke_raw<T: IntLike>(&self, pos: u32, t: T) {
     let len = mem::size_of::<T>();
     self.debug_check_range_usize(pos, len);
+    self.mark_initialized(pos, len);
 
     memcpy(
-      self.buf.get().offset((self.lo + pos) as isize),
+      self.buf.as_ptr().offset((self.lo + pos) as isize),
       &t as *const T as *const u8,
       len);
   }
@@ -1270,7 +2088,7 @@ impl<'a> RawIobuf<'a> {
   }
 
   #[inline(always)]
-  pub fn ptr(&self) -> NonZero<*mut u8> {
+  pub fn ptr(&self) -> NonNull<u8> {
     self.buf
   }
 
@@ -1286,79 +2104,95 @@ impl<'a> RawIobuf<'a> {
 
   #[inline(always)]
   pub fn hi_max(&self) -> u32 {
-    self.hi_max
+    self.hi_max_and_uninit_bit & !UNINIT_MASK
   }
 
-  #[cold]
-  fn show_hex(&self, f: &mut Formatter, half_line: &[u8])
-      -> fmt::Result {
-    for &x in half_line.iter() {
-      try!(write!(f, "{:02x} ", x));
-    }
-    Ok(())
+  #[inline]
+  fn set_hi_max(&mut self, new_value: u32) {
+    self.hi_max_and_uninit_bit &= UNINIT_MASK;
+    self.hi_max_and_uninit_bit |= new_value;
   }
 
-  #[cold]
-  fn show_ascii(&self, f: &mut Formatter, half_line: &[u8])
-      -> fmt::Result {
-    for &x in half_line.iter() {
-      let c = if x >= 32 && x < 126 { x as char } else { '.' };
-      try!(write!(f, "{}", c));
-    }
-    Ok(())
+  /// Whether reads have to be checked against the header's `initialized`
+  /// mark. Only buffers made by `new_uninit`, or copied from one, do.
+  #[inline(always)]
+  fn tracks_initialized(&self) -> bool {
+    self.hi_max_and_uninit_bit & UNINIT_MASK != 0
   }
 
   #[cold]
-  fn show_line(&self, f: &mut Formatter, line_number: usize, chunk: &[u8])
-      -> fmt::Result {
+  pub fn show(&self, f: &mut Formatter, ty: &str) -> fmt::Result {
+    writeln!(f, "{} IObuf, limits=[{},{}), bounds=[{},{})",
+                ty, self.lo_min(), self.hi_max(), self.lo, self.hi)?;
 
-    if      self.len() <= 1 <<  8 { try!(write!(f, "0x{:02x}",  line_number * 8)) }
-    else if self.len() <= 1 << 16 { try!(write!(f, "0x{:04x}",  line_number * 8)) }
-    else if self.len() <= 1 << 24 { try!(write!(f, "0x{:06x}",  line_number * 8)) }
-    else                          { try!(write!(f, "0x{:08x}",  line_number * 8)) }
+    if self.lo == self.hi { return write!(f, "<empty buffer>"); }
 
-    try!(write!(f, ":  "));
+    show_bytes(f, unsafe { self.as_window_slice() })
+  }
+}
 
-    let chunk_len = chunk.len();
+#[cold]
+fn show_hex(f: &mut Formatter, half_line: &[u8]) -> fmt::Result {
+  for &x in half_line.iter() {
+    write!(f, "{:02x} ", x)?;
+  }
+  Ok(())
+}
 
-    let (left_slice, right_slice) =
-      if chunk_len >= 4 {
-        (&chunk[0..4], Some(&chunk[4..]))
-      } else {
-        (chunk, None)
-      };
+#[cold]
+fn show_ascii(f: &mut Formatter, half_line: &[u8]) -> fmt::Result {
+  for &x in half_line.iter() {
+    let c = if (32..126).contains(&x) { x as char } else { '.' };
+    write!(f, "{}", c)?;
+  }
+  Ok(())
+}
 
-    try!(self.show_hex(f, left_slice));
-    try!(write!(f, "  "));
-    try!(self.show_ascii(f, left_slice));
-    try!(write!(f, "  "));
-    match right_slice {
-      None => {},
-      Some(right_slice) => {
-        try!(self.show_ascii(f, right_slice));
-        try!(write!(f, "  "));
-        try!(self.show_hex(f, right_slice));
-      }
-    }
+#[cold]
+fn show_line(f: &mut Formatter, total_len: usize, line_number: usize, chunk: &[u8])
+    -> fmt::Result {
 
-    write!(f, "\n")
-  }
+  if      total_len <= 1 <<  8 { write!(f, "0x{:02x}",  line_number * 8)? }
+  else if total_len <= 1 << 16 { write!(f, "0x{:04x}",  line_number * 8)? }
+  else if total_len <= 1 << 24 { write!(f, "0x{:06x}",  line_number * 8)? }
+  else                         { write!(f, "0x{:08x}",  line_number * 8)? }
 
-  #[cold]
-  pub fn show(&self, f: &mut Formatter, ty: &str) -> fmt::Result {
-    try!(write!(f, "{} IObuf, limits=[{},{}), bounds=[{},{})\n",
-                ty, self.lo_min(), self.hi_max, self.lo, self.hi));
+  write!(f, ":  ")?;
 
-    if self.lo == self.hi { return write!(f, "<empty buffer>"); }
+  let chunk_len = chunk.len();
 
-    let b = unsafe { self.as_window_slice() };
+  let (left_slice, right_slice) =
+    if chunk_len >= 4 {
+      (&chunk[0..4], Some(&chunk[4..]))
+    } else {
+      (chunk, None)
+    };
 
-    for (i, c) in b.chunks(8).enumerate() {
-      try!(self.show_line(f, i, c));
+  show_hex(f, left_slice)?;
+  write!(f, "  ")?;
+  show_ascii(f, left_slice)?;
+  write!(f, "  ")?;
+  match right_slice {
+    None => {},
+    Some(right_slice) => {
+      show_ascii(f, right_slice)?;
+      write!(f, "  ")?;
+      show_hex(f, right_slice)?;
     }
+  }
 
-    Ok(())
+  writeln!(f)
+}
+
+/// Hexdumps `b`, eight bytes to a line, in the format used by the `Debug`
+/// impls of all the Iobufs.
+#[cold]
+pub fn show_bytes(f: &mut Formatter, b: &[u8]) -> fmt::Result {
+  for (i, c) in b.chunks(8).enumerate() {
+    show_line(f, b.len(), i, c)?;
   }
+
+  Ok(())
 }
 
 #[test]
@@ -1419,7 +2253,7 @@ fn over_resize() {
   use impls::RWIobuf;
   let mut b = RWIobuf::new(1024);
   assert_eq!(b.advance(512), Ok(()));
-  assert_eq!(b.resize(0x7FFF_FFFF), Err(()));
+  assert_eq!(b.resize(0x7FFF_FFFF).map_err(|e| e.kind()), Err(ErrorKind::Incomplete));
 }
 
 #[test]
@@ -1434,7 +2268,10 @@ fn check_large_range_pos() {
   use impls::RWIobuf;
   use iobuf::Iobuf;
   let b = RWIobuf::new(100);
-  unsafe { assert_eq!(b.as_raw().check_range(0x8000_0000, 0), Err(())); }
+  unsafe {
+    assert_eq!(b.as_raw().check_range(0x8000_0000, 0).map_err(|e| e.kind()),
+               Err(ErrorKind::OutOfRange));
+  }
 }
 
 #[test]
@@ -1442,7 +2279,32 @@ fn check_large_range_len() {
   use impls::RWIobuf;
   use iobuf::Iobuf;
   let b = RWIobuf::new(100);
-  unsafe { assert_eq!(b.as_raw().check_range(0, 0x8000_0000), Err(())); }
+  unsafe {
+    assert_eq!(b.as_raw().check_range(0, 0x8000_0000).map_err(|e| e.kind()),
+               Err(ErrorKind::Incomplete));
+  }
+}
+
+#[test]
+fn error_kinds() {
+  use impls::ROIobuf;
+  use iobuf::Iobuf;
+
+  let mut a = ROIobuf::from_str_copy("hello");
+  let b = ROIobuf::from_str_copy("world");
+
+  let e = a.extend_with(&b).unwrap_err();
+  assert_eq!(e.kind(), ErrorKind::NotAdjacent);
+  assert_eq!(e.range(), (5, 5));
+
+  let e = a.extend(1).unwrap_err();
+  assert_eq!(e.kind(), ErrorKind::OutOfRange);
+
+  assert_eq!(a.advance(1), Ok(()));
+  let e = a.set_limits_and_window((0, 5), (0, 5)).unwrap_err();
+  assert_eq!(e.kind(), ErrorKind::InvalidLimits);
+  assert_eq!(e.window(), (1, 5));
+  assert_eq!(e.limits(), (0, 5));
 }
 
 #[test]
@@ -1453,19 +2315,172 @@ fn test_allocator() {
   struct MyAllocator;
 
   impl Allocator for MyAllocator {
-    fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+    fn allocate(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
       unsafe {
-        Heap.alloc(Layout::from_size_align(size, align).unwrap())
-          .expect("could not allocate memory")
+        NonNull::new(alloc::alloc(Layout::from_size_align(size, align).unwrap()))
       }
     }
 
-    fn deallocate(&self, ptr: NonZero<*mut u8>, len: usize, align: usize) {
+    fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
       unsafe {
-        Heap.dealloc(ptr.get(), Layout::from_size_align(len, align).unwrap())
+        alloc::dealloc(ptr.as_ptr(), Layout::from_size_align(len, align).unwrap())
       }
     }
   }
 
   RWIobuf::new_with_allocator(1000, Arc::new(Box::new(MyAllocator)));
 }
+
+#[test]
+fn test_failing_allocator() {
+  use impls::RWIobuf;
+  use self::Allocator;
+
+  struct NoMemory;
+
+  impl Allocator for NoMemory {
+    fn allocate(&self, _len: usize, _align: usize) -> Option<NonNull<u8>> { None }
+    fn deallocate(&self, _ptr: NonNull<u8>, _len: usize, _align: usize) {}
+  }
+
+  let allocator: Arc<Box<dyn Allocator>> = Arc::new(Box::new(NoMemory));
+
+  let e = RWIobuf::try_new_with_allocator(10, allocator.clone()).unwrap_err();
+  assert_eq!(e.kind(), ErrorKind::AllocationFailed);
+  assert_eq!(e.range(), (0, 10));
+
+  let e = RWIobuf::try_new(MAX_BUFFER_LEN + 1).unwrap_err();
+  assert_eq!(e.kind(), ErrorKind::TooLarge);
+
+  // The failed allocations released their references to the allocator.
+  assert_eq!(Arc::strong_count(&allocator), 1);
+}
+
+#[test]
+fn test_aligned_allocation() {
+  use impls::RWIobuf;
+  use iobuf::Iobuf;
+
+  for &align in &[1, 16, 32, 64, 512, 4096] {
+    let b = RWIobuf::new_aligned(100, align);
+    assert_eq!(b.ptr().as_ptr() as usize % align, 0);
+    assert_eq!(b.cap(), 100);
+    b.poke(0, b"hello").unwrap();
+
+    let mut c = b.clone();
+    c.advance(1).unwrap();
+    let d = c.deep_clone_aligned(align);
+    assert_eq!(d.ptr().as_ptr() as usize % align, 0);
+    assert_eq!(d.cap(), 100);
+    assert_eq!(d.len(), 99);
+    unsafe { assert_eq!(&d.as_window_slice()[..4], b"ello"); }
+  }
+
+  // Custom allocators are asked for the padded length and full alignment.
+  static REQUESTED: AtomicUsize = AtomicUsize::new(0);
+
+  struct Recording;
+
+  impl Allocator for Recording {
+    fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+      REQUESTED.store(align, Ordering::SeqCst);
+      unsafe { NonNull::new(alloc::alloc(Layout::from_size_align(len, align).unwrap())) }
+    }
+
+    fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+      assert_eq!(len, 4096 + 10);
+      unsafe { alloc::dealloc(ptr.as_ptr(), Layout::from_size_align(len, align).unwrap()) }
+    }
+  }
+
+  let recording: Arc<Box<dyn Allocator>> = Arc::new(Box::new(Recording));
+  let mut raw = RawIobuf::try_new_impl(10, Some(recording), 4096).unwrap();
+  assert_eq!(REQUESTED.load(Ordering::SeqCst), 4096);
+  assert_eq!(raw.ptr().as_ptr() as usize % 4096, 0);
+  unsafe { raw.drop_nonatomic(); }
+}
+
+#[test]
+#[should_panic]
+fn test_bad_alignment() {
+  RawIobuf::new_aligned(10, 48);
+}
+
+#[test]
+fn test_uninit_tracking() {
+  use std::io::Read;
+  use impls::RWIobuf;
+  use iobuf::Iobuf;
+
+  let b = RWIobuf::new_uninit(8);
+  let c = b.clone();
+
+  // Writing past the mark doesn't move it.
+  b.poke(4, b"efgh").unwrap();
+  assert_eq!(c.peek_be::<u8>(4).unwrap_err().kind(), ErrorKind::Uninitialized);
+  assert_eq!(b.try_read_only().unwrap_err().kind(), ErrorKind::Uninitialized);
+
+  // Filling in the gap does, and every clone sees it.
+  b.poke(0, b"ab").unwrap();
+  assert_eq!(c.peek_be::<u16>(0), Ok(0x6162));
+  assert!(c.peek_be::<u8>(2).is_err());
+
+  // Copies only get the written bytes.
+  let mut d = c.deep_clone();
+  assert_eq!(d.peek_be::<u16>(0), Ok(0x6162));
+  assert_eq!(d.peek_be::<u8>(2).unwrap_err().kind(), ErrorKind::Uninitialized);
+
+  // Plain reads stop early.
+  let mut dst = [0; 8];
+  assert_eq!(d.read(&mut dst).unwrap(), 2);
+  assert_eq!(d.read(&mut dst).unwrap(), 0);
+
+  // The earlier write past the mark still has to be redone.
+  b.poke(2, b"cd").unwrap();
+  assert!(b.try_read_only().is_err());
+  b.poke(4, b"efgh").unwrap();
+  let ro = b.read_only();
+  unsafe { assert_eq!(ro.as_window_slice(), b"abcdefgh"); }
+
+  let z = RWIobuf::new_zeroed(100);
+  unsafe { assert!(z.as_window_slice().iter().all(|&x| x == 0)); }
+
+  // Only buffers that track what was written pay for checking it.
+  unsafe {
+    assert!(d.as_raw().tracks_initialized());
+    assert!(!z.as_raw().tracks_initialized());
+    assert_eq!(z.as_raw().initialized_hi(), 100);
+  }
+}
+
+#[test]
+fn test_reserve() {
+  use impls::RWIobuf;
+  use iobuf::Iobuf;
+
+  // Growing within the limits just moves the end of the window.
+  let mut b = RWIobuf::new_uninit(16);
+  b.sub_to(10).unwrap();
+  b.fill(b"abcd").unwrap();
+  b.resize(2).unwrap();
+  let p = b.ptr();
+  assert_eq!(b.reserve(5), Ok(()));
+  assert_eq!((b.ptr(), b.lo(), b.hi()), (p, 4, 10));
+
+  // Past them, the limits move into a new buffer, and clones stay behind.
+  let c = b.clone();
+  assert_eq!(b.reserve(100), Ok(()));
+  assert!(!b.shares_buffer_with(&c));
+  assert_eq!((b.lo_min(), b.lo(), b.hi()), (0, 4, b.hi_max()));
+  assert!(b.len() >= 100);
+  unsafe { assert_eq!(&b.as_limit_slice()[..4], b"abcd"); }
+
+  // Still tracking what was written.
+  assert_eq!(b.peek_be::<u8>(0).unwrap_err().kind(), ErrorKind::Uninitialized);
+  b.fill(b"e").unwrap();
+  b.flip_lo();
+  unsafe { assert_eq!(b.try_read_only().unwrap().as_window_slice(), b"abcde"); }
+
+  let mut b = RWIobuf::new(1);
+  assert_eq!(b.reserve(MAX_BUFFER_LEN + 1).unwrap_err().kind(), ErrorKind::TooLarge);
+}
@@ -1,38 +1,160 @@
-use std::mem;
+use std::cmp;
 
+use error::{ErrorKind, IobufError, iobuf_error};
 use iobuf::Iobuf;
 use impls::{RWIobuf, ROIobuf};
 
-/// A ring buffer implemented with `Iobuf`s.
+/// A circular ring buffer of bytes, implemented with `Iobuf`s.
+///
+/// All the data lives in a single allocation, tracked by a read position and
+/// a length. The free space and the stored data are each exposed as up to two
+/// contiguous regions: the second region is only non-empty when that range
+/// wraps around the end of the allocation.
+///
+/// There are two ways to use a ring buffer. `push_buf` and `pop_buf` hand out
+/// Iobufs to be filled and consumed in place, and the ring buffer notices how
+/// far they were advanced the next time it's touched. Alternatively,
+/// `writable` and `readable` return both regions at once, and `advance_write`
+/// and `advance_read` commit the bytes written or read.
+///
+/// ```rust
+/// use iobuf::{IORingbuf, Iobuf};
+///
+/// let mut rb = IORingbuf::new(8);
+/// rb.push_buf().fill(b"abcdef").unwrap();
+/// assert_eq!(rb.len(), 6);
+///
+/// let mut dst = [0; 4];
+/// rb.pop_buf().consume(&mut dst).unwrap();
+/// assert_eq!(&dst, b"abcd");
+///
+/// // The free space wraps around the end of the buffer.
+/// let (a, b) = rb.writable();
+/// assert_eq!((a.len(), b.len()), (2, 4));
+/// a.poke(0, b"gh").unwrap();
+/// b.poke(0, b"ijk").unwrap();
+/// rb.advance_write(5).unwrap();
+///
+/// let (a, b) = rb.readable();
+/// unsafe {
+///   assert_eq!(a.as_window_slice(), b"efgh");
+///   assert_eq!(b.as_window_slice(), b"ijk");
+/// }
+/// ```
 pub struct IORingbuf {
-  /// The contents of the window is space for input to be put into. Therefore,
-  /// initially full.
+  /// The whole allocation. Its window is never changed.
+  buf:   RWIobuf<'static>,
+  /// Index into `buf` of the first byte waiting to be popped.
+  head:  u32,
+  /// The number of bytes waiting to be popped, as of the last `sync`.
+  len:   u32,
+  /// Handed out by `push_buf`. Its window is (part of) the free space.
   i_buf: RWIobuf<'static>,
-  /// The contents of the window are things needing to be output. Therefore,
-  /// initally empty.
-  o_buf: RWIobuf<'static>,
+  /// Where `i_buf`'s window started, as of the last `sync`.
+  i_lo:  u32,
+  /// Handed out by `pop_buf`. Its window is (part of) the stored data.
+  o_buf: ROIobuf<'static>,
+  /// Where `o_buf`'s window started, as of the last `sync`.
+  o_lo:  u32,
 }
 
 impl IORingbuf {
   /// Creates a new ring buffer, with room for `cap` bytes.
   pub fn new(cap: usize) -> Self {
-    let left_size = cap / 2;
+    let buf = RWIobuf::new(cap);
     let mut ret =
       IORingbuf {
-        i_buf: RWIobuf::new(left_size),
-        o_buf: RWIobuf::new(cap - left_size),
+        i_buf: buf.clone(),
+        i_lo:  0,
+        o_buf: buf.read_only(),
+        o_lo:  0,
+        head:  0,
+        len:   0,
+        buf:   buf,
       };
-    ret.o_buf.flip_lo(); // start with an empty o_buf.
+    ret.refresh();
     ret
   }
 
+  /// Wraps an index in `[0, 2*cap)` back into `[0, cap)`.
+  #[inline]
+  fn wrap(&self, i: u32) -> u32 {
+    if i >= self.cap() { i - self.cap() } else { i }
+  }
+
+  /// How far `push_buf` and `pop_buf`'s Iobufs were filled and consumed since
+  /// the last `sync`.
+  #[inline]
+  fn progress(&self) -> (u32, u32) {
+    (self.i_buf.lo() - self.i_lo, self.o_buf.lo() - self.o_lo)
+  }
+
+  /// Folds any progress made on `push_buf` and `pop_buf`'s Iobufs into the
+  /// read position and length.
+  #[inline]
+  fn sync(&mut self) {
+    let (pushed, popped) = self.progress();
+    self.len  = self.len + pushed - popped;
+    self.head = self.wrap(self.head + popped);
+    self.i_lo = self.i_buf.lo();
+    self.o_lo = self.o_buf.lo();
+  }
+
+  /// Points `push_buf` and `pop_buf`'s Iobufs at the first free and stored
+  /// regions. Must only be called right after a `sync`.
+  fn refresh(&mut self) {
+    // Nothing can be outstanding, so start over from the beginning to make the
+    // free space as contiguous as possible.
+    if self.len == 0 { self.head = 0; }
+
+    let ((i_pos, i_len), _) = self.free_regions();
+    let ((o_pos, o_len), _) = self.data_regions();
+
+    self.i_buf = self.region(i_pos, i_len);
+    self.o_buf = self.region(o_pos, o_len).read_only();
+    self.i_lo  = i_pos;
+    self.o_lo  = o_pos;
+  }
+
+  /// `(pos, len)` of the two regions of `len` bytes starting at `pos`.
+  #[inline]
+  fn split_regions(&self, pos: u32, len: u32) -> ((u32, u32), (u32, u32)) {
+    let first = cmp::min(len, self.cap() - pos);
+    ((pos, first), (0, len - first))
+  }
+
+  #[inline]
+  fn free_regions(&self) -> ((u32, u32), (u32, u32)) {
+    let tail = self.wrap(self.head + self.len);
+    self.split_regions(tail, self.cap() - self.len)
+  }
+
+  #[inline]
+  fn data_regions(&self) -> ((u32, u32), (u32, u32)) {
+    self.split_regions(self.head, self.len)
+  }
+
+  /// An Iobuf whose limits and window are `[pos, pos + len)`.
+  #[inline]
+  fn region(&self, pos: u32, len: u32) -> RWIobuf<'static> {
+    let mut b = self.buf.clone();
+    unsafe { b.unsafe_sub(pos, len) };
+    b
+  }
+
   /// Returns an Iobuf, whose window may be filled with new data. This acts as
   /// the "push" operations for the ringbuf.
   ///
+  /// The window is the first contiguous region of free space. If the free
+  /// space wraps around, filling the returned Iobuf and calling `push_buf`
+  /// again will return the rest of it.
+  ///
   /// It is easy to get garbage data if using a clone of the returned Iobuf.
   /// This is not memory-unsafe, but should be avoided.
-  #[inline(always)]
+  #[inline]
   pub fn push_buf(&mut self) -> &mut RWIobuf<'static> {
+    self.sync();
+    self.refresh();
     &mut self.i_buf
   }
 
@@ -47,25 +169,153 @@ impl IORingbuf {
   /// This is not memory-unsafe, but should be avoided.
   #[inline]
   pub fn pop_buf(&mut self) -> &mut ROIobuf<'static> {
-    if self.o_buf.is_empty() {
-      self.i_buf.flip_lo();
-      self.o_buf.reset();
-      mem::swap(&mut self.i_buf, &mut self.o_buf);
+    self.sync();
+    self.refresh();
+    &mut self.o_buf
+  }
+
+  /// Returns the free space as two Iobufs. The second is empty unless the
+  /// free space wraps around the end of the buffer. Call `advance_write` to
+  /// commit bytes written into them, before any other `&mut self` method: an
+  /// empty ring buffer may move its free space back to the start.
+  pub fn writable(&mut self) -> (RWIobuf<'static>, RWIobuf<'static>) {
+    self.sync();
+    self.refresh();
+    let ((p0, l0), (p1, l1)) = self.free_regions();
+    (self.region(p0, l0), self.region(p1, l1))
+  }
+
+  /// Returns the stored data as two Iobufs. The second is empty unless the
+  /// data wraps around the end of the buffer. Call `advance_read` to release
+  /// bytes read out of them.
+  pub fn readable(&mut self) -> (ROIobuf<'static>, ROIobuf<'static>) {
+    self.sync();
+    self.refresh();
+    let ((p0, l0), (p1, l1)) = self.data_regions();
+    (self.region(p0, l0).read_only(), self.region(p1, l1).read_only())
+  }
+
+  /// Marks the first `n` bytes of free space, as returned by `writable`, as
+  /// data to be popped.
+  pub fn advance_write(&mut self, n: u32) -> Result<(), IobufError> {
+    self.sync();
+    let free = self.cap() - self.len;
+    if n > free {
+      return Err(self.range_error(n, free));
+    }
+    self.len += n;
+    self.refresh();
+    Ok(())
+  }
+
+  /// Releases the first `n` bytes of data, as returned by `readable`, back
+  /// into free space.
+  pub fn advance_read(&mut self, n: u32) -> Result<(), IobufError> {
+    self.sync();
+    if n > self.len {
+      return Err(self.range_error(n, self.len));
     }
-    // Clients should only be doing read-only operations into the iobuf, so
-    // return a ROIobuf.
-    unsafe { mem::transmute(&mut self.o_buf) }
+    self.head = self.wrap(self.head + n);
+    self.len -= n;
+    self.refresh();
+    Ok(())
+  }
+
+  #[cold]
+  fn range_error(&self, n: u32, avail: u32) -> IobufError {
+    iobuf_error(ErrorKind::Incomplete, 0, n as u64,
+                (0, self.cap() as u64), (0, avail as u64))
+  }
+
+  /// The number of bytes waiting to be popped.
+  #[inline]
+  pub fn len(&self) -> u32 {
+    let (pushed, popped) = self.progress();
+    self.len + pushed - popped
+  }
+
+  /// The total number of bytes the ring buffer can hold.
+  #[inline(always)]
+  pub fn cap(&self) -> u32 {
+    self.buf.cap()
   }
 
   /// `true` if there is no data to pop in the Iobuf.
   #[inline]
   pub fn is_empty(&self) -> bool {
-    self.i_buf.cap() == self.i_buf.len() && self.o_buf.is_empty()
+    self.len() == 0
   }
 
   /// `true` if there is no room for new data in the Iobuf.
-  #[inline(always)]
+  #[inline]
   pub fn is_full(&self) -> bool {
-    self.i_buf.is_empty()
+    self.len() == self.cap()
   }
 }
+
+#[test]
+fn full_capacity() {
+  let mut rb = IORingbuf::new(8);
+  assert!(rb.is_empty());
+  assert_eq!(rb.push_buf().len(), 8);
+
+  rb.push_buf().fill(b"12345678").unwrap();
+  assert!(rb.is_full());
+  assert_eq!(rb.push_buf().len(), 0);
+
+  let mut dst = [0; 8];
+  rb.pop_buf().consume(&mut dst).unwrap();
+  assert_eq!(&dst, b"12345678");
+  assert!(rb.is_empty());
+}
+
+#[test]
+fn wrap_around() {
+  let mut rb = IORingbuf::new(8);
+  rb.push_buf().fill(b"123456").unwrap();
+
+  let mut dst = [0; 5];
+  rb.pop_buf().consume(&mut dst).unwrap();
+  assert_eq!(rb.len(), 1);
+
+  // Two bytes until the end of the allocation, then the rest wraps.
+  assert_eq!(rb.push_buf().len(), 2);
+  rb.push_buf().fill(b"ab").unwrap();
+  assert_eq!(rb.push_buf().len(), 5);
+  rb.push_buf().fill(b"cdefg").unwrap();
+  assert!(rb.is_full());
+
+  let (a, b) = rb.readable();
+  unsafe {
+    assert_eq!(a.as_window_slice(), b"6ab");
+    assert_eq!(b.as_window_slice(), b"cdefg");
+  }
+
+  let mut dst = [0; 3];
+  rb.pop_buf().consume(&mut dst).unwrap();
+  assert_eq!(&dst, b"6ab");
+  let mut dst = [0; 5];
+  rb.pop_buf().consume(&mut dst).unwrap();
+  assert_eq!(&dst, b"cdefg");
+  assert!(rb.is_empty());
+}
+
+#[test]
+fn advance_errors() {
+  let mut rb = IORingbuf::new(4);
+  assert_eq!(rb.advance_read(1).unwrap_err().kind(), ErrorKind::Incomplete);
+  assert!(rb.advance_write(5).is_err());
+  assert_eq!(rb.advance_write(4), Ok(()));
+  assert!(rb.is_full());
+  assert_eq!(rb.advance_read(4), Ok(()));
+  assert!(rb.is_empty());
+}
+
+#[test]
+fn zero_capacity() {
+  let mut rb = IORingbuf::new(0);
+  assert!(rb.is_empty());
+  assert!(rb.is_full());
+  assert_eq!(rb.push_buf().len(), 0);
+  assert_eq!(rb.pop_buf().len(), 0);
+}
@@ -0,0 +1,851 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::ptr::{self, NonNull};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use libc;
+
+use impls::{AROIobuf, ROIobuf};
+use iobuf::Iobuf;
+use mmap::{MmapAllocator, map_behind_header, page_size};
+use raw::{Allocator, RawIobuf, ALLOCATION_HEADER_SIZE, DATA_ALIGNMENT, MAX_BUFFER_LEN};
+use raw::{allocator_from_raw, header_of};
+
+/// Written at the start of every region, to catch imports from the wrong file.
+const REGION_MAGIC: u64 = 0x696f_6275_6673_686d; // "iobufshm"
+
+/// Where the export slots start, after the `RegionHeader`.
+const SLOTS_START: usize = 64;
+
+/// How many buffers can be exported and not yet imported at once.
+const EXPORT_SLOTS: usize = 1024;
+
+/// Where buffers may start, after the export slots.
+const DATA_START: usize = SLOTS_START + EXPORT_SLOTS * mem::size_of::<u64>();
+
+/// Lives at the start of the shared region, and is seen by every process that
+/// maps it.
+#[repr(C)]
+struct RegionHeader {
+  magic:  u64,
+  id:     u64,
+  len:    u64,
+  /// A stack of buffers whose last reference was dropped by an importer, which
+  /// the creator has yet to free. It holds the buffer's offset in the region,
+  /// or `0` once it's empty. Each buffer's `tag` links to the next one, since
+  /// nothing else reads it anymore.
+  ///
+  /// Any process can write this, so the creator only takes it as a hint. See
+  /// `RegionInner::reap`.
+  reaped: AtomicU64,
+}
+
+/// What the creator knows about a buffer it exported. It's read out of the
+/// buffer's header when it's first exported, so nothing another process
+/// writes to the region later changes how the buffer is freed, whichever side
+/// drops it last.
+struct Exported {
+  /// The bytes allocated for it, header included, or just the header once
+  /// its data was released.
+  len:       usize,
+  /// The reference to its allocator that the buffer holds, as a `usize` to
+  /// keep us `Send`.
+  allocator: usize,
+}
+
+/// One process's mapping of a region.
+struct RegionInner {
+  file:  File,
+  /// The address of the mapping, kept as a `usize` to keep us `Send`.
+  base:  usize,
+  len:   usize,
+  /// The free ranges of the region, as `(offset, len)`, sorted and never
+  /// adjacent. Only the process that created the region allocates out of it,
+  /// so this is empty everywhere else.
+  free:  Mutex<Vec<(usize, usize)>>,
+  /// The buffers `export` handed out, by offset, that haven't been freed yet.
+  /// Only the creator exports, so this is empty everywhere else.
+  exported: Mutex<HashMap<usize, Exported>>,
+  /// Where the creator starts looking for a free export slot.
+  next_slot: AtomicUsize,
+}
+
+/// The regions created in this process, as `(base, len, region)`, so that
+/// the buffers they exported are freed with their `Exported` record, rather
+/// than through the allocator in their headers, which importers can write.
+static CREATED: Mutex<Vec<(usize, usize, usize)>> = Mutex::new(Vec::new());
+
+/// How many regions are in `CREATED`, so that freeing any other buffer
+/// doesn't have to take its lock.
+static CREATED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+fn lock_created() -> MutexGuard<'static, Vec<(usize, usize, usize)>> {
+  CREATED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Runs `f` on the records of the region the buffer at `buf` was allocated
+/// out of, and the buffer's offset in it, if it's from a region this process
+/// created.
+fn with_exported<T, F>(buf: NonNull<u8>, f: F) -> Option<T>
+    where F: FnOnce(&mut HashMap<usize, Exported>, usize) -> Option<T> {
+  if CREATED_COUNT.load(Ordering::Acquire) == 0 {
+    return None;
+  }
+
+  let addr = buf.as_ptr() as usize;
+  let created = lock_created();
+  let &(base, _, region) = created.iter().find(|&&(base, len, _)| addr > base && addr < base + len)?;
+  // Regions take themselves out of `CREATED` before they're unmapped.
+  let region = unsafe { &*(region as *const RegionInner) };
+  let result = f(&mut region.lock_exported(), addr - base);
+  result
+}
+
+/// Takes the record of the exported buffer at `buf`, as the length of its
+/// allocation and the reference to its allocator, to free it with.
+pub fn take_exported(buf: NonNull<u8>) -> Option<(usize, NonNull<()>)> {
+  with_exported(buf, |exported, offset| exported.remove(&offset)).map(|e| unsafe {
+    (e.len, NonNull::new_unchecked(e.allocator as *mut ()))
+  })
+}
+
+/// Hands the data of the exported buffer at `buf` back through the allocator
+/// in its record. Returns `None` if it wasn't exported, or else whether the
+/// data was released.
+pub unsafe fn release_exported_data(buf: NonNull<u8>) -> Option<bool> {
+  let (len, allocator) =
+    with_exported(buf, |exported, offset| exported.get(&offset).map(|e| (e.len, e.allocator)))?;
+
+  // The record holds a reference to the allocator until the buffer's freed.
+  let data_len = len - ALLOCATION_HEADER_SIZE;
+  let released =
+    data_len > 0 && (*(allocator as *const Box<dyn Allocator>)).release_data(buf, data_len);
+  if released {
+    with_exported(buf, |exported, offset| {
+      exported.get_mut(&offset).map(|e| e.len = ALLOCATION_HEADER_SIZE)
+    });
+  }
+  Some(released)
+}
+
+impl RegionInner {
+  #[inline]
+  fn header(&self) -> &RegionHeader {
+    unsafe { &*(self.base as *const RegionHeader) }
+  }
+
+  #[inline]
+  fn lock_free(&self) -> MutexGuard<'_, Vec<(usize, usize)>> {
+    self.free.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  #[inline]
+  fn lock_exported(&self) -> MutexGuard<'_, HashMap<usize, Exported>> {
+    self.exported.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Each export slot holds the offset of a buffer that was exported and not
+  /// yet imported, or `0` if it's free. Only the creator fills them, and the
+  /// import that claims one empties it.
+  #[inline]
+  fn slots(&self) -> &[AtomicU64] {
+    unsafe {
+      ::std::slice::from_raw_parts((self.base + SLOTS_START) as *const AtomicU64, EXPORT_SLOTS)
+    }
+  }
+
+  /// Finds a free export slot for the buffer at `offset`.
+  fn fill_slot(&self, offset: usize) -> Option<usize> {
+    let slots = self.slots();
+    let start = self.next_slot.load(Ordering::Relaxed);
+    for i in (start..EXPORT_SLOTS).chain(0..start) {
+      if slots[i].compare_exchange(0, offset as u64, Ordering::Release, Ordering::Relaxed).is_ok() {
+        self.next_slot.store((i + 1) % EXPORT_SLOTS, Ordering::Relaxed);
+        return Some(i);
+      }
+    }
+    None
+  }
+
+  /// Takes the reference to the buffer at `offset` that `export` left in
+  /// `slot`, if it's still there.
+  fn claim_slot(&self, slot: u32, offset: u64) -> bool {
+    offset != 0
+      && self.slots().get(slot as usize)
+           .is_some_and(|s| s.compare_exchange(offset, 0, Ordering::Acquire, Ordering::Relaxed).is_ok())
+  }
+
+  /// The buffer at `offset` in the region.
+  #[inline]
+  fn buffer(&self, offset: usize) -> NonNull<u8> {
+    unsafe { NonNull::new_unchecked((self.base + offset) as *mut u8) }
+  }
+
+  /// Drops the reference to the buffer at `offset` that was handed out by
+  /// `export`, and claimed by `import`. If it was the last one, the buffer is
+  /// left for its creator to free, since only it may touch the allocator in
+  /// the buffer's header.
+  fn release(&self, offset: usize) {
+    unsafe {
+      let h = header_of(self.buffer(offset));
+      if h.dec_ref_count_atomic().is_ok() {
+        return;
+      }
+
+      let reaped = &self.header().reaped;
+      let mut head = reaped.load(Ordering::Relaxed);
+      loop {
+        h.tag().store(head, Ordering::Relaxed);
+        match reaped.compare_exchange_weak(head, offset as u64, Ordering::Release, Ordering::Relaxed) {
+          Ok(_)  => return,
+          Err(h) => head = h,
+        }
+      }
+    }
+  }
+
+  /// Frees the buffers that importers dropped the last reference to.
+  ///
+  /// The stack lives in shared memory, so only buffers we exported and haven't
+  /// freed yet are taken off it, each at most once, and they're freed with
+  /// what we knew about them when they were exported. The walk stops at the
+  /// first offset we don't know.
+  fn reap(&self) {
+    let mut offset = self.header().reaped.swap(0, Ordering::Acquire) as usize;
+    while offset != 0 {
+      let exported = match self.lock_exported().remove(&offset) {
+        Some(exported) => exported,
+        None           => return,
+      };
+
+      unsafe {
+        let buf = self.buffer(offset);
+        let next = header_of(buf).tag().load(Ordering::Relaxed) as usize;
+        // Also releases the reference the buffer held.
+        let allocator = allocator_from_raw(NonNull::new_unchecked(exported.allocator as *mut ()));
+        allocator.deallocate(self.buffer(offset - ALLOCATION_HEADER_SIZE), exported.len, DATA_ALIGNMENT);
+        offset = next;
+      }
+    }
+  }
+
+  /// Gives `len` bytes at `start` back to the free list.
+  fn free_range(&self, start: usize, len: usize) {
+    let mut free = self.lock_free();
+
+    // Merge with the free ranges on either side.
+    let i = free.iter().position(|&(s, _)| s > start).unwrap_or(free.len());
+    let (mut start, mut len) = (start, len);
+    if i < free.len() && start + len == free[i].0 {
+      len += free.remove(i).1;
+    }
+    if i > 0 && free[i - 1].0 + free[i - 1].1 == start {
+      start = free[i - 1].0;
+      len  += free.remove(i - 1).1;
+      free.insert(i - 1, (start, len));
+    } else {
+      free.insert(i, (start, len));
+    }
+  }
+}
+
+impl Drop for RegionInner {
+  fn drop(&mut self) {
+    if CREATED_COUNT.load(Ordering::Acquire) != 0 {
+      let me = self as *const RegionInner as usize;
+      let mut created = lock_created();
+      if let Some(i) = created.iter().position(|&(_, _, region)| region == me) {
+        created.remove(i);
+        CREATED_COUNT.fetch_sub(1, Ordering::Release);
+      }
+    }
+    unsafe { libc::munmap(self.base as *mut libc::c_void, self.len); }
+  }
+}
+
+/// Maps all `len` bytes of the shared memory behind `file`.
+fn map_region(file: File, len: usize, owner: bool) -> io::Result<RegionInner> {
+  unsafe {
+    let base =
+      libc::mmap(
+        ptr::null_mut(), len,
+        libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED,
+        file.as_raw_fd(), 0);
+    if base == libc::MAP_FAILED {
+      return Err(io::Error::last_os_error());
+    }
+
+    let free = if owner { vec![(DATA_START, len - DATA_START)] } else { Vec::new() };
+    Ok(RegionInner {
+      file: file,
+      base: base as usize,
+      len:  len,
+      free: Mutex::new(free),
+      exported: Mutex::new(HashMap::new()),
+      next_slot: AtomicUsize::new(0),
+    })
+  }
+}
+
+/// Makes a new, anonymous shared memory file.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn shared_memory_file() -> io::Result<File> {
+  let fd = unsafe { libc::memfd_create(b"iobuf\0".as_ptr() as *const libc::c_char, libc::MFD_CLOEXEC) };
+  if fd < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn shared_memory_file() -> io::Result<File> {
+  // Unlinked right away, so it's only reachable through the descriptor.
+  let name = format!("/iobuf-{}-{:x}\0", ::std::process::id(), random_id());
+  unsafe {
+    let name = name.as_ptr() as *const libc::c_char;
+    let fd = libc::shm_open(name, libc::O_RDWR | libc::O_CREAT | libc::O_EXCL, 0o600);
+    if fd < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    libc::shm_unlink(name);
+    Ok(File::from_raw_fd(fd))
+  }
+}
+
+fn random_id() -> u64 {
+  static COUNTER: AtomicUsize = AtomicUsize::new(0);
+  let mut h = RandomState::new().build_hasher();
+  h.write_u32(::std::process::id());
+  h.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+  h.finish()
+}
+
+/// An exported Iobuf, as made by `ShmAllocator::export`: where its window is
+/// in a shared memory region. It's plain data, to be sent to another process
+/// and imported there.
+///
+/// Each export holds a reference to the buffer, kept in one of the region's
+/// export slots, which is given to the first Iobuf `import` makes from it.
+/// Importing it again, or importing a `SharedIobuf` that wasn't exported,
+/// fails. If it's never imported, the buffer and the slot aren't freed until
+/// the region is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SharedIobuf {
+  /// The `id` of the region.
+  pub region: u64,
+  /// Where the buffer starts in the region.
+  pub buffer: u64,
+  /// The export slot holding the reference.
+  pub slot:   u32,
+  /// Where the window starts in the buffer.
+  pub offset: u32,
+  /// The length of the window.
+  pub len:    u32,
+}
+
+/// An `Allocator` whose buffers live in a region of shared memory, so that
+/// they can be handed to other processes without copying.
+///
+/// The process that `create`s a region allocates out of it, and `export`s
+/// buffers as `SharedIobuf`s. It also passes the region's `file` to the other
+/// processes, for example over a Unix socket, which `open` it and `import` the
+/// buffers as `ROIobuf`s.
+///
+/// Refcounts are kept in the buffers' headers, in the shared memory, so an
+/// exported buffer stays alive until both sides are done with it. Imported
+/// Iobufs can be cloned and sliced like any other, and count as a single
+/// reference however many there are. When the last reference goes away in
+/// an importing process, the buffer is passed back to be freed by the
+/// creator, the next time it allocates, exports or calls `reap`.
+///
+/// `ShmAllocator` is a cheap handle: clones share the same region.
+///
+/// ```rust
+/// use iobuf::{Iobuf, RWIobuf, ShmAllocator};
+///
+/// let region = ShmAllocator::create(1 << 20).unwrap();
+///
+/// let b = RWIobuf::new_with_allocator(5, region.allocator());
+/// b.poke(0, b"hello").unwrap();
+/// let shared = region.export(&b.atomic_read_only().unwrap()).unwrap();
+///
+/// // In another process, given the file:
+/// let helper = ShmAllocator::open(region.file().unwrap()).unwrap();
+///
+/// let b = helper.import(&shared).unwrap();
+/// unsafe { assert_eq!(b.as_window_slice(), b"hello"); }
+/// ```
+#[derive(Clone)]
+pub struct ShmAllocator {
+  inner: Arc<RegionInner>,
+}
+
+impl ShmAllocator {
+  /// Creates a new region of `len` bytes of shared memory, to allocate out of.
+  pub fn create(len: usize) -> io::Result<ShmAllocator> {
+    if len <= DATA_START {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "shared memory region too small"));
+    }
+
+    let file = shared_memory_file()?;
+    file.set_len(len as u64)?;
+
+    let inner = map_region(file, len, true)?;
+    unsafe {
+      ptr::write(inner.base as *mut RegionHeader, RegionHeader {
+        magic:  REGION_MAGIC,
+        id:     random_id(),
+        len:    len as u64,
+        reaped: AtomicU64::new(0),
+      });
+    }
+
+    let inner = Arc::new(inner);
+    lock_created().push((inner.base, inner.len, Arc::as_ptr(&inner) as usize));
+    CREATED_COUNT.fetch_add(1, Ordering::Release);
+
+    Ok(ShmAllocator { inner: inner })
+  }
+
+  /// Maps a region made by `create` in another process, given its file. The
+  /// region can only be used to `import` buffers, and never allocates.
+  pub fn open(file: File) -> io::Result<ShmAllocator> {
+    let len = file.metadata()?.len() as usize;
+    if len <= DATA_START {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "not an Iobuf shared memory region"));
+    }
+
+    let inner = map_region(file, len, false)?;
+    let h = inner.header();
+    if h.magic != REGION_MAGIC || h.len != len as u64 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "not an Iobuf shared memory region"));
+    }
+
+    Ok(ShmAllocator { inner: Arc::new(inner) })
+  }
+
+  /// Returns this allocator in the form `new_with_allocator` and friends
+  /// expect.
+  #[inline]
+  pub fn allocator(&self) -> Arc<Box<dyn Allocator>> {
+    Arc::new(Box::new(self.clone()))
+  }
+
+  /// A new handle to the region's shared memory file, for other processes to
+  /// `open`.
+  #[inline]
+  pub fn file(&self) -> io::Result<File> {
+    self.inner.file.try_clone()
+  }
+
+  /// Identifies the region, in every process that maps it.
+  #[inline]
+  pub fn id(&self) -> u64 {
+    self.inner.header().id
+  }
+
+  /// Frees the buffers whose last reference was dropped in another process.
+  /// This also happens on every allocation and export.
+  #[inline]
+  pub fn reap(&self) {
+    self.inner.reap()
+  }
+
+  /// Describes the window of `b` for another process to `import`. The
+  /// buffer must have been allocated out of this region. The description
+  /// holds a reference to the buffer, so it stays alive even if `b` is
+  /// dropped before it's imported.
+  ///
+  /// Fails if all of the region's export slots are waiting to be imported.
+  pub fn export(&self, b: &AROIobuf) -> io::Result<SharedIobuf> {
+    self.reap();
+
+    let inner = &*self.inner;
+    unsafe {
+      let raw  = b.as_raw();
+      let addr = raw.ptr().as_ptr() as usize;
+      if !raw.is_owned() || addr < inner.base + DATA_START || addr >= inner.base + inner.len {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is not in this region"));
+      }
+
+      let h = header_of(raw.ptr());
+      let buffer = addr - inner.base;
+      let fits =
+        buffer >= DATA_START + ALLOCATION_HEADER_SIZE
+          && (buffer - ALLOCATION_HEADER_SIZE).checked_add(h.allocation_length())
+               .is_some_and(|end| end <= inner.len);
+      let allocator =
+        match h.raw_allocator() {
+          Some(allocator) if fits => allocator.as_ptr() as usize,
+          _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is not in this region")),
+        };
+
+      h.inc_ref_count_atomic();
+      let slot =
+        match inner.fill_slot(buffer) {
+          Some(slot) => slot as u32,
+          None => {
+            // `b` still holds a reference, so this was never the last one.
+            let _ = h.dec_ref_count_atomic();
+            return Err(io::Error::other("too many buffers waiting to be imported"));
+          },
+        };
+      inner.lock_exported().entry(buffer).or_insert(Exported {
+        len:       h.allocation_length(),
+        allocator: allocator,
+      });
+
+      Ok(SharedIobuf {
+        region: self.id(),
+        buffer: buffer as u64,
+        slot:   slot,
+        offset: raw.lo(),
+        len:    raw.len(),
+      })
+    }
+  }
+
+  /// Maps the window described by `shared` into this process, as the buffer
+  /// of a new Iobuf. Nothing is copied. Its limits and window cover the
+  /// exported window.
+  ///
+  /// The window is mapped read-only, since the creator and other importers
+  /// still see it. The Iobuf is never unique, so `make_mut` copies it.
+  ///
+  /// Each export can only be imported once: this fails if `shared` was
+  /// imported before, or wasn't made by `export`. If its window was changed
+  /// to lie outside the buffer, it fails too, and the export is used up.
+  pub fn import(&self, shared: &SharedIobuf) -> io::Result<ROIobuf<'static>> {
+    let inner = &*self.inner;
+    if shared.region != self.id() || !inner.claim_slot(shared.slot, shared.buffer) {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an unclaimed export from this region"));
+    }
+
+    // The reference is ours now, and goes to the new Iobuf, or back if it
+    // can't be made.
+    self.map_import(shared).inspect_err(|_| inner.release(shared.buffer as usize))
+  }
+
+  fn map_import(&self, shared: &SharedIobuf) -> io::Result<ROIobuf<'static>> {
+    let inner = &*self.inner;
+
+    // Only the creator writes the slots, so `buffer` is one it exported.
+    let buffer = shared.buffer as usize;
+    let limit  = unsafe { header_of(inner.buffer(buffer)).allocation_length() }.saturating_sub(ALLOCATION_HEADER_SIZE);
+    let end    = shared.offset as usize + shared.len as usize;
+    if end > limit || buffer + end > inner.len {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "window is not in the buffer"));
+    }
+
+    let page_size = page_size();
+    let start     = buffer + shared.offset as usize;
+    let file_off  = start & !(page_size - 1);
+    let lo        = start - file_off;
+    let cap       = lo + shared.len as usize;
+    if cap > MAX_BUFFER_LEN {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "window too large for an Iobuf"));
+    }
+
+    unsafe {
+      // Like `map_file`, but for just the pages holding the window.
+      let map = map_behind_header(inner.file.as_raw_fd(), file_off as u64, cap, libc::PROT_READ)?;
+      let allocator =
+        ImportAllocator {
+          map:      map,
+          region:   self.inner.clone(),
+          buffer:   buffer,
+          released: AtomicBool::new(false),
+        };
+
+      // The size was checked above, and the allocator can't fail.
+      let mut raw = RawIobuf::new_with_allocator(cap, Arc::new(Box::new(allocator)));
+      raw.advance(lo as u32).unwrap();
+      raw.narrow();
+      Ok(mem::transmute::<RawIobuf<'static>, ROIobuf<'static>>(raw))
+    }
+  }
+}
+
+impl Allocator for ShmAllocator {
+  fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+    self.reap();
+
+    let inner = &*self.inner;
+    let mut free = inner.lock_free();
+
+    for i in 0..free.len() {
+      let (start, n) = free[i];
+      let aligned = start.checked_add(align - 1)? & !(align - 1);
+      let end = match aligned.checked_add(len) { Some(end) => end, None => break };
+      if end > start + n {
+        continue;
+      }
+
+      free.remove(i);
+      if end < start + n {
+        free.insert(i, (end, start + n - end));
+      }
+      if aligned > start {
+        free.insert(i, (start, aligned - start));
+      }
+      return Some(inner.buffer(aligned));
+    }
+
+    None
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, _align: usize) {
+    let inner = &*self.inner;
+    inner.free_range(ptr.as_ptr() as usize - inner.base, len);
+  }
+
+  fn release_data(&self, ptr: NonNull<u8>, len: usize) -> bool {
+    let inner = &*self.inner;
+    inner.free_range(ptr.as_ptr() as usize - inner.base, len);
+    true
+  }
+}
+
+/// An imported window's mapping, which releases the importer's reference to
+/// the shared buffer once the window is unmapped.
+struct ImportAllocator {
+  map:      MmapAllocator,
+  region:   Arc<RegionInner>,
+  /// The offset of the exported buffer in the region.
+  buffer:   usize,
+  /// Set once the reference to the shared buffer is released.
+  released: AtomicBool,
+}
+
+impl ImportAllocator {
+  #[inline]
+  fn release(&self) {
+    if !self.released.swap(true, Ordering::Relaxed) {
+      self.region.release(self.buffer);
+    }
+  }
+}
+
+impl Allocator for ImportAllocator {
+  fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+    self.map.allocate(len, align)
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+    self.map.deallocate(ptr, len, align);
+    self.release();
+  }
+
+  fn release_data(&self, ptr: NonNull<u8>, len: usize) -> bool {
+    let unmapped = self.map.release_data(ptr, len);
+    self.release();
+    unmapped
+  }
+
+  fn read_only(&self) -> bool {
+    true
+  }
+}
+
+#[cfg(test)]
+fn open_copy(region: &ShmAllocator) -> ShmAllocator {
+  ShmAllocator::open(region.file().unwrap()).unwrap()
+}
+
+#[test]
+fn freed_by_the_creator() {
+  use impls::RWIobuf;
+
+  let region = ShmAllocator::create(1 << 16).unwrap();
+  let helper = open_copy(&region);
+  assert_eq!(helper.id(), region.id());
+
+  let mut b = RWIobuf::new_with_allocator(100, region.allocator());
+  b.poke(0, &[7; 100]).unwrap();
+  b.sub(10, 20).unwrap();
+  let p = b.ptr();
+  let shared = region.export(&b.atomic_read_only().unwrap()).unwrap();
+  assert_eq!((shared.offset, shared.len), (10, 20));
+
+  // The export keeps it alive.
+  let imported = helper.import(&shared).unwrap();
+  assert_eq!(imported.len(), 20);
+  assert_eq!(imported.cap(), 20);
+  unsafe { assert_eq!(imported.as_window_slice(), &[7; 20][..]); }
+
+  let c = imported.clone();
+  drop(imported);
+  assert!(RWIobuf::new_with_allocator(100, region.allocator()).ptr() != p);
+
+  // Dropped last by the importer, so it's freed by the creator's next
+  // allocation.
+  drop(c);
+  assert!(RWIobuf::new_with_allocator(100, region.allocator()).ptr() == p);
+}
+
+#[test]
+fn imports_are_checked() {
+  use impls::RWIobuf;
+
+  let region = ShmAllocator::create(1 << 16).unwrap();
+  let other  = ShmAllocator::create(1 << 16).unwrap();
+
+  let b = RWIobuf::new_with_allocator(16, region.allocator()).atomic_read_only().unwrap();
+  assert!(other.export(&b).is_err());
+  assert!(region.export(&RWIobuf::new(16).atomic_read_only().unwrap()).is_err());
+
+  let shared = region.export(&b).unwrap();
+  assert!(other.import(&shared).is_err());
+  assert!(region.import(&SharedIobuf { slot: shared.slot + 1, ..shared }).is_err());
+  assert!(region.import(&SharedIobuf { slot: 1 << 20, ..shared }).is_err());
+  assert!(region.import(&SharedIobuf { buffer: shared.buffer + 8, ..shared }).is_err());
+
+  // Importers can't allocate.
+  assert!(RWIobuf::try_new_with_allocator(16, open_copy(&region).allocator()).is_err());
+
+  // Each export is imported once.
+  drop(region.import(&shared).unwrap());
+  assert!(region.import(&shared).is_err());
+
+  // A window outside the buffer uses the export up, without mapping it.
+  let shared = region.export(&b).unwrap();
+  assert!(region.import(&SharedIobuf { len: 17, ..shared }).is_err());
+  assert!(region.import(&shared).is_err());
+
+  // All of those references were dropped.
+  assert!(b.unique().is_ok());
+}
+
+#[test]
+fn export_slots_run_out() {
+  use impls::RWIobuf;
+
+  let region = ShmAllocator::create(1 << 16).unwrap();
+  let b = RWIobuf::new_with_allocator(16, region.allocator()).atomic_read_only().unwrap();
+
+  let shared: Vec<_> = (0..EXPORT_SLOTS).map(|_| region.export(&b).unwrap()).collect();
+  assert!(region.export(&b).is_err());
+
+  // An import frees its slot.
+  drop(region.import(&shared[7]).unwrap());
+  let again = region.export(&b).unwrap();
+  assert_eq!(again.slot, shared[7].slot);
+}
+
+#[test]
+fn only_exported_buffers_are_reaped() {
+  use impls::RWIobuf;
+
+  let region = ShmAllocator::create(1 << 16).unwrap();
+  let helper = open_copy(&region);
+  let reaped = &helper.inner.header().reaped;
+  let free = |r: &ShmAllocator| r.inner.lock_free().clone();
+
+  let b = RWIobuf::new_with_allocator(16, region.allocator()).atomic_read_only().unwrap();
+  let offset = b.ptr().as_ptr() as usize - region.inner.base;
+  let before = free(&region);
+
+  // Neither a buffer that was never exported, nor garbage, is freed.
+  reaped.store(offset as u64, Ordering::SeqCst);
+  region.reap();
+  reaped.store(12345, Ordering::SeqCst);
+  region.reap();
+  assert_eq!(free(&region), before);
+
+  // Nor is an exported buffer twice, even if it's pushed again.
+  let shared = region.export(&b).unwrap();
+  drop(b);
+  drop(helper.import(&shared).unwrap());
+  region.reap();
+  let after = free(&region);
+  assert!(after != before);
+
+  reaped.store(offset as u64, Ordering::SeqCst);
+  region.reap();
+  assert_eq!(free(&region), after);
+  assert!(region.inner.lock_exported().is_empty());
+
+  // The buffer's reference to its allocator went with it.
+  assert_eq!(Arc::strong_count(&region.inner), 1);
+}
+
+#[test]
+fn exported_buffers_are_freed_by_their_record() {
+  use impls::RWIobuf;
+
+  let region = ShmAllocator::create(1 << 16).unwrap();
+  let helper = open_copy(&region);
+  let empty  = region.inner.lock_free().clone();
+
+  let b = RWIobuf::new_with_allocator(100, region.allocator()).atomic_read_only().unwrap();
+  let w = b.downgrade();
+  let shared = region.export(&b).unwrap();
+  drop(helper.import(&shared).unwrap());
+
+  // An importer overwrites the header, but the creator drops it last, and
+  // frees it as it was exported.
+  unsafe {
+    header_of(helper.inner.buffer(shared.buffer as usize)).scribble(NonNull::new(8 as *mut ()), 1 << 15);
+  }
+  drop(b);
+  assert!(*region.inner.lock_free() != empty);
+  drop(w);
+  assert_eq!(*region.inner.lock_free(), empty);
+
+  assert!(region.inner.lock_exported().is_empty());
+  assert_eq!(Arc::strong_count(&region.inner), 1);
+}
+
+#[test]
+fn dead_buffers_give_back_their_data() {
+  use impls::RWIobuf;
+
+  let region = ShmAllocator::create(1 << 16).unwrap();
+  let empty  = region.inner.lock_free().clone();
+
+  let b = RWIobuf::new_with_allocator(1000, region.allocator());
+  let w = b.downgrade();
+  let data = b.ptr().as_ptr() as usize - region.inner.base;
+  drop(b);
+
+  // Only the header is still allocated.
+  assert_eq!(*region.inner.lock_free(),
+             vec![(data, (1 << 16) - data)]);
+  assert_eq!(data - ALLOCATION_HEADER_SIZE, DATA_START);
+  drop(w);
+  assert_eq!(*region.inner.lock_free(), empty);
+}
+
+#[test]
+fn imports_are_read_only() {
+  use impls::RWIobuf;
+
+  let region = ShmAllocator::create(1 << 16).unwrap();
+  let helper = open_copy(&region);
+
+  let b = RWIobuf::new_with_allocator(5, region.allocator());
+  b.poke(0, b"hello").unwrap();
+  let b = b.atomic_read_only().unwrap();
+  let shared = region.export(&b).unwrap();
+
+  // Even as the only Iobuf in this process, it's not unique.
+  let imported = helper.import(&shared).unwrap();
+  let imported = imported.unique().unwrap_err();
+  let imported = imported.atomic_read_only().unwrap_err();
+
+  let copy = imported.clone().make_mut();
+  assert!(!copy.shares_buffer_with(&imported));
+  copy.poke(0, b"j").unwrap();
+  unsafe {
+    assert_eq!(copy.as_window_slice(), b"jello");
+    assert_eq!(imported.as_window_slice(), b"hello");
+    assert_eq!(b.as_window_slice(), b"hello");
+  }
+}
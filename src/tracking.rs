@@ -0,0 +1,430 @@
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::slice;
+
+use raw::{Allocator, HeapAllocator, ALLOCATION_HEADER_SIZE, header_of, show_bytes, written_len};
+
+/// Memory accounting for a `TrackingAllocator`, or for one of its labels.
+///
+/// Byte counts are what was asked of the wrapped allocator, so they include
+/// the allocation header of every buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TrackingStats {
+  /// Bytes allocated and not yet freed.
+  pub live_bytes:    u64,
+  /// The most `live_bytes` has ever been.
+  pub peak_bytes:    u64,
+  /// The number of allocations made.
+  pub allocations:   u64,
+  /// The number of allocations freed.
+  pub deallocations: u64,
+}
+
+impl TrackingStats {
+  /// The number of buffers allocated and not yet freed.
+  #[inline]
+  pub fn live_allocations(&self) -> u64 {
+    self.allocations - self.deallocations
+  }
+}
+
+#[derive(Default)]
+struct Counters {
+  live_bytes:    AtomicU64,
+  peak_bytes:    AtomicU64,
+  allocations:   AtomicU64,
+  deallocations: AtomicU64,
+}
+
+impl Counters {
+  #[inline]
+  fn allocated(&self, len: usize) {
+    let live = self.live_bytes.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+    self.peak_bytes.fetch_max(live, Ordering::Relaxed);
+    self.allocations.fetch_add(1, Ordering::Relaxed);
+  }
+
+  #[inline]
+  fn deallocated(&self, len: usize) {
+    self.live_bytes.fetch_sub(len as u64, Ordering::Relaxed);
+    self.deallocations.fetch_add(1, Ordering::Relaxed);
+  }
+
+  #[inline]
+  fn released(&self, len: usize) {
+    self.live_bytes.fetch_sub(len as u64, Ordering::Relaxed);
+  }
+
+  fn snapshot(&self) -> TrackingStats {
+    TrackingStats {
+      live_bytes:    self.live_bytes.load(Ordering::Relaxed),
+      peak_bytes:    self.peak_bytes.load(Ordering::Relaxed),
+      allocations:   self.allocations.load(Ordering::Relaxed),
+      deallocations: self.deallocations.load(Ordering::Relaxed),
+    }
+  }
+}
+
+/// A buffer that hasn't been freed yet, recorded in debug mode.
+struct LiveAllocation {
+  len:       usize,
+  align:     usize,
+  label:     Option<&'static str>,
+  backtrace: Backtrace,
+}
+
+struct TrackingInner {
+  allocator:  Arc<Box<dyn Allocator>>,
+  total:      Counters,
+  labels:     Mutex<HashMap<&'static str, Arc<Counters>>>,
+  /// Whether new allocations go into `live`.
+  debug:      AtomicBool,
+  /// Set once `debug` is first turned on. Until then, `live` is known to be
+  /// empty, and deallocations don't have to lock it.
+  ever_debug: AtomicBool,
+  /// The live allocations made in debug mode, by address.
+  live:       Mutex<HashMap<usize, LiveAllocation>>,
+}
+
+/// Where the data starts in an allocation aligned to `align`: at the first
+/// multiple of it after the header, which is right after it, unless it's from
+/// `new_aligned`.
+#[inline]
+fn data_offset(align: usize) -> usize {
+  (ALLOCATION_HEADER_SIZE + align - 1) & !(align - 1)
+}
+
+#[inline]
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+  m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// An `Allocator` which wraps another one, and keeps track of how much memory
+/// went through it.
+///
+/// It counts the bytes that are live, the peak, and the number of allocations
+/// and deallocations. Handles made with `labeled` are also counted separately
+/// under their label, so you can tell which subsystem is holding on to memory.
+///
+/// In debug mode (see `set_debug`), the allocator also remembers where every
+/// new buffer was allocated until it's freed, and `live_buffers` can show
+/// them, and `live_buffers_with_contents` their bytes too. That's slow, so
+/// it's meant for hunting down leaks.
+///
+/// `TrackingAllocator` is a cheap handle: clones share the same statistics.
+///
+/// ```rust
+/// use iobuf::{RWIobuf, TrackingAllocator};
+///
+/// let tracker = TrackingAllocator::heap();
+/// let http = tracker.labeled("http");
+///
+/// let a = RWIobuf::new_with_allocator(100, tracker.allocator());
+/// let b = RWIobuf::new_with_allocator(200, http.allocator());
+/// drop(a);
+///
+/// let stats = tracker.stats();
+/// assert_eq!(stats.allocations, 2);
+/// assert_eq!(stats.live_allocations(), 1);
+/// assert!(stats.peak_bytes > stats.live_bytes);
+///
+/// let labels = tracker.label_stats();
+/// assert_eq!(labels.len(), 1);
+/// assert_eq!(labels[0].0, "http");
+/// assert_eq!(labels[0].1.live_bytes, stats.live_bytes);
+/// # drop(b);
+/// ```
+#[derive(Clone)]
+pub struct TrackingAllocator {
+  inner: Arc<TrackingInner>,
+  label: Option<(&'static str, Arc<Counters>)>,
+}
+
+impl TrackingAllocator {
+  /// Tracks the memory allocated out of `allocator`.
+  pub fn new(allocator: Arc<Box<dyn Allocator>>) -> TrackingAllocator {
+    TrackingAllocator {
+      inner: Arc::new(TrackingInner {
+        allocator:  allocator,
+        total:      Counters::default(),
+        labels:     Mutex::new(HashMap::new()),
+        debug:      AtomicBool::new(false),
+        ever_debug: AtomicBool::new(false),
+        live:       Mutex::new(HashMap::new()),
+      }),
+      label: None,
+    }
+  }
+
+  /// Tracks the memory allocated out of the heap.
+  pub fn heap() -> TrackingAllocator {
+    TrackingAllocator::new(Arc::new(Box::new(HeapAllocator)))
+  }
+
+  /// Turns debug mode on or off, for every handle. While it's on, a backtrace
+  /// is recorded for each new allocation, and kept until it's freed. Turning
+  /// it off only stops recording new ones. See `live_buffers`.
+  pub fn set_debug(&self, enabled: bool) {
+    if enabled {
+      self.inner.ever_debug.store(true, Ordering::SeqCst);
+    }
+    self.inner.debug.store(enabled, Ordering::SeqCst);
+  }
+
+  /// Returns a handle to the same allocator, whose allocations are also
+  /// counted under `label`.
+  pub fn labeled(&self, label: &'static str) -> TrackingAllocator {
+    let counters = lock(&self.inner.labels).entry(label).or_default().clone();
+    TrackingAllocator {
+      inner: self.inner.clone(),
+      label: Some((label, counters)),
+    }
+  }
+
+  /// Returns this allocator in the form `new_with_allocator` and friends
+  /// expect.
+  #[inline]
+  pub fn allocator(&self) -> Arc<Box<dyn Allocator>> {
+    Arc::new(Box::new(self.clone()))
+  }
+
+  /// A snapshot of the counters for everything allocated through any handle.
+  #[inline]
+  pub fn stats(&self) -> TrackingStats {
+    self.inner.total.snapshot()
+  }
+
+  /// A snapshot of the counters for each label, sorted by label.
+  pub fn label_stats(&self) -> Vec<(&'static str, TrackingStats)> {
+    let mut stats: Vec<_> =
+      lock(&self.inner.labels).iter().map(|(&l, c)| (l, c.snapshot())).collect();
+    stats.sort_by_key(|&(l, _)| l);
+    stats
+  }
+
+  /// Shows every buffer that hasn't been freed yet: its label, its size, its
+  /// tag, and where it was allocated. Only allocations made in debug mode are
+  /// known, so the rest aren't shown.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf, TrackingAllocator};
+  ///
+  /// let tracker = TrackingAllocator::heap();
+  /// tracker.set_debug(true);
+  ///
+  /// let leaked = RWIobuf::new_with_allocator(4, tracker.labeled("parser").allocator());
+  ///
+  /// let dump = tracker.live_buffers().to_string();
+  /// assert!(dump.contains("parser buffer, 4 bytes"));
+  /// # drop(leaked);
+  /// ```
+  #[inline]
+  pub fn live_buffers<'a>(&'a self) -> LiveBuffers<'a> {
+    LiveBuffers { inner: &self.inner, contents: false }
+  }
+
+  /// The same as `live_buffers`, but also shows a hexdump of each buffer's
+  /// written bytes, in the same format as the `Debug` impls of the Iobufs.
+  ///
+  /// # Safety
+  ///
+  /// The buffers are read while formatting, so none of them may be written to
+  /// until the report is dropped. This is best called once everything should
+  /// have been dropped, like at the end of a test.
+  ///
+  /// ```rust
+  /// use iobuf::{RWIobuf, TrackingAllocator};
+  ///
+  /// let tracker = TrackingAllocator::heap();
+  /// tracker.set_debug(true);
+  ///
+  /// let leaked = RWIobuf::new_with_allocator(4, tracker.labeled("parser").allocator());
+  /// leaked.poke(0, b"oops").unwrap();
+  ///
+  /// let dump = unsafe { tracker.live_buffers_with_contents() }.to_string();
+  /// assert!(dump.contains("parser buffer"));
+  /// assert!(dump.contains("6f 6f 70 73"));
+  /// ```
+  #[inline]
+  pub unsafe fn live_buffers_with_contents<'a>(&'a self) -> LiveBuffers<'a> {
+    LiveBuffers { inner: &self.inner, contents: true }
+  }
+}
+
+impl Allocator for TrackingAllocator {
+  fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+    let ptr = self.inner.allocator.allocate(len, align)?;
+
+    self.inner.total.allocated(len);
+    if let Some((_, ref counters)) = self.label {
+      counters.allocated(len);
+    }
+
+    if self.inner.debug.load(Ordering::Relaxed) {
+      let allocation =
+        LiveAllocation {
+          len:       len,
+          align:     align,
+          label:     self.label.as_ref().map(|&(l, _)| l),
+          backtrace: Backtrace::force_capture(),
+        };
+      lock(&self.inner.live).insert(ptr.as_ptr() as usize, allocation);
+    }
+
+    Some(ptr)
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+    // Forgotten before it's freed, so `live_buffers` never sees freed memory.
+    if self.inner.ever_debug.load(Ordering::Relaxed) {
+      lock(&self.inner.live).remove(&(ptr.as_ptr() as usize));
+    }
+
+    self.inner.total.deallocated(len);
+    if let Some((_, ref counters)) = self.label {
+      counters.deallocated(len);
+    }
+
+    self.inner.allocator.deallocate(ptr, len, align)
+  }
+
+  fn flush(&self, ptr: NonNull<u8>, len: usize) -> io::Result<()> {
+    self.inner.allocator.flush(ptr, len)
+  }
+
+  fn read_only(&self) -> bool {
+    self.inner.allocator.read_only()
+  }
+
+  fn release_data(&self, ptr: NonNull<u8>, len: usize) -> bool {
+    if !self.inner.allocator.release_data(ptr, len) {
+      return false;
+    }
+
+    self.inner.total.released(len);
+    if let Some((_, ref counters)) = self.label {
+      counters.released(len);
+    }
+
+    // Only the header is left to show.
+    if self.inner.ever_debug.load(Ordering::Relaxed) {
+      for (&addr, a) in lock(&self.inner.live).iter_mut() {
+        if addr + data_offset(a.align) == ptr.as_ptr() as usize {
+          a.len = data_offset(a.align);
+        }
+      }
+    }
+    true
+  }
+
+  fn reclaim_vec(&self) -> Option<(NonNull<u8>, usize)> {
+    self.inner.allocator.reclaim_vec()
+  }
+}
+
+/// The buffers a `TrackingAllocator` hasn't freed yet, made by `live_buffers`.
+/// Formats as a report of each one.
+pub struct LiveBuffers<'a> {
+  inner:    &'a TrackingInner,
+  /// Whether to dump the buffers' bytes, too.
+  contents: bool,
+}
+
+impl<'a> Display for LiveBuffers<'a> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    let live = lock(&self.inner.live);
+    let mut allocations: Vec<_> = live.iter().collect();
+    allocations.sort_by_key(|&(&addr, _)| addr);
+
+    for (&addr, a) in allocations {
+      let offset = data_offset(a.align);
+      let cap    = a.len - offset;
+
+      unsafe {
+        let buf = NonNull::new_unchecked((addr + offset) as *mut u8);
+        writeln!(f, "{} buffer, {} bytes at {:p}, tag={}, allocated at:",
+                 a.label.unwrap_or("unlabeled"), cap, buf.as_ptr(),
+                 header_of(buf).tag().load(Ordering::Relaxed))?;
+        writeln!(f, "{}", a.backtrace)?;
+        if self.contents {
+          show_bytes(f, slice::from_raw_parts(buf.as_ptr(), written_len(buf, cap)))?;
+        }
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[test]
+fn counts_by_label() {
+  use impls::RWIobuf;
+
+  let tracker = TrackingAllocator::heap();
+  let a = tracker.labeled("a");
+  let b = tracker.labeled("b");
+
+  let x = RWIobuf::new_with_allocator(64, a.allocator());
+  let y = RWIobuf::new_with_allocator(32, b.allocator());
+  let z = RWIobuf::new_with_allocator(16, a.allocator());
+  let h = ALLOCATION_HEADER_SIZE as u64;
+
+  let stats = tracker.label_stats();
+  assert_eq!(stats[0].0, "a");
+  assert_eq!(stats[0].1.live_bytes, 80 + 2 * h);
+  assert_eq!(stats[1].0, "b");
+  assert_eq!(stats[1].1.live_bytes, 32 + h);
+
+  // Clones don't allocate.
+  let x2 = x.clone();
+  drop(x);
+  drop(y);
+  assert_eq!(tracker.stats().live_bytes, 80 + 2 * h);
+  drop(x2);
+  drop(z);
+
+  let stats = tracker.stats();
+  assert_eq!(stats.live_bytes, 0);
+  assert_eq!(stats.peak_bytes, 112 + 3 * h);
+  assert_eq!((stats.allocations, stats.deallocations), (3, 3));
+  assert_eq!(tracker.label_stats()[1].1.deallocations, 1);
+}
+
+#[test]
+fn shows_only_live_written_bytes() {
+  use impls::RWIobuf;
+  use iobuf::Iobuf;
+  use raw::RawIobuf;
+
+  let tracker = TrackingAllocator::heap();
+  tracker.set_debug(true);
+
+  // The copy keeps track of which bytes were written.
+  let a = RWIobuf::new_uninit(64);
+  a.poke(0, b"abc").unwrap();
+  let a = a.deep_clone_with_allocator(tracker.labeled("uninit").allocator());
+
+  // Aligned buffers have padding in front of the header.
+  let mut b = RawIobuf::try_new_impl(8, Some(tracker.allocator()), 256).unwrap();
+  unsafe { b.unsafe_poke(0, b"abcdefgh") };
+
+  let dump = tracker.live_buffers().to_string();
+  assert!(dump.contains("uninit buffer, 64 bytes at "));
+  assert!(!dump.contains("0x00:"));
+
+  let dump = unsafe { tracker.live_buffers_with_contents() }.to_string();
+  assert!(dump.contains("uninit buffer, 64 bytes at "));
+  assert!(dump.contains("0x00:  61 62 63   abc  \n"));
+  assert!(dump.contains(&format!("unlabeled buffer, 8 bytes at {:p}", b.ptr().as_ptr())));
+  assert!(dump.contains("0x00:  61 62 63 64   abcd  efgh  65 66 67 68"));
+
+  drop(a);
+  unsafe { b.drop_nonatomic() };
+  assert_eq!(tracker.live_buffers().to_string(), "");
+  assert_eq!(tracker.stats().live_allocations(), 0);
+}
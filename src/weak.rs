@@ -0,0 +1,251 @@
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+
+use impls::{AROIobuf, ROIobuf};
+use raw::RawIobuf;
+
+/// A weak reference to the buffer of a `ROIobuf` or `RWIobuf`, made by
+/// `downgrade`.
+///
+/// It remembers the limits and window of the Iobuf it was made from, but
+/// doesn't keep the buffer alive: once every other Iobuf over it is dropped,
+/// `upgrade` returns `None`. The data is then handed back to allocators that
+/// keep it apart from the allocation header, like those of mapped files and
+/// shared memory. The header holds the refcounts, so it stays until the last
+/// weak reference goes.
+///
+/// Buffers on the heap, or adopted with `from_vec` or
+/// `from_raw_parts_with_release`, keep their data in the same allocation as
+/// the header. For them, a weak reference keeps the whole allocation around.
+///
+/// While a buffer has weak references, it isn't unique: `unique`,
+/// `atomic_read_only` and friends will refuse, since a weak reference could
+/// turn back into a strong one at any time.
+///
+/// ```rust
+/// use iobuf::{ROIobuf, Iobuf};
+///
+/// let b = ROIobuf::from_str_copy("hello, world");
+/// let w = b.downgrade();
+///
+/// let c = w.upgrade().unwrap();
+/// unsafe { assert_eq!(c.as_window_slice(), b"hello, world"); }
+///
+/// drop(b);
+/// drop(c);
+/// assert!(w.upgrade().is_none());
+/// ```
+pub struct WeakIobuf<'a> {
+  raw: RawIobuf<'a>,
+}
+
+impl<'a> WeakIobuf<'a> {
+  #[inline]
+  pub(crate) fn new(raw: &RawIobuf<'a>) -> WeakIobuf<'a> {
+    WeakIobuf { raw: unsafe { raw.downgrade_nonatomic() } }
+  }
+
+  /// Gets a read-only view of the buffer, with the limits and window it had
+  /// when this was made, if the buffer is still alive. As with
+  /// `RWIobuf::read_only`, they stop at the last written byte.
+  #[inline]
+  pub fn upgrade(&self) -> Option<ROIobuf<'a>> {
+    unsafe {
+      self.raw.upgrade_nonatomic().map(|mut raw| {
+        raw.clamp_to_initialized();
+        mem::transmute(raw)
+      })
+    }
+  }
+}
+
+impl<'a> Clone for WeakIobuf<'a> {
+  #[inline]
+  fn clone(&self) -> Self {
+    WeakIobuf { raw: unsafe { self.raw.downgrade_nonatomic() } }
+  }
+}
+
+impl<'a> Drop for WeakIobuf<'a> {
+  #[inline]
+  fn drop(&mut self) { unsafe { self.raw.drop_weak_nonatomic() } }
+}
+
+impl<'a> Debug for WeakIobuf<'a> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "WeakIobuf {{ lo_min: {}, lo: {}, hi: {}, hi_max: {}, alive: {} }}",
+           self.raw.lo_min(), self.raw.lo(), self.raw.hi(), self.raw.hi_max(),
+           self.upgrade().is_some())
+  }
+}
+
+/// A weak reference to the buffer of an `AROIobuf`, which may be shared
+/// between threads. See `WeakIobuf`.
+///
+/// ```rust
+/// use iobuf::{AROIobuf, ROIobuf, Iobuf};
+/// use std::thread;
+///
+/// let b: AROIobuf = ROIobuf::from_str_copy("hello").atomic_read_only().unwrap();
+/// let w = b.downgrade();
+///
+/// let alive = thread::spawn(move || w.upgrade().map(|b| b.len())).join().unwrap();
+/// assert_eq!(alive, Some(5));
+///
+/// let w = b.downgrade();
+/// drop(b);
+/// assert!(w.upgrade().is_none());
+/// ```
+pub struct AWeakIobuf {
+  raw: RawIobuf<'static>,
+}
+
+unsafe impl Send for AWeakIobuf {}
+unsafe impl Sync for AWeakIobuf {}
+
+impl AWeakIobuf {
+  #[inline]
+  pub(crate) fn new(raw: &RawIobuf<'static>) -> AWeakIobuf {
+    AWeakIobuf { raw: unsafe { raw.downgrade_atomic() } }
+  }
+
+  /// Gets an atomically refcounted view of the buffer, with the limits and
+  /// window it had when this was made, if the buffer is still alive.
+  #[inline]
+  pub fn upgrade(&self) -> Option<AROIobuf> {
+    unsafe { self.raw.upgrade_atomic().map(|raw| mem::transmute(raw)) }
+  }
+}
+
+impl Clone for AWeakIobuf {
+  #[inline]
+  fn clone(&self) -> Self {
+    AWeakIobuf { raw: unsafe { self.raw.downgrade_atomic() } }
+  }
+}
+
+impl Drop for AWeakIobuf {
+  #[inline]
+  fn drop(&mut self) { unsafe { self.raw.drop_weak_atomic() } }
+}
+
+impl Debug for AWeakIobuf {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "AWeakIobuf {{ lo_min: {}, lo: {}, hi: {}, hi_max: {}, alive: {} }}",
+           self.raw.lo_min(), self.raw.lo(), self.raw.hi(), self.raw.hi_max(),
+           self.upgrade().is_some())
+  }
+}
+
+#[test]
+fn weak_refs_free_the_allocation_last() {
+  use std::alloc::{self, Layout};
+  use std::ptr::NonNull;
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use impls::RWIobuf;
+  use iobuf::Iobuf;
+  use raw::Allocator;
+
+  static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+  struct Counting;
+
+  impl Allocator for Counting {
+    fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+      LIVE.fetch_add(1, Ordering::SeqCst);
+      unsafe { NonNull::new(alloc::alloc(Layout::from_size_align(len, align).unwrap())) }
+    }
+
+    fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+      LIVE.fetch_sub(1, Ordering::SeqCst);
+      unsafe { alloc::dealloc(ptr.as_ptr(), Layout::from_size_align(len, align).unwrap()) }
+    }
+  }
+
+  let mut b = RWIobuf::new_with_allocator(64, Arc::new(Box::new(Counting)));
+  b.advance(4).unwrap();
+  b.resize(8).unwrap();
+
+  let w  = b.downgrade();
+  let w2 = w.clone();
+  assert!(!b.is_unique());
+  assert_eq!(b.ref_count(), 1);
+
+  let c = w.upgrade().unwrap();
+  assert_eq!((c.lo_min(), c.lo(), c.hi(), c.hi_max()), (0, 4, 12, 64));
+  assert_eq!(b.ref_count(), 2);
+  drop(c);
+  drop(b);
+
+  // The buffer is dead, but the header is still in use.
+  assert!(w.upgrade().is_none());
+  drop(w);
+  assert_eq!(LIVE.load(Ordering::SeqCst), 1);
+  drop(w2);
+  assert_eq!(LIVE.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn dead_buffers_release_their_data() {
+  use std::alloc::{self, Layout};
+  use std::ptr::NonNull;
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use impls::RWIobuf;
+  use raw::{Allocator, ALLOCATION_HEADER_SIZE};
+
+  static RELEASED: AtomicUsize = AtomicUsize::new(0);
+  static FREED:    AtomicUsize = AtomicUsize::new(0);
+
+  // Only pretends to free the data early, so it frees the whole allocation
+  // at the end, whatever `len` it's told.
+  struct Releasing;
+
+  const SIZE: usize = ALLOCATION_HEADER_SIZE + 64;
+
+  impl Allocator for Releasing {
+    fn allocate(&self, len: usize, align: usize) -> Option<NonNull<u8>> {
+      assert_eq!(len, SIZE);
+      unsafe { NonNull::new(alloc::alloc(Layout::from_size_align(SIZE, align).unwrap())) }
+    }
+
+    fn deallocate(&self, ptr: NonNull<u8>, len: usize, align: usize) {
+      FREED.store(len, Ordering::SeqCst);
+      unsafe { alloc::dealloc(ptr.as_ptr(), Layout::from_size_align(SIZE, align).unwrap()) }
+    }
+
+    fn release_data(&self, _ptr: NonNull<u8>, len: usize) -> bool {
+      RELEASED.store(len, Ordering::SeqCst);
+      true
+    }
+  }
+
+  // Without weak references, the buffer's freed in one go.
+  drop(RWIobuf::new_with_allocator(64, Arc::new(Box::new(Releasing))));
+  assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+  assert_eq!(FREED.load(Ordering::SeqCst), SIZE);
+
+  let b = RWIobuf::new_with_allocator(64, Arc::new(Box::new(Releasing)));
+  let w = b.downgrade();
+  let a = b.read_only();
+  drop(b);
+  assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+
+  // The last strong reference hands the data back, and the weak one only
+  // keeps the header.
+  drop(a);
+  assert_eq!(RELEASED.load(Ordering::SeqCst), 64);
+  assert!(w.upgrade().is_none());
+  drop(w);
+  assert_eq!(FREED.load(Ordering::SeqCst), ALLOCATION_HEADER_SIZE);
+}
+
+#[test]
+fn borrowed_weak_refs_always_upgrade() {
+  let s = b"hello";
+  let b = ROIobuf::from_slice(s);
+  let w = b.downgrade();
+  drop(b);
+  assert!(w.upgrade().is_some());
+}
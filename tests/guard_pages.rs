@@ -0,0 +1,79 @@
+//! Checks that `GuardPageAllocator` turns bad accesses into faults.
+//!
+//! Each access that may fault runs in a forked child. Forking is only safe
+//! from a single-threaded process, so this runs without the test harness.
+
+extern crate iobuf;
+#[cfg(unix)] extern crate libc;
+
+#[cfg(unix)]
+mod unix {
+  use std::ptr;
+
+  use iobuf::{FreedMemory, GuardPageAllocator, Iobuf, RWIobuf, POISON};
+  use libc;
+
+  /// Runs `f` in a forked child, and returns whether it was killed by a
+  /// memory fault.
+  fn faults<F: FnOnce()>(f: F) -> bool {
+    unsafe {
+      match libc::fork() {
+        0 => {
+          f();
+          libc::_exit(0);
+        },
+        pid => {
+          assert!(pid > 0);
+          let mut status = 0;
+          assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+          libc::WIFSIGNALED(status)
+            && (libc::WTERMSIG(status) == libc::SIGSEGV || libc::WTERMSIG(status) == libc::SIGBUS)
+        },
+      }
+    }
+  }
+
+  fn overruns_fault() {
+    let guarded = GuardPageAllocator::new(FreedMemory::Unmap);
+    let b = RWIobuf::new_with_allocator(64, guarded.allocator());
+    assert_eq!(b.ptr().as_ptr() as usize % 16, 0);
+
+    assert!(!faults(|| unsafe { b.unsafe_poke(0, &[1; 64]) }));
+    assert!(faults(|| unsafe { b.unsafe_poke(60, &[1; 8]) }));
+    assert!(faults(|| unsafe { b.as_mut_limit_slice().as_mut_ptr().add(64).write_volatile(1) }));
+
+    // Buffers spanning several pages still start inside the mapping.
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+    let big = RWIobuf::new_with_allocator(3 * page_size, guarded.allocator());
+    assert!(!faults(|| unsafe { big.unsafe_poke(0, &[1]) }));
+  }
+
+  fn freed_memory() {
+    let protected = GuardPageAllocator::new(FreedMemory::Protect);
+    let b = RWIobuf::new_with_allocator(16, protected.allocator());
+    let p = b.ptr().as_ptr();
+    drop(b);
+    assert!(faults(|| unsafe { ptr::read_volatile(p); }));
+
+    let poisoned = GuardPageAllocator::new(FreedMemory::Poison);
+    let b = RWIobuf::new_with_allocator(16, poisoned.allocator());
+    b.poke(0, b"hello").unwrap();
+    let p = b.ptr().as_ptr();
+    drop(b);
+    assert_eq!(unsafe { ptr::read_volatile(p) }, POISON);
+    assert!(faults(|| unsafe { ptr::write_volatile(p, 0) }));
+  }
+
+  pub fn main() {
+    overruns_fault();
+    freed_memory();
+  }
+}
+
+#[cfg(unix)]
+fn main() {
+  unix::main();
+}
+
+#[cfg(not(unix))]
+fn main() {}
@@ -0,0 +1,58 @@
+//! Hands a buffer in shared memory to a forked child, which imports it and
+//! drops the last reference.
+//!
+//! Forking is only safe from a single-threaded process, so this runs without
+//! the test harness.
+
+extern crate iobuf;
+#[cfg(unix)] extern crate libc;
+
+#[cfg(unix)]
+mod unix {
+  use iobuf::{Iobuf, RWIobuf, ShmAllocator};
+  use libc;
+
+  fn shared_with_a_child_process() {
+    let region = ShmAllocator::create(1 << 16).unwrap();
+    let b = RWIobuf::new_with_allocator(5, region.allocator());
+    b.poke(0, b"hello").unwrap();
+    let p = b.ptr();
+    let b = b.atomic_read_only().unwrap();
+    let shared = region.export(&b).unwrap();
+    drop(b);
+
+    unsafe {
+      match libc::fork() {
+        0 => {
+          let helper = ShmAllocator::open(region.file().unwrap()).unwrap();
+          drop(region);
+          let ok = helper.import(&shared).map(|b| b.as_window_slice() == b"hello");
+          libc::_exit(if ok.unwrap_or(false) { 0 } else { 1 });
+        },
+        pid => {
+          assert!(pid > 0);
+          let mut status = 0;
+          assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+          assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+        },
+      }
+    }
+
+    // The child dropped the last reference, and the creator frees it, so the
+    // next buffer goes in the same place.
+    region.reap();
+    assert!(RWIobuf::new_with_allocator(5, region.allocator()).ptr() == p);
+  }
+
+  pub fn main() {
+    shared_with_a_child_process();
+  }
+}
+
+#[cfg(unix)]
+fn main() {
+  unix::main();
+}
+
+#[cfg(not(unix))]
+fn main() {}